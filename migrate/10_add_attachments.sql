@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS
+    attachments (
+        id          bigserial PRIMARY KEY,
+        owner_table text NOT NULL CHECK (owner_table IN ('certificates', 'practices', 'companies', 'sirens', 'educations')),
+        owner_id    bigint NOT NULL,
+        filename    text NOT NULL,
+        mime_type   text NOT NULL,
+        size        bigint NOT NULL DEFAULT 0,
+        checksum    text NOT NULL DEFAULT '',
+        note        text,
+        created_at  timestamp without time zone,
+        updated_at  timestamp without time zone DEFAULT now()
+    );
+
+CREATE TABLE IF NOT EXISTS
+    attachment_chunks (
+        id            bigserial PRIMARY KEY,
+        attachment_id bigint NOT NULL,
+        seq           integer NOT NULL,
+        data          bytea NOT NULL,
+        UNIQUE (attachment_id, seq)
+    );
\ No newline at end of file
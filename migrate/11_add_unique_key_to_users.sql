@@ -0,0 +1 @@
+ALTER TABLE users ADD UNIQUE (key);
\ No newline at end of file
@@ -0,0 +1,22 @@
+UPDATE addresses SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE attachments SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE certificates SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE companies SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE contacts SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE departments SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE educations SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE employments SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE kinds SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE posts SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE practices SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE ranks SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE required_posts SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE scenarios SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE scopes SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE settlements SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE siren_checks SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE siren_types SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE sirens SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE streets SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE users SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
+UPDATE warning_zones SET updated_at = COALESCE(created_at, now()) WHERE updated_at IS NULL;
\ No newline at end of file
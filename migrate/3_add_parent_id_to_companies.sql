@@ -0,0 +1 @@
+ALTER TABLE companies ADD COLUMN parent_id BIGINT;
@@ -0,0 +1,64 @@
+ALTER TABLE departments ADD COLUMN company_id BIGINT;
+ALTER TABLE departments ADD COLUMN parent_id BIGINT;
+ALTER TABLE departments DROP CONSTRAINT IF EXISTS departments_name_key;
+ALTER TABLE departments ADD COLUMN split_from BIGINT;
+
+-- A department used by the contacts of a single company moves into it.
+UPDATE departments AS d SET
+    company_id = u.company_id
+FROM (
+    SELECT
+        department_id,
+        min(company_id) AS company_id
+    FROM
+        contacts
+    WHERE
+        department_id IS NOT NULL
+    AND
+        company_id IS NOT NULL
+    GROUP BY
+        department_id
+    HAVING
+        count(DISTINCT company_id) = 1
+) AS u
+WHERE
+    d.id = u.department_id;
+
+-- One shared by several companies is copied into each of them and their
+-- contacts move to the copy.
+INSERT INTO departments (company_id, name, note, split_from, created_at, updated_at)
+SELECT DISTINCT
+    c.company_id,
+    d.name,
+    d.note,
+    d.id,
+    now(),
+    now()
+FROM
+    departments AS d
+JOIN
+    contacts AS c ON c.department_id = d.id
+WHERE
+    d.company_id IS NULL
+AND
+    c.company_id IS NOT NULL;
+
+UPDATE contacts AS c SET
+    department_id = copy.id
+FROM
+    departments AS copy
+WHERE
+    copy.split_from = c.department_id
+AND
+    copy.company_id = c.company_id;
+
+-- The shared original stays only while a contact without a company uses it.
+DELETE FROM
+    departments AS d
+WHERE
+    d.id IN (SELECT split_from FROM departments)
+AND
+    NOT EXISTS (SELECT 1 FROM contacts AS c WHERE c.department_id = d.id);
+
+ALTER TABLE departments DROP COLUMN split_from;
+ALTER TABLE departments ADD CONSTRAINT departments_company_id_name_key UNIQUE (company_id, name);
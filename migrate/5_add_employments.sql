@@ -0,0 +1,32 @@
+CREATE TABLE IF NOT EXISTS
+    employments (
+        id            bigserial PRIMARY KEY,
+        contact_id    bigint,
+        company_id    bigint,
+        department_id bigint,
+        post_id       bigint,
+        post_go_id    bigint,
+        rank_id       bigint,
+        date_from     date,
+        date_to       date,
+        note          text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now()
+    );
+
+-- Everyone starts with an open period of unknown start for the position they
+-- hold now.
+INSERT INTO employments (contact_id, company_id, department_id, post_id, post_go_id, rank_id, created_at, updated_at)
+SELECT
+    id,
+    company_id,
+    department_id,
+    post_id,
+    post_go_id,
+    rank_id,
+    now(),
+    now()
+FROM
+    contacts
+WHERE
+    COALESCE(company_id, department_id, post_id, post_go_id, rank_id) IS NOT NULL;
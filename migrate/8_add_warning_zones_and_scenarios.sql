@@ -0,0 +1,39 @@
+CREATE TABLE IF NOT EXISTS
+    warning_zones (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    warning_zone_sirens (
+        id              bigserial PRIMARY KEY,
+        warning_zone_id bigint NOT NULL,
+        siren_id        bigint NOT NULL,
+        created_at      timestamp without time zone,
+        updated_at      timestamp without time zone DEFAULT now(),
+        UNIQUE (warning_zone_id, siren_id)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    scenarios (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    scenario_zones (
+        id              bigserial PRIMARY KEY,
+        scenario_id     bigint NOT NULL,
+        warning_zone_id bigint NOT NULL,
+        created_at      timestamp without time zone,
+        updated_at      timestamp without time zone DEFAULT now(),
+        UNIQUE (scenario_id, warning_zone_id)
+    );
@@ -0,0 +1,40 @@
+CREATE TABLE IF NOT EXISTS
+    settlements (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        kind       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name, kind)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    streets (
+        id            bigserial PRIMARY KEY,
+        settlement_id bigint,
+        name          text,
+        kind          text,
+        aliases       text[] NOT NULL DEFAULT '{}',
+        note          text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now(),
+        UNIQUE (settlement_id, name, kind)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    addresses (
+        id            bigserial PRIMARY KEY,
+        company_id    bigint,
+        siren_id      bigint,
+        settlement_id bigint,
+        street_id     bigint,
+        house         text,
+        building      text,
+        entrance      text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now(),
+        UNIQUE (company_id),
+        UNIQUE (siren_id),
+        CHECK (company_id IS NULL OR siren_id IS NULL)
+    );
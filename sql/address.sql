@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS
+    addresses (
+        id            bigserial PRIMARY KEY,
+        company_id    bigint,
+        siren_id      bigint,
+        settlement_id bigint,
+        street_id     bigint,
+        house         text,
+        building      text,
+        entrance      text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now(),
+        UNIQUE (company_id),
+        UNIQUE (siren_id),
+        CHECK (company_id IS NULL OR siren_id IS NULL)
+    );
\ No newline at end of file
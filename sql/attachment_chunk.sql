@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS
+    attachment_chunks (
+        id            bigserial PRIMARY KEY,
+        attachment_id bigint NOT NULL,
+        seq           integer NOT NULL,
+        data          bytea NOT NULL,
+        UNIQUE (attachment_id, seq)
+    );
\ No newline at end of file
@@ -5,6 +5,7 @@ CREATE TABLE IF NOT EXISTS
         full_name  text,
         address    text,
         scope_id   bigint,
+        parent_id  bigint,
         note       text,
         created_at timestamp without time zone,
         updated_at timestamp without time zone DEFAULT now(),
@@ -3,7 +3,7 @@ CREATE TABLE IF NOT EXISTS
         id            bigserial PRIMARY KEY,
         name          text,
         company_id    bigint,
-        department_id bigint
+        department_id bigint,
         post_id       bigint,
         post_go_id    bigint,
         rank_id       bigint,
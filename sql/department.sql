@@ -1,9 +1,11 @@
 CREATE TABLE IF NOT EXISTS
     departments (
         id         bigserial primary key,
+        company_id bigint,
+        parent_id  bigint,
         name       text,
         note       text,
         created_at TIMESTAMP without time zone,
         updated_at TIMESTAMP without time zone,
-        UNIQUE (name)
+        UNIQUE (company_id, name)
     );
\ No newline at end of file
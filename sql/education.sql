@@ -3,8 +3,8 @@ CREATE TABLE IF NOT EXISTS
         id         bigserial PRIMARY KEY,
         contact_id bigint,
         post_id    bigint,
-        start_date time,
-        end_date   time,
+        start_date date,
+        end_date   date,
         note       text,
         created_at timestamp without time zone,
         updated_at timestamp without time zone DEFAULT now()
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS
+    employments (
+        id            bigserial PRIMARY KEY,
+        contact_id    bigint,
+        company_id    bigint,
+        department_id bigint,
+        post_id       bigint,
+        post_go_id    bigint,
+        rank_id       bigint,
+        date_from     date,
+        date_to       date,
+        note          text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now()
+    );
\ No newline at end of file
@@ -4,7 +4,7 @@ CREATE TABLE IF NOT EXISTS
         company_id       bigint,
         kind_id          bigint,
         topic            text,
-        date_of_practice time,
+        date_of_practice date,
         note             text,
         created_at       timestamp without time zone,
         updated_at       timestamp without time zone DEFAULT now(),
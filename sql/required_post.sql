@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS
+    required_posts (
+        id         bigserial PRIMARY KEY,
+        scope_id   bigint,
+        post_go_id bigint,
+        head       bool NOT NULL DEFAULT FALSE,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (scope_id, post_go_id)
+    );
\ No newline at end of file
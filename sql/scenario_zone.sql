@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS
+    scenario_zones (
+        id              bigserial PRIMARY KEY,
+        scenario_id     bigint NOT NULL,
+        warning_zone_id bigint NOT NULL,
+        created_at      timestamp without time zone,
+        updated_at      timestamp without time zone DEFAULT now(),
+        UNIQUE (scenario_id, warning_zone_id)
+    );
\ No newline at end of file
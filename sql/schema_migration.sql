@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS
+    schema_migrations (
+        version    bigint PRIMARY KEY,
+        name       text NOT NULL,
+        applied_at timestamp without time zone DEFAULT now()
+    );
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS
+    settlements (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        kind       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name, kind)
+    );
\ No newline at end of file
@@ -16,5 +16,5 @@ CREATE TABLE IF NOT EXISTS
         note          text,
         created_at    TIMESTAMP without time zone,
         updated_at    TIMESTAMP without time zone,
-        UNIQUE(num_id, num_pass, type_id)
+        UNIQUE(num_id, num_pass, siren_type_id)
     );
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS
+    siren_checks (
+        id         bigserial PRIMARY KEY,
+        siren_id   bigint,
+        check_date date NOT NULL,
+        kind       text NOT NULL CHECK (kind IN ('test', 'alert', 'maintenance')),
+        result     text NOT NULL CHECK (result IN ('worked', 'faulty', 'no_power')),
+        contact_id bigint,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now()
+    );
\ No newline at end of file
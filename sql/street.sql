@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS
+    streets (
+        id            bigserial PRIMARY KEY,
+        settlement_id bigint,
+        name          text,
+        kind          text,
+        aliases       text[] NOT NULL DEFAULT '{}',
+        note          text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now(),
+        UNIQUE (settlement_id, name, kind)
+    );
\ No newline at end of file
@@ -6,5 +6,6 @@ CREATE TABLE IF NOT EXISTS
         role       bigint NOT NULL,
         created_at TIMESTAMP without time zone,
         updated_at TIMESTAMP without time zone,
-        UNIQUE (name)
+        UNIQUE (name),
+        UNIQUE (key)
     );
\ No newline at end of file
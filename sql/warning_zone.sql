@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS
+    warning_zones (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS
+    warning_zone_sirens (
+        id              bigserial PRIMARY KEY,
+        warning_zone_id bigint NOT NULL,
+        siren_id        bigint NOT NULL,
+        created_at      timestamp without time zone,
+        updated_at      timestamp without time zone DEFAULT now(),
+        UNIQUE (warning_zone_id, siren_id)
+    );
\ No newline at end of file
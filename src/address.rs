@@ -0,0 +1,996 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    settlement::Settlement,
+    street::Street,
+    trace::{prepare, single_row, traced, Rows},
+    RpelPool,
+};
+
+// The structured address of a company or a siren, pointing into the
+// gazetteer. The free-text `address` of the owner is left as it was.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Address {
+    #[serde(default)]
+    pub id: i64,
+    pub company_id: Option<i64>,
+    pub siren_id: Option<i64>,
+    pub settlement_id: Option<i64>,
+    pub street_id: Option<i64>,
+    pub house: Option<String>,
+    pub building: Option<String>,
+    pub entrance: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Address);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AddressList {
+    pub id: i64,
+    pub company_id: Option<i64>,
+    pub company_name: Option<String>,
+    pub siren_id: Option<i64>,
+    pub siren_num_id: Option<i64>,
+    pub settlement_id: Option<i64>,
+    pub settlement_name: Option<String>,
+    pub street_id: Option<i64>,
+    pub street_name: Option<String>,
+    pub house: Option<String>,
+    pub building: Option<String>,
+    pub entrance: Option<String>,
+}
+
+// A free-text address the parser could not place, with the reason.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct AddressFailure {
+    pub table: String,
+    pub id: i64,
+    pub address: String,
+    pub reason: String,
+}
+
+// The outcome of a parse run over the owners without a structured address:
+// how many were parsed, and the ones that were not.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AddressReport {
+    pub parsed: u64,
+    pub failures: Vec<AddressFailure>,
+}
+
+impl Rows for AddressReport {
+    fn rows(&self) -> u64 {
+        self.parsed
+    }
+}
+
+pub(crate) fn check_owner(address: &Address) -> Result<(), RpelError> {
+    match (address.company_id, address.siren_id) {
+        (Some(company_id), Some(siren_id)) => Err(RpelError::Invalid(format!(
+            "address belongs to company {company_id} and siren {siren_id} at once"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Spellings met in the addresses, the first one of each group being the
+// short form the gazetteer keeps.
+const STREET_KINDS: [&[&str]; 10] = [
+    &["ул", "улица"],
+    &["пр-т", "пр", "просп", "проспект"],
+    &["пер", "переулок"],
+    &["б-р", "бул", "бульвар"],
+    &["пл", "площадь"],
+    &["ш", "шоссе"],
+    &["наб", "набережная"],
+    &["пр-д", "проезд"],
+    &["мкр", "микрорайон"],
+    &["туп", "тупик"],
+];
+
+const SETTLEMENT_KINDS: [&[&str]; 7] = [
+    &["г", "город"],
+    &["пгт"],
+    &["п", "пос", "поселок"],
+    &["с", "село"],
+    &["д", "дер", "деревня"],
+    &["х", "хутор"],
+    &["ст-ца", "станица"],
+];
+
+// Parts naming the region or the country say nothing the gazetteer keeps.
+const REGION_WORDS: [&str; 10] = [
+    "обл",
+    "область",
+    "край",
+    "респ",
+    "республика",
+    "р-н",
+    "район",
+    "россия",
+    "рф",
+    "ао",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    House,
+    Building,
+    Entrance,
+}
+
+impl Field {
+    fn label(self) -> &'static str {
+        match self {
+            Field::House => "house",
+            Field::Building => "building",
+            Field::Entrance => "entrance",
+        }
+    }
+}
+
+const MARKERS: [(Field, &[&str]); 3] = [
+    (Field::House, &["д", "дом"]),
+    (Field::Building, &["к", "корп", "корпус", "стр", "строение"]),
+    (Field::Entrance, &["под", "подъезд", "п-д"]),
+];
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .replace('ё', "е")
+        .replace(['.', '№', '"', '«', '»'], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn starts_with_digit(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+fn kind_of(kinds: &[&[&'static str]], word: &str) -> Option<&'static str> {
+    kinds
+        .iter()
+        .find(|spellings| spellings.contains(&word))
+        .map(|spellings| spellings[0])
+}
+
+// The name with the kind words and "им" dropped, and the kind it was given.
+fn name_key(words: &[&str], kinds: &[&[&'static str]]) -> (String, Option<&'static str>) {
+    let mut kind = None;
+    let mut key = Vec::new();
+    for word in words {
+        match kind_of(kinds, word) {
+            Some(found) => kind = Some(found),
+            None if *word != "им" => key.push(*word),
+            None => {}
+        }
+    }
+    (key.join(" "), kind)
+}
+
+// A marker word with the number glued to it, if any: "д" gives "", "к2" "2".
+fn marker(word: &str) -> Option<(Field, &str)> {
+    MARKERS.iter().find_map(|(field, spellings)| {
+        spellings.iter().find_map(|spelling| {
+            word.strip_prefix(spelling)
+                .filter(|rest| rest.is_empty() || starts_with_digit(rest))
+                .map(|rest| (*field, rest))
+        })
+    })
+}
+
+fn numberish(word: &str) -> bool {
+    starts_with_digit(word) || marker(word).is_some()
+}
+
+// Splits "12к1" or "12стр1" into the house and the building.
+fn split_house(word: &str) -> (&str, Option<&str>) {
+    for (at, _) in word.char_indices().skip(1) {
+        for spelling in ["корп", "стр", "к"] {
+            if let Some(rest) = word[at..].strip_prefix(spelling) {
+                if starts_with_digit(rest) {
+                    return (&word[..at], Some(rest));
+                }
+            }
+        }
+    }
+    (word, None)
+}
+
+struct Entry {
+    id: i64,
+    settlement_id: Option<i64>,
+    keys: Vec<String>,
+    kind: Option<&'static str>,
+}
+
+#[derive(Default)]
+struct Parts {
+    settlement_id: Option<i64>,
+    street: Option<(String, Option<&'static str>, String)>,
+    house: Option<String>,
+    building: Option<String>,
+    entrance: Option<String>,
+}
+
+impl Parts {
+    fn set(&mut self, field: Field, value: &str, text: &str) -> Result<(), String> {
+        let slot = match field {
+            Field::House => &mut self.house,
+            Field::Building => &mut self.building,
+            Field::Entrance => &mut self.entrance,
+        };
+        if slot.is_some() {
+            return Err(format!("more than one {} in \"{text}\"", field.label()));
+        }
+        *slot = Some(value.to_string());
+        Ok(())
+    }
+
+    // Reads the house, building and entrance numbers that follow the name.
+    fn numbers(&mut self, words: &[&str], text: &str) -> Result<(), String> {
+        let mut pending = None;
+        for word in words {
+            if let Some((field, rest)) = marker(word) {
+                if rest.is_empty() {
+                    pending = Some(field);
+                } else {
+                    self.set(field, rest, text)?;
+                    pending = None;
+                }
+                continue;
+            }
+            match pending.take().unwrap_or(Field::House) {
+                Field::House => {
+                    let (house, building) = split_house(word);
+                    self.set(Field::House, house, text)?;
+                    if let Some(building) = building {
+                        self.set(Field::Building, building, text)?;
+                    }
+                }
+                field => self.set(field, word, text)?,
+            }
+        }
+        match pending {
+            Some(field) => Err(format!("no {} number in \"{text}\"", field.label())),
+            None => Ok(()),
+        }
+    }
+}
+
+// The settlements and streets the parser matches free-text addresses against,
+// their names reduced the way the addresses are.
+pub struct Gazetteer {
+    settlements: Vec<Entry>,
+    streets: Vec<Entry>,
+}
+
+impl Gazetteer {
+    pub fn new(settlements: Vec<Settlement>, streets: Vec<Street>) -> Gazetteer {
+        let kind = |kinds: &[&[&'static str]], kind: &Option<String>| {
+            kind.as_deref()
+                .and_then(|kind| kind_of(kinds, normalize(kind).as_str()))
+        };
+        let key = |kinds: &[&[&'static str]], name: &str| {
+            let name = normalize(name);
+            name_key(&name.split(' ').collect::<Vec<_>>(), kinds).0
+        };
+        let settlements = settlements
+            .into_iter()
+            .map(|s| Entry {
+                id: s.id,
+                settlement_id: Some(s.id),
+                keys: s.name.iter().map(|n| key(&SETTLEMENT_KINDS, n)).collect(),
+                kind: kind(&SETTLEMENT_KINDS, &s.kind),
+            })
+            .collect();
+        let streets = streets
+            .into_iter()
+            .map(|s| Entry {
+                id: s.id,
+                settlement_id: s.settlement_id,
+                keys: s
+                    .name
+                    .iter()
+                    .chain(&s.aliases)
+                    .map(|n| key(&STREET_KINDS, n))
+                    .collect(),
+                kind: kind(&STREET_KINDS, &s.kind),
+            })
+            .collect();
+        Gazetteer {
+            settlements,
+            streets,
+        }
+    }
+
+    pub async fn load_with<C: GenericClient>(client: &C) -> Result<Gazetteer, RpelError> {
+        let stmt = prepare(
+            client,
+            "
+                SELECT
+                    id,
+                    name,
+                    kind
+                FROM
+                    settlements
+                ORDER BY
+                    id ASC
+            ",
+        )
+        .await?;
+        let mut settlements = Vec::new();
+        for row in client.query(&stmt, &[]).await? {
+            settlements.push(Settlement {
+                id: row.try_get(0)?,
+                name: row.try_get(1)?,
+                kind: row.try_get(2)?,
+                ..Default::default()
+            });
+        }
+        let stmt = prepare(
+            client,
+            "
+                SELECT
+                    id,
+                    settlement_id,
+                    name,
+                    kind,
+                    aliases
+                FROM
+                    streets
+                ORDER BY
+                    id ASC
+            ",
+        )
+        .await?;
+        let mut streets = Vec::new();
+        for row in client.query(&stmt, &[]).await? {
+            streets.push(Street {
+                id: row.try_get(0)?,
+                settlement_id: row.try_get(1)?,
+                name: row.try_get(2)?,
+                kind: row.try_get(3)?,
+                aliases: row.try_get(4)?,
+                ..Default::default()
+            });
+        }
+        Ok(Gazetteer::new(settlements, streets))
+    }
+
+    // The one entry named `key`, narrowed down by kind when the name alone
+    // is not enough.
+    fn find<'a>(
+        entries: impl Iterator<Item = &'a Entry>,
+        key: &str,
+        kind: Option<&str>,
+    ) -> Result<Option<&'a Entry>, ()> {
+        if key.is_empty() {
+            return Ok(None);
+        }
+        let mut found: Vec<&Entry> = entries
+            .filter(|e| e.keys.iter().any(|k| k == key))
+            .collect();
+        if found.len() > 1 && kind.is_some() {
+            found.retain(|e| e.kind == kind);
+        }
+        match found.len() {
+            0 => Ok(None),
+            1 => Ok(Some(found[0])),
+            _ => Err(()),
+        }
+    }
+
+    // Reads a free-text address such as "г. Москва, ул. Ленина, д. 5, корп. 2"
+    // into gazetteer ids and numbers. The settlement may be left out when the
+    // street is known in only one. The error says what could not be placed.
+    pub fn parse(&self, text: &str) -> Result<Address, String> {
+        let mut parts = Parts::default();
+        let mut rest = Vec::new();
+        for part in text.split(',').map(normalize).filter(|p| !p.is_empty()) {
+            let words: Vec<&str> = part.split(' ').collect();
+            let postcode = words.len() == 1 && part.len() == 6 && part.parse::<u32>().is_ok();
+            if postcode || words.iter().any(|w| REGION_WORDS.contains(w)) {
+                continue;
+            }
+            if words
+                .iter()
+                .any(|w| starts_with_digit(w) || kind_of(&STREET_KINDS, w).is_some())
+            {
+                rest.push(part);
+                continue;
+            }
+            let (key, kind) = name_key(&words, &SETTLEMENT_KINDS);
+            match Gazetteer::find(self.settlements.iter(), &key, kind) {
+                Ok(Some(settlement))
+                    if parts.settlement_id.is_some_and(|id| id != settlement.id) =>
+                {
+                    return Err(format!("more than one settlement in \"{text}\""));
+                }
+                Ok(Some(settlement)) => parts.settlement_id = Some(settlement.id),
+                Ok(None)
+                    if words.iter().any(|w| {
+                        marker(w).is_none() && kind_of(&SETTLEMENT_KINDS, w).is_some()
+                    }) =>
+                {
+                    return Err(format!("unknown settlement \"{part}\""));
+                }
+                Ok(None) => rest.push(part),
+                Err(()) => return Err(format!("ambiguous settlement \"{part}\"")),
+            }
+        }
+        for part in &rest {
+            let words: Vec<&str> = part.split(' ').collect();
+            let split = words
+                .iter()
+                .rposition(|w| !numberish(w))
+                .map_or(0, |at| at + 1);
+            if split > 0 {
+                if parts.street.is_some() {
+                    return Err(format!("unrecognised part \"{part}\""));
+                }
+                let (key, kind) = name_key(&words[..split], &STREET_KINDS);
+                parts.street = Some((key, kind, words[..split].join(" ")));
+            }
+            parts.numbers(&words[split..], text)?;
+        }
+        let mut street_id = None;
+        if let Some((key, kind, name)) = &parts.street {
+            let streets = self.streets.iter().filter(|s| {
+                parts
+                    .settlement_id
+                    .is_none_or(|id| s.settlement_id == Some(id))
+            });
+            match Gazetteer::find(streets, key, *kind) {
+                Ok(Some(street)) => {
+                    street_id = Some(street.id);
+                    parts.settlement_id = parts.settlement_id.or(street.settlement_id);
+                }
+                Ok(None) => return Err(format!("unknown street \"{name}\"")),
+                Err(()) => return Err(format!("ambiguous street \"{name}\"")),
+            }
+        }
+        if parts.settlement_id.is_none() && street_id.is_none() {
+            return Err(match text.trim() {
+                "" => "empty address".to_string(),
+                _ => format!("no settlement or street in \"{text}\""),
+            });
+        }
+        Ok(Address {
+            settlement_id: parts.settlement_id,
+            street_id,
+            house: parts.house,
+            building: parts.building,
+            entrance: parts.entrance,
+            ..Default::default()
+        })
+    }
+}
+
+// Parses the free-text address of each (table, id, address) owner, giving
+// the addresses to store and the failures to report.
+pub(crate) fn parse_owners(
+    gazetteer: &Gazetteer,
+    owners: Vec<(String, i64, String)>,
+) -> (Vec<Address>, Vec<AddressFailure>) {
+    let mut addresses = Vec::new();
+    let mut failures = Vec::new();
+    for (table, id, text) in owners {
+        match gazetteer.parse(&text) {
+            Ok(mut address) => {
+                match table.as_str() {
+                    "companies" => address.company_id = Some(id),
+                    _ => address.siren_id = Some(id),
+                }
+                addresses.push(address);
+            }
+            Err(reason) => failures.push(AddressFailure {
+                table,
+                id,
+                address: text,
+                reason,
+            }),
+        }
+    }
+    (addresses, failures)
+}
+
+impl Address {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Address, RpelError> {
+        let client = acquire(pool).await?;
+        Address::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Address, RpelError> {
+        traced("Address", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        company_id,
+                        siren_id,
+                        settlement_id,
+                        street_id,
+                        house,
+                        building,
+                        entrance,
+                        created_at,
+                        updated_at
+                    FROM
+                        addresses
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("addresses".to_string(), id))?;
+            let address = Address {
+                id,
+                company_id: row.try_get(0)?,
+                siren_id: row.try_get(1)?,
+                settlement_id: row.try_get(2)?,
+                street_id: row.try_get(3)?,
+                house: row.try_get(4)?,
+                building: row.try_get(5)?,
+                entrance: row.try_get(6)?,
+                created_at: row.try_get(7)?,
+                updated_at: row.try_get(8)?,
+            };
+            Ok(address)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, address: Address) -> Result<Address, RpelError> {
+        let client = acquire(pool).await?;
+        Address::insert_with(&client, address).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        address: Address,
+    ) -> Result<Address, RpelError> {
+        traced("Address", "insert", async move {
+            let mut address = address;
+            check_owner(&address)?;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO addresses
+                    (
+                        company_id,
+                        siren_id,
+                        settlement_id,
+                        street_id,
+                        house,
+                        building,
+                        entrance,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6,
+                        $7,
+                        $8,
+                        $9
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &address.company_id,
+                        &address.siren_id,
+                        &address.settlement_id,
+                        &address.street_id,
+                        &address.house,
+                        &address.building,
+                        &address.entrance,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            address.id = row.get(0);
+            notify(client, "addresses", address.id, Operation::Insert, 1).await?;
+            Ok(address)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, address: Address) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Address::update_with(&client, address).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        address: Address,
+    ) -> Result<u64, RpelError> {
+        traced("Address", "update", async move {
+            check_owner(&address)?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE addresses SET
+                        company_id = $2,
+                        siren_id = $3,
+                        settlement_id = $4,
+                        street_id = $5,
+                        house = $6,
+                        building = $7,
+                        entrance = $8,
+                        updated_at = $9
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $10
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &address.id,
+                        &address.company_id,
+                        &address.siren_id,
+                        &address.settlement_id,
+                        &address.street_id,
+                        &address.house,
+                        &address.building,
+                        &address.entrance,
+                        &Local::now().naive_local(),
+                        &address.updated_at,
+                    ],
+                )
+                .await?;
+            let result =
+                check_version(client, "addresses", address.id, address.updated_at, result).await?;
+            notify(client, "addresses", address.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Address::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Address", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        addresses
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "addresses", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete_companies_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+    ) -> Result<u64, RpelError> {
+        traced("Address", "delete_companies", async move {
+            Address::delete_owned(client, "company_id", company_id).await
+        })
+        .await
+    }
+
+    pub async fn delete_sirens_with<C: GenericClient>(
+        client: &C,
+        siren_id: i64,
+    ) -> Result<u64, RpelError> {
+        traced("Address", "delete_sirens", async move {
+            Address::delete_owned(client, "siren_id", siren_id).await
+        })
+        .await
+    }
+
+    async fn delete_owned<C: GenericClient>(
+        client: &C,
+        column: &str,
+        id: i64,
+    ) -> Result<u64, RpelError> {
+        let stmt = prepare(
+            client,
+            format!(
+                "
+                    DELETE FROM
+                        addresses
+                    WHERE
+                        {column} = $1
+                    RETURNING
+                        id
+                "
+            )
+            .as_str(),
+        )
+        .await?;
+        let rows = client.query(&stmt, &[&id]).await?;
+        for row in &rows {
+            notify(client, "addresses", row.try_get(0)?, Operation::Delete, 1).await?;
+        }
+        Ok(rows.len() as u64)
+    }
+
+    // Parses the free-text address of every company and siren that has no
+    // structured one yet. Only with `apply` are the parsed addresses stored;
+    // either way the report lists the ones that failed.
+    pub async fn parse_all(pool: &RpelPool, apply: bool) -> Result<AddressReport, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let report = Address::parse_all_with(&tx, apply).await?;
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    pub async fn parse_all_with<C: GenericClient>(
+        client: &C,
+        apply: bool,
+    ) -> Result<AddressReport, RpelError> {
+        traced("Address", "parse_all", async move {
+            let gazetteer = Gazetteer::load_with(client).await?;
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        'companies' AS owner,
+                        c.id,
+                        c.address
+                    FROM
+                        companies AS c
+                    WHERE
+                        btrim(c.address) <> ''
+                    AND
+                        NOT EXISTS (SELECT 1 FROM addresses AS a WHERE a.company_id = c.id)
+                    UNION ALL
+                    SELECT
+                        'sirens' AS owner,
+                        s.id,
+                        s.address
+                    FROM
+                        sirens AS s
+                    WHERE
+                        btrim(s.address) <> ''
+                    AND
+                        NOT EXISTS (SELECT 1 FROM addresses AS a WHERE a.siren_id = s.id)
+                    ORDER BY
+                        owner ASC,
+                        id ASC
+                ",
+            )
+            .await?;
+            let mut owners = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                owners.push((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?));
+            }
+            let (addresses, failures) = parse_owners(&gazetteer, owners);
+            let parsed = addresses.len() as u64;
+            if apply {
+                for address in addresses {
+                    Address::insert_with(client, address).await?;
+                }
+            }
+            Ok(AddressReport { parsed, failures })
+        })
+        .await
+    }
+}
+
+impl AddressList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<AddressList>, RpelError> {
+        let client = acquire(pool).await?;
+        AddressList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<AddressList>, RpelError> {
+        traced("AddressList", "get_all", async move {
+            let mut addresses = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        a.id,
+                        a.company_id,
+                        c.name AS company_name,
+                        a.siren_id,
+                        r.num_id AS siren_num_id,
+                        a.settlement_id,
+                        s.name AS settlement_name,
+                        a.street_id,
+                        t.name AS street_name,
+                        a.house,
+                        a.building,
+                        a.entrance
+                    FROM
+                        addresses AS a
+                    LEFT JOIN
+                        companies AS c ON c.id = a.company_id
+                    LEFT JOIN
+                        sirens AS r ON r.id = a.siren_id
+                    LEFT JOIN
+                        settlements AS s ON s.id = a.settlement_id
+                    LEFT JOIN
+                        streets AS t ON t.id = a.street_id
+                    ORDER BY
+                        s.name ASC,
+                        t.name ASC,
+                        a.house ASC,
+                        a.id ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                addresses.push(AddressList {
+                    id: row.try_get(0)?,
+                    company_id: row.try_get(1)?,
+                    company_name: row.try_get(2)?,
+                    siren_id: row.try_get(3)?,
+                    siren_num_id: row.try_get(4)?,
+                    settlement_id: row.try_get(5)?,
+                    settlement_name: row.try_get(6)?,
+                    street_id: row.try_get(7)?,
+                    street_name: row.try_get(8)?,
+                    house: row.try_get(9)?,
+                    building: row.try_get(10)?,
+                    entrance: row.try_get(11)?,
+                });
+            }
+            Ok(addresses)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gazetteer() -> Gazetteer {
+        let settlement = |id: i64, name: &str, kind: &str| Settlement {
+            id,
+            name: Some(name.to_string()),
+            kind: Some(kind.to_string()),
+            ..Default::default()
+        };
+        let street = |id: i64, settlement_id: i64, name: &str, kind: &str| Street {
+            id,
+            settlement_id: Some(settlement_id),
+            name: Some(name.to_string()),
+            kind: Some(kind.to_string()),
+            ..Default::default()
+        };
+        Gazetteer::new(
+            vec![settlement(1, "Москва", "г"), settlement(2, "Ивановка", "д")],
+            vec![
+                street(10, 1, "Ленина", "ул"),
+                street(11, 1, "Ленина", "пер"),
+                Street {
+                    aliases: vec!["Восьмого Марта".to_string()],
+                    ..street(12, 1, "8 Марта", "ул")
+                },
+                street(13, 1, "Мира", "пр-т"),
+                street(14, 1, "Садовая", "ул"),
+                street(20, 2, "Садовая", "ул"),
+            ],
+        )
+    }
+
+    type Parsed = (
+        Option<i64>,
+        Option<i64>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    );
+
+    fn parse(text: &str) -> Result<Parsed, String> {
+        gazetteer().parse(text).map(|a| {
+            (
+                a.settlement_id,
+                a.street_id,
+                a.house,
+                a.building,
+                a.entrance,
+            )
+        })
+    }
+
+    fn parsed(
+        settlement_id: i64,
+        street_id: Option<i64>,
+        house: Option<&str>,
+        building: Option<&str>,
+        entrance: Option<&str>,
+    ) -> Result<Parsed, String> {
+        let text = |value: Option<&str>| value.map(str::to_string);
+        Ok((
+            Some(settlement_id),
+            street_id,
+            text(house),
+            text(building),
+            text(entrance),
+        ))
+    }
+
+    #[test]
+    fn parses_spelling_variants() {
+        assert_eq!(
+            parse("г. Москва, ул. Ленина, д. 5, корп. 2, подъезд 3"),
+            parsed(1, Some(10), Some("5"), Some("2"), Some("3"))
+        );
+        assert_eq!(
+            parse("Москва, Ленина пер., 7а"),
+            parsed(1, Some(11), Some("7а"), None, None)
+        );
+        assert_eq!(
+            parse("ул.8 Марта 12к1"),
+            parsed(1, Some(12), Some("12"), Some("1"), None)
+        );
+        assert_eq!(
+            parse("улица Восьмого марта, дом 4"),
+            parsed(1, Some(12), Some("4"), None, None)
+        );
+        assert_eq!(
+            parse("123456, Московская обл., г Москва, проспект Мира, 10 стр 2"),
+            parsed(1, Some(13), Some("10"), Some("2"), None)
+        );
+        assert_eq!(
+            parse("д. Ивановка, Садовая, 3"),
+            parsed(2, Some(20), Some("3"), None, None)
+        );
+        assert_eq!(
+            parse("Ивановка, 15"),
+            parsed(2, None, Some("15"), None, None)
+        );
+    }
+
+    #[test]
+    fn reports_what_it_cannot_place() {
+        let reason = |text: &str| parse(text).unwrap_err();
+        assert_eq!(reason(" "), "empty address");
+        assert_eq!(reason("Ленина, 5"), "ambiguous street \"ленина\"");
+        assert_eq!(reason("Садовая 3"), "ambiguous street \"садовая\"");
+        assert_eq!(
+            reason("г. Тверь, ул. Ленина"),
+            "unknown settlement \"г тверь\""
+        );
+        assert_eq!(reason("ул. Пушкина, д. 1"), "unknown street \"ул пушкина\"");
+        assert_eq!(
+            reason("ул. Ленина, д. 5, д. 6"),
+            "more than one house in \"ул. Ленина, д. 5, д. 6\""
+        );
+    }
+}
@@ -0,0 +1,714 @@
+use std::pin::Pin;
+
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use futures_core::Stream;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_postgres::Row;
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// File contents are kept in `attachment_chunks` rows of at most this many
+// bytes, so that neither an upload nor a download holds a whole file.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+// The contents of a stored file, chunk by chunk.
+pub type Chunks = Pin<Box<dyn Stream<Item = Result<Vec<u8>, RpelError>> + Send>>;
+
+// The records a file can be attached to, named after their tables.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentOwner {
+    Certificates,
+    Practices,
+    #[default]
+    Companies,
+    Sirens,
+    Educations,
+}
+
+impl AttachmentOwner {
+    pub const ALL: [AttachmentOwner; 5] = [
+        AttachmentOwner::Certificates,
+        AttachmentOwner::Practices,
+        AttachmentOwner::Companies,
+        AttachmentOwner::Sirens,
+        AttachmentOwner::Educations,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttachmentOwner::Certificates => "certificates",
+            AttachmentOwner::Practices => "practices",
+            AttachmentOwner::Companies => "companies",
+            AttachmentOwner::Sirens => "sirens",
+            AttachmentOwner::Educations => "educations",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<AttachmentOwner, RpelError> {
+        AttachmentOwner::ALL
+            .into_iter()
+            .find(|owner| owner.as_str() == value)
+            .ok_or_else(|| RpelError::Invalid(format!("unknown attachment owner {value:?}")))
+    }
+}
+
+// A stored file. `size` and `checksum`, the hex SHA-256 of the contents, are
+// worked out while uploading; whatever the caller sends for them is ignored.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Attachment {
+    #[serde(default)]
+    pub id: i64,
+    #[serde(default)]
+    pub owner: AttachmentOwner,
+    #[serde(default)]
+    pub owner_id: i64,
+    pub filename: String,
+    pub mime_type: String,
+    #[serde(default)]
+    pub size: i64,
+    #[serde(default)]
+    pub checksum: String,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Attachment);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AttachmentList {
+    pub id: i64,
+    pub owner: AttachmentOwner,
+    pub owner_id: i64,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+    pub note: Option<String>,
+    pub uploaded_at: Option<NaiveDateTime>,
+}
+
+// Size and SHA-256 of contents fed in pieces.
+pub(crate) struct Checksum {
+    hasher: Sha256,
+    size: i64,
+}
+
+impl Checksum {
+    pub(crate) fn new() -> Checksum {
+        Checksum {
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.size += data.len() as i64;
+    }
+
+    pub(crate) fn finish(self) -> (i64, String) {
+        let hex = self
+            .hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        (self.size, hex)
+    }
+}
+
+// A file needs a name to be downloaded under; an unknown type is sent as
+// plain bytes.
+pub(crate) fn check_attachment(attachment: &mut Attachment) -> Result<(), RpelError> {
+    attachment.filename = attachment.filename.trim().to_string();
+    if attachment.filename.is_empty() {
+        return Err(RpelError::Invalid("attachment has no filename".to_string()));
+    }
+    attachment.mime_type = attachment.mime_type.trim().to_string();
+    if attachment.mime_type.is_empty() {
+        attachment.mime_type = "application/octet-stream".to_string();
+    }
+    Ok(())
+}
+
+// Cuts the contents into `attachment_chunks` rows and returns their size and
+// checksum.
+async fn write_chunks<C, S, B>(client: &C, id: i64, chunks: S) -> Result<(i64, String), RpelError>
+where
+    C: GenericClient,
+    S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+    B: AsRef<[u8]> + Send,
+{
+    let mut chunks = chunks;
+    let insert = prepare(
+        client,
+        "
+            INSERT INTO attachment_chunks
+            (
+                attachment_id,
+                seq,
+                data
+            )
+            VALUES
+            (
+                $1,
+                $2,
+                $3
+            )
+        ",
+    )
+    .await?;
+    let mut checksum = Checksum::new();
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+    let mut seq = 0i32;
+    loop {
+        let piece = chunks.next().await.transpose()?;
+        let mut data = piece.as_ref().map_or(&[][..], |piece| piece.as_ref());
+        checksum.update(data);
+        // Cut the body into whole chunks; the rest waits for more.
+        while !data.is_empty() {
+            let take = data.len().min(CHUNK_SIZE - buffer.len());
+            buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if buffer.len() == CHUNK_SIZE {
+                client.execute(&insert, &[&id, &seq, &buffer]).await?;
+                buffer.clear();
+                seq += 1;
+            }
+        }
+        if piece.is_none() {
+            break;
+        }
+    }
+    if !buffer.is_empty() {
+        client.execute(&insert, &[&id, &seq, &buffer]).await?;
+    }
+    Ok(checksum.finish())
+}
+
+const SELECT: &str = "
+    SELECT
+        id,
+        owner_table,
+        owner_id,
+        filename,
+        mime_type,
+        size,
+        checksum,
+        note,
+        created_at,
+        updated_at
+    FROM
+        attachments
+";
+
+fn from_row(row: &Row) -> Result<Attachment, RpelError> {
+    Ok(Attachment {
+        id: row.try_get(0)?,
+        owner: AttachmentOwner::parse(row.try_get(1)?)?,
+        owner_id: row.try_get(2)?,
+        filename: row.try_get(3)?,
+        mime_type: row.try_get(4)?,
+        size: row.try_get(5)?,
+        checksum: row.try_get(6)?,
+        note: row.try_get(7)?,
+        created_at: row.try_get(8)?,
+        updated_at: row.try_get(9)?,
+    })
+}
+
+fn list_row(row: &Row) -> Result<AttachmentList, RpelError> {
+    let attachment = from_row(row)?;
+    Ok(AttachmentList {
+        id: attachment.id,
+        owner: attachment.owner,
+        owner_id: attachment.owner_id,
+        filename: attachment.filename,
+        mime_type: attachment.mime_type,
+        size: attachment.size,
+        checksum: attachment.checksum,
+        note: attachment.note,
+        uploaded_at: attachment.created_at,
+    })
+}
+
+impl Attachment {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Attachment, RpelError> {
+        let client = acquire(pool).await?;
+        Attachment::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Attachment, RpelError> {
+        traced("Attachment", "get", async move {
+            let stmt = prepare(client, format!("{SELECT} WHERE id = $1").as_str()).await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("attachments".to_string(), id))?;
+            from_row(&row)
+        })
+        .await
+    }
+
+    // Stores a file read from `chunks` in one transaction, so that a broken
+    // upload leaves nothing behind.
+    pub async fn upload<S, B>(
+        pool: &RpelPool,
+        attachment: Attachment,
+        chunks: S,
+    ) -> Result<Attachment, RpelError>
+    where
+        S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+        B: AsRef<[u8]> + Send,
+    {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Attachment::upload_with(&tx, attachment, chunks).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn upload_with<C, S, B>(
+        client: &C,
+        attachment: Attachment,
+        chunks: S,
+    ) -> Result<Attachment, RpelError>
+    where
+        C: GenericClient,
+        S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+        B: AsRef<[u8]> + Send,
+    {
+        traced("Attachment", "upload", async move {
+            let mut attachment = attachment;
+            check_attachment(&mut attachment)?;
+            let table = attachment.owner.as_str();
+            let stmt = prepare(
+                client,
+                format!("SELECT id FROM {table} WHERE id = $1").as_str(),
+            )
+            .await?;
+            if client
+                .query_opt(&stmt, &[&attachment.owner_id])
+                .await?
+                .is_none()
+            {
+                return Err(RpelError::Invalid(format!(
+                    "owner_id points to missing {table} {}",
+                    attachment.owner_id
+                )));
+            }
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO attachments
+                    (
+                        owner_table,
+                        owner_id,
+                        filename,
+                        mime_type,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6,
+                        $7
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let now = Local::now().naive_local();
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &table,
+                        &attachment.owner_id,
+                        &attachment.filename,
+                        &attachment.mime_type,
+                        &attachment.note,
+                        &now,
+                        &now,
+                    ],
+                )
+                .await?;
+            attachment.id = row.get(0);
+            (attachment.size, attachment.checksum) =
+                write_chunks(client, attachment.id, chunks).await?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE attachments SET
+                        size = $2,
+                        checksum = $3
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            client
+                .execute(
+                    &stmt,
+                    &[&attachment.id, &attachment.size, &attachment.checksum],
+                )
+                .await?;
+            attachment.created_at = Some(now);
+            attachment.updated_at = Some(now);
+            notify(client, "attachments", attachment.id, Operation::Insert, 1).await?;
+            Ok(attachment)
+        })
+        .await
+    }
+
+    // Puts back the contents of an attachment restored from a snapshot, which
+    // carries the row but not the file. The contents have to match the size
+    // and checksum recorded in the row.
+    pub async fn restore_content<S, B>(pool: &RpelPool, id: i64, chunks: S) -> Result<(), RpelError>
+    where
+        S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+        B: AsRef<[u8]> + Send,
+    {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        Attachment::restore_content_with(&tx, id, chunks).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn restore_content_with<C, S, B>(
+        client: &C,
+        id: i64,
+        chunks: S,
+    ) -> Result<(), RpelError>
+    where
+        C: GenericClient,
+        S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+        B: AsRef<[u8]> + Send,
+    {
+        traced("Attachment", "restore_content", async move {
+            let attachment = Attachment::get_with(client, id).await?;
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        1
+                    FROM
+                        attachment_chunks
+                    WHERE
+                        attachment_id = $1
+                    LIMIT 1
+                ",
+            )
+            .await?;
+            if client.query_opt(&stmt, &[&id]).await?.is_some() {
+                return Err(RpelError::Invalid(format!(
+                    "attachment {id} already has contents"
+                )));
+            }
+            let (size, checksum) = write_chunks(client, id, chunks).await?;
+            if (size, checksum) != (attachment.size, attachment.checksum) {
+                return Err(RpelError::Invalid(format!(
+                    "contents do not match the size and checksum of attachment {id}"
+                )));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    // The metadata of the file and a stream of its contents. The stream keeps
+    // a pooled connection and reads one chunk at a time as it is polled; when
+    // fewer bytes than `size` turn up, because the file was deleted meanwhile
+    // or chunks are missing, it ends in an error instead of a short file.
+    pub async fn content(pool: &RpelPool, id: i64) -> Result<(Attachment, Chunks), RpelError> {
+        let client = acquire(pool).await?;
+        let attachment = Attachment::get_with(&client, id).await?;
+        let size = attachment.size;
+        let state = (client, 0i32, 0i64);
+        let chunks = stream::try_unfold(state, move |(client, seq, read)| async move {
+            let stmt = prepare(
+                &client,
+                "
+                    SELECT
+                        data
+                    FROM
+                        attachment_chunks
+                    WHERE
+                        attachment_id = $1
+                    AND
+                        seq = $2
+                ",
+            )
+            .await?;
+            match client.query_opt(&stmt, &[&id, &seq]).await? {
+                Some(row) => {
+                    let data: Vec<u8> = row.try_get(0)?;
+                    let read = read + data.len() as i64;
+                    Ok(Some((data, (client, seq + 1, read))))
+                }
+                None if read == size => Ok(None),
+                None => Err(match Attachment::get_with(&client, id).await {
+                    Ok(_) => RpelError::Invalid(format!(
+                        "attachment {id} has {read} of {size} bytes stored"
+                    )),
+                    Err(err) => err,
+                }),
+            }
+        });
+        Ok((attachment, Box::pin(chunks)))
+    }
+
+    // Only the name, type and note can change; the contents are replaced by
+    // uploading a new file.
+    pub async fn update(pool: &RpelPool, attachment: Attachment) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Attachment::update_with(&client, attachment).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        attachment: Attachment,
+    ) -> Result<u64, RpelError> {
+        traced("Attachment", "update", async move {
+            let mut attachment = attachment;
+            check_attachment(&mut attachment)?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE attachments SET
+                        filename = $2,
+                        mime_type = $3,
+                        note = $4,
+                        updated_at = $5
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $6
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &attachment.id,
+                        &attachment.filename,
+                        &attachment.mime_type,
+                        &attachment.note,
+                        &Local::now().naive_local(),
+                        &attachment.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "attachments",
+                attachment.id,
+                attachment.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "attachments",
+                attachment.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Attachment::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Attachment", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        attachment_chunks
+                    WHERE
+                        attachment_id = $1
+                ",
+            )
+            .await?;
+            client.execute(&stmt, &[&id]).await?;
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        attachments
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "attachments", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    // Removes the files of a record that is being deleted.
+    pub async fn delete_owned_with<C: GenericClient>(
+        client: &C,
+        owner: AttachmentOwner,
+        owner_id: i64,
+    ) -> Result<u64, RpelError> {
+        traced("Attachment", "delete_owned", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        attachment_chunks AS c
+                    USING
+                        attachments AS a
+                    WHERE
+                        a.id = c.attachment_id
+                    AND
+                        a.owner_table = $1
+                    AND
+                        a.owner_id = $2
+                ",
+            )
+            .await?;
+            client.execute(&stmt, &[&owner.as_str(), &owner_id]).await?;
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        attachments
+                    WHERE
+                        owner_table = $1
+                    AND
+                        owner_id = $2
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let rows = client.query(&stmt, &[&owner.as_str(), &owner_id]).await?;
+            for row in &rows {
+                notify(client, "attachments", row.try_get(0)?, Operation::Delete, 1).await?;
+            }
+            Ok(rows.len() as u64)
+        })
+        .await
+    }
+}
+
+impl AttachmentList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<AttachmentList>, RpelError> {
+        let client = acquire(pool).await?;
+        AttachmentList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<AttachmentList>, RpelError> {
+        traced("AttachmentList", "get_all", async move {
+            let stmt = prepare(
+                client,
+                format!("{SELECT} ORDER BY owner_table ASC, owner_id ASC, filename ASC, id ASC")
+                    .as_str(),
+            )
+            .await?;
+            let mut attachments = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                attachments.push(list_row(&row)?);
+            }
+            Ok(attachments)
+        })
+        .await
+    }
+
+    // The files of one record, by name.
+    pub async fn get_by_owner(
+        pool: &RpelPool,
+        owner: AttachmentOwner,
+        owner_id: i64,
+    ) -> Result<Vec<AttachmentList>, RpelError> {
+        let client = acquire(pool).await?;
+        AttachmentList::get_by_owner_with(&client, owner, owner_id).await
+    }
+
+    pub async fn get_by_owner_with<C: GenericClient>(
+        client: &C,
+        owner: AttachmentOwner,
+        owner_id: i64,
+    ) -> Result<Vec<AttachmentList>, RpelError> {
+        traced("AttachmentList", "get_by_owner", async move {
+            let stmt = prepare(
+                client,
+                format!(
+                    "{SELECT} WHERE owner_table = $1 AND owner_id = $2 ORDER BY filename ASC, id ASC"
+                )
+                .as_str(),
+            )
+            .await?;
+            let mut attachments = Vec::new();
+            for row in client.query(&stmt, &[&owner.as_str(), &owner_id]).await? {
+                attachments.push(list_row(&row)?);
+            }
+            Ok(attachments)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_is_sha256_hex() {
+        let mut checksum = Checksum::new();
+        checksum.update(b"ab");
+        checksum.update(b"c");
+        assert_eq!(
+            checksum.finish(),
+            (
+                3,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn owners_round_trip() {
+        for owner in AttachmentOwner::ALL {
+            assert_eq!(AttachmentOwner::parse(owner.as_str()).unwrap(), owner);
+        }
+        assert!(AttachmentOwner::parse("contacts").is_err());
+    }
+}
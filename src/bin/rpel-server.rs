@@ -0,0 +1,22 @@
+use std::env;
+
+use rpel::{error::RpelError, get_pool_from_settings, init, server, settings::Settings};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+// Serves the JSON API. The only argument is an optional settings file; the
+// address comes from `listen` there or `RPEL_LISTEN`.
+#[tokio::main]
+async fn main() -> Result<(), RpelError> {
+    let settings = Settings::load(env::args().nth(1).as_deref())?;
+    init(&settings);
+    let pool = get_pool_from_settings(&settings)?;
+    let listen = settings.listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    axum::serve(listener, server::router(pool.into()))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
@@ -1,9 +1,19 @@
 use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    attachment::{Attachment, AttachmentOwner},
+    check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Certificate {
     #[serde(default)]
     pub id: i64,
@@ -14,7 +24,23 @@ pub struct Certificate {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Certificate);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CertificatePatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub num: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub contact_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub company_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub cert_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
     pub updated_at: Option<NaiveDateTime>,
 }
 
@@ -36,10 +62,15 @@ impl Certificate {
     }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Certificate, RpelError> {
-        let mut certificate = Certificate::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Certificate::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Certificate, RpelError> {
+        traced("Certificate", "get", async move {
+            let mut certificate = Certificate::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         num,
@@ -56,26 +87,39 @@ impl Certificate {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        certificate.id = id;
-        certificate.num = row.get(0);
-        certificate.contact_id = row.get(1);
-        certificate.company_id = row.get(2);
-        certificate.cert_date = row.get(3);
-        certificate.note = row.get(4);
-        certificate.created_at = row.get(5);
-        certificate.updated_at = row.get(6);
-        Ok(certificate)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("certificates".to_string(), id))?;
+            certificate.id = id;
+            certificate.num = row.get(0);
+            certificate.contact_id = row.get(1);
+            certificate.company_id = row.get(2);
+            certificate.cert_date = row.get(3);
+            certificate.note = row.get(4);
+            certificate.created_at = row.get(5);
+            certificate.updated_at = row.get(6);
+            Ok(certificate)
+        })
+        .await
     }
 
     pub async fn insert(
         pool: &RpelPool,
         certificate: Certificate,
     ) -> Result<Certificate, RpelError> {
-        let mut certificate = certificate;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Certificate::insert_with(&client, certificate).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        certificate: Certificate,
+    ) -> Result<Certificate, RpelError> {
+        traced("Certificate", "insert", async move {
+            let mut certificate = certificate;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO certificates
                     (
@@ -102,28 +146,39 @@ impl Certificate {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &certificate.num,
-                    &certificate.contact_id,
-                    &certificate.company_id,
-                    &certificate.cert_date,
-                    &certificate.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        certificate.id = row.get(0);
-        Ok(certificate)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &certificate.num,
+                        &certificate.contact_id,
+                        &certificate.company_id,
+                        &certificate.cert_date,
+                        &certificate.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            certificate.id = row.get(0);
+            notify(client, "certificates", certificate.id, Operation::Insert, 1).await?;
+            Ok(certificate)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, certificate: Certificate) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Certificate::update_with(&client, certificate).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        certificate: Certificate,
+    ) -> Result<u64, RpelError> {
+        traced("Certificate", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE certificates SET
                         num = $2,
@@ -134,29 +189,87 @@ impl Certificate {
                         updated_at = $7
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $8
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &certificate.id,
-                    &certificate.num,
-                    &certificate.contact_id,
-                    &certificate.company_id,
-                    &certificate.cert_date,
-                    &certificate.note,
-                    &Local::now().naive_local(),
-                ],
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &certificate.id,
+                        &certificate.num,
+                        &certificate.contact_id,
+                        &certificate.company_id,
+                        &certificate.cert_date,
+                        &certificate.note,
+                        &Local::now().naive_local(),
+                        &certificate.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "certificates",
+                certificate.id,
+                certificate.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "certificates",
+                certificate.id,
+                Operation::Update,
+                result,
             )
-            .await?)
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn patch(
+        pool: &RpelPool,
+        id: i64,
+        certificate: CertificatePatch,
+    ) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Certificate::patch_with(&client, id, certificate).await
+    }
+
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        certificate: CertificatePatch,
+    ) -> Result<u64, RpelError> {
+        traced("Certificate", "patch", async move {
+            let mut patch = Patch::new("certificates", &id);
+            patch
+                .set("num", &certificate.num)
+                .set("contact_id", &certificate.contact_id)
+                .set("company_id", &certificate.company_id)
+                .set("cert_date", &certificate.cert_date)
+                .set("note", &certificate.note);
+            patch.execute(client, &certificate.updated_at).await
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Certificate::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Certificate", "delete", async move {
+            Attachment::delete_owned_with(client, AttachmentOwner::Certificates, id).await?;
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         certificates
@@ -165,16 +278,27 @@ impl Certificate {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "certificates", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl CertificateList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<CertificateList>, RpelError> {
-        let mut certificates = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        CertificateList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<CertificateList>, RpelError> {
+        traced("CertificateList", "get_all", async move {
+            let mut certificates = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.id,
@@ -200,19 +324,21 @@ impl CertificateList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            let date: Option<NaiveDate> = row.get(6);
-            certificates.push(CertificateList {
-                id: row.get("id"),
-                num: row.get("num"),
-                contact_id: row.try_get(2)?,
-                contact_name: row.try_get(3)?,
-                company_id: row.try_get(4)?,
-                company_name: row.try_get(5)?,
-                cert_date: date.map(|d| d.format("%Y-%m-%d").to_string()),
-                note: row.try_get(7)?,
-            });
-        }
-        Ok(certificates)
+            for row in client.query(&stmt, &[]).await? {
+                let date: Option<NaiveDate> = row.get(6);
+                certificates.push(CertificateList {
+                    id: row.get("id"),
+                    num: row.get("num"),
+                    contact_id: row.try_get(2)?,
+                    contact_name: row.try_get(3)?,
+                    company_id: row.try_get(4)?,
+                    company_name: row.try_get(5)?,
+                    cert_date: date.map(|d| d.format("%Y-%m-%d").to_string()),
+                    note: row.try_get(7)?,
+                });
+            }
+            Ok(certificates)
+        })
+        .await
     }
 }
@@ -1,12 +1,30 @@
 use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
 
 use crate::{
-    contact::ContactShort, email::Email, error::RpelError, phone::Phone, practice::PracticeList,
+    acquire,
+    address::Address,
+    attachment::{Attachment, AttachmentOwner},
+    check_version,
+    contact::ContactShort,
+    email::Email,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    phone::Phone,
+    practice::PracticeList,
+    select::SelectItem,
+    trace::{prepare, single_row, traced},
     RpelPool,
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+// Taken by every transaction that sets a parent, so two concurrent moves
+// cannot close a loop that neither of them sees on its own.
+const PARENT_LOCK: i64 = 0x7270_656c_0001;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Company {
     #[serde(default)]
     pub id: i64,
@@ -14,10 +32,10 @@ pub struct Company {
     pub full_name: Option<String>,
     pub address: Option<String>,
     pub scope_id: Option<i64>,
+    pub parent_id: Option<i64>,
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
     pub emails: Vec<String>,
     pub phones: Vec<i64>,
@@ -26,6 +44,30 @@ pub struct Company {
     pub practices: Vec<PracticeList>,
     #[serde(skip_deserializing)]
     pub contacts: Vec<ContactShort>,
+    #[serde(skip_deserializing)]
+    pub parent: Option<SelectItem>,
+    #[serde(skip_deserializing)]
+    pub children: Vec<SelectItem>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CompanyPatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub full_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub address: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub scope_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub parent_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub emails: Option<Vec<String>>,
+    pub phones: Option<Vec<i64>>,
+    pub faxes: Option<Vec<i64>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -35,27 +77,191 @@ pub struct CompanyList {
     pub full_name: Option<String>,
     pub address: Option<String>,
     pub scope_name: Option<String>,
+    pub parent_name: Option<String>,
     pub emails: Vec<String>,
     pub phones: Vec<i64>,
     pub faxes: Vec<i64>,
     pub practices: Vec<NaiveDate>,
 }
 
+// One company of a hierarchy; `depth` counts the steps from the company the
+// walk started at.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct CompanyNode {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub name: Option<String>,
+    pub depth: i32,
+}
+
+// Totals over a company and all of its branches.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct CompanyRollup {
+    pub id: i64,
+    pub name: Option<String>,
+    pub companies: i64,
+    pub contacts: i64,
+    pub practices: i64,
+    pub sirens: i64,
+    pub last_practice: Option<NaiveDate>,
+    pub next_practice: Option<NaiveDate>,
+}
+
+single_row!(Company, CompanyRollup);
+
+// The path column stops the walks from looping over a cycle that was written
+// past `check_parent`, e.g. by hand.
+const SUBTREE: &str = "
+    WITH RECURSIVE tree AS (
+        SELECT
+            id,
+            parent_id,
+            name,
+            0 AS depth,
+            ARRAY[id] AS path
+        FROM
+            companies
+        WHERE
+            id = $1
+        UNION ALL
+        SELECT
+            c.id,
+            c.parent_id,
+            c.name,
+            t.depth + 1,
+            t.path || c.id
+        FROM
+            companies AS c
+        JOIN
+            tree AS t ON c.parent_id = t.id
+        WHERE
+            NOT c.id = ANY(t.path)
+    )
+    SELECT
+        id,
+        parent_id,
+        name,
+        depth
+    FROM
+        tree
+    ORDER BY
+        path ASC
+";
+
+const ANCESTORS: &str = "
+    WITH RECURSIVE chain AS (
+        SELECT
+            id,
+            parent_id,
+            name,
+            0 AS depth,
+            ARRAY[id] AS path
+        FROM
+            companies
+        WHERE
+            id = $1
+        UNION ALL
+        SELECT
+            c.id,
+            c.parent_id,
+            c.name,
+            ch.depth + 1,
+            ch.path || c.id
+        FROM
+            companies AS c
+        JOIN
+            chain AS ch ON c.id = ch.parent_id
+        WHERE
+            NOT c.id = ANY(ch.path)
+    )
+    SELECT
+        id,
+        parent_id,
+        name,
+        depth
+    FROM
+        chain
+    ORDER BY
+        depth ASC
+";
+
+// Runs SUBTREE or ANCESTORS; both start with the company itself.
+async fn walk<C: GenericClient>(
+    client: &C,
+    query: &str,
+    id: i64,
+) -> Result<Vec<CompanyNode>, RpelError> {
+    let stmt = prepare(client, query).await?;
+    let mut nodes = Vec::new();
+    for row in client.query(&stmt, &[&id]).await? {
+        nodes.push(CompanyNode {
+            id: row.try_get(0)?,
+            parent_id: row.try_get(1)?,
+            name: row.try_get(2)?,
+            depth: row.try_get(3)?,
+        });
+    }
+    if nodes.is_empty() {
+        return Err(RpelError::NotFound("companies".to_string(), id));
+    }
+    Ok(nodes)
+}
+
+// A parent has to exist and must not be the company itself or one of its
+// branches. `id` is None for a company that is not inserted yet.
+async fn check_parent<C: GenericClient>(
+    client: &C,
+    id: Option<i64>,
+    parent_id: Option<i64>,
+) -> Result<(), RpelError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+    if id == Some(parent_id) {
+        return Err(RpelError::Invalid(format!(
+            "company {parent_id} cannot be its own parent"
+        )));
+    }
+    client
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&PARENT_LOCK])
+        .await?;
+    let ancestors = match walk(client, ANCESTORS, parent_id).await {
+        Err(RpelError::NotFound(_, _)) => {
+            return Err(RpelError::Invalid(format!(
+                "parent company {parent_id} does not exist"
+            )))
+        }
+        result => result?,
+    };
+    if let Some(id) = id.filter(|id| ancestors.iter().any(|node| node.id == *id)) {
+        return Err(RpelError::Invalid(format!(
+            "company {parent_id} is a branch of company {id}"
+        )));
+    }
+    Ok(())
+}
+
 impl Company {
     // pub fn new() -> Self {
     //     Default::default()
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Company, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Company::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Company, RpelError> {
+        traced("Company", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.name,
                         c.full_name,
                         c.address,
                         c.scope_id,
+                        c.parent_id,
                         c.note,
                         c.created_at,
                         c.updated_at,
@@ -77,32 +283,83 @@ impl Company {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let practices = PracticeList::get_by_company(pool, id).await?;
-        let contacts = ContactShort::get_by_company(pool, id).await?;
-        let company = Company {
-            id,
-            name: row.try_get(0)?,
-            full_name: row.try_get(1)?,
-            address: row.try_get(2)?,
-            scope_id: row.try_get(3)?,
-            note: row.try_get(4)?,
-            created_at: row.try_get(5)?,
-            updated_at: row.try_get(6)?,
-            emails: row.try_get(7)?,
-            phones: row.try_get(8)?,
-            faxes: row.try_get(9)?,
-            practices,
-            contacts,
-        };
-        Ok(company)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("companies".to_string(), id))?;
+            let practices = PracticeList::get_by_company_with(client, id).await?;
+            let contacts = ContactShort::get_by_company_with(client, id).await?;
+            let parent_id: Option<i64> = row.try_get(4)?;
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        id,
+                        name
+                    FROM
+                        companies
+                    WHERE
+                        id = $1
+                    OR
+                        parent_id = $2
+                    ORDER BY
+                        name ASC
+                ",
+            )
+            .await?;
+            let mut parent = None;
+            let mut children = Vec::new();
+            for related in client.query(&stmt, &[&parent_id, &id]).await? {
+                let item = SelectItem {
+                    id: related.try_get(0)?,
+                    name: related.try_get(1)?,
+                };
+                if Some(item.id) == parent_id {
+                    parent = Some(item);
+                } else {
+                    children.push(item);
+                }
+            }
+            let company = Company {
+                id,
+                name: row.try_get(0)?,
+                full_name: row.try_get(1)?,
+                address: row.try_get(2)?,
+                scope_id: row.try_get(3)?,
+                parent_id,
+                note: row.try_get(5)?,
+                created_at: row.try_get(6)?,
+                updated_at: row.try_get(7)?,
+                emails: row.try_get(8)?,
+                phones: row.try_get(9)?,
+                faxes: row.try_get(10)?,
+                practices,
+                contacts,
+                parent,
+                children,
+            };
+            Ok(company)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, company: Company) -> Result<Company, RpelError> {
-        let mut company = company;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Company::insert_with(&tx, company).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        company: Company,
+    ) -> Result<Company, RpelError> {
+        traced("Company", "insert", async move {
+            let mut company = company;
+            check_parent(client, None, company.parent_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO companies
                     (
@@ -110,6 +367,7 @@ impl Company {
                         full_name,
                         address,
                         scope_id,
+                        parent_id,
                         note,
                         created_at,
                         updated_at
@@ -122,79 +380,221 @@ impl Company {
                         $4,
                         $5,
                         $6,
-                        $7
+                        $7,
+                        $8
                     )
                     RETURNING
                         id
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &company.name,
-                    &company.full_name,
-                    &company.address,
-                    &company.scope_id,
-                    &company.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        company.id = row.get(0);
-        Email::update_companies(pool, company.id, company.emails.clone()).await?;
-        Phone::update_companies(pool, company.id, false, company.phones.clone()).await?;
-        Phone::update_companies(pool, company.id, true, company.faxes.clone()).await?;
-        Ok(company)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &company.name,
+                        &company.full_name,
+                        &company.address,
+                        &company.scope_id,
+                        &company.parent_id,
+                        &company.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            company.id = row.get(0);
+            notify(client, "companies", company.id, Operation::Insert, 1).await?;
+            Email::update_companies_with(client, company.id, company.emails.clone()).await?;
+            Phone::update_companies_with(client, company.id, false, company.phones.clone()).await?;
+            Phone::update_companies_with(client, company.id, true, company.faxes.clone()).await?;
+            Ok(company)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, company: Company) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Company::update_with(&tx, company).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        company: Company,
+    ) -> Result<u64, RpelError> {
+        traced("Company", "update", async move {
+            check_parent(client, Some(company.id), company.parent_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     UPDATE companies SET    
                         name = $2,
                         full_name = $3,
                         address = $4,
                         scope_id = $5,
-                        note = $6,
-                        updated_at = $7
+                        parent_id = $6,
+                        note = $7,
+                        updated_at = $8
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $9
                 ",
             )
             .await?;
-        let result = client
-            .execute(
-                &stmt,
-                &[
-                    &company.id,
-                    &company.name,
-                    &company.full_name,
-                    &company.address,
-                    &company.scope_id,
-                    &company.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        Email::update_companies(pool, company.id, company.emails).await?;
-        Phone::update_companies(pool, company.id, false, company.phones).await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &company.id,
+                        &company.name,
+                        &company.full_name,
+                        &company.address,
+                        &company.scope_id,
+                        &company.parent_id,
+                        &company.note,
+                        &Local::now().naive_local(),
+                        &company.updated_at,
+                    ],
+                )
+                .await?;
+            check_version(client, "companies", company.id, company.updated_at, result).await?;
+            notify(client, "companies", company.id, Operation::Update, result).await?;
+            // The id is gone, so there is no owner for the phones and emails.
+            if result == 0 {
+                return Ok(result);
+            }
+            Email::update_companies_with(client, company.id, company.emails).await?;
+            Phone::update_companies_with(client, company.id, false, company.phones).await?;
+            Phone::update_companies_with(client, company.id, true, company.faxes).await?;
+            Ok(result)
+        })
+        .await
+    }
 
-        Phone::update_companies(pool, company.id, true, company.faxes).await?;
+    pub async fn patch(pool: &RpelPool, id: i64, company: CompanyPatch) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Company::patch_with(&tx, id, company).await?;
+        tx.commit().await?;
         Ok(result)
     }
 
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        company: CompanyPatch,
+    ) -> Result<u64, RpelError> {
+        traced("Company", "patch", async move {
+            if let Some(parent_id) = company.parent_id {
+                check_parent(client, Some(id), parent_id).await?;
+            }
+            let mut patch = Patch::new("companies", &id);
+            patch
+                .set("name", &company.name)
+                .set("full_name", &company.full_name)
+                .set("address", &company.address)
+                .set("scope_id", &company.scope_id)
+                .set("parent_id", &company.parent_id)
+                .set("note", &company.note);
+            let result = patch.execute(client, &company.updated_at).await?;
+            if result == 0 {
+                return Ok(result);
+            }
+            if let Some(emails) = company.emails {
+                Email::update_companies_with(client, id, emails).await?;
+            }
+            if let Some(phones) = company.phones {
+                Phone::update_companies_with(client, id, false, phones).await?;
+            }
+            if let Some(faxes) = company.faxes {
+                Phone::update_companies_with(client, id, true, faxes).await?;
+            }
+            Ok(result)
+        })
+        .await
+    }
+
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        Phone::delete_companies(pool, id, true).await?;
-        Phone::delete_companies(pool, id, false).await?;
-        Email::delete_companies(pool, id).await?;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Company::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Company", "delete", async move {
+            // Departments go with their company, but not while contacts are
+            // still in them.
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        c.id
+                    FROM
+                        contacts AS c
+                    JOIN
+                        departments AS d ON d.id = c.department_id
+                    WHERE
+                        d.company_id = $1
+                    ORDER BY
+                        c.id ASC
+                    LIMIT 1
+                ",
+            )
+            .await?;
+            if let Some(row) = client.query_opt(&stmt, &[&id]).await? {
+                return Err(RpelError::Invalid(format!(
+                    "contact {} is in a department of company {id}",
+                    row.try_get::<_, i64>(0)?
+                )));
+            }
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        departments
+                    WHERE
+                        company_id = $1
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[&id]).await? {
+                notify(client, "departments", row.try_get(0)?, Operation::Delete, 1).await?;
+            }
+            Phone::delete_companies_with(client, id, true).await?;
+            Phone::delete_companies_with(client, id, false).await?;
+            Email::delete_companies_with(client, id).await?;
+            Address::delete_companies_with(client, id).await?;
+            Attachment::delete_owned_with(client, AttachmentOwner::Companies, id).await?;
+            // Branches of a removed company move up to its own parent.
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE companies SET
+                        parent_id = (SELECT parent_id FROM companies WHERE id = $1),
+                        updated_at = $2
+                    WHERE
+                        parent_id = $1
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            for row in client
+                .query(&stmt, &[&id, &Local::now().naive_local()])
+                .await?
+            {
+                notify(client, "companies", row.try_get(0)?, Operation::Update, 1).await?;
+            }
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         companies
@@ -203,16 +603,59 @@ impl Company {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "companies", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    // The company followed by all of its branches, each one after its parent.
+    pub async fn subtree(pool: &RpelPool, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        let client = acquire(pool).await?;
+        Company::subtree_with(&client, id).await
+    }
+
+    pub async fn subtree_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<Vec<CompanyNode>, RpelError> {
+        traced("Company", "subtree", async move {
+            walk(client, SUBTREE, id).await
+        })
+        .await
+    }
+
+    // The parent, its parent and so on up to the head organisation.
+    pub async fn ancestors(pool: &RpelPool, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        let client = acquire(pool).await?;
+        Company::ancestors_with(&client, id).await
+    }
+
+    pub async fn ancestors_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<Vec<CompanyNode>, RpelError> {
+        traced("Company", "ancestors", async move {
+            let mut nodes = walk(client, ANCESTORS, id).await?;
+            nodes.remove(0);
+            Ok(nodes)
+        })
+        .await
     }
 }
 
 impl CompanyList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<CompanyList>, RpelError> {
-        let mut companies = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        CompanyList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<CompanyList>, RpelError> {
+        traced("CompanyList", "get_all", async move {
+            let mut companies = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.id,
@@ -220,6 +663,7 @@ impl CompanyList {
                         c.full_name,
                         c.address,
                         s.name AS scope_name,
+                        pa.name AS parent_name,
                         array_remove(array_agg(DISTINCT e.email), NULL) AS emails,
                         array_remove(array_agg(DISTINCT p.phone), NULL) AS phones,
                         array_remove(array_agg(DISTINCT f.phone), NULL) AS faxes,
@@ -228,6 +672,8 @@ impl CompanyList {
                         companies AS c
                     LEFT JOIN
                         scopes AS s ON c.scope_id = s.id
+                    LEFT JOIN
+                        companies AS pa ON c.parent_id = pa.id
                     LEFT JOIN
                         emails AS e ON c.id = e.company_id
                     LEFT JOIN
@@ -238,25 +684,145 @@ impl CompanyList {
                         practices AS pr ON c.id = pr.company_id
                     GROUP BY
                         c.id,
-                        s.name
+                        s.name,
+                        pa.name
                     ORDER BY
                         c.name ASC
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            companies.push(CompanyList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                full_name: row.try_get(2)?,
-                address: row.try_get(3)?,
-                scope_name: row.try_get(4)?,
-                emails: row.try_get(5)?,
-                phones: row.try_get(6)?,
-                faxes: row.try_get(7)?,
-                practices: row.try_get(8)?,
-            });
-        }
-        Ok(companies)
+            for row in client.query(&stmt, &[]).await? {
+                companies.push(CompanyList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    full_name: row.try_get(2)?,
+                    address: row.try_get(3)?,
+                    scope_name: row.try_get(4)?,
+                    parent_name: row.try_get(5)?,
+                    emails: row.try_get(6)?,
+                    phones: row.try_get(7)?,
+                    faxes: row.try_get(8)?,
+                    practices: row.try_get(9)?,
+                });
+            }
+            Ok(companies)
+        })
+        .await
+    }
+}
+
+// `filter` picks the head companies from `companies AS r`.
+fn rollup_query(filter: &str) -> String {
+    format!(
+        "
+            WITH RECURSIVE tree AS (
+                SELECT
+                    r.id AS root_id,
+                    r.id,
+                    ARRAY[r.id] AS path
+                FROM
+                    companies AS r
+                WHERE
+                    {filter}
+                UNION ALL
+                SELECT
+                    t.root_id,
+                    c.id,
+                    t.path || c.id
+                FROM
+                    companies AS c
+                JOIN
+                    tree AS t ON c.parent_id = t.id
+                WHERE
+                    NOT c.id = ANY(t.path)
+            )
+            SELECT
+                r.id,
+                r.name,
+                (SELECT count(*) FROM tree AS t WHERE t.root_id = r.id) AS companies,
+                (
+                    SELECT count(*) FROM contacts AS c
+                    JOIN tree AS t ON c.company_id = t.id WHERE t.root_id = r.id
+                ) AS contacts,
+                (
+                    SELECT count(*) FROM practices AS p
+                    JOIN tree AS t ON p.company_id = t.id WHERE t.root_id = r.id
+                ) AS practices,
+                (
+                    SELECT count(*) FROM sirens AS s
+                    JOIN tree AS t ON s.company_id = t.id WHERE t.root_id = r.id
+                ) AS sirens,
+                (
+                    SELECT max(p.date_of_practice) FROM practices AS p
+                    JOIN tree AS t ON p.company_id = t.id
+                    WHERE t.root_id = r.id AND p.date_of_practice <= current_date
+                ) AS last_practice,
+                (
+                    SELECT min(p.date_of_practice) FROM practices AS p
+                    JOIN tree AS t ON p.company_id = t.id
+                    WHERE t.root_id = r.id AND p.date_of_practice > current_date
+                ) AS next_practice
+            FROM
+                companies AS r
+            WHERE
+                {filter}
+            ORDER BY
+                r.name ASC
+        "
+    )
+}
+
+impl CompanyRollup {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<CompanyRollup, RpelError> {
+        let client = acquire(pool).await?;
+        CompanyRollup::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<CompanyRollup, RpelError> {
+        traced("CompanyRollup", "get", async move {
+            let stmt = prepare(client, rollup_query("r.id = $1").as_str()).await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("companies".to_string(), id))?;
+            CompanyRollup::from_row(&row)
+        })
+        .await
+    }
+
+    // One row per head organisation, i.e. per company without a parent.
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<CompanyRollup>, RpelError> {
+        let client = acquire(pool).await?;
+        CompanyRollup::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<CompanyRollup>, RpelError> {
+        traced("CompanyRollup", "get_all", async move {
+            let stmt = prepare(client, rollup_query("r.parent_id IS NULL").as_str()).await?;
+            let mut rollups = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                rollups.push(CompanyRollup::from_row(&row)?);
+            }
+            Ok(rollups)
+        })
+        .await
+    }
+
+    fn from_row(row: &Row) -> Result<CompanyRollup, RpelError> {
+        Ok(CompanyRollup {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            companies: row.try_get(2)?,
+            contacts: row.try_get(3)?,
+            practices: row.try_get(4)?,
+            sirens: row.try_get(5)?,
+            last_practice: row.try_get(6)?,
+            next_practice: row.try_get(7)?,
+        })
     }
 }
@@ -1,9 +1,21 @@
 use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{email::Email, error::RpelError, phone::Phone, RpelPool};
+use crate::{
+    acquire, check_version,
+    department::check_department,
+    email::Email,
+    employment::Employment,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    phone::Phone,
+    trace::{prepare, single_row, traced, Rows},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Contact {
     #[serde(default)]
     pub id: i64,
@@ -17,7 +29,6 @@ pub struct Contact {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
     pub emails: Vec<String>,
     pub phones: Vec<i64>,
@@ -26,6 +37,32 @@ pub struct Contact {
     pub educations: Vec<NaiveDate>,
 }
 
+single_row!(Contact);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ContactPatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub company_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub department_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub post_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub post_go_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub rank_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub birthday: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub emails: Option<Vec<String>>,
+    pub phones: Option<Vec<i64>>,
+    pub faxes: Option<Vec<i64>>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ContactList {
     pub id: i64,
@@ -33,11 +70,12 @@ pub struct ContactList {
     pub company_id: Option<i64>,
     pub company_name: Option<String>,
     pub post_name: Option<String>,
+    pub emails: Vec<String>,
     pub phones: Vec<i64>,
     pub faxes: Vec<i64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ContactShort {
     pub id: i64,
     pub name: Option<String>,
@@ -46,15 +84,43 @@ pub struct ContactShort {
     pub post_go_name: Option<String>,
 }
 
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ContactMerge {
+    pub phones: u64,
+    pub emails: u64,
+    pub educations: u64,
+    pub certificates: u64,
+    pub sirens: u64,
+    pub siren_checks: u64,
+    pub employments: u64,
+}
+
+impl Rows for ContactMerge {
+    fn rows(&self) -> u64 {
+        self.phones
+            + self.emails
+            + self.educations
+            + self.certificates
+            + self.sirens
+            + self.siren_checks
+            + self.employments
+    }
+}
+
 impl Contact {
     // pub fn new() -> Self {
     //     Default::default()
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Contact, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Contact::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Contact, RpelError> {
+        traced("Contact", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.name,
@@ -88,32 +154,49 @@ impl Contact {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let contact = Contact {
-            id,
-            name: row.try_get("name")?,
-            company_id: row.try_get("company_id")?,
-            department_id: row.try_get("department_id")?,
-            post_id: row.try_get("post_id")?,
-            post_go_id: row.try_get("post_go_id")?,
-            rank_id: row.try_get("rank_id")?,
-            birthday: row.try_get("birthday")?,
-            note: row.try_get("note")?,
-            created_at: row.try_get("created_at")?,
-            updated_at: row.try_get("updated_at")?,
-            emails: row.try_get("emails")?,
-            phones: row.try_get("phones")?,
-            faxes: row.try_get("faxes")?,
-            educations: row.try_get("educations")?,
-        };
-        Ok(contact)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("contacts".to_string(), id))?;
+            let contact = Contact {
+                id,
+                name: row.try_get("name")?,
+                company_id: row.try_get("company_id")?,
+                department_id: row.try_get("department_id")?,
+                post_id: row.try_get("post_id")?,
+                post_go_id: row.try_get("post_go_id")?,
+                rank_id: row.try_get("rank_id")?,
+                birthday: row.try_get("birthday")?,
+                note: row.try_get("note")?,
+                created_at: row.try_get("created_at")?,
+                updated_at: row.try_get("updated_at")?,
+                emails: row.try_get("emails")?,
+                phones: row.try_get("phones")?,
+                faxes: row.try_get("faxes")?,
+                educations: row.try_get("educations")?,
+            };
+            Ok(contact)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, contact: Contact) -> Result<Contact, RpelError> {
-        let mut contact = contact;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Contact::insert_with(&tx, contact).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        contact: Contact,
+    ) -> Result<Contact, RpelError> {
+        traced("Contact", "insert", async move {
+            let mut contact = contact;
+            check_department(client, contact.company_id, contact.department_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO contacts
                     (
@@ -146,34 +229,50 @@ impl Contact {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &contact.name,
-                    &contact.company_id,
-                    &contact.department_id,
-                    &contact.post_id,
-                    &contact.post_go_id,
-                    &contact.rank_id,
-                    &contact.birthday,
-                    &contact.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        contact.id = row.get(0);
-        Email::update_contacts(pool, contact.id, contact.emails.clone()).await?;
-        Phone::update_contacts(pool, contact.id, false, contact.phones.clone()).await?;
-        Phone::update_contacts(pool, contact.id, true, contact.faxes.clone()).await?;
-        Ok(contact)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &contact.name,
+                        &contact.company_id,
+                        &contact.department_id,
+                        &contact.post_id,
+                        &contact.post_go_id,
+                        &contact.rank_id,
+                        &contact.birthday,
+                        &contact.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            contact.id = row.get(0);
+            notify(client, "contacts", contact.id, Operation::Insert, 1).await?;
+            Email::update_contacts_with(client, contact.id, contact.emails.clone()).await?;
+            Phone::update_contacts_with(client, contact.id, false, contact.phones.clone()).await?;
+            Phone::update_contacts_with(client, contact.id, true, contact.faxes.clone()).await?;
+            Employment::sync_with(client, contact.id, Local::now().date_naive()).await?;
+            Ok(contact)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, contact: Contact) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Contact::update_with(&tx, contact).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        contact: Contact,
+    ) -> Result<u64, RpelError> {
+        traced("Contact", "update", async move {
+            check_department(client, contact.company_id, contact.department_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     UPDATE contacts SET
                         name = $2,
@@ -187,56 +286,396 @@ impl Contact {
                         updated_at = $10
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $11
                     ",
             )
             .await?;
-        Email::update_contacts(pool, contact.id, contact.emails).await?;
-        Phone::update_contacts(pool, contact.id, false, contact.phones).await?;
-        Phone::update_contacts(pool, contact.id, true, contact.faxes).await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &contact.id,
-                    &contact.name,
-                    &contact.company_id,
-                    &contact.department_id,
-                    &contact.post_id,
-                    &contact.post_go_id,
-                    &contact.rank_id,
-                    &contact.birthday,
-                    &contact.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &contact.id,
+                        &contact.name,
+                        &contact.company_id,
+                        &contact.department_id,
+                        &contact.post_id,
+                        &contact.post_go_id,
+                        &contact.rank_id,
+                        &contact.birthday,
+                        &contact.note,
+                        &Local::now().naive_local(),
+                        &contact.updated_at,
+                    ],
+                )
+                .await?;
+            check_version(client, "contacts", contact.id, contact.updated_at, result).await?;
+            notify(client, "contacts", contact.id, Operation::Update, result).await?;
+            // The id is gone, so there is no owner for the phones and emails.
+            if result == 0 {
+                return Ok(result);
+            }
+            Email::update_contacts_with(client, contact.id, contact.emails).await?;
+            Phone::update_contacts_with(client, contact.id, false, contact.phones).await?;
+            Phone::update_contacts_with(client, contact.id, true, contact.faxes).await?;
+            Employment::sync_with(client, contact.id, Local::now().date_naive()).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn patch(pool: &RpelPool, id: i64, contact: ContactPatch) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Contact::patch_with(&tx, id, contact).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        contact: ContactPatch,
+    ) -> Result<u64, RpelError> {
+        traced("Contact", "patch", async move {
+            // Either side of the pair may come from the stored row.
+            if contact.company_id.is_some() || contact.department_id.is_some() {
+                let stmt = prepare(
+                    client,
+                    "
+                        SELECT
+                            company_id,
+                            department_id
+                        FROM
+                            contacts
+                        WHERE
+                            id = $1
+                    ",
+                )
+                .await?;
+                if let Some(row) = client.query_opt(&stmt, &[&id]).await? {
+                    let company_id = match contact.company_id {
+                        Some(company_id) => company_id,
+                        None => row.try_get(0)?,
+                    };
+                    let department_id = match contact.department_id {
+                        Some(department_id) => department_id,
+                        None => row.try_get(1)?,
+                    };
+                    check_department(client, company_id, department_id).await?;
+                }
+            }
+            let mut patch = Patch::new("contacts", &id);
+            patch
+                .set("name", &contact.name)
+                .set("company_id", &contact.company_id)
+                .set("department_id", &contact.department_id)
+                .set("post_id", &contact.post_id)
+                .set("post_go_id", &contact.post_go_id)
+                .set("rank_id", &contact.rank_id)
+                .set("birthday", &contact.birthday)
+                .set("note", &contact.note);
+            let result = patch.execute(client, &contact.updated_at).await?;
+            if result == 0 {
+                return Ok(result);
+            }
+            if let Some(emails) = contact.emails {
+                Email::update_contacts_with(client, id, emails).await?;
+            }
+            if let Some(phones) = contact.phones {
+                Phone::update_contacts_with(client, id, false, phones).await?;
+            }
+            if let Some(faxes) = contact.faxes {
+                Phone::update_contacts_with(client, id, true, faxes).await?;
+            }
+            Employment::sync_with(client, id, Local::now().date_naive()).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        Phone::delete_contacts(pool, id, true).await?;
-        Phone::delete_contacts(pool, id, false).await?;
-        Email::delete_contacts(pool, id).await?;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Contact::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Contact", "delete", async move {
+            Phone::delete_contacts_with(client, id, true).await?;
+            Phone::delete_contacts_with(client, id, false).await?;
+            Email::delete_contacts_with(client, id).await?;
+            Employment::close_contacts_with(client, id, Local::now().date_naive()).await?;
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        contacts
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "contacts", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn merge(
+        pool: &RpelPool,
+        survivor_id: i64,
+        duplicate_id: i64,
+    ) -> Result<ContactMerge, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Contact::merge_with(&tx, survivor_id, duplicate_id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    // Moves everything that hangs off `duplicate_id` onto `survivor_id`, fills
+    // the survivor's empty fields from the duplicate and deletes it. Run it in
+    // a transaction: a half-done merge leaves both contacts incomplete.
+    pub async fn merge_with<C: GenericClient>(
+        client: &C,
+        survivor_id: i64,
+        duplicate_id: i64,
+    ) -> Result<ContactMerge, RpelError> {
+        traced("Contact", "merge", async move {
+            if survivor_id == duplicate_id {
+                return Err(RpelError::Invalid(format!(
+                    "cannot merge contact {duplicate_id} into itself"
+                )));
+            }
+            for id in [survivor_id, duplicate_id] {
+                let row = client
+                    .query_opt("SELECT id FROM contacts WHERE id = $1 FOR UPDATE", &[&id])
+                    .await?;
+                if row.is_none() {
+                    return Err(RpelError::NotFound("contacts".to_string(), id));
+                }
+            }
+            let params: [&(dyn tokio_postgres::types::ToSql + Sync); 2] =
+                [&survivor_id, &duplicate_id];
+
+            // A number or address the survivor already has is dropped rather
+            // than stored twice.
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        phones AS d
+                    WHERE
+                        d.contact_id = $2
+                    AND
+                        EXISTS (
+                            SELECT 1 FROM phones AS s
+                            WHERE s.contact_id = $1 AND s.phone = d.phone AND s.fax = d.fax
+                        )
+                ",
+            )
+            .await?;
+            client.execute(&stmt, &params).await?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE phones SET
+                        contact_id = $1,
+                        updated_at = $3
+                    WHERE
+                        contact_id = $2
+                ",
+            )
+            .await?;
+            let now = Local::now().naive_local();
+            let phones = client
+                .execute(&stmt, &[&survivor_id, &duplicate_id, &now])
+                .await?;
+
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        emails AS d
+                    WHERE
+                        d.contact_id = $2
+                    AND
+                        EXISTS (
+                            SELECT 1 FROM emails AS s
+                            WHERE s.contact_id = $1 AND lower(s.email) = lower(d.email)
+                        )
+                ",
+            )
+            .await?;
+            client.execute(&stmt, &params).await?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE emails SET
+                        contact_id = $1,
+                        updated_at = $3
+                    WHERE
+                        contact_id = $2
+                ",
+            )
+            .await?;
+            let emails = client
+                .execute(&stmt, &[&survivor_id, &duplicate_id, &now])
+                .await?;
+
+            // The duplicate's current position goes on as the survivor's, so
+            // its own open periods end today.
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE employments SET
+                        date_to = $2,
+                        updated_at = $3
+                    WHERE
+                        contact_id = $1
+                    AND
+                        date_to IS NULL
+                ",
+            )
+            .await?;
+            client
+                .execute(&stmt, &[&duplicate_id, &now.date(), &now])
+                .await?;
+
+            let mut result = ContactMerge {
+                phones,
+                emails,
+                ..Default::default()
+            };
+            for (table, moved) in [
+                ("educations", &mut result.educations),
+                ("certificates", &mut result.certificates),
+                ("sirens", &mut result.sirens),
+                ("siren_checks", &mut result.siren_checks),
+                ("employments", &mut result.employments),
+            ] {
+                let stmt = prepare(
+                    client,
+                    &format!(
+                        "
+                            UPDATE {table} SET
+                                contact_id = $1,
+                                updated_at = $3
+                            WHERE
+                                contact_id = $2
+                            RETURNING
+                                id
+                        "
+                    ),
+                )
+                .await?;
+                let rows = client
+                    .query(&stmt, &[&survivor_id, &duplicate_id, &now])
+                    .await?;
+                for row in &rows {
+                    notify(client, table, row.try_get(0)?, Operation::Update, 1).await?;
+                }
+                *moved = rows.len() as u64;
+            }
+
+            // The duplicate goes first so that taking over its birthday cannot
+            // collide with it on UNIQUE (name, birthday). Its department only
+            // comes along when it fits the survivor's company: the same one,
+            // none yet, or a department shared by all companies.
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         contacts
                     WHERE
                         id = $1
+                    RETURNING
+                        company_id,
+                        department_id,
+                        post_id,
+                        post_go_id,
+                        rank_id,
+                        birthday,
+                        note
+                ",
+            )
+            .await?;
+            let duplicate = client.query_one(&stmt, &[&duplicate_id]).await?;
+            notify(client, "contacts", duplicate_id, Operation::Delete, 1).await?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE contacts SET
+                        company_id = COALESCE(company_id, $2),
+                        department_id = COALESCE(
+                            department_id,
+                            CASE WHEN
+                                company_id IS NULL
+                            OR
+                                company_id = $2
+                            OR
+                                (SELECT d.company_id IS NULL FROM departments AS d WHERE d.id = $3)
+                            THEN
+                                $3
+                            END
+                        ),
+                        post_id = COALESCE(post_id, $4),
+                        post_go_id = COALESCE(post_go_id, $5),
+                        rank_id = COALESCE(rank_id, $6),
+                        birthday = COALESCE(birthday, $7),
+                        note = COALESCE(note, $8),
+                        updated_at = $9
+                    WHERE
+                        id = $1
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let company_id: Option<i64> = duplicate.try_get(0)?;
+            let department_id: Option<i64> = duplicate.try_get(1)?;
+            let post_id: Option<i64> = duplicate.try_get(2)?;
+            let post_go_id: Option<i64> = duplicate.try_get(3)?;
+            let rank_id: Option<i64> = duplicate.try_get(4)?;
+            let birthday: Option<NaiveDate> = duplicate.try_get(5)?;
+            let note: Option<String> = duplicate.try_get(6)?;
+            let updated = client
+                .execute(
+                    &stmt,
+                    &[
+                        &survivor_id,
+                        &company_id,
+                        &department_id,
+                        &post_id,
+                        &post_go_id,
+                        &rank_id,
+                        &birthday,
+                        &note,
+                        &now,
+                    ],
+                )
+                .await?;
+            notify(client, "contacts", survivor_id, Operation::Update, updated).await?;
+            Employment::sync_with(client, survivor_id, now.date()).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl ContactList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<ContactList>, RpelError> {
-        let mut contacts = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        ContactList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<ContactList>, RpelError> {
+        traced("ContactList", "get_all", async move {
+            let mut contacts = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.id,
@@ -244,6 +683,7 @@ impl ContactList {
                         co.id AS company_id,
                         co.name AS company_name,
                         po.name AS post_name,
+                        array_remove(array_agg(DISTINCT e.email), NULL) AS emails,
                         array_remove(array_agg(DISTINCT ph.phone), NULL) AS phones,
                         array_remove(array_agg(DISTINCT f.phone), NULL) AS faxes
                     FROM
@@ -252,6 +692,8 @@ impl ContactList {
                         companies AS co ON c.company_id = co.id
                     LEFT JOIN
                         posts AS po ON c.post_id = po.id
+                    LEFT JOIN
+                        emails AS e ON c.id = e.contact_id
                     LEFT JOIN
                         phones AS ph ON c.id = ph.contact_id AND ph.fax = false
                     LEFT JOIN
@@ -265,18 +707,21 @@ impl ContactList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            contacts.push(ContactList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                company_id: row.try_get(2)?,
-                company_name: row.try_get(3)?,
-                post_name: row.try_get(4)?,
-                phones: row.try_get(5)?,
-                faxes: row.try_get(6)?,
-            });
-        }
-        Ok(contacts)
+            for row in client.query(&stmt, &[]).await? {
+                contacts.push(ContactList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    company_id: row.try_get(2)?,
+                    company_name: row.try_get(3)?,
+                    post_name: row.try_get(4)?,
+                    emails: row.try_get(5)?,
+                    phones: row.try_get(6)?,
+                    faxes: row.try_get(7)?,
+                });
+            }
+            Ok(contacts)
+        })
+        .await
     }
 }
 
@@ -285,10 +730,18 @@ impl ContactShort {
         pool: &RpelPool,
         company_id: i64,
     ) -> Result<Vec<ContactShort>, RpelError> {
-        let mut contacts = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        ContactShort::get_by_company_with(&client, company_id).await
+    }
+
+    pub async fn get_by_company_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+    ) -> Result<Vec<ContactShort>, RpelError> {
+        traced("ContactShort", "get_by_company", async move {
+            let mut contacts = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         c.id,
@@ -303,21 +756,23 @@ impl ContactShort {
                     LEFT JOIN
                         posts AS p ON c.post_id = p.id AND p.go = false
                     LEFT JOIN
-                        posts AS pg ON c.post_go_id = p.id AND p.go = true
+                        posts AS pg ON c.post_go_id = pg.id AND pg.go = true
                     WHERE
                         c.company_id = $1
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[&company_id]).await? {
-            contacts.push(ContactShort {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                department_name: row.try_get(2)?,
-                post_name: row.try_get(3)?,
-                post_go_name: row.try_get(4)?,
-            });
-        }
-        Ok(contacts)
+            for row in client.query(&stmt, &[&company_id]).await? {
+                contacts.push(ContactShort {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    department_name: row.try_get(2)?,
+                    post_name: row.try_get(3)?,
+                    post_go_name: row.try_get(4)?,
+                });
+            }
+            Ok(contacts)
+        })
+        .await
     }
 }
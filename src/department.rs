@@ -1,38 +1,233 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+// Taken by every transaction that sets a parent; see company.rs.
+const PARENT_LOCK: i64 = 0x7270_656c_0002;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Department {
     #[serde(default)]
     pub id: i64,
+    pub company_id: Option<i64>,
+    pub parent_id: Option<i64>,
     pub name: Option<String>,
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(Department);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DepartmentList {
     pub id: i64,
+    pub company_id: Option<i64>,
+    pub company_name: Option<String>,
+    pub parent_name: Option<String>,
     pub name: Option<String>,
     pub note: Option<String>,
 }
 
+// A parent has to be a department of the same company and must not be the
+// department itself or one nested inside it. `id` is None on insert.
+async fn check_parent<C: GenericClient>(
+    client: &C,
+    id: Option<i64>,
+    company_id: Option<i64>,
+    parent_id: Option<i64>,
+) -> Result<(), RpelError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+    if id == Some(parent_id) {
+        return Err(RpelError::Invalid(format!(
+            "department {parent_id} cannot be its own parent"
+        )));
+    }
+    client
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&PARENT_LOCK])
+        .await?;
+    let stmt = prepare(
+        client,
+        "
+            WITH RECURSIVE chain AS (
+                SELECT
+                    id,
+                    parent_id,
+                    company_id,
+                    ARRAY[id] AS path
+                FROM
+                    departments
+                WHERE
+                    id = $1
+                UNION ALL
+                SELECT
+                    d.id,
+                    d.parent_id,
+                    d.company_id,
+                    ch.path || d.id
+                FROM
+                    departments AS d
+                JOIN
+                    chain AS ch ON d.id = ch.parent_id
+                WHERE
+                    NOT d.id = ANY(ch.path)
+            )
+            SELECT
+                id,
+                company_id
+            FROM
+                chain
+        ",
+    )
+    .await?;
+    let rows = client.query(&stmt, &[&parent_id]).await?;
+    let Some(parent) = rows.first() else {
+        return Err(RpelError::Invalid(format!(
+            "parent department {parent_id} does not exist"
+        )));
+    };
+    if parent.try_get::<_, Option<i64>>(1)? != company_id {
+        return Err(RpelError::Invalid(format!(
+            "parent department {parent_id} belongs to another company"
+        )));
+    }
+    for row in &rows {
+        if Some(row.try_get::<_, i64>(0)?) == id {
+            return Err(RpelError::Invalid(format!(
+                "department {parent_id} is nested in department {}",
+                row.try_get::<_, i64>(0)?
+            )));
+        }
+    }
+    Ok(())
+}
+
+// The department of a contact has to exist and belong to the contact's
+// company, unless it is one still shared by companies.
+pub(crate) async fn check_department<C: GenericClient>(
+    client: &C,
+    company_id: Option<i64>,
+    department_id: Option<i64>,
+) -> Result<(), RpelError> {
+    let Some(department_id) = department_id else {
+        return Ok(());
+    };
+    let stmt = prepare(
+        client,
+        "
+            SELECT
+                company_id
+            FROM
+                departments
+            WHERE
+                id = $1
+        ",
+    )
+    .await?;
+    let Some(row) = client.query_opt(&stmt, &[&department_id]).await? else {
+        return Err(RpelError::Invalid(format!(
+            "department {department_id} does not exist"
+        )));
+    };
+    let owner: Option<i64> = row.try_get(0)?;
+    if owner.is_some() && owner != company_id {
+        return Err(RpelError::Invalid(format!(
+            "department {department_id} belongs to another company"
+        )));
+    }
+    Ok(())
+}
+
+// Contacts are checked against the company of their department, so it cannot
+// change company or go away while any contact is in it.
+async fn check_unused<C: GenericClient>(client: &C, id: i64) -> Result<(), RpelError> {
+    let stmt = prepare(
+        client,
+        "
+            SELECT
+                id
+            FROM
+                contacts
+            WHERE
+                department_id = $1
+            ORDER BY
+                id ASC
+            LIMIT 1
+        ",
+    )
+    .await?;
+    if let Some(row) = client.query_opt(&stmt, &[&id]).await? {
+        return Err(RpelError::Invalid(format!(
+            "contact {} is in department {id}",
+            row.try_get::<_, i64>(0)?
+        )));
+    }
+    Ok(())
+}
+
+// Moving a department to another company would leave its contacts and nested
+// departments pointing across companies.
+async fn check_move<C: GenericClient>(
+    client: &C,
+    id: i64,
+    company_id: Option<i64>,
+) -> Result<(), RpelError> {
+    let stmt = prepare(
+        client,
+        "
+            SELECT
+                company_id,
+                (SELECT min(id) FROM departments WHERE parent_id = $1)
+            FROM
+                departments
+            WHERE
+                id = $1
+        ",
+    )
+    .await?;
+    let Some(row) = client.query_opt(&stmt, &[&id]).await? else {
+        return Ok(());
+    };
+    if row.try_get::<_, Option<i64>>(0)? == company_id {
+        return Ok(());
+    }
+    if let Some(child) = row.try_get::<_, Option<i64>>(1)? {
+        return Err(RpelError::Invalid(format!(
+            "department {child} is nested in department {id}"
+        )));
+    }
+    check_unused(client, id).await
+}
+
 impl Department {
     // pub fn new() -> Self {
     //     Default::default()
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Department, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Department::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Department, RpelError> {
+        traced("Department", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
+                        company_id,
+                        parent_id,
                         name,
                         note,
                         created_at,
@@ -44,25 +239,46 @@ impl Department {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let department = Department {
-            id,
-            name: row.try_get(0)?,
-            note: row.try_get(1)?,
-            created_at: row.try_get(2)?,
-            updated_at: row.try_get(3)?,
-        };
-        Ok(department)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("departments".to_string(), id))?;
+            let department = Department {
+                id,
+                company_id: row.try_get(0)?,
+                parent_id: row.try_get(1)?,
+                name: row.try_get(2)?,
+                note: row.try_get(3)?,
+                created_at: row.try_get(4)?,
+                updated_at: row.try_get(5)?,
+            };
+            Ok(department)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, department: Department) -> Result<Department, RpelError> {
-        let mut department = department;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Department::insert_with(&tx, department).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        department: Department,
+    ) -> Result<Department, RpelError> {
+        traced("Department", "insert", async move {
+            let mut department = department;
+            check_parent(client, None, department.company_id, department.parent_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO departments
                     (
+                        company_id,
+                        parent_id,
                         name,
                         note,
                         created_at,
@@ -73,59 +289,140 @@ impl Department {
                         $1,
                         $2,
                         $3,
-                        $4
+                        $4,
+                        $5,
+                        $6
                     )
                     RETURNING
                         id
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &department.name,
-                    &department.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        department.id = row.get(0);
-        Ok(department)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &department.company_id,
+                        &department.parent_id,
+                        &department.name,
+                        &department.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            department.id = row.get(0);
+            notify(client, "departments", department.id, Operation::Insert, 1).await?;
+            Ok(department)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, department: Department) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Department::update_with(&tx, department).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        department: Department,
+    ) -> Result<u64, RpelError> {
+        traced("Department", "update", async move {
+            check_parent(
+                client,
+                Some(department.id),
+                department.company_id,
+                department.parent_id,
+            )
+            .await?;
+            check_move(client, department.id, department.company_id).await?;
+            let stmt = prepare(
+                client,
                 "
                     UPDATE departments SET
-                        name = $2,
-                        note = $3,
-                        updated_at = $4
+                        company_id = $2,
+                        parent_id = $3,
+                        name = $4,
+                        note = $5,
+                        updated_at = $6
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $7
                     ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &department.id,
-                    &department.name,
-                    &department.note,
-                    &Local::now().naive_local(),
-                ],
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &department.id,
+                        &department.company_id,
+                        &department.parent_id,
+                        &department.name,
+                        &department.note,
+                        &Local::now().naive_local(),
+                        &department.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "departments",
+                department.id,
+                department.updated_at,
+                result,
             )
-            .await?)
+            .await?;
+            notify(
+                client,
+                "departments",
+                department.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Department::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Department", "delete", async move {
+            check_unused(client, id).await?;
+            // Nested departments move up to the parent of the removed one.
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE departments SET
+                        parent_id = (SELECT parent_id FROM departments WHERE id = $1),
+                        updated_at = $2
+                    WHERE
+                        parent_id = $1
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            for row in client
+                .query(&stmt, &[&id, &Local::now().naive_local()])
+                .await?
+            {
+                notify(client, "departments", row.try_get(0)?, Operation::Update, 1).await?;
+            }
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         departments
@@ -134,35 +431,59 @@ impl Department {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "departments", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl DepartmentList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<DepartmentList>, RpelError> {
-        let mut departments = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        DepartmentList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<DepartmentList>, RpelError> {
+        traced("DepartmentList", "get_all", async move {
+            let mut departments = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
-                        id,
-                        name,
-                        note
+                        d.id,
+                        d.company_id,
+                        c.name AS company_name,
+                        p.name AS parent_name,
+                        d.name,
+                        d.note
                     FROM
-                        departments
+                        departments AS d
+                    LEFT JOIN
+                        companies AS c ON d.company_id = c.id
+                    LEFT JOIN
+                        departments AS p ON d.parent_id = p.id
                     ORDER BY
-                        name ASC
+                        c.name ASC,
+                        d.name ASC
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            departments.push(DepartmentList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                note: row.try_get(2)?,
-            });
-        }
-        Ok(departments)
+            for row in client.query(&stmt, &[]).await? {
+                departments.push(DepartmentList {
+                    id: row.try_get(0)?,
+                    company_id: row.try_get(1)?,
+                    company_name: row.try_get(2)?,
+                    parent_name: row.try_get(3)?,
+                    name: row.try_get(4)?,
+                    note: row.try_get(5)?,
+                });
+            }
+            Ok(departments)
+        })
+        .await
     }
 }
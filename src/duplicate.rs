@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire,
+    error::RpelError,
+    trace::{prepare, traced},
+    RpelPool,
+};
+
+pub const NAME_SCORE: i64 = 40;
+pub const BIRTHDAY_SCORE: i64 = 30;
+pub const BIRTHDAY_MISMATCH_SCORE: i64 = -60;
+// A shared name, phone or email on its own is enough to list a pair at the
+// default threshold; a different birthday pushes it back below.
+pub const PHONE_SCORE: i64 = 40;
+pub const EMAIL_SCORE: i64 = 40;
+pub const DEFAULT_MIN_SCORE: i64 = 40;
+// A phone or email shared by more contacts than this is a switchboard or a
+// common mailbox rather than a person, and pairing all of them would be
+// quadratic in the group size.
+pub const MAX_SHARED_CONTACTS: usize = 20;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct DuplicateCandidate {
+    pub id: i64,
+    pub other_id: i64,
+    pub name: Option<String>,
+    pub other_name: Option<String>,
+    pub score: i64,
+    pub same_name: bool,
+    pub same_birthday: Option<bool>,
+    pub shared_phones: Vec<i64>,
+    pub shared_emails: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Person {
+    pub(crate) id: i64,
+    pub(crate) name: Option<String>,
+    pub(crate) birthday: Option<NaiveDate>,
+    pub(crate) phones: BTreeSet<i64>,
+    pub(crate) emails: BTreeSet<String>,
+}
+
+// Case, punctuation, word order and the ё/е spelling do not tell people apart.
+pub fn normalize_name(name: &str) -> String {
+    let mut words: Vec<String> = name
+        .to_lowercase()
+        .replace('ё', "е")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+    words.sort();
+    words.join(" ")
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// A missing birthday neither helps nor hurts; two different ones almost
+// certainly mean two people with the same name.
+fn score(a: &Person, b: &Person) -> DuplicateCandidate {
+    let same_name = match (&a.name, &b.name) {
+        (Some(a), Some(b)) => {
+            let a = normalize_name(a);
+            !a.is_empty() && a == normalize_name(b)
+        }
+        _ => false,
+    };
+    let same_birthday = match (a.birthday, b.birthday) {
+        (Some(a), Some(b)) => Some(a == b),
+        _ => None,
+    };
+    let shared_phones: Vec<i64> = a.phones.intersection(&b.phones).copied().collect();
+    let shared_emails: Vec<String> = a.emails.intersection(&b.emails).cloned().collect();
+    let mut score = 0;
+    if same_name {
+        score += NAME_SCORE;
+    }
+    score += match same_birthday {
+        Some(true) => BIRTHDAY_SCORE,
+        Some(false) => BIRTHDAY_MISMATCH_SCORE,
+        None => 0,
+    };
+    score += PHONE_SCORE * shared_phones.len() as i64;
+    score += EMAIL_SCORE * shared_emails.len() as i64;
+    DuplicateCandidate {
+        id: a.id,
+        other_id: b.id,
+        name: a.name.clone(),
+        other_name: b.name.clone(),
+        score,
+        same_name,
+        same_birthday,
+        shared_phones,
+        shared_emails,
+    }
+}
+
+// Only pairs that share a normalised name, a phone or an email are scored.
+// Pairing is quadratic in the size of each group, so phone and email groups
+// over MAX_SHARED_CONTACTS are skipped; name groups are as large as a full
+// name is common.
+pub(crate) fn candidates(people: &[Person], min_score: i64) -> Vec<DuplicateCandidate> {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, person) in people.iter().enumerate() {
+        if let Some(name) = &person.name {
+            let name = normalize_name(name);
+            if !name.is_empty() {
+                groups.entry(format!("n:{name}")).or_default().push(index);
+            }
+        }
+        for phone in &person.phones {
+            groups.entry(format!("p:{phone}")).or_default().push(index);
+        }
+        for email in &person.emails {
+            groups.entry(format!("e:{email}")).or_default().push(index);
+        }
+    }
+    let mut pairs = BTreeSet::new();
+    for (key, members) in &groups {
+        if !key.starts_with("n:") && members.len() > MAX_SHARED_CONTACTS {
+            continue;
+        }
+        for (i, &a) in members.iter().enumerate() {
+            for &b in &members[i + 1..] {
+                if a != b {
+                    pairs.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+    let mut found: Vec<DuplicateCandidate> = pairs
+        .into_iter()
+        .map(|(a, b)| score(&people[a], &people[b]))
+        .filter(|candidate| candidate.score >= min_score)
+        .collect();
+    found.sort_by(|a, b| b.score.cmp(&a.score).then(a.id.cmp(&b.id)));
+    found
+}
+
+impl DuplicateCandidate {
+    pub async fn find(
+        pool: &RpelPool,
+        min_score: i64,
+    ) -> Result<Vec<DuplicateCandidate>, RpelError> {
+        let client = acquire(pool).await?;
+        DuplicateCandidate::find_with(&client, min_score).await
+    }
+
+    pub async fn find_with<C: GenericClient>(
+        client: &C,
+        min_score: i64,
+    ) -> Result<Vec<DuplicateCandidate>, RpelError> {
+        traced("DuplicateCandidate", "find", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        c.id,
+                        c.name,
+                        c.birthday,
+                        array_remove(array_agg(DISTINCT p.phone), NULL) AS phones,
+                        array_remove(array_agg(DISTINCT e.email), NULL) AS emails
+                    FROM
+                        contacts AS c
+                    LEFT JOIN
+                        phones AS p ON p.contact_id = c.id AND p.fax = false
+                    LEFT JOIN
+                        emails AS e ON e.contact_id = c.id
+                    GROUP BY
+                        c.id
+                    ORDER BY
+                        c.id ASC
+                ",
+            )
+            .await?;
+            let mut people = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                let phones: Vec<i64> = row.try_get(3)?;
+                let emails: Vec<String> = row.try_get(4)?;
+                people.push(Person {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    birthday: row.try_get(2)?,
+                    phones: phones.into_iter().collect(),
+                    emails: emails.iter().map(|e| normalize_email(e)).collect(),
+                });
+            }
+            Ok(candidates(&people, min_score))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person(id: i64, name: &str, birthday: Option<NaiveDate>, phones: &[i64]) -> Person {
+        Person {
+            id,
+            name: Some(name.to_string()),
+            birthday,
+            phones: phones.iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn normalize_name_ignores_case_order_and_yo() {
+        assert_eq!(
+            normalize_name("  Фёдоров,  Иван "),
+            normalize_name("иван федоров")
+        );
+    }
+
+    #[test]
+    fn candidates_score_names_birthdays_and_phones() {
+        let born = NaiveDate::from_ymd_opt(1980, 5, 1);
+        let other = NaiveDate::from_ymd_opt(1991, 2, 3);
+        let people = [
+            person(1, "Ivanov Ivan", born, &[]),
+            person(2, "ivan ivanov", None, &[]),
+            person(3, "Ivan Ivanov", other, &[]),
+            person(4, "Petrov", None, &[555]),
+            person(5, "Sidorov", None, &[555]),
+        ];
+        let found = candidates(&people, DEFAULT_MIN_SCORE);
+        let pairs: Vec<(i64, i64, i64)> =
+            found.iter().map(|c| (c.id, c.other_id, c.score)).collect();
+        assert_eq!(pairs, vec![(1, 2, 40), (2, 3, 40), (4, 5, 40)]);
+        assert_eq!(found[2].shared_phones, vec![555]);
+        assert!(!found[2].same_name);
+    }
+
+    #[test]
+    fn candidates_skip_switchboard_numbers() {
+        let people: Vec<Person> = (0..=MAX_SHARED_CONTACTS as i64)
+            .map(|id| person(id, &format!("Clerk {id}"), None, &[100]))
+            .collect();
+        assert!(candidates(&people, DEFAULT_MIN_SCORE).is_empty());
+        let found = candidates(&people[..2], DEFAULT_MIN_SCORE);
+        assert_eq!(found.len(), 1);
+    }
+}
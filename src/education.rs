@@ -1,9 +1,19 @@
 use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    attachment::{Attachment, AttachmentOwner},
+    check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Education {
     #[serde(default)]
     pub id: i64,
@@ -14,7 +24,23 @@ pub struct Education {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Education);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EducationPatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub contact_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub start_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub end_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub post_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
     pub updated_at: Option<NaiveDateTime>,
 }
 
@@ -48,9 +74,14 @@ impl Education {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Education, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Education::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Education, RpelError> {
+        traced("Education", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         contact_id,
@@ -67,25 +98,38 @@ impl Education {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let education = Education {
-            id,
-            contact_id: row.try_get(0)?,
-            start_date: row.try_get(1)?,
-            end_date: row.try_get(2)?,
-            post_id: row.try_get(3)?,
-            note: row.try_get(4)?,
-            created_at: row.try_get(5)?,
-            updated_at: row.try_get(6)?,
-        };
-        Ok(education)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("educations".to_string(), id))?;
+            let education = Education {
+                id,
+                contact_id: row.try_get(0)?,
+                start_date: row.try_get(1)?,
+                end_date: row.try_get(2)?,
+                post_id: row.try_get(3)?,
+                note: row.try_get(4)?,
+                created_at: row.try_get(5)?,
+                updated_at: row.try_get(6)?,
+            };
+            Ok(education)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, education: Education) -> Result<Education, RpelError> {
-        let mut education = education;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Education::insert_with(&client, education).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        education: Education,
+    ) -> Result<Education, RpelError> {
+        traced("Education", "insert", async move {
+            let mut education = education;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO educations
                     (
@@ -112,28 +156,39 @@ impl Education {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &education.contact_id,
-                    &education.start_date,
-                    &education.end_date,
-                    &education.post_id,
-                    &education.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        education.id = row.get(0);
-        Ok(education)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &education.contact_id,
+                        &education.start_date,
+                        &education.end_date,
+                        &education.post_id,
+                        &education.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            education.id = row.get(0);
+            notify(client, "educations", education.id, Operation::Insert, 1).await?;
+            Ok(education)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, education: Education) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Education::update_with(&client, education).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        education: Education,
+    ) -> Result<u64, RpelError> {
+        traced("Education", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE educations SET
                         contact_id = $2,
@@ -144,29 +199,87 @@ impl Education {
                         updated_at = $7
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $8
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &education.id,
-                    &education.contact_id,
-                    &education.start_date,
-                    &education.end_date,
-                    &education.post_id,
-                    &education.note,
-                    &Local::now().naive_local(),
-                ],
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &education.id,
+                        &education.contact_id,
+                        &education.start_date,
+                        &education.end_date,
+                        &education.post_id,
+                        &education.note,
+                        &Local::now().naive_local(),
+                        &education.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "educations",
+                education.id,
+                education.updated_at,
+                result,
             )
-            .await?)
+            .await?;
+            notify(
+                client,
+                "educations",
+                education.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn patch(
+        pool: &RpelPool,
+        id: i64,
+        education: EducationPatch,
+    ) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Education::patch_with(&client, id, education).await
+    }
+
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        education: EducationPatch,
+    ) -> Result<u64, RpelError> {
+        traced("Education", "patch", async move {
+            let mut patch = Patch::new("educations", &id);
+            patch
+                .set("contact_id", &education.contact_id)
+                .set("start_date", &education.start_date)
+                .set("end_date", &education.end_date)
+                .set("post_id", &education.post_id)
+                .set("note", &education.note);
+            patch.execute(client, &education.updated_at).await
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Education::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Education", "delete", async move {
+            Attachment::delete_owned_with(client, AttachmentOwner::Educations, id).await?;
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         educations
@@ -175,16 +288,27 @@ impl Education {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "educations", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl EducationList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<EducationList>, RpelError> {
-        let mut educations = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        EducationList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<EducationList>, RpelError> {
+        traced("EducationList", "get_all", async move {
+            let mut educations = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         e.id,
@@ -206,32 +330,41 @@ impl EducationList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            let start_str: Option<NaiveDate> = row.get(3);
-            let end_str: Option<NaiveDate> = row.get(4);
-            educations.push(EducationList {
-                id: row.try_get(0)?,
-                contact_id: row.try_get(1)?,
-                contact_name: row.try_get(2)?,
-                start_date: row.try_get(3)?,
-                end_date: row.try_get(4)?,
-                start_str: start_str.map(|d| d.format("%Y-%m-%d").to_string()),
-                end_str: end_str.map(|d| d.format("%Y-%m-%d").to_string()),
-                post_id: row.try_get(5)?,
-                post_name: row.try_get(6)?,
-                note: row.try_get(7)?,
-            });
-        }
-        Ok(educations)
+            for row in client.query(&stmt, &[]).await? {
+                let start_str: Option<NaiveDate> = row.get(3);
+                let end_str: Option<NaiveDate> = row.get(4);
+                educations.push(EducationList {
+                    id: row.try_get(0)?,
+                    contact_id: row.try_get(1)?,
+                    contact_name: row.try_get(2)?,
+                    start_date: row.try_get(3)?,
+                    end_date: row.try_get(4)?,
+                    start_str: start_str.map(|d| d.format("%Y-%m-%d").to_string()),
+                    end_str: end_str.map(|d| d.format("%Y-%m-%d").to_string()),
+                    post_id: row.try_get(5)?,
+                    post_name: row.try_get(6)?,
+                    note: row.try_get(7)?,
+                });
+            }
+            Ok(educations)
+        })
+        .await
     }
 }
 
 impl EducationShort {
     pub async fn get_near(pool: &RpelPool) -> Result<Vec<EducationShort>, RpelError> {
-        let mut educations = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        EducationShort::get_near_with(&client).await
+    }
+
+    pub async fn get_near_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<EducationShort>, RpelError> {
+        traced("EducationShort", "get_near", async move {
+            let mut educations = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         e.id,
@@ -254,16 +387,18 @@ impl EducationShort {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            educations.push(EducationShort {
-                id: row.try_get(0)?,
-                contact_id: row.try_get(1)?,
-                contact_name: row.try_get(2)?,
-                company_id: row.try_get(3)?,
-                company_name: row.try_get(4)?,
-                start_date: row.try_get(5)?,
-            });
-        }
-        Ok(educations)
+            for row in client.query(&stmt, &[]).await? {
+                educations.push(EducationShort {
+                    id: row.try_get(0)?,
+                    contact_id: row.try_get(1)?,
+                    contact_name: row.try_get(2)?,
+                    company_id: row.try_get(3)?,
+                    company_name: row.try_get(4)?,
+                    start_date: row.try_get(5)?,
+                });
+            }
+            Ok(educations)
+        })
+        .await
     }
 }
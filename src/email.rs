@@ -1,7 +1,13 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    error::RpelError,
+    trace::{prepare, traced},
+    RpelPool,
+};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Email {
@@ -20,42 +26,68 @@ impl Email {
         Default::default()
     }
 
-    async fn insert(pool: &RpelPool, email: Email) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+    // Same diff as Phone::replace: unchanged addresses keep their rows, new
+    // ones are inserted in one statement and only removed ones are deleted.
+    async fn replace<C: GenericClient>(
+        client: &C,
+        owner: &str,
+        id: i64,
+        emails: Vec<String>,
+    ) -> Result<(), RpelError> {
+        let delete = prepare(
+            client,
+            format!(
+                "
+                    DELETE FROM
+                        emails
+                    WHERE
+                        {owner} = $1
+                    AND
+                        (email IS NULL OR email <> ALL($2))
+                "
+            )
+            .as_str(),
+        )
+        .await?;
+        client.execute(&delete, &[&id, &emails]).await?;
+        let insert = prepare(
+            client,
+            format!(
                 "
                     INSERT INTO emails
                     (
-                        company_id,
-                        contact_id,
+                        {owner},
                         email,
                         created_at,
                         updated_at
                     )
-                    VALUES
-                    (
-                        $1,
-                        $2,
-                        $3,
-                        $4,
-                        $5
-                    )
-                ",
+                    SELECT DISTINCT
+                        $1::bigint,
+                        e.email,
+                        $3::timestamp,
+                        $3::timestamp
+                    FROM
+                        unnest($2::text[]) AS e(email)
+                    WHERE
+                        NOT EXISTS (
+                            SELECT
+                                1
+                            FROM
+                                emails AS o
+                            WHERE
+                                o.{owner} = $1
+                            AND
+                                o.email = e.email
+                        )
+                "
             )
+            .as_str(),
+        )
+        .await?;
+        client
+            .execute(&insert, &[&id, &emails, &Local::now().naive_local()])
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &email.company_id,
-                    &email.contact_id,
-                    &email.email,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+        Ok(())
     }
 
     pub async fn update_contacts(
@@ -63,35 +95,59 @@ impl Email {
         id: i64,
         emails: Vec<String>,
     ) -> Result<(), RpelError> {
-        Email::delete_contacts(pool, id).await?;
-        for value in emails {
-            let mut email = Email::new();
-            email.contact_id = Some(id);
-            email.email = Some(value);
-            Email::insert(pool, email).await?;
-        }
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        Email::update_contacts_with(&tx, id, emails).await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    pub async fn update_contacts_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        emails: Vec<String>,
+    ) -> Result<(), RpelError> {
+        traced("Email", "update_contacts", async move {
+            Email::replace(client, "contact_id", id, emails).await
+        })
+        .await
+    }
+
     pub async fn update_companies(
         pool: &RpelPool,
         id: i64,
         emails: Vec<String>,
     ) -> Result<(), RpelError> {
-        Email::delete_companies(pool, id).await?;
-        for value in emails {
-            let mut email = Email::new();
-            email.company_id = Some(id);
-            email.email = Some(value);
-            Email::insert(pool, email).await?;
-        }
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        Email::update_companies_with(&tx, id, emails).await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    pub async fn update_companies_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        emails: Vec<String>,
+    ) -> Result<(), RpelError> {
+        traced("Email", "update_companies", async move {
+            Email::replace(client, "company_id", id, emails).await
+        })
+        .await
+    }
+
     pub async fn delete_contacts(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Email::delete_contacts_with(&client, id).await
+    }
+
+    pub async fn delete_contacts_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<u64, RpelError> {
+        traced("Email", "delete_contacts", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         emails
@@ -100,13 +156,23 @@ impl Email {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            Ok(client.execute(&stmt, &[&id]).await?)
+        })
+        .await
     }
 
     pub async fn delete_companies(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Email::delete_companies_with(&client, id).await
+    }
+
+    pub async fn delete_companies_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<u64, RpelError> {
+        traced("Email", "delete_companies", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         emails
@@ -115,6 +181,8 @@ impl Email {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            Ok(client.execute(&stmt, &[&id]).await?)
+        })
+        .await
     }
 }
@@ -0,0 +1,611 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// One period of a contact's career. `date_to` is the first day the position
+// no longer held, so a change on one day closes the old period and opens the
+// new one with the same date. An open period has no `date_to`; a missing
+// `date_from` means it started before anything was recorded.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Employment {
+    #[serde(default)]
+    pub id: i64,
+    pub contact_id: Option<i64>,
+    pub company_id: Option<i64>,
+    pub department_id: Option<i64>,
+    pub post_id: Option<i64>,
+    pub post_go_id: Option<i64>,
+    pub rank_id: Option<i64>,
+    pub date_from: Option<NaiveDate>,
+    pub date_to: Option<NaiveDate>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Employment);
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct EmploymentList {
+    pub id: i64,
+    pub contact_id: Option<i64>,
+    pub contact_name: Option<String>,
+    pub company_id: Option<i64>,
+    pub company_name: Option<String>,
+    pub department_name: Option<String>,
+    pub post_name: Option<String>,
+    pub post_go_name: Option<String>,
+    pub rank_name: Option<String>,
+    pub date_from: Option<NaiveDate>,
+    pub date_to: Option<NaiveDate>,
+    pub note: Option<String>,
+}
+
+// The columns of `contacts` that a period records.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Position {
+    company_id: Option<i64>,
+    department_id: Option<i64>,
+    post_id: Option<i64>,
+    post_go_id: Option<i64>,
+    rank_id: Option<i64>,
+}
+
+impl Position {
+    fn is_empty(&self) -> bool {
+        *self == Position::default()
+    }
+}
+
+pub(crate) fn check_dates(employment: &Employment) -> Result<(), RpelError> {
+    match (employment.date_from, employment.date_to) {
+        (Some(from), Some(to)) if to < from => Err(RpelError::Invalid(format!(
+            "employment ends on {to} before it starts on {from}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+impl Employment {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Employment, RpelError> {
+        let client = acquire(pool).await?;
+        Employment::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Employment, RpelError> {
+        traced("Employment", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        contact_id,
+                        company_id,
+                        department_id,
+                        post_id,
+                        post_go_id,
+                        rank_id,
+                        date_from,
+                        date_to,
+                        note,
+                        created_at,
+                        updated_at
+                    FROM
+                        employments
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("employments".to_string(), id))?;
+            let employment = Employment {
+                id,
+                contact_id: row.try_get(0)?,
+                company_id: row.try_get(1)?,
+                department_id: row.try_get(2)?,
+                post_id: row.try_get(3)?,
+                post_go_id: row.try_get(4)?,
+                rank_id: row.try_get(5)?,
+                date_from: row.try_get(6)?,
+                date_to: row.try_get(7)?,
+                note: row.try_get(8)?,
+                created_at: row.try_get(9)?,
+                updated_at: row.try_get(10)?,
+            };
+            Ok(employment)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, employment: Employment) -> Result<Employment, RpelError> {
+        let client = acquire(pool).await?;
+        Employment::insert_with(&client, employment).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        employment: Employment,
+    ) -> Result<Employment, RpelError> {
+        traced("Employment", "insert", async move {
+            let mut employment = employment;
+            check_dates(&employment)?;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO employments
+                    (
+                        contact_id,
+                        company_id,
+                        department_id,
+                        post_id,
+                        post_go_id,
+                        rank_id,
+                        date_from,
+                        date_to,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6,
+                        $7,
+                        $8,
+                        $9,
+                        $10,
+                        $11
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &employment.contact_id,
+                        &employment.company_id,
+                        &employment.department_id,
+                        &employment.post_id,
+                        &employment.post_go_id,
+                        &employment.rank_id,
+                        &employment.date_from,
+                        &employment.date_to,
+                        &employment.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            employment.id = row.get(0);
+            notify(client, "employments", employment.id, Operation::Insert, 1).await?;
+            Ok(employment)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, employment: Employment) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Employment::update_with(&client, employment).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        employment: Employment,
+    ) -> Result<u64, RpelError> {
+        traced("Employment", "update", async move {
+            check_dates(&employment)?;
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE employments SET
+                        contact_id = $2,
+                        company_id = $3,
+                        department_id = $4,
+                        post_id = $5,
+                        post_go_id = $6,
+                        rank_id = $7,
+                        date_from = $8,
+                        date_to = $9,
+                        note = $10,
+                        updated_at = $11
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $12
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &employment.id,
+                        &employment.contact_id,
+                        &employment.company_id,
+                        &employment.department_id,
+                        &employment.post_id,
+                        &employment.post_go_id,
+                        &employment.rank_id,
+                        &employment.date_from,
+                        &employment.date_to,
+                        &employment.note,
+                        &Local::now().naive_local(),
+                        &employment.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "employments",
+                employment.id,
+                employment.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "employments",
+                employment.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Employment::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Employment", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        employments
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "employments", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    // Ends the open period of a contact that is being deleted on `date`. The
+    // closed periods stay: they are the record of who held which post.
+    pub async fn close_contacts_with<C: GenericClient>(
+        client: &C,
+        contact_id: i64,
+        date: NaiveDate,
+    ) -> Result<u64, RpelError> {
+        traced("Employment", "close_contacts", async move {
+            // Like in sync_with, a period that starts on `date` lasted no time
+            // at all and goes.
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        employments
+                    WHERE
+                        contact_id = $1
+                    AND
+                        date_to IS NULL
+                    AND
+                        date_from >= $2
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[&contact_id, &date]).await? {
+                notify(client, "employments", row.try_get(0)?, Operation::Delete, 1).await?;
+            }
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE employments SET
+                        date_to = $2,
+                        updated_at = $3
+                    WHERE
+                        contact_id = $1
+                    AND
+                        date_to IS NULL
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let rows = client
+                .query(&stmt, &[&contact_id, &date, &Local::now().naive_local()])
+                .await?;
+            for row in &rows {
+                notify(client, "employments", row.try_get(0)?, Operation::Update, 1).await?;
+            }
+            Ok(rows.len() as u64)
+        })
+        .await
+    }
+
+    // Brings the open period of a contact in line with its current company,
+    // department, posts and rank, closing the old period on `date`. Called by
+    // every write to `contacts`.
+    pub(crate) async fn sync_with<C: GenericClient>(
+        client: &C,
+        contact_id: i64,
+        date: NaiveDate,
+    ) -> Result<(), RpelError> {
+        let stmt = prepare(
+            client,
+            "
+                SELECT
+                    company_id,
+                    department_id,
+                    post_id,
+                    post_go_id,
+                    rank_id
+                FROM
+                    contacts
+                WHERE
+                    id = $1
+            ",
+        )
+        .await?;
+        let current = match client.query_opt(&stmt, &[&contact_id]).await? {
+            Some(row) => position(&row, 0)?,
+            None => return Ok(()),
+        };
+        let stmt = prepare(
+            client,
+            "
+                SELECT
+                    id,
+                    date_from,
+                    company_id,
+                    department_id,
+                    post_id,
+                    post_go_id,
+                    rank_id
+                FROM
+                    employments
+                WHERE
+                    contact_id = $1
+                AND
+                    date_to IS NULL
+                ORDER BY
+                    date_from DESC NULLS LAST,
+                    id DESC
+                LIMIT 1
+            ",
+        )
+        .await?;
+        let now = Local::now().naive_local();
+        if let Some(open) = client.query_opt(&stmt, &[&contact_id]).await? {
+            if position(&open, 2)? == current {
+                return Ok(());
+            }
+            let id: i64 = open.try_get(0)?;
+            let date_from: Option<NaiveDate> = open.try_get(1)?;
+            // A second change on the day a period started replaces it rather
+            // than leaving a period that lasted no time at all.
+            if date_from == Some(date) {
+                Employment::delete_with(client, id).await?;
+            } else {
+                let stmt = prepare(
+                    client,
+                    "
+                        UPDATE employments SET
+                            date_to = $2,
+                            updated_at = $3
+                        WHERE
+                            id = $1
+                    ",
+                )
+                .await?;
+                let result = client.execute(&stmt, &[&id, &date, &now]).await?;
+                notify(client, "employments", id, Operation::Update, result).await?;
+            }
+        }
+        if !current.is_empty() {
+            Employment::insert_with(
+                client,
+                Employment {
+                    contact_id: Some(contact_id),
+                    company_id: current.company_id,
+                    department_id: current.department_id,
+                    post_id: current.post_id,
+                    post_go_id: current.post_go_id,
+                    rank_id: current.rank_id,
+                    date_from: Some(date),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+fn position(row: &Row, start: usize) -> Result<Position, RpelError> {
+    Ok(Position {
+        company_id: row.try_get(start)?,
+        department_id: row.try_get(start + 1)?,
+        post_id: row.try_get(start + 2)?,
+        post_go_id: row.try_get(start + 3)?,
+        rank_id: row.try_get(start + 4)?,
+    })
+}
+
+// `filter` narrows `employments AS e`.
+fn list_query(filter: &str) -> String {
+    format!(
+        "
+            SELECT
+                e.id,
+                e.contact_id,
+                ct.name AS contact_name,
+                e.company_id,
+                c.name AS company_name,
+                d.name AS department_name,
+                p.name AS post_name,
+                pg.name AS post_go_name,
+                r.name AS rank_name,
+                e.date_from,
+                e.date_to,
+                e.note
+            FROM
+                employments AS e
+            LEFT JOIN
+                contacts AS ct ON e.contact_id = ct.id
+            LEFT JOIN
+                companies AS c ON e.company_id = c.id
+            LEFT JOIN
+                departments AS d ON e.department_id = d.id
+            LEFT JOIN
+                posts AS p ON e.post_id = p.id AND p.go = false
+            LEFT JOIN
+                posts AS pg ON e.post_go_id = pg.id AND pg.go = true
+            LEFT JOIN
+                ranks AS r ON e.rank_id = r.id
+            WHERE
+                {filter}
+            ORDER BY
+                e.date_from ASC NULLS FIRST,
+                e.id ASC
+        "
+    )
+}
+
+fn list_row(row: &Row) -> Result<EmploymentList, RpelError> {
+    Ok(EmploymentList {
+        id: row.try_get(0)?,
+        contact_id: row.try_get(1)?,
+        contact_name: row.try_get(2)?,
+        company_id: row.try_get(3)?,
+        company_name: row.try_get(4)?,
+        department_name: row.try_get(5)?,
+        post_name: row.try_get(6)?,
+        post_go_name: row.try_get(7)?,
+        rank_name: row.try_get(8)?,
+        date_from: row.try_get(9)?,
+        date_to: row.try_get(10)?,
+        note: row.try_get(11)?,
+    })
+}
+
+impl EmploymentList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<EmploymentList>, RpelError> {
+        let client = acquire(pool).await?;
+        EmploymentList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        traced("EmploymentList", "get_all", async move {
+            let stmt = prepare(client, list_query("true").as_str()).await?;
+            let mut employments = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                employments.push(list_row(&row)?);
+            }
+            Ok(employments)
+        })
+        .await
+    }
+
+    // A contact's career, oldest period first.
+    pub async fn get_by_contact(
+        pool: &RpelPool,
+        contact_id: i64,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        let client = acquire(pool).await?;
+        EmploymentList::get_by_contact_with(&client, contact_id).await
+    }
+
+    pub async fn get_by_contact_with<C: GenericClient>(
+        client: &C,
+        contact_id: i64,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        traced("EmploymentList", "get_by_contact", async move {
+            let stmt = prepare(client, list_query("e.contact_id = $1").as_str()).await?;
+            let mut employments = Vec::new();
+            for row in client.query(&stmt, &[&contact_id]).await? {
+                employments.push(list_row(&row)?);
+            }
+            Ok(employments)
+        })
+        .await
+    }
+
+    // Who held a GO post at a company on `date`.
+    pub async fn get_holders(
+        pool: &RpelPool,
+        company_id: i64,
+        post_go_id: i64,
+        date: NaiveDate,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        let client = acquire(pool).await?;
+        EmploymentList::get_holders_with(&client, company_id, post_go_id, date).await
+    }
+
+    pub async fn get_holders_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+        post_go_id: i64,
+        date: NaiveDate,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        traced("EmploymentList", "get_holders", async move {
+            let stmt = prepare(
+                client,
+                list_query(
+                    "
+                        e.company_id = $1
+                    AND
+                        e.post_go_id = $2
+                    AND
+                        (e.date_from IS NULL OR e.date_from <= $3)
+                    AND
+                        (e.date_to IS NULL OR e.date_to > $3)
+                    ",
+                )
+                .as_str(),
+            )
+            .await?;
+            let mut employments = Vec::new();
+            for row in client
+                .query(&stmt, &[&company_id, &post_go_id, &date])
+                .await?
+            {
+                employments.push(list_row(&row)?);
+            }
+            Ok(employments)
+        })
+        .await
+    }
+}
@@ -10,4 +10,46 @@ pub enum RpelError {
     CreatePool(#[from] deadpool_postgres::CreatePoolError),
     #[error("pool: {0}")]
     Pool(#[from] deadpool_postgres::PoolError),
+    #[error("build pool: {0}")]
+    BuildPool(#[from] deadpool_postgres::BuildError),
+    #[error("tls: {0}")]
+    Tls(#[from] native_tls::Error),
+    #[error("xlsx: {0}")]
+    Xlsx(#[from] rust_xlsxwriter::XlsxError),
+    #[error("zip: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("snapshot: {0}")]
+    Snapshot(String),
+    #[error("conflict: {0} {1} was modified by another user")]
+    Conflict(String, i64),
+    #[error("not found: {0} {1}")]
+    NotFound(String, i64),
+    #[error("invalid: {0}")]
+    Invalid(String),
+}
+
+impl RpelError {
+    // Stable variant name, used as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RpelError::DBQuery(_) => "DBQuery",
+            RpelError::Config(_) => "Config",
+            RpelError::CreatePool(_) => "CreatePool",
+            RpelError::Pool(_) => "Pool",
+            RpelError::BuildPool(_) => "BuildPool",
+            RpelError::Tls(_) => "Tls",
+            RpelError::Xlsx(_) => "Xlsx",
+            RpelError::Zip(_) => "Zip",
+            RpelError::Io(_) => "Io",
+            RpelError::Json(_) => "Json",
+            RpelError::Snapshot(_) => "Snapshot",
+            RpelError::Conflict(_, _) => "Conflict",
+            RpelError::NotFound(_, _) => "NotFound",
+            RpelError::Invalid(_) => "Invalid",
+        }
+    }
 }
@@ -0,0 +1,490 @@
+use std::io::{Cursor, Write};
+
+use chrono::{Datelike, NaiveDate};
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    certificate::CertificateList, company::CompanyList, contact::ContactList,
+    education::EducationList, error::RpelError, practice::PracticeList, siren::SirenList,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    #[default]
+    Ru,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SheetFormat {
+    Xlsx,
+    Ods,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Empty,
+    Text(String),
+    Number(i64),
+    Date(NaiveDate),
+    List(Vec<String>),
+    Dates(Vec<NaiveDate>),
+}
+
+pub trait Sheet {
+    fn title(lang: Lang) -> &'static str;
+
+    fn headers(lang: Lang) -> Vec<&'static str>;
+
+    fn cells(&self) -> Vec<Cell>;
+}
+
+impl Lang {
+    fn title(self, en: &'static str, ru: &'static str) -> &'static str {
+        match self {
+            Lang::En => en,
+            Lang::Ru => ru,
+        }
+    }
+
+    fn pick(self, pairs: &[(&'static str, &'static str)]) -> Vec<&'static str> {
+        pairs.iter().map(|(en, ru)| self.title(en, ru)).collect()
+    }
+
+    fn date_format(self) -> &'static str {
+        match self {
+            Lang::En => "yyyy-mm-dd",
+            Lang::Ru => "dd.mm.yyyy",
+        }
+    }
+
+    fn format_date(self, date: &NaiveDate) -> String {
+        match self {
+            Lang::En => date.format("%Y-%m-%d").to_string(),
+            Lang::Ru => date.format("%d.%m.%Y").to_string(),
+        }
+    }
+}
+
+impl Cell {
+    fn text(value: &Option<String>) -> Cell {
+        value
+            .as_ref()
+            .map_or(Cell::Empty, |v| Cell::Text(v.clone()))
+    }
+
+    fn date(value: Option<NaiveDate>) -> Cell {
+        value.map_or(Cell::Empty, Cell::Date)
+    }
+
+    fn list<T: ToString>(values: &[T]) -> Cell {
+        Cell::List(values.iter().map(|v| v.to_string()).collect())
+    }
+}
+
+impl Sheet for ContactList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Contacts", "Контакты")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Name", "ФИО"),
+            ("Company", "Организация"),
+            ("Post", "Должность"),
+            ("Emails", "Эл. почта"),
+            ("Phones", "Телефоны"),
+            ("Faxes", "Факсы"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Number(self.id),
+            Cell::text(&self.name),
+            Cell::text(&self.company_name),
+            Cell::text(&self.post_name),
+            Cell::list(&self.emails),
+            Cell::list(&self.phones),
+            Cell::list(&self.faxes),
+        ]
+    }
+}
+
+impl Sheet for CompanyList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Companies", "Организации")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Name", "Наименование"),
+            ("Full name", "Полное наименование"),
+            ("Address", "Адрес"),
+            ("Scope", "Сфера деятельности"),
+            ("Emails", "Эл. почта"),
+            ("Phones", "Телефоны"),
+            ("Faxes", "Факсы"),
+            ("Practices", "Учения"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Number(self.id),
+            Cell::text(&self.name),
+            Cell::text(&self.full_name),
+            Cell::text(&self.address),
+            Cell::text(&self.scope_name),
+            Cell::list(&self.emails),
+            Cell::list(&self.phones),
+            Cell::list(&self.faxes),
+            Cell::Dates(self.practices.clone()),
+        ]
+    }
+}
+
+impl Sheet for PracticeList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Practices", "Учения")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Date", "Дата"),
+            ("Company", "Организация"),
+            ("Kind", "Вид"),
+            ("Topic", "Тема"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Number(self.id),
+            Cell::date(self.date_of_practice),
+            Cell::text(&self.company_name),
+            Cell::text(&self.kind_name),
+            Cell::text(&self.topic),
+        ]
+    }
+}
+
+impl Sheet for EducationList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Educations", "Обучение")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Contact", "ФИО"),
+            ("Start", "Начало"),
+            ("End", "Окончание"),
+            ("Post", "Должность"),
+            ("Note", "Примечание"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Number(self.id),
+            Cell::text(&self.contact_name),
+            Cell::date(self.start_date),
+            Cell::date(self.end_date),
+            Cell::text(&self.post_name),
+            Cell::text(&self.note),
+        ]
+    }
+}
+
+impl Sheet for CertificateList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Certificates", "Удостоверения")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Number", "Номер"),
+            ("Contact", "ФИО"),
+            ("Company", "Организация"),
+            ("Date", "Дата"),
+            ("Note", "Примечание"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let cert_date = self.cert_date.as_ref().map(|d| {
+            NaiveDate::parse_from_str(d, "%Y-%m-%d").map_or(Cell::Text(d.clone()), Cell::Date)
+        });
+        vec![
+            Cell::Number(self.id),
+            Cell::text(&self.num),
+            Cell::text(&self.contact_name),
+            Cell::text(&self.company_name),
+            cert_date.unwrap_or(Cell::Empty),
+            Cell::text(&self.note),
+        ]
+    }
+}
+
+impl Sheet for SirenList {
+    fn title(lang: Lang) -> &'static str {
+        lang.title("Sirens", "Сирены")
+    }
+
+    fn headers(lang: Lang) -> Vec<&'static str> {
+        lang.pick(&[
+            ("ID", "ID"),
+            ("Type", "Тип"),
+            ("Address", "Адрес"),
+            ("Contact", "Ответственный"),
+            ("Phones", "Телефоны"),
+        ])
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Number(self.id),
+            Cell::text(&self.siren_type_name),
+            Cell::text(&self.address),
+            Cell::text(&self.contact_name),
+            Cell::list(&self.phones),
+        ]
+    }
+}
+
+pub fn export<T: Sheet>(rows: &[T], format: SheetFormat, lang: Lang) -> Result<Vec<u8>, RpelError> {
+    match format {
+        SheetFormat::Xlsx => to_xlsx(rows, lang),
+        SheetFormat::Ods => to_ods(rows, lang),
+    }
+}
+
+pub fn to_xlsx<T: Sheet>(rows: &[T], lang: Lang) -> Result<Vec<u8>, RpelError> {
+    let header = Format::new().set_bold();
+    let date = Format::new().set_num_format(lang.date_format());
+    let wrap = Format::new().set_text_wrap();
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(T::title(lang))?;
+    for (col, name) in T::headers(lang).into_iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, name, &header)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    for (row, item) in rows.iter().enumerate() {
+        let row = row as u32 + 1;
+        for (col, cell) in item.cells().into_iter().enumerate() {
+            let col = col as u16;
+            match cell {
+                Cell::Empty => {}
+                Cell::Text(value) => {
+                    sheet.write_string(row, col, value)?;
+                }
+                Cell::Number(value) => {
+                    sheet.write_number(row, col, value as f64)?;
+                }
+                Cell::Date(value) => {
+                    let value = ExcelDateTime::from_ymd(
+                        value.year() as u16,
+                        value.month() as u8,
+                        value.day() as u8,
+                    )?;
+                    sheet.write_datetime_with_format(row, col, &value, &date)?;
+                }
+                Cell::List(values) => {
+                    sheet.write_string_with_format(row, col, values.join("\n"), &wrap)?;
+                }
+                Cell::Dates(values) => {
+                    let values: Vec<String> = values.iter().map(|d| lang.format_date(d)).collect();
+                    sheet.write_string_with_format(row, col, values.join("\n"), &wrap)?;
+                }
+            }
+        }
+    }
+    sheet.autofit();
+    Ok(workbook.save_to_buffer()?)
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn push_list(content: &mut String, values: Vec<String>) {
+    content.push_str(r#"<table:table-cell table:style-name="ce3" office:value-type="string">"#);
+    for value in values {
+        content.push_str(&format!("<text:p>{}</text:p>", escape_xml(&value)));
+    }
+    content.push_str("</table:table-cell>");
+}
+
+fn ods_date_style(lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => {
+            r#"<number:date-style style:name="N1"><number:year number:style="long"/><number:text>-</number:text><number:month number:style="long"/><number:text>-</number:text><number:day number:style="long"/></number:date-style>"#
+        }
+        Lang::Ru => {
+            r#"<number:date-style style:name="N1"><number:day number:style="long"/><number:text>.</number:text><number:month number:style="long"/><number:text>.</number:text><number:year number:style="long"/></number:date-style>"#
+        }
+    }
+}
+
+// Named styles the automatic ones in content.xml build on.
+const ODS_STYLES: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?><office:document-styles xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" office:version="1.2"><office:styles><style:style style:name="Default" style:family="table-cell"/><style:style style:name="Heading" style:family="table-cell" style:parent-style-name="Default"><style:text-properties fo:font-weight="bold"/></style:style></office:styles></office:document-styles>"#;
+
+fn ods_content<T: Sheet>(rows: &[T], lang: Lang) -> String {
+    let mut content = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:number="urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" office:version="1.2"><office:automatic-styles>"#,
+    );
+    content.push_str(ods_date_style(lang));
+    content.push_str(
+        r#"<style:style style:name="ce1" style:family="table-cell" style:parent-style-name="Heading"/><style:style style:name="ce2" style:family="table-cell" style:parent-style-name="Default" style:data-style-name="N1"/><style:style style:name="ce3" style:family="table-cell"><style:table-cell-properties fo:wrap-option="wrap"/></style:style></office:automatic-styles><office:body><office:spreadsheet>"#,
+    );
+    content.push_str(&format!(
+        r#"<table:table table:name="{}"><table:table-row>"#,
+        escape_xml(T::title(lang))
+    ));
+    for name in T::headers(lang) {
+        content.push_str(&format!(
+            r#"<table:table-cell table:style-name="ce1" office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+            escape_xml(name)
+        ));
+    }
+    content.push_str("</table:table-row>");
+    for item in rows {
+        content.push_str("<table:table-row>");
+        for cell in item.cells() {
+            match cell {
+                Cell::Empty => content.push_str("<table:table-cell/>"),
+                Cell::Text(value) => content.push_str(&format!(
+                    r#"<table:table-cell office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                    escape_xml(&value)
+                )),
+                Cell::Number(value) => content.push_str(&format!(
+                    r#"<table:table-cell office:value-type="float" office:value="{value}"><text:p>{value}</text:p></table:table-cell>"#
+                )),
+                Cell::Date(value) => content.push_str(&format!(
+                    r#"<table:table-cell table:style-name="ce2" office:value-type="date" office:date-value="{}"><text:p>{}</text:p></table:table-cell>"#,
+                    value.format("%Y-%m-%d"),
+                    lang.format_date(&value)
+                )),
+                Cell::Dates(values) => {
+                    let values = values.iter().map(|d| lang.format_date(d)).collect();
+                    push_list(&mut content, values);
+                }
+                Cell::List(values) => push_list(&mut content, values),
+            }
+        }
+        content.push_str("</table:table-row>");
+    }
+    content.push_str("</table:table></office:spreadsheet></office:body></office:document-content>");
+    content
+}
+
+pub fn to_ods<T: Sheet>(rows: &[T], lang: Lang) -> Result<Vec<u8>, RpelError> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet")?;
+    zip.start_file("META-INF/manifest.xml", deflated)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?><manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2"><manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/><manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/><manifest:file-entry manifest:full-path="styles.xml" manifest:media-type="text/xml"/></manifest:manifest>"#,
+    )?;
+    zip.start_file("styles.xml", deflated)?;
+    zip.write_all(ODS_STYLES)?;
+    zip.start_file("content.xml", deflated)?;
+    zip.write_all(ods_content(rows, lang).as_bytes())?;
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn sirens() -> Vec<SirenList> {
+        vec![SirenList {
+            id: 1,
+            siren_type_name: Some("С-40".to_string()),
+            address: Some("ул. Ленина & 5".to_string()),
+            contact_name: None,
+            phones: vec![123456, 654321],
+        }]
+    }
+
+    #[test]
+    fn ods_content_escapes_and_splits_lists() {
+        let content = ods_content(&sirens(), Lang::Ru);
+        assert!(content.contains(r#"table:name="Сирены""#));
+        assert!(content.contains("ул. Ленина &amp; 5"));
+        assert!(content.contains("<text:p>123456</text:p><text:p>654321</text:p>"));
+    }
+
+    #[test]
+    fn contact_sheet_lists_emails() {
+        let contact = ContactList {
+            id: 1,
+            name: Some("Иванов".to_string()),
+            company_id: None,
+            company_name: None,
+            post_name: None,
+            emails: vec!["a@grid.test".to_string(), "b@grid.test".to_string()],
+            phones: vec![123456],
+            faxes: Vec::new(),
+        };
+        let column = ContactList::headers(Lang::En)
+            .iter()
+            .position(|header| *header == "Emails")
+            .unwrap();
+        assert_eq!(
+            contact.cells()[column],
+            Cell::List(vec!["a@grid.test".to_string(), "b@grid.test".to_string()])
+        );
+        assert_eq!(ContactList::headers(Lang::Ru).len(), contact.cells().len());
+    }
+
+    #[test]
+    fn ods_package_has_styles() {
+        let data = to_ods(&sirens(), Lang::En).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        let mut styles = String::new();
+        archive
+            .by_name("styles.xml")
+            .unwrap()
+            .read_to_string(&mut styles)
+            .unwrap();
+        assert!(styles.contains(r#"style:name="Heading""#));
+        let mut manifest = String::new();
+        archive
+            .by_name("META-INF/manifest.xml")
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        assert!(manifest.contains(r#"manifest:full-path="styles.xml""#));
+    }
+
+    #[test]
+    fn export_produces_zip_documents() {
+        for format in [SheetFormat::Xlsx, SheetFormat::Ods] {
+            let data = export(&sirens(), format, Lang::En).unwrap();
+            assert_eq!(&data[..2], b"PK");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{acquire, error::RpelError, RpelPool};
+
+// How long readiness waits for a connection and the round-trip before it
+// reports the database as unavailable.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PoolStats {
+    pub max_size: usize,
+    pub size: usize,
+    pub available: usize,
+    pub waiting: usize,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Health {
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub server_version: Option<String>,
+    pub migration: Option<i64>,
+    pub pool: PoolStats,
+    pub error: Option<String>,
+}
+
+impl PoolStats {
+    pub fn get(pool: &RpelPool) -> PoolStats {
+        let status = pool.status();
+        PoolStats {
+            max_size: status.max_size,
+            size: status.size,
+            available: status.available,
+            waiting: status.waiting,
+        }
+    }
+}
+
+impl Health {
+    // Liveness only needs the pool to exist, so it never touches the server.
+    pub fn live(pool: &RpelPool) -> Health {
+        Health {
+            ok: true,
+            pool: PoolStats::get(pool),
+            ..Default::default()
+        }
+    }
+
+    // Readiness does one round-trip; a failure is reported in the struct
+    // rather than returned, so callers can always serialize the answer.
+    pub async fn ready(pool: &RpelPool) -> Health {
+        Health::ready_within(pool, READY_TIMEOUT).await
+    }
+
+    // An exhausted pool without a wait timeout would block forever, so the
+    // whole check is bounded here.
+    pub async fn ready_within(pool: &RpelPool, limit: Duration) -> Health {
+        let started = Instant::now();
+        let checked = match tokio::time::timeout(limit, Health::check(pool)).await {
+            Ok(checked) => checked.map_err(|err| err.to_string()),
+            Err(_) => Err(format!("no answer within {} ms", limit.as_millis())),
+        };
+        match checked {
+            Ok((server_version, migration)) => Health {
+                ok: true,
+                latency_ms: Some(started.elapsed().as_millis() as u64),
+                server_version: Some(server_version),
+                migration,
+                pool: PoolStats::get(pool),
+                error: None,
+            },
+            Err(err) => Health {
+                ok: false,
+                latency_ms: Some(started.elapsed().as_millis() as u64),
+                pool: PoolStats::get(pool),
+                error: Some(err),
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn check(pool: &RpelPool) -> Result<(String, Option<i64>), RpelError> {
+        let client = acquire(pool).await?;
+        let row = client
+            .query_one(
+                "
+                    SELECT
+                        current_setting('server_version'),
+                        to_regclass('schema_migrations') IS NOT NULL
+                ",
+                &[],
+            )
+            .await?;
+        let server_version: String = row.try_get(0)?;
+        let tracked: bool = row.try_get(1)?;
+        let migration = if tracked {
+            let row = client
+                .query_one(
+                    "
+                        SELECT
+                            MAX(version)
+                        FROM
+                            schema_migrations
+                    ",
+                    &[],
+                )
+                .await?;
+            row.try_get(0)?
+        } else {
+            None
+        };
+        Ok((server_version, migration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deadpool_postgres::{Manager, ManagerConfig, Pool, Runtime};
+    use tokio_postgres::{Config, NoTls};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unreachable_server_is_not_ready() {
+        let mut cfg = Config::new();
+        cfg.host_path("/nonexistent").user("rpel").dbname("rpel");
+        let manager = Manager::from_config(cfg, NoTls, ManagerConfig::default());
+        let pool = Pool::builder(manager)
+            .runtime(Runtime::Tokio1)
+            .max_size(1)
+            .build()
+            .unwrap();
+
+        let health = Health::ready_within(&pool, Duration::from_secs(5)).await;
+        assert!(!health.ok);
+        assert!(health.error.is_some());
+        assert!(health.server_version.is_none());
+        assert!(Health::live(&pool).ok);
+    }
+}
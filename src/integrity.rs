@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{acquire, attachment::AttachmentOwner, error::RpelError, RpelPool};
+
+// The schema has no foreign keys, so every reference column is listed here as
+// (table, column, referenced table). employments.contact_id is left out: the
+// closed periods of a deleted contact are kept as history.
+pub const REFERENCES: [(&str, &str, &str); 41] = [
+    ("companies", "scope_id", "scopes"),
+    ("companies", "parent_id", "companies"),
+    ("required_posts", "scope_id", "scopes"),
+    ("required_posts", "post_go_id", "posts"),
+    ("departments", "company_id", "companies"),
+    ("departments", "parent_id", "departments"),
+    ("contacts", "company_id", "companies"),
+    ("contacts", "department_id", "departments"),
+    ("contacts", "post_id", "posts"),
+    ("contacts", "post_go_id", "posts"),
+    ("contacts", "rank_id", "ranks"),
+    ("employments", "company_id", "companies"),
+    ("employments", "department_id", "departments"),
+    ("employments", "post_id", "posts"),
+    ("employments", "post_go_id", "posts"),
+    ("employments", "rank_id", "ranks"),
+    ("phones", "contact_id", "contacts"),
+    ("phones", "company_id", "companies"),
+    ("emails", "contact_id", "contacts"),
+    ("emails", "company_id", "companies"),
+    ("practices", "company_id", "companies"),
+    ("practices", "kind_id", "kinds"),
+    ("educations", "contact_id", "contacts"),
+    ("educations", "post_id", "posts"),
+    ("certificates", "contact_id", "contacts"),
+    ("certificates", "company_id", "companies"),
+    ("sirens", "siren_type_id", "siren_types"),
+    ("sirens", "contact_id", "contacts"),
+    ("sirens", "company_id", "companies"),
+    ("siren_checks", "siren_id", "sirens"),
+    ("siren_checks", "contact_id", "contacts"),
+    ("warning_zone_sirens", "warning_zone_id", "warning_zones"),
+    ("warning_zone_sirens", "siren_id", "sirens"),
+    ("scenario_zones", "scenario_id", "scenarios"),
+    ("scenario_zones", "warning_zone_id", "warning_zones"),
+    ("streets", "settlement_id", "settlements"),
+    ("addresses", "company_id", "companies"),
+    ("addresses", "siren_id", "sirens"),
+    ("addresses", "settlement_id", "settlements"),
+    ("addresses", "street_id", "streets"),
+    ("attachment_chunks", "attachment_id", "attachments"),
+];
+
+// Phones and emails belong to a contact or a company, addresses to a company
+// or a siren; a row with neither is unreachable from the UI.
+const OWNED: [(&str, &str, &str); 3] = [
+    ("phones", "contact_id", "company_id"),
+    ("emails", "contact_id", "company_id"),
+    ("addresses", "company_id", "siren_id"),
+];
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Issue {
+    pub check: String,
+    pub table: String,
+    pub id: i64,
+    pub detail: String,
+}
+
+pub async fn check(pool: &RpelPool) -> Result<Vec<Issue>, RpelError> {
+    let client = acquire(pool).await?;
+    let mut issues = Vec::new();
+    for (table, column, target) in REFERENCES {
+        let stmt = format!(
+            "
+                SELECT
+                    t.id,
+                    t.{column}
+                FROM
+                    {table} AS t
+                WHERE
+                    t.{column} IS NOT NULL
+                AND
+                    NOT EXISTS (SELECT 1 FROM {target} AS r WHERE r.id = t.{column})
+                ORDER BY
+                    t.id ASC
+            "
+        );
+        for row in client.query(stmt.as_str(), &[]).await? {
+            let missing: i64 = row.try_get(1)?;
+            issues.push(Issue {
+                check: "orphan_reference".to_string(),
+                table: table.to_string(),
+                id: row.try_get(0)?,
+                detail: format!("{column} points to missing {target} {missing}"),
+            });
+        }
+    }
+    for (table, first, second) in OWNED {
+        let stmt = format!(
+            "
+                SELECT
+                    id
+                FROM
+                    {table}
+                WHERE
+                    {first} IS NULL
+                AND
+                    {second} IS NULL
+                ORDER BY
+                    id ASC
+            "
+        );
+        for row in client.query(stmt.as_str(), &[]).await? {
+            issues.push(Issue {
+                check: "no_owner".to_string(),
+                table: table.to_string(),
+                id: row.try_get(0)?,
+                detail: format!("neither {first} nor {second} is set"),
+            });
+        }
+    }
+    // Attachments name their owner's table in a column of their own.
+    for owner in AttachmentOwner::ALL {
+        let target = owner.as_str();
+        let stmt = format!(
+            "
+                SELECT
+                    a.id,
+                    a.owner_id
+                FROM
+                    attachments AS a
+                WHERE
+                    a.owner_table = $1
+                AND
+                    NOT EXISTS (SELECT 1 FROM {target} AS r WHERE r.id = a.owner_id)
+                ORDER BY
+                    a.id ASC
+            "
+        );
+        for row in client.query(stmt.as_str(), &[&target]).await? {
+            let missing: i64 = row.try_get(1)?;
+            issues.push(Issue {
+                check: "orphan_reference".to_string(),
+                table: "attachments".to_string(),
+                id: row.try_get(0)?,
+                detail: format!("owner_id points to missing {target} {missing}"),
+            });
+        }
+    }
+    // Chunks lost or written past Attachment::upload leave a file that no
+    // longer matches its recorded size.
+    let stmt = "
+        SELECT
+            a.id,
+            a.size,
+            coalesce(sum(length(c.data)), 0)::bigint AS stored
+        FROM
+            attachments AS a
+        LEFT JOIN
+            attachment_chunks AS c ON c.attachment_id = a.id
+        GROUP BY
+            a.id
+        HAVING
+            a.size <> coalesce(sum(length(c.data)), 0)
+        ORDER BY
+            a.id ASC
+    ";
+    for row in client.query(stmt, &[]).await? {
+        let size: i64 = row.try_get(1)?;
+        let stored: i64 = row.try_get(2)?;
+        issues.push(Issue {
+            check: "attachment_size".to_string(),
+            table: "attachments".to_string(),
+            id: row.try_get(0)?,
+            detail: format!("size is {size} but {stored} bytes are stored"),
+        });
+    }
+    let stmt = "
+        SELECT
+            c.id,
+            c.department_id
+        FROM
+            contacts AS c
+        JOIN
+            departments AS d ON d.id = c.department_id
+        WHERE
+            d.company_id IS NOT NULL
+        AND
+            c.company_id IS DISTINCT FROM d.company_id
+        ORDER BY
+            c.id ASC
+    ";
+    for row in client.query(stmt, &[]).await? {
+        let department_id: i64 = row.try_get(1)?;
+        issues.push(Issue {
+            check: "foreign_department".to_string(),
+            table: "contacts".to_string(),
+            id: row.try_get(0)?,
+            detail: format!("department {department_id} belongs to another company"),
+        });
+    }
+    let stmt = "
+        SELECT
+            a.id,
+            a.street_id
+        FROM
+            addresses AS a
+        JOIN
+            streets AS t ON t.id = a.street_id
+        WHERE
+            a.settlement_id <> t.settlement_id
+        ORDER BY
+            a.id ASC
+    ";
+    for row in client.query(stmt, &[]).await? {
+        let street_id: i64 = row.try_get(1)?;
+        issues.push(Issue {
+            check: "foreign_street".to_string(),
+            table: "addresses".to_string(),
+            id: row.try_get(0)?,
+            detail: format!("street {street_id} lies in another settlement"),
+        });
+    }
+    // A loop in parent_id can only be written past Company::update, e.g. by
+    // hand, but it hides every company on it from the head organisations.
+    let stmt = "
+        WITH RECURSIVE chain AS (
+            SELECT
+                id AS start_id,
+                parent_id,
+                ARRAY[id] AS path,
+                false AS cycle
+            FROM
+                companies
+            WHERE
+                parent_id IS NOT NULL
+            UNION ALL
+            SELECT
+                ch.start_id,
+                c.parent_id,
+                ch.path || c.id,
+                c.id = ANY(ch.path)
+            FROM
+                companies AS c
+            JOIN
+                chain AS ch ON c.id = ch.parent_id
+            WHERE
+                NOT ch.cycle
+        )
+        SELECT DISTINCT
+            start_id
+        FROM
+            chain
+        WHERE
+            cycle
+        ORDER BY
+            start_id ASC
+    ";
+    for row in client.query(stmt, &[]).await? {
+        issues.push(Issue {
+            check: "company_cycle".to_string(),
+            table: "companies".to_string(),
+            id: row.try_get(0)?,
+            detail: "parent_id chain loops back on itself".to_string(),
+        });
+    }
+    Ok(issues)
+}
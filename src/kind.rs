@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
 pub struct Kind {
     #[serde(default)]
     pub id: i64,
@@ -12,10 +19,11 @@ pub struct Kind {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(Kind);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct KindList {
     pub id: i64,
@@ -30,9 +38,14 @@ impl Kind {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Kind, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Kind::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Kind, RpelError> {
+        traced("Kind", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -47,23 +60,33 @@ impl Kind {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let kind = Kind {
-            id,
-            name: row.try_get(0)?,
-            short_name: row.try_get(1)?,
-            note: row.try_get(2)?,
-            created_at: row.try_get(3)?,
-            updated_at: row.try_get(4)?,
-        };
-        Ok(kind)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("kinds".to_string(), id))?;
+            let kind = Kind {
+                id,
+                name: row.try_get(0)?,
+                short_name: row.try_get(1)?,
+                note: row.try_get(2)?,
+                created_at: row.try_get(3)?,
+                updated_at: row.try_get(4)?,
+            };
+            Ok(kind)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, kind: Kind) -> Result<Kind, RpelError> {
-        let mut kind = kind;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Kind::insert_with(&client, kind).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(client: &C, kind: Kind) -> Result<Kind, RpelError> {
+        traced("Kind", "insert", async move {
+            let mut kind = kind;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO kinds
                     (
@@ -86,26 +109,34 @@ impl Kind {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &kind.name,
-                    &kind.note,
-                    &kind.short_name,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        kind.id = row.get(0);
-        Ok(kind)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &kind.name,
+                        &kind.short_name,
+                        &kind.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            kind.id = row.get(0);
+            notify(client, "kinds", kind.id, Operation::Insert, 1).await?;
+            Ok(kind)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, kind: Kind) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Kind::update_with(&client, kind).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, kind: Kind) -> Result<u64, RpelError> {
+        traced("Kind", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE kinds SET
                         name = $2,
@@ -114,27 +145,40 @@ impl Kind {
                         updated_at = $5
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $6
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &kind.id,
-                    &kind.name,
-                    &kind.short_name,
-                    &kind.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &kind.id,
+                        &kind.name,
+                        &kind.short_name,
+                        &kind.note,
+                        &Local::now().naive_local(),
+                        &kind.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(client, "kinds", kind.id, kind.updated_at, result).await?;
+            notify(client, "kinds", kind.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Kind::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Kind", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         kinds
@@ -143,16 +187,25 @@ impl Kind {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "kinds", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl KindList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<KindList>, RpelError> {
-        let mut kinds = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        KindList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<KindList>, RpelError> {
+        traced("KindList", "get_all", async move {
+            let mut kinds = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -166,14 +219,16 @@ impl KindList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            kinds.push(KindList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                short_name: row.try_get(2)?,
-                note: row.try_get(3)?,
-            });
-        }
-        Ok(kinds)
+            for row in client.query(&stmt, &[]).await? {
+                kinds.push(KindList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    short_name: row.try_get(2)?,
+                    note: row.try_get(3)?,
+                });
+            }
+            Ok(kinds)
+        })
+        .await
     }
 }
@@ -1,58 +1,187 @@
-use deadpool_postgres::{Config, Pool, Runtime};
-use tokio_postgres::{config::Host, NoTls};
+use std::{
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
-use crate::error::RpelError;
+use chrono::NaiveDateTime;
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, Runtime};
+use tokio_postgres::{
+    config::SslMode,
+    tls::{MakeTlsConnect, TlsConnect},
+    NoTls, Socket,
+};
 
+use crate::{
+    error::RpelError,
+    settings::{PoolSettings, Settings},
+    tls::TlsOptions,
+};
+
+pub mod address;
+pub mod attachment;
 pub mod certificate;
 pub mod company;
 pub mod contact;
 pub mod department;
+pub mod duplicate;
 pub mod education;
 pub mod email;
+pub mod employment;
 pub mod error;
+pub mod export;
+pub mod health;
+pub mod integrity;
 pub mod kind;
+pub mod memory;
+pub mod metrics;
+pub mod migrate;
+pub mod notify;
+pub mod patch;
 pub mod phone;
 pub mod post;
 pub mod practice;
 pub mod rank;
+pub mod required_post;
+pub mod scenario;
 pub mod scope;
 pub mod select;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod settings;
+pub mod settlement;
 pub mod siren;
+pub mod siren_check;
 pub mod siren_type;
+pub mod snapshot;
+pub mod staffing;
+pub mod store;
+pub mod street;
 pub mod tcc;
+pub mod tls;
+pub mod trace;
 pub mod user;
+pub mod warning_zone;
+
+pub use deadpool_postgres::{GenericClient, Transaction};
 
 pub type RpelPool = Pool;
 
-fn option_str(value: Option<&str>) -> Option<String> {
-    value.map(|v| v.to_owned())
+pub type Work<'a, T> = Pin<Box<dyn Future<Output = Result<T, RpelError>> + Send + 'a>>;
+
+// Every connection is taken here, so a pool that is slow or fails to hand
+// one out shows up in the metrics even though no operation got to run.
+async fn acquire(pool: &RpelPool) -> Result<Object, RpelError> {
+    let started = Instant::now();
+    let client = pool.get().await.map_err(RpelError::from);
+    metrics::observe_acquire(started.elapsed(), client.as_ref().err());
+    client
 }
 
-fn get_config(pg_cfg: &str) -> Result<Config, RpelError> {
-    let tokio_cfg = pg_cfg.parse::<tokio_postgres::Config>()?;
-    let mut cfg = Config::new();
-    cfg.user = option_str(tokio_cfg.get_user());
-    cfg.password = tokio_cfg
-        .get_password()
-        .map(String::from_utf8_lossy)
-        .map(|cow| cow.to_string());
-    cfg.dbname = option_str(tokio_cfg.get_dbname());
-    cfg.options = option_str(tokio_cfg.get_options());
-    cfg.application_name = option_str(tokio_cfg.get_application_name());
-    // cfg.ssl_mode = tokio_cfg.get_ssl_mode();
-    cfg.host = tokio_cfg.get_hosts().first().map(|host| match host {
-        Host::Tcp(host) => host.to_string(),
-        #[cfg(unix)]
-        Host::Unix(buf) => buf.to_string_lossy().to_string(),
-    });
-    cfg.port = tokio_cfg.get_ports().first().cloned();
-    Ok(cfg)
+// Every update is guarded by `updated_at`: without it the write would silently
+// overwrite whatever was saved in between, so it is refused. A guarded update
+// touches no rows both when the record is gone and when someone else saved it
+// first; only the latter is a conflict.
+async fn check_version<C: GenericClient>(
+    client: &C,
+    table: &str,
+    id: i64,
+    updated_at: Option<NaiveDateTime>,
+    result: u64,
+) -> Result<u64, RpelError> {
+    if updated_at.is_none() {
+        return Err(RpelError::Invalid(format!(
+            "{table} {id} cannot be saved without updated_at"
+        )));
+    }
+    if result == 0 {
+        let stmt = format!("SELECT id FROM {table} WHERE id = $1");
+        if client.query_opt(stmt.as_str(), &[&id]).await?.is_some() {
+            return Err(RpelError::Conflict(table.to_string(), id));
+        }
+    }
+    Ok(result)
+}
+
+// The parsed connection string goes to the manager as is, so every setting it
+// carries (all hosts and ports, sslmode, target_session_attrs, timeouts and
+// keepalives) reaches the connections the pool opens.
+fn build_pool<T>(
+    pg_cfg: tokio_postgres::Config,
+    tls: T,
+    settings: &PoolSettings,
+) -> Result<RpelPool, RpelError>
+where
+    T: MakeTlsConnect<Socket> + Clone + Sync + Send + 'static,
+    T::Stream: Sync + Send,
+    T::TlsConnect: Sync + Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let manager = Manager::from_config(pg_cfg, tls, ManagerConfig::default());
+    let mut builder = Pool::builder(manager)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(settings.wait_timeout_ms.map(Duration::from_millis))
+        .create_timeout(settings.create_timeout_ms.map(Duration::from_millis))
+        .recycle_timeout(settings.recycle_timeout_ms.map(Duration::from_millis));
+    if let Some(max_size) = settings.max_size {
+        builder = builder.max_size(max_size);
+    }
+    Ok(builder.build()?)
+}
+
+// TLS is mandatory here: the default sslmode=prefer is raised to require so a
+// server without TLS is refused instead of silently used in plain text.
+fn require_tls(mut pg_cfg: tokio_postgres::Config) -> tokio_postgres::Config {
+    if pg_cfg.get_ssl_mode() == SslMode::Prefer {
+        pg_cfg.ssl_mode(SslMode::Require);
+    }
+    pg_cfg
 }
 
 pub fn get_pool(pg_cfg: &str) -> Result<RpelPool, RpelError> {
-    let cfg = get_config(pg_cfg)?;
-    let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls)?;
-    Ok(pool)
+    build_pool(pg_cfg.parse()?, NoTls, &PoolSettings::default())
+}
+
+pub fn get_pool_tls(pg_cfg: &str, tls: &TlsOptions) -> Result<RpelPool, RpelError> {
+    build_pool(
+        require_tls(pg_cfg.parse()?),
+        tls.connector()?,
+        &PoolSettings::default(),
+    )
+}
+
+// Applies the process-wide part of `settings`, the slow-query threshold.
+// Call it once at startup; building a pool leaves it alone.
+pub fn init(settings: &Settings) {
+    trace::set_slow_query_threshold(settings.slow_query_ms.map(Duration::from_millis));
+}
+
+pub fn get_pool_from_settings(settings: &Settings) -> Result<RpelPool, RpelError> {
+    let pg_cfg = settings.pg_config()?;
+    match &settings.tls {
+        Some(tls) => build_pool(require_tls(pg_cfg), tls.connector()?, &settings.pool),
+        None => build_pool(pg_cfg, NoTls, &settings.pool),
+    }
+}
+
+// Runs `work` inside one transaction on a pooled connection. The transaction is
+// committed when `work` succeeds and rolled back when it returns an error.
+pub async fn unit_of_work<T, F>(pool: &RpelPool, work: F) -> Result<T, RpelError>
+where
+    F: for<'a> FnOnce(&'a Transaction<'a>) -> Work<'a, T>,
+{
+    let mut client = acquire(pool).await?;
+    let tx = client.transaction().await?;
+    match work(&tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(err) => {
+            tx.rollback().await?;
+            Err(err)
+        }
+    }
 }
 
 #[cfg(test)]
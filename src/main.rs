@@ -0,0 +1,578 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use chrono::{Duration, Local, NaiveDate};
+use clap::{Parser, Subcommand};
+use futures_util::{stream, Stream, StreamExt};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+
+use rpel::{
+    address::Address,
+    attachment::{Attachment, CHUNK_SIZE},
+    education::EducationList,
+    error::RpelError,
+    get_pool_from_settings, init, integrity, migrate,
+    practice::PracticeList,
+    scenario::Scenario,
+    settings::Settings,
+    siren_check::SirenStatus,
+    snapshot::Snapshot,
+    staffing::Vacancy,
+    user::{User, UserList},
+    RpelPool,
+};
+
+const KEY_LEN: usize = 32;
+
+#[derive(Parser)]
+#[command(
+    name = "rpel",
+    version,
+    about = "Maintenance tasks for the rpel database"
+)]
+struct Cli {
+    /// Connection string; DATABASE_URL, RPEL_* and PG* variables are used when omitted
+    #[arg(long, global = true)]
+    database_url: Option<String>,
+    /// Settings file read before the environment
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// Print JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply pending migrations, or create the schema on an empty database
+    Migrate {
+        /// Only show which migrations are applied
+        #[arg(long, conflicts_with = "baseline")]
+        status: bool,
+        /// Record migrations up to VERSION as applied without running them
+        #[arg(long, value_name = "VERSION")]
+        baseline: Option<i64>,
+    },
+    /// Manage users
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Write a JSON snapshot of the whole database
+    Export {
+        /// Output file, stdout when omitted
+        #[arg(long, short)]
+        output: Option<String>,
+        /// Directory for the contents of attachments, one file per attachment id
+        #[arg(long, value_name = "DIR")]
+        files: Option<String>,
+        /// Include the API keys of users, which are left out otherwise
+        #[arg(long)]
+        with_keys: bool,
+    },
+    /// Restore a JSON snapshot into an empty database
+    Import {
+        file: String,
+        /// Directory holding the contents of attachments written by export
+        #[arg(long, value_name = "DIR")]
+        files: Option<String>,
+    },
+    /// List practices in the coming days
+    Practices {
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+    /// List educations starting in the coming days
+    Educations {
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+    /// List sirens with their last check
+    Sirens {
+        /// Only sirens never sounded by a test or an alert
+        #[arg(long, conflicts_with = "failed")]
+        untested: bool,
+        /// Only sirens whose last test or alert did not work
+        #[arg(long)]
+        failed: bool,
+    },
+    /// Print the duty checklist of a scenario: its sirens and whom to call
+    Checklist { scenario: i64 },
+    /// List required civil defence posts nobody holds
+    Vacancies {
+        /// Only companies without a head of civil defence
+        #[arg(long)]
+        heads: bool,
+    },
+    /// Parse free-text addresses into structured ones; exits with 2 when some fail
+    Addresses {
+        /// Store the parsed addresses instead of only reporting the failures
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Run the integrity checks; exits with 2 when problems are found
+    Check,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// List users
+    List,
+    /// Create a user, generating a key unless one is given
+    Add {
+        name: String,
+        /// 0 may only read, any other role may also write
+        #[arg(long)]
+        role: i64,
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Replace the key of a user, generating one unless it is given
+    ResetKey {
+        name: String,
+        #[arg(long)]
+        key: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct Report {
+    message: String,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("rpel: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, RpelError> {
+    let mut settings = Settings::load(cli.config.as_deref())?;
+    if cli.database_url.is_some() {
+        settings.database_url = cli.database_url;
+    }
+    init(&settings);
+    let pool = get_pool_from_settings(&settings)?;
+    let out = Output { json: cli.json };
+    match cli.command {
+        Command::Migrate { status: true, .. } => {
+            let migrations = migrate::status(&pool).await?;
+            let rows = migrations
+                .iter()
+                .map(|m| {
+                    let applied = match (m.applied, m.applied_at) {
+                        (_, Some(at)) => at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        (true, None) => "yes".to_string(),
+                        (false, None) => "pending".to_string(),
+                    };
+                    vec![m.version.to_string(), m.name.clone(), applied]
+                })
+                .collect();
+            out.table(&migrations, &["VERSION", "NAME", "APPLIED"], rows)?;
+        }
+        Command::Migrate {
+            baseline: Some(version),
+            ..
+        } => {
+            let versions = migrate::baseline(&pool, version).await?;
+            out.message(format!("marked as applied: {}", join(&versions)))?;
+        }
+        Command::Migrate { .. } => {
+            let versions = migrate::run(&pool).await?;
+            out.message(format!("applied: {}", join(&versions)))?;
+        }
+        Command::User(command) => return user(&pool, &out, command).await,
+        Command::Export {
+            output,
+            files,
+            with_keys,
+        } => {
+            let snapshot = Snapshot::export(&pool, with_keys).await?;
+            let attachments = snapshot.attachment_ids();
+            if !attachments.is_empty() {
+                let Some(dir) = files else {
+                    eprintln!(
+                        "rpel: the database has {} attachments, give --files to export their contents",
+                        attachments.len()
+                    );
+                    return Ok(ExitCode::FAILURE);
+                };
+                fs::create_dir_all(&dir)?;
+                for id in attachments {
+                    let (_, mut chunks) = Attachment::content(&pool, id).await?;
+                    let mut file = fs::File::create(Path::new(&dir).join(id.to_string()))?;
+                    while let Some(chunk) = chunks.next().await {
+                        file.write_all(&chunk?)?;
+                    }
+                }
+            }
+            let data = serde_json::to_vec_pretty(&snapshot)?;
+            match output {
+                Some(path) => fs::write(path, data)?,
+                None => std::io::stdout().write_all(&data)?,
+            }
+        }
+        Command::Import { file, files } => {
+            let snapshot: Snapshot = serde_json::from_slice(&fs::read(file)?)?;
+            let attachments = snapshot.attachment_ids();
+            if !attachments.is_empty() && files.is_none() {
+                eprintln!(
+                    "rpel: the snapshot has {} attachments, give --files with their contents",
+                    attachments.len()
+                );
+                return Ok(ExitCode::FAILURE);
+            }
+            let restored = Snapshot::restore(&pool, &snapshot).await?;
+            if let Some(dir) = files {
+                for &id in &attachments {
+                    let chunks = file_chunks(&Path::new(&dir).join(id.to_string()))?;
+                    Attachment::restore_content(&pool, id, chunks).await?;
+                }
+            }
+            out.message(format!(
+                "restored {restored} rows and {} attachments",
+                attachments.len()
+            ))?;
+        }
+        Command::Practices { days } => {
+            let (from, to) = window(days);
+            let mut practices: Vec<PracticeList> = PracticeList::get_all(&pool)
+                .await?
+                .into_iter()
+                .filter(|p| p.date_of_practice.is_some_and(|d| d >= from && d <= to))
+                .collect();
+            practices.sort_by_key(|p| p.date_of_practice);
+            let rows = practices
+                .iter()
+                .map(|p| {
+                    vec![
+                        text(&p.date_of_practice),
+                        text(&p.kind_short_name),
+                        text(&p.company_name),
+                        text(&p.topic),
+                    ]
+                })
+                .collect();
+            out.table(&practices, &["DATE", "KIND", "COMPANY", "TOPIC"], rows)?;
+        }
+        Command::Educations { days } => {
+            let (from, to) = window(days);
+            let mut educations: Vec<EducationList> = EducationList::get_all(&pool)
+                .await?
+                .into_iter()
+                .filter(|e| e.start_date.is_some_and(|d| d >= from && d <= to))
+                .collect();
+            educations.sort_by_key(|e| e.start_date);
+            let rows = educations
+                .iter()
+                .map(|e| {
+                    vec![
+                        text(&e.start_date),
+                        text(&e.end_date),
+                        text(&e.contact_name),
+                        text(&e.post_name),
+                    ]
+                })
+                .collect();
+            out.table(&educations, &["START", "END", "CONTACT", "POST"], rows)?;
+        }
+        Command::Sirens { untested, failed } => {
+            let sirens = if untested {
+                SirenStatus::get_untested(&pool).await?
+            } else if failed {
+                SirenStatus::get_failed(&pool).await?
+            } else {
+                SirenStatus::get_all(&pool).await?
+            };
+            let rows = sirens
+                .iter()
+                .map(|s| {
+                    vec![
+                        text(&s.num_id),
+                        text(&s.address),
+                        text(&s.last_check),
+                        s.last_kind
+                            .map(|k| k.as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                        text(&s.last_test),
+                        s.last_test_result
+                            .map(|r| r.as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                    ]
+                })
+                .collect();
+            out.table(
+                &sirens,
+                &["NUM", "ADDRESS", "CHECKED", "KIND", "TESTED", "RESULT"],
+                rows,
+            )?;
+        }
+        Command::Checklist { scenario } => {
+            let items = Scenario::checklist(&pool, scenario).await?;
+            let rows = items
+                .iter()
+                .map(|i| {
+                    vec![
+                        text(&i.num_id),
+                        text(&i.address),
+                        i.zone_names.join(", "),
+                        text(&i.contact_name),
+                        i.phones
+                            .iter()
+                            .map(i64::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    ]
+                })
+                .collect();
+            out.table(
+                &items,
+                &["NUM", "ADDRESS", "ZONES", "CONTACT", "PHONES"],
+                rows,
+            )?;
+        }
+        Command::Vacancies { heads } => {
+            let vacancies = if heads {
+                Vacancy::get_heads(&pool).await?
+            } else {
+                Vacancy::get_all(&pool).await?
+            };
+            let rows = vacancies
+                .iter()
+                .map(|v| {
+                    vec![
+                        text(&v.company_name),
+                        text(&v.scope_name),
+                        text(&v.post_go_name),
+                    ]
+                })
+                .collect();
+            out.table(&vacancies, &["COMPANY", "SCOPE", "POST"], rows)?;
+        }
+        Command::Addresses { apply } => {
+            let report = Address::parse_all(&pool, apply).await?;
+            if out.json {
+                out.print_json(&report)?;
+            } else {
+                let rows = report
+                    .failures
+                    .iter()
+                    .map(|f| {
+                        vec![
+                            f.table.clone(),
+                            f.id.to_string(),
+                            f.address.clone(),
+                            f.reason.clone(),
+                        ]
+                    })
+                    .collect();
+                out.table(
+                    &report.failures,
+                    &["TABLE", "ID", "ADDRESS", "REASON"],
+                    rows,
+                )?;
+                let stored = if apply { " and stored" } else { "" };
+                out.message(format!(
+                    "{} parsed{stored}, {} failed",
+                    report.parsed,
+                    report.failures.len()
+                ))?;
+            }
+            if !report.failures.is_empty() {
+                return Ok(ExitCode::from(2));
+            }
+        }
+        Command::Check => {
+            let issues = integrity::check(&pool).await?;
+            let rows = issues
+                .iter()
+                .map(|i| {
+                    vec![
+                        i.check.clone(),
+                        i.table.clone(),
+                        i.id.to_string(),
+                        i.detail.clone(),
+                    ]
+                })
+                .collect();
+            out.table(&issues, &["CHECK", "TABLE", "ID", "DETAIL"], rows)?;
+            if !issues.is_empty() {
+                return Ok(ExitCode::from(2));
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn user(pool: &RpelPool, out: &Output, command: UserCommand) -> Result<ExitCode, RpelError> {
+    match command {
+        UserCommand::List => {
+            let users = UserList::get_all(pool).await?;
+            let rows = users
+                .iter()
+                .map(|u| vec![u.id.to_string(), u.name.clone(), u.role.to_string()])
+                .collect();
+            out.table(&users, &["ID", "NAME", "ROLE"], rows)?;
+        }
+        UserCommand::Add { name, role, key } => {
+            let key = key.unwrap_or_else(new_key);
+            if User::get_by_key(pool, &key).await?.is_some() {
+                eprintln!("rpel: another user already has this key");
+                return Ok(ExitCode::FAILURE);
+            }
+            let user = User::insert(
+                pool,
+                User {
+                    id: 0,
+                    name,
+                    key,
+                    role,
+                    created_at: None,
+                    updated_at: None,
+                },
+            )
+            .await?;
+            out.user(&user)?;
+        }
+        UserCommand::ResetKey { name, key } => {
+            let Some(mut user) = User::get_by_name(pool, &name).await? else {
+                eprintln!("rpel: no user named {name:?}");
+                return Ok(ExitCode::FAILURE);
+            };
+            let id = user.id;
+            user.key = key.unwrap_or_else(new_key);
+            if User::get_by_key(pool, &user.key)
+                .await?
+                .is_some_and(|other| other.id != id)
+            {
+                eprintln!("rpel: another user already has this key");
+                return Ok(ExitCode::FAILURE);
+            }
+            User::update(pool, user).await?;
+            out.user(&User::get(pool, id).await?)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn new_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LEN)
+        .map(char::from)
+        .collect()
+}
+
+// Reads a file a chunk at a time rather than all at once.
+fn file_chunks(
+    path: &Path,
+) -> Result<impl Stream<Item = Result<Vec<u8>, RpelError>> + Send + Unpin, RpelError> {
+    let mut file = fs::File::open(path)?;
+    Ok(stream::iter(std::iter::from_fn(move || {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        match file.read(&mut buffer) {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some(Ok(buffer))
+            }
+            Err(err) => Some(Err(err.into())),
+        }
+    })))
+}
+
+fn window(days: i64) -> (NaiveDate, NaiveDate) {
+    let today = Local::now().date_naive();
+    (today, today + Duration::days(days))
+}
+
+fn text<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+fn join(versions: &[i64]) -> String {
+    if versions.is_empty() {
+        return "nothing".to_string();
+    }
+    versions
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn print_json<T: Serialize + ?Sized>(&self, value: &T) -> Result<(), RpelError> {
+        println!("{}", serde_json::to_string_pretty(value)?);
+        Ok(())
+    }
+
+    fn message(&self, message: String) -> Result<(), RpelError> {
+        if self.json {
+            self.print_json(&Report { message })
+        } else {
+            println!("{message}");
+            Ok(())
+        }
+    }
+
+    fn user(&self, user: &User) -> Result<(), RpelError> {
+        if self.json {
+            self.print_json(user)
+        } else {
+            println!(
+                "{} (id {}, role {}): key {}",
+                user.name, user.id, user.role, user.key
+            );
+            Ok(())
+        }
+    }
+
+    // Text output pads every column to its widest cell.
+    fn table<T: Serialize>(
+        &self,
+        values: &[T],
+        headers: &[&str],
+        rows: Vec<Vec<String>>,
+    ) -> Result<(), RpelError> {
+        if self.json {
+            return self.print_json(values);
+        }
+        let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let line = |cells: Vec<&str>| {
+            let padded: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            println!("{}", padded.join("  ").trim_end());
+        };
+        line(headers.to_vec());
+        for row in &rows {
+            line(row.iter().map(String::as_str).collect());
+        }
+        Ok(())
+    }
+}
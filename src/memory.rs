@@ -0,0 +1,3100 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{Local, Months, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{
+    address::{check_owner, parse_owners, Address, AddressList, AddressReport, Gazetteer},
+    attachment::{check_attachment, Attachment, AttachmentList, AttachmentOwner, Checksum},
+    certificate::{Certificate, CertificateList, CertificatePatch},
+    company::{Company, CompanyList, CompanyNode, CompanyPatch, CompanyRollup},
+    contact::{Contact, ContactList, ContactMerge, ContactPatch, ContactShort},
+    department::{Department, DepartmentList},
+    duplicate::{self, normalize_email, DuplicateCandidate, Person},
+    education::{Education, EducationList, EducationPatch, EducationShort},
+    employment::{check_dates, Employment, EmploymentList},
+    error::RpelError,
+    kind::{Kind, KindList},
+    post::{Post, PostList},
+    practice::{Practice, PracticeList, PracticePatch, PracticeShort},
+    rank::{Rank, RankList},
+    required_post::{RequiredPost, RequiredPostList},
+    scenario::{ChecklistItem, Scenario, ScenarioList},
+    scope::{Scope, ScopeList},
+    select::SelectItem,
+    settlement::{Settlement, SettlementList},
+    siren::{Siren, SirenList, SirenPatch},
+    siren_check::{CheckResult, SirenCheck, SirenCheckList, SirenStatus},
+    siren_type::{SirenType, SirenTypeList},
+    staffing::{Staffing, StaffingPost, Vacancy},
+    street::{Street, StreetList},
+    user::{User, UserList},
+    warning_zone::{WarningZone, WarningZoneList},
+};
+
+trait Record: Clone {
+    const TABLE: &'static str;
+    fn id(&self) -> i64;
+    fn set_id(&mut self, id: i64);
+    fn created_at(&self) -> Option<NaiveDateTime>;
+    fn updated_at(&self) -> Option<NaiveDateTime>;
+    fn stamp(&mut self, created_at: Option<NaiveDateTime>, updated_at: NaiveDateTime);
+}
+
+macro_rules! record {
+    ($($entity:ty => $table:literal),* $(,)?) => {
+        $(impl Record for $entity {
+            const TABLE: &'static str = $table;
+
+            fn id(&self) -> i64 {
+                self.id
+            }
+
+            fn set_id(&mut self, id: i64) {
+                self.id = id;
+            }
+
+            fn created_at(&self) -> Option<NaiveDateTime> {
+                self.created_at
+            }
+
+            fn updated_at(&self) -> Option<NaiveDateTime> {
+                self.updated_at
+            }
+
+            fn stamp(&mut self, created_at: Option<NaiveDateTime>, updated_at: NaiveDateTime) {
+                self.created_at = created_at;
+                self.updated_at = Some(updated_at);
+            }
+        })*
+    };
+}
+
+record!(
+    Address => "addresses",
+    Attachment => "attachments",
+    Certificate => "certificates",
+    Company => "companies",
+    Contact => "contacts",
+    Department => "departments",
+    Education => "educations",
+    Employment => "employments",
+    Kind => "kinds",
+    Post => "posts",
+    Practice => "practices",
+    Rank => "ranks",
+    RequiredPost => "required_posts",
+    Scenario => "scenarios",
+    Scope => "scopes",
+    Settlement => "settlements",
+    Siren => "sirens",
+    SirenCheck => "siren_checks",
+    SirenType => "siren_types",
+    Street => "streets",
+    User => "users",
+    WarningZone => "warning_zones",
+);
+
+// Copies the fields a patch carries, leaving the ones it left out alone.
+macro_rules! apply {
+    ($row:expr, $patch:expr, $($field:ident),*) => {
+        $(if let Some(value) = $patch.$field {
+            $row.$field = value;
+        })*
+    };
+}
+
+struct Table<R> {
+    rows: BTreeMap<i64, R>,
+    last_id: i64,
+}
+
+impl<R> Default for Table<R> {
+    fn default() -> Self {
+        Table {
+            rows: BTreeMap::new(),
+            last_id: 0,
+        }
+    }
+}
+
+impl<R: Record> Table<R> {
+    fn get(&self, id: i64) -> Result<R, RpelError> {
+        self.rows
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| RpelError::NotFound(R::TABLE.to_string(), id))
+    }
+
+    fn find(&self, id: Option<i64>) -> Option<&R> {
+        id.and_then(|id| self.rows.get(&id))
+    }
+
+    // Ids come from a sequence, so like bigserial they are never reused.
+    fn insert(&mut self, mut row: R) -> i64 {
+        self.last_id += 1;
+        let now = now();
+        row.set_id(self.last_id);
+        row.stamp(Some(now), now);
+        self.rows.insert(self.last_id, row);
+        self.last_id
+    }
+
+    fn update(&mut self, mut row: R) -> Result<u64, RpelError> {
+        let id = row.id();
+        let version = row.updated_at();
+        self.patch(id, version, |stored| {
+            row.stamp(stored.created_at(), now());
+            *stored = row;
+        })
+    }
+
+    // Like the guarded UPDATE, a write without the version it was based on is
+    // refused rather than let through.
+    fn patch(
+        &mut self,
+        id: i64,
+        version: Option<NaiveDateTime>,
+        apply: impl FnOnce(&mut R),
+    ) -> Result<u64, RpelError> {
+        if version.is_none() {
+            return Err(RpelError::Invalid(format!(
+                "{} {id} cannot be saved without updated_at",
+                R::TABLE
+            )));
+        }
+        match self.rows.get(&id) {
+            Some(stored) if stored.updated_at() != version => {
+                Err(RpelError::Conflict(R::TABLE.to_string(), id))
+            }
+            _ => Ok(self.overwrite(id, apply)),
+        }
+    }
+
+    // Unguarded write for changes the store makes on its own behalf, such as
+    // reparenting children or closing an employment period.
+    fn overwrite(&mut self, id: i64, apply: impl FnOnce(&mut R)) -> u64 {
+        let Some(stored) = self.rows.get_mut(&id) else {
+            return 0;
+        };
+        apply(stored);
+        stored.stamp(stored.created_at(), now());
+        1
+    }
+
+    fn delete(&mut self, id: i64) -> u64 {
+        self.rows.remove(&id).map_or(0, |_| 1)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &R> {
+        self.rows.values()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Owner {
+    Contact(i64),
+    Company(i64),
+}
+
+struct PhoneRow {
+    owner: Owner,
+    phone: i64,
+    fax: bool,
+}
+
+struct EmailRow {
+    owner: Owner,
+    email: String,
+}
+
+#[derive(Default)]
+struct Tables {
+    addresses: Table<Address>,
+    attachments: Table<Attachment>,
+    // File contents by attachment id, kept apart like `attachment_chunks`.
+    attachment_data: BTreeMap<i64, Vec<u8>>,
+    certificates: Table<Certificate>,
+    companies: Table<Company>,
+    contacts: Table<Contact>,
+    departments: Table<Department>,
+    educations: Table<Education>,
+    employments: Table<Employment>,
+    kinds: Table<Kind>,
+    posts: Table<Post>,
+    practices: Table<Practice>,
+    ranks: Table<Rank>,
+    required_posts: Table<RequiredPost>,
+    scenarios: Table<Scenario>,
+    scopes: Table<Scope>,
+    settlements: Table<Settlement>,
+    sirens: Table<Siren>,
+    siren_checks: Table<SirenCheck>,
+    siren_types: Table<SirenType>,
+    streets: Table<Street>,
+    users: Table<User>,
+    warning_zones: Table<WarningZone>,
+    phones: Vec<PhoneRow>,
+    emails: Vec<EmailRow>,
+}
+
+impl Tables {
+    fn owner_exists(&self, owner: AttachmentOwner, id: i64) -> bool {
+        match owner {
+            AttachmentOwner::Certificates => self.certificates.rows.contains_key(&id),
+            AttachmentOwner::Practices => self.practices.rows.contains_key(&id),
+            AttachmentOwner::Companies => self.companies.rows.contains_key(&id),
+            AttachmentOwner::Sirens => self.sirens.rows.contains_key(&id),
+            AttachmentOwner::Educations => self.educations.rows.contains_key(&id),
+        }
+    }
+
+    fn delete_attachments(&mut self, owner: AttachmentOwner, owner_id: i64) {
+        let ids: Vec<i64> = self
+            .attachments
+            .values()
+            .filter(|a| a.owner == owner && a.owner_id == owner_id)
+            .map(|a| a.id)
+            .collect();
+        for id in ids {
+            self.attachments.delete(id);
+            self.attachment_data.remove(&id);
+        }
+    }
+
+    fn phones(&self, owner: Owner, fax: bool) -> Vec<i64> {
+        self.phones
+            .iter()
+            .filter(|row| row.owner == owner && row.fax == fax)
+            .map(|row| row.phone)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn emails(&self, owner: Owner) -> Vec<String> {
+        self.emails
+            .iter()
+            .filter(|row| row.owner == owner)
+            .map(|row| row.email.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn replace_phones(&mut self, owner: Owner, fax: bool, phones: Vec<i64>) {
+        self.phones
+            .retain(|row| row.owner != owner || row.fax != fax || phones.contains(&row.phone));
+        for phone in phones.into_iter().collect::<BTreeSet<_>>() {
+            let exists = self
+                .phones
+                .iter()
+                .any(|row| row.owner == owner && row.fax == fax && row.phone == phone);
+            if !exists {
+                self.phones.push(PhoneRow { owner, phone, fax });
+            }
+        }
+    }
+
+    fn replace_emails(&mut self, owner: Owner, emails: Vec<String>) {
+        self.emails
+            .retain(|row| row.owner != owner || emails.contains(&row.email));
+        for email in emails.into_iter().collect::<BTreeSet<_>>() {
+            let exists = self
+                .emails
+                .iter()
+                .any(|row| row.owner == owner && row.email == email);
+            if !exists {
+                self.emails.push(EmailRow { owner, email });
+            }
+        }
+    }
+
+    fn delete_phones(&mut self, owner: Owner, fax: bool) -> u64 {
+        let before = self.phones.len();
+        self.phones
+            .retain(|row| row.owner != owner || row.fax != fax);
+        (before - self.phones.len()) as u64
+    }
+
+    fn delete_emails(&mut self, owner: Owner) -> u64 {
+        let before = self.emails.len();
+        self.emails.retain(|row| row.owner != owner);
+        (before - self.emails.len()) as u64
+    }
+
+    fn company_name(&self, id: Option<i64>) -> Option<String> {
+        self.companies.find(id).and_then(|c| c.name.clone())
+    }
+
+    fn children(&self, id: i64) -> impl Iterator<Item = &Company> {
+        self.companies
+            .values()
+            .filter(move |c| c.parent_id == Some(id))
+    }
+
+    // The company first and then its branches, each after its parent and
+    // siblings by id, in the order of the Postgres walk.
+    fn subtree(&self, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        let mut stack = vec![(self.companies.get(id)?, 0)];
+        let mut seen = BTreeSet::new();
+        let mut nodes = Vec::new();
+        while let Some((company, depth)) = stack.pop() {
+            if !seen.insert(company.id) {
+                continue;
+            }
+            let children: Vec<&Company> = self.children(company.id).collect();
+            stack.extend(children.into_iter().rev().map(|c| (c.clone(), depth + 1)));
+            nodes.push(company_node(&company, depth));
+        }
+        Ok(nodes)
+    }
+
+    // The company first and then its parent, grandparent and so on.
+    fn ancestors(&self, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        let mut nodes = vec![company_node(&self.companies.get(id)?, 0)];
+        let mut seen = BTreeSet::from([id]);
+        while let Some(parent) = self.companies.find(nodes[nodes.len() - 1].parent_id) {
+            if !seen.insert(parent.id) {
+                break;
+            }
+            nodes.push(company_node(parent, nodes.len() as i32));
+        }
+        Ok(nodes)
+    }
+
+    fn check_parent(&self, id: Option<i64>, parent_id: Option<i64>) -> Result<(), RpelError> {
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        if id == Some(parent_id) {
+            return Err(RpelError::Invalid(format!(
+                "company {parent_id} cannot be its own parent"
+            )));
+        }
+        let Ok(ancestors) = self.ancestors(parent_id) else {
+            return Err(RpelError::Invalid(format!(
+                "parent company {parent_id} does not exist"
+            )));
+        };
+        if let Some(id) = id.filter(|id| ancestors.iter().any(|node| node.id == *id)) {
+            return Err(RpelError::Invalid(format!(
+                "company {parent_id} is a branch of company {id}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_department_parent(
+        &self,
+        id: Option<i64>,
+        company_id: Option<i64>,
+        parent_id: Option<i64>,
+    ) -> Result<(), RpelError> {
+        let Some(parent_id) = parent_id else {
+            return Ok(());
+        };
+        if id == Some(parent_id) {
+            return Err(RpelError::Invalid(format!(
+                "department {parent_id} cannot be its own parent"
+            )));
+        }
+        let Some(parent) = self.departments.find(Some(parent_id)) else {
+            return Err(RpelError::Invalid(format!(
+                "parent department {parent_id} does not exist"
+            )));
+        };
+        if parent.company_id != company_id {
+            return Err(RpelError::Invalid(format!(
+                "parent department {parent_id} belongs to another company"
+            )));
+        }
+        let mut seen = BTreeSet::new();
+        let mut next = Some(parent);
+        while let Some(department) = next.filter(|d| seen.insert(d.id)) {
+            if Some(department.id) == id {
+                return Err(RpelError::Invalid(format!(
+                    "department {parent_id} is nested in department {}",
+                    department.id
+                )));
+            }
+            next = self.departments.find(department.parent_id);
+        }
+        Ok(())
+    }
+
+    // Mirrors check_unused in department.rs.
+    fn check_department_unused(&self, id: i64) -> Result<(), RpelError> {
+        match self.contacts.values().find(|c| c.department_id == Some(id)) {
+            Some(contact) => Err(RpelError::Invalid(format!(
+                "contact {} is in department {id}",
+                contact.id
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Mirrors check_move in department.rs.
+    fn check_department_move(&self, id: i64, company_id: Option<i64>) -> Result<(), RpelError> {
+        let Some(stored) = self.departments.find(Some(id)) else {
+            return Ok(());
+        };
+        if stored.company_id == company_id {
+            return Ok(());
+        }
+        if let Some(child) = self.departments.values().find(|d| d.parent_id == Some(id)) {
+            return Err(RpelError::Invalid(format!(
+                "department {} is nested in department {id}",
+                child.id
+            )));
+        }
+        self.check_department_unused(id)
+    }
+
+    fn check_department(
+        &self,
+        company_id: Option<i64>,
+        department_id: Option<i64>,
+    ) -> Result<(), RpelError> {
+        let Some(department_id) = department_id else {
+            return Ok(());
+        };
+        let Some(department) = self.departments.find(Some(department_id)) else {
+            return Err(RpelError::Invalid(format!(
+                "department {department_id} does not exist"
+            )));
+        };
+        if department.company_id.is_some() && department.company_id != company_id {
+            return Err(RpelError::Invalid(format!(
+                "department {department_id} belongs to another company"
+            )));
+        }
+        Ok(())
+    }
+
+    // "Logistics / Warehouse" for a department nested in another of the same
+    // company; None when the chain loops, which the Postgres walk skips too.
+    fn department_path(&self, department: &Department) -> Option<Option<String>> {
+        let mut names = vec![department.name.clone()];
+        let mut seen = BTreeSet::from([department.id]);
+        let mut parent = self
+            .departments
+            .find(department.parent_id)
+            .filter(|p| p.company_id == department.company_id);
+        while let Some(p) = parent {
+            if !seen.insert(p.id) {
+                return None;
+            }
+            names.push(p.name.clone());
+            parent = self
+                .departments
+                .find(p.parent_id)
+                .filter(|pp| pp.company_id == department.company_id);
+        }
+        let names: Vec<String> = names.into_iter().rev().flatten().collect();
+        Some((!names.is_empty()).then(|| names.join(" / ")))
+    }
+
+    // Mirrors Employment::sync_with.
+    fn sync_employment(&mut self, contact_id: i64, date: NaiveDate) {
+        let Some(contact) = self.contacts.find(Some(contact_id)) else {
+            return;
+        };
+        let current = Employment {
+            contact_id: Some(contact_id),
+            company_id: contact.company_id,
+            department_id: contact.department_id,
+            post_id: contact.post_id,
+            post_go_id: contact.post_go_id,
+            rank_id: contact.rank_id,
+            date_from: Some(date),
+            ..Default::default()
+        };
+        let position = |e: &Employment| {
+            (
+                e.company_id,
+                e.department_id,
+                e.post_id,
+                e.post_go_id,
+                e.rank_id,
+            )
+        };
+        let open = self
+            .employments
+            .values()
+            .filter(|e| e.contact_id == Some(contact_id) && e.date_to.is_none())
+            .max_by_key(|e| (e.date_from, e.id))
+            .map(|e| (e.id, e.date_from, position(e)));
+        if let Some((id, date_from, held)) = open {
+            if held == position(&current) {
+                return;
+            }
+            if date_from == Some(date) {
+                self.employments.delete(id);
+            } else {
+                self.employments
+                    .overwrite(id, |row| row.date_to = Some(date));
+            }
+        }
+        if position(&current) != Default::default() {
+            self.employments.insert(current);
+        }
+    }
+
+    fn employment_list(&self, e: &Employment) -> EmploymentList {
+        EmploymentList {
+            id: e.id,
+            contact_id: e.contact_id,
+            contact_name: self.contact_name(e.contact_id),
+            company_id: e.company_id,
+            company_name: self.company_name(e.company_id),
+            department_name: self
+                .departments
+                .find(e.department_id)
+                .and_then(|d| d.name.clone()),
+            post_name: self.post_name(e.post_id, Some(false)),
+            post_go_name: self.post_name(e.post_go_id, Some(true)),
+            rank_name: self.ranks.find(e.rank_id).and_then(|r| r.name.clone()),
+            date_from: e.date_from,
+            date_to: e.date_to,
+            note: e.note.clone(),
+        }
+    }
+
+    fn employments_where(&self, keep: impl Fn(&Employment) -> bool) -> Vec<EmploymentList> {
+        let mut employments: Vec<&Employment> =
+            self.employments.values().filter(|e| keep(e)).collect();
+        // NULLS FIRST, as the Postgres list orders them.
+        employments.sort_by_key(|e| (e.date_from, e.id));
+        employments
+            .into_iter()
+            .map(|e| self.employment_list(e))
+            .collect()
+    }
+
+    fn rollup(&self, company: &Company) -> CompanyRollup {
+        let ids: BTreeSet<Option<i64>> = self
+            .subtree(company.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|node| Some(node.id))
+            .collect();
+        let today = now().date();
+        let dates: Vec<NaiveDate> = self
+            .practices
+            .values()
+            .filter(|p| ids.contains(&p.company_id))
+            .filter_map(|p| p.date_of_practice)
+            .collect();
+        CompanyRollup {
+            id: company.id,
+            name: company.name.clone(),
+            companies: ids.len() as i64,
+            contacts: self
+                .contacts
+                .values()
+                .filter(|c| ids.contains(&c.company_id))
+                .count() as i64,
+            practices: self
+                .practices
+                .values()
+                .filter(|p| ids.contains(&p.company_id))
+                .count() as i64,
+            sirens: self
+                .sirens
+                .values()
+                .filter(|s| ids.contains(&s.company_id))
+                .count() as i64,
+            last_practice: dates.iter().filter(|d| **d <= today).max().copied(),
+            next_practice: dates.iter().filter(|d| **d > today).min().copied(),
+        }
+    }
+
+    // Mirrors Staffing::get_with.
+    fn staffing(&self, company: &Company) -> Staffing {
+        let mut heads: BTreeMap<i64, bool> = BTreeMap::new();
+        for r in self
+            .required_posts
+            .values()
+            .filter(|r| r.scope_id.is_none() || r.scope_id == company.scope_id)
+        {
+            if let Some(post_go_id) = r.post_go_id {
+                *heads.entry(post_go_id).or_default() |= r.head;
+            }
+        }
+        let mut required: Vec<StaffingPost> = heads
+            .into_iter()
+            .map(|(post_go_id, head)| StaffingPost {
+                post_go_id,
+                post_go_name: self.post_name(Some(post_go_id), None),
+                required: true,
+                head,
+                holders: Vec::new(),
+            })
+            .collect();
+        required.sort_by(|a, b| {
+            b.head
+                .cmp(&a.head)
+                .then(asc(&a.post_go_name, &b.post_go_name))
+                .then(a.post_go_id.cmp(&b.post_go_id))
+        });
+        let mut holders: Vec<(i64, Option<String>, SelectItem)> = self
+            .contacts
+            .values()
+            .filter(|c| c.company_id == Some(company.id))
+            .filter_map(|c| {
+                let post_go_id = c.post_go_id?;
+                let holder = SelectItem {
+                    id: c.id,
+                    name: c.name.clone(),
+                };
+                Some((post_go_id, self.post_name(c.post_go_id, Some(true)), holder))
+            })
+            .collect();
+        holders.sort_by(|a, b| {
+            asc(&a.1, &b.1)
+                .then(a.0.cmp(&b.0))
+                .then(asc(&a.2.name, &b.2.name))
+                .then(a.2.id.cmp(&b.2.id))
+        });
+        let scope_name = self
+            .scopes
+            .find(company.scope_id)
+            .and_then(|s| s.name.clone());
+        Staffing::new(
+            (company.id, company.name.clone(), scope_name),
+            required,
+            holders,
+        )
+    }
+
+    fn vacancies(&self, keep: impl Fn(&StaffingPost) -> bool) -> Vec<Vacancy> {
+        let mut companies: Vec<&Company> = self.companies.values().collect();
+        companies.sort_by(|a, b| asc(&a.name, &b.name).then(a.id.cmp(&b.id)));
+        companies
+            .into_iter()
+            .flat_map(|company| {
+                let staffing = self.staffing(company);
+                staffing
+                    .posts
+                    .into_iter()
+                    .filter(|p| p.required && p.holders.is_empty() && keep(p))
+                    .map(move |p| Vacancy {
+                        company_id: staffing.company_id,
+                        company_name: staffing.company_name.clone(),
+                        scope_name: staffing.scope_name.clone(),
+                        post_go_id: p.post_go_id,
+                        post_go_name: p.post_go_name,
+                        head: p.head,
+                    })
+            })
+            .collect()
+    }
+
+    fn siren_checks_where(&self, keep: impl Fn(&SirenCheck) -> bool) -> Vec<SirenCheckList> {
+        let mut checks: Vec<&SirenCheck> = self.siren_checks.values().filter(|c| keep(c)).collect();
+        checks.sort_by_key(|c| Reverse((c.check_date, c.id)));
+        checks
+            .into_iter()
+            .map(|c| SirenCheckList {
+                id: c.id,
+                siren_id: c.siren_id,
+                siren_address: self.sirens.find(c.siren_id).and_then(|s| s.address.clone()),
+                check_date: c.check_date,
+                kind: c.kind,
+                result: c.result,
+                contact_id: c.contact_id,
+                contact_name: self.contact_name(c.contact_id),
+                note: c.note.clone(),
+            })
+            .collect()
+    }
+
+    // Mirrors the lateral joins of SirenStatus::query.
+    fn siren_statuses(&self, keep: impl Fn(&SirenStatus) -> bool) -> Vec<SirenStatus> {
+        let latest = |siren_id: i64, activation: bool| {
+            self.siren_checks
+                .values()
+                .filter(|c| c.siren_id == Some(siren_id))
+                .filter(|c| !activation || c.kind.is_activation())
+                .max_by_key(|c| (c.check_date, c.id))
+        };
+        let mut statuses: Vec<SirenStatus> = self
+            .sirens
+            .values()
+            .map(|s| {
+                let last = latest(s.id, false);
+                let test = latest(s.id, true);
+                SirenStatus {
+                    siren_id: s.id,
+                    num_id: s.num_id,
+                    address: s.address.clone(),
+                    siren_type_name: self
+                        .siren_types
+                        .find(s.siren_type_id)
+                        .and_then(|t| t.name.clone()),
+                    company_name: self.company_name(s.company_id),
+                    last_check: last.map(|c| c.check_date),
+                    last_kind: last.map(|c| c.kind),
+                    last_result: last.map(|c| c.result),
+                    last_test: test.map(|c| c.check_date),
+                    last_test_result: test.map(|c| c.result),
+                }
+            })
+            .filter(|s| keep(s))
+            .collect();
+        statuses.sort_by(|a, b| asc(&a.address, &b.address).then(a.siren_id.cmp(&b.siren_id)));
+        statuses
+    }
+
+    fn contact_name(&self, id: Option<i64>) -> Option<String> {
+        self.contacts.find(id).and_then(|c| c.name.clone())
+    }
+
+    fn post_name(&self, id: Option<i64>, go: Option<bool>) -> Option<String> {
+        self.posts
+            .find(id)
+            .filter(|p| go.is_none_or(|go| p.go == go))
+            .and_then(|p| p.name.clone())
+    }
+
+    fn practice_list(&self, practice: &Practice) -> PracticeList {
+        let kind = self.kinds.find(practice.kind_id);
+        PracticeList {
+            id: practice.id,
+            company_id: practice.company_id,
+            company_name: self.company_name(practice.company_id),
+            kind_id: practice.kind_id,
+            kind_name: kind.and_then(|k| k.name.clone()),
+            kind_short_name: kind.and_then(|k| k.short_name.clone()),
+            topic: practice.topic.clone(),
+            date_of_practice: practice.date_of_practice,
+            date_str: date_str(practice.date_of_practice),
+        }
+    }
+
+    fn practices_by_company(&self, company_id: i64) -> Vec<PracticeList> {
+        let mut practices: Vec<PracticeList> = self
+            .practices
+            .values()
+            .filter(|p| p.company_id == Some(company_id))
+            .map(|p| self.practice_list(p))
+            .collect();
+        practices.sort_by(|a, b| desc(&a.date_of_practice, &b.date_of_practice));
+        practices
+    }
+
+    fn contacts_by_company(&self, company_id: i64) -> Vec<ContactShort> {
+        self.contacts
+            .values()
+            .filter(|c| c.company_id == Some(company_id))
+            .map(|c| ContactShort {
+                id: c.id,
+                name: c.name.clone(),
+                department_name: self
+                    .departments
+                    .find(c.department_id)
+                    .and_then(|d| d.name.clone()),
+                post_name: self.post_name(c.post_id, Some(false)),
+                post_go_name: self.post_name(c.post_go_id, Some(true)),
+            })
+            .collect()
+    }
+
+    fn educations_of(&self, contact_id: i64) -> Vec<NaiveDate> {
+        self.educations
+            .values()
+            .filter(|e| e.contact_id == Some(contact_id))
+            .filter_map(|e| e.start_date)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+fn company_node(company: &Company, depth: i32) -> CompanyNode {
+    CompanyNode {
+        id: company.id,
+        parent_id: company.parent_id,
+        name: company.name.clone(),
+        depth,
+    }
+}
+
+// The ids a link table would hold, sorted and without repeats, or the error
+// replace_links gives for the first one that `exists` does not know.
+fn links(
+    ids: &[i64],
+    column: &str,
+    target: &str,
+    exists: impl Fn(i64) -> bool,
+) -> Result<Vec<i64>, RpelError> {
+    let ids: BTreeSet<i64> = ids.iter().copied().collect();
+    if let Some(missing) = ids.iter().find(|id| !exists(**id)) {
+        return Err(RpelError::Invalid(format!(
+            "{column} points to missing {target} {missing}"
+        )));
+    }
+    Ok(ids.into_iter().collect())
+}
+
+fn attachment_list(attachment: &Attachment) -> AttachmentList {
+    AttachmentList {
+        id: attachment.id,
+        owner: attachment.owner,
+        owner_id: attachment.owner_id,
+        filename: attachment.filename.clone(),
+        mime_type: attachment.mime_type.clone(),
+        size: attachment.size,
+        checksum: attachment.checksum.clone(),
+        note: attachment.note.clone(),
+        uploaded_at: attachment.created_at,
+    }
+}
+
+fn date_str(date: Option<NaiveDate>) -> Option<String> {
+    date.map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+// Postgres sorts NULLs last in ascending order and first in descending order.
+fn asc<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+    }
+}
+
+fn desc<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    asc(a, b).reverse()
+}
+
+// The upcoming lists look one month back, like `now - '1 month'::interval`.
+fn is_near(date: Option<NaiveDate>) -> bool {
+    let since = now().checked_sub_months(Months::new(1));
+    date.is_some_and(|d| Some(d.and_time(NaiveTime::MIN)) > since)
+}
+
+fn select<'a, R: 'a>(
+    rows: impl Iterator<Item = &'a R>,
+    item: impl Fn(&R) -> SelectItem,
+) -> Vec<SelectItem> {
+    let mut items: Vec<SelectItem> = rows.map(item).collect();
+    items.sort_by(|a, b| asc(&a.name, &b.name));
+    items
+}
+
+// Keeps every table in process memory and answers the same calls as the
+// Postgres pool, joins included, so handlers can be tested without a server.
+// Clones share the same tables. Unique constraints are not checked.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn company_get(&self, id: i64) -> Result<Company, RpelError> {
+        let tables = self.lock();
+        let mut company = tables.companies.get(id)?;
+        company.emails = tables.emails(Owner::Company(id));
+        company.phones = tables.phones(Owner::Company(id), false);
+        company.faxes = tables.phones(Owner::Company(id), true);
+        company.practices = tables.practices_by_company(id);
+        company.contacts = tables.contacts_by_company(id);
+        company.parent = tables
+            .companies
+            .find(company.parent_id)
+            .map(|c| SelectItem {
+                id: c.id,
+                name: c.name.clone(),
+            });
+        company.children = select(tables.children(id), |c| SelectItem {
+            id: c.id,
+            name: c.name.clone(),
+        });
+        Ok(company)
+    }
+
+    pub fn company_insert(&self, company: Company) -> Result<Company, RpelError> {
+        let mut tables = self.lock();
+        let mut company = company;
+        tables.check_parent(None, company.parent_id)?;
+        company.id = tables.companies.insert(Company {
+            emails: Vec::new(),
+            phones: Vec::new(),
+            faxes: Vec::new(),
+            practices: Vec::new(),
+            contacts: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            ..company.clone()
+        });
+        let owner = Owner::Company(company.id);
+        tables.replace_emails(owner, company.emails.clone());
+        tables.replace_phones(owner, false, company.phones.clone());
+        tables.replace_phones(owner, true, company.faxes.clone());
+        Ok(company)
+    }
+
+    pub fn company_update(&self, company: Company) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let owner = Owner::Company(company.id);
+        tables.check_parent(Some(company.id), company.parent_id)?;
+        let result = tables.companies.update(Company {
+            emails: Vec::new(),
+            phones: Vec::new(),
+            faxes: Vec::new(),
+            practices: Vec::new(),
+            contacts: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+            ..company.clone()
+        })?;
+        if result == 0 {
+            return Ok(result);
+        }
+        tables.replace_emails(owner, company.emails);
+        tables.replace_phones(owner, false, company.phones);
+        tables.replace_phones(owner, true, company.faxes);
+        Ok(result)
+    }
+
+    pub fn company_patch(&self, id: i64, company: CompanyPatch) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let owner = Owner::Company(id);
+        if let Some(parent_id) = company.parent_id {
+            tables.check_parent(Some(id), parent_id)?;
+        }
+        let result = tables.companies.patch(id, company.updated_at, |row| {
+            apply!(row, company, name, full_name, address, scope_id, parent_id, note);
+        })?;
+        if result == 0 {
+            return Ok(result);
+        }
+        if let Some(emails) = company.emails {
+            tables.replace_emails(owner, emails);
+        }
+        if let Some(phones) = company.phones {
+            tables.replace_phones(owner, false, phones);
+        }
+        if let Some(faxes) = company.faxes {
+            tables.replace_phones(owner, true, faxes);
+        }
+        Ok(result)
+    }
+
+    pub fn company_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let departments: BTreeSet<i64> = tables
+            .departments
+            .values()
+            .filter(|d| d.company_id == Some(id))
+            .map(|d| d.id)
+            .collect();
+        if let Some(contact) = tables
+            .contacts
+            .values()
+            .find(|c| c.department_id.is_some_and(|d| departments.contains(&d)))
+        {
+            return Err(RpelError::Invalid(format!(
+                "contact {} is in a department of company {id}",
+                contact.id
+            )));
+        }
+        for department in departments {
+            tables.departments.delete(department);
+        }
+        let owner = Owner::Company(id);
+        tables.delete_phones(owner, false);
+        tables.delete_phones(owner, true);
+        tables.delete_emails(owner);
+        tables
+            .addresses
+            .rows
+            .retain(|_, a| a.company_id != Some(id));
+        tables.delete_attachments(AttachmentOwner::Companies, id);
+        let parent_id = tables.companies.find(Some(id)).and_then(|c| c.parent_id);
+        let children: Vec<i64> = tables.children(id).map(|c| c.id).collect();
+        for child in children {
+            tables
+                .companies
+                .overwrite(child, |row| row.parent_id = parent_id);
+        }
+        Ok(tables.companies.delete(id))
+    }
+
+    pub fn company_subtree(&self, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        self.lock().subtree(id)
+    }
+
+    pub fn company_ancestors(&self, id: i64) -> Result<Vec<CompanyNode>, RpelError> {
+        let mut nodes = self.lock().ancestors(id)?;
+        nodes.remove(0);
+        Ok(nodes)
+    }
+
+    pub fn company_rollup(&self, id: i64) -> Result<CompanyRollup, RpelError> {
+        let tables = self.lock();
+        let company = tables.companies.get(id)?;
+        Ok(tables.rollup(&company))
+    }
+
+    pub fn company_rollup_all(&self) -> Result<Vec<CompanyRollup>, RpelError> {
+        let tables = self.lock();
+        let mut rollups: Vec<CompanyRollup> = tables
+            .companies
+            .values()
+            .filter(|c| c.parent_id.is_none())
+            .map(|c| tables.rollup(c))
+            .collect();
+        rollups.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(rollups)
+    }
+
+    pub fn company_list(&self) -> Result<Vec<CompanyList>, RpelError> {
+        let tables = self.lock();
+        let mut companies: Vec<CompanyList> = tables
+            .companies
+            .values()
+            .map(|c| {
+                let owner = Owner::Company(c.id);
+                CompanyList {
+                    id: c.id,
+                    name: c.name.clone(),
+                    full_name: c.full_name.clone(),
+                    address: c.address.clone(),
+                    scope_name: tables.scopes.find(c.scope_id).and_then(|s| s.name.clone()),
+                    parent_name: tables.company_name(c.parent_id),
+                    emails: tables.emails(owner),
+                    phones: tables.phones(owner, false),
+                    faxes: tables.phones(owner, true),
+                    practices: tables
+                        .practices
+                        .values()
+                        .filter(|p| p.company_id == Some(c.id))
+                        .filter_map(|p| p.date_of_practice)
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect(),
+                }
+            })
+            .collect();
+        companies.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(companies)
+    }
+
+    pub fn contact_get(&self, id: i64) -> Result<Contact, RpelError> {
+        let tables = self.lock();
+        let mut contact = tables.contacts.get(id)?;
+        contact.emails = tables.emails(Owner::Contact(id));
+        contact.phones = tables.phones(Owner::Contact(id), false);
+        contact.faxes = tables.phones(Owner::Contact(id), true);
+        contact.educations = tables.educations_of(id);
+        Ok(contact)
+    }
+
+    pub fn contact_insert(&self, contact: Contact) -> Result<Contact, RpelError> {
+        let mut tables = self.lock();
+        let mut contact = contact;
+        tables.check_department(contact.company_id, contact.department_id)?;
+        contact.id = tables.contacts.insert(Contact {
+            emails: Vec::new(),
+            phones: Vec::new(),
+            faxes: Vec::new(),
+            educations: Vec::new(),
+            ..contact.clone()
+        });
+        let owner = Owner::Contact(contact.id);
+        tables.replace_emails(owner, contact.emails.clone());
+        tables.replace_phones(owner, false, contact.phones.clone());
+        tables.replace_phones(owner, true, contact.faxes.clone());
+        tables.sync_employment(contact.id, now().date());
+        Ok(contact)
+    }
+
+    pub fn contact_update(&self, contact: Contact) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let owner = Owner::Contact(contact.id);
+        tables.check_department(contact.company_id, contact.department_id)?;
+        let result = tables.contacts.update(Contact {
+            emails: Vec::new(),
+            phones: Vec::new(),
+            faxes: Vec::new(),
+            educations: Vec::new(),
+            ..contact.clone()
+        })?;
+        if result == 0 {
+            return Ok(result);
+        }
+        tables.replace_emails(owner, contact.emails);
+        tables.replace_phones(owner, false, contact.phones);
+        tables.replace_phones(owner, true, contact.faxes);
+        tables.sync_employment(contact.id, now().date());
+        Ok(result)
+    }
+
+    pub fn contact_patch(&self, id: i64, contact: ContactPatch) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let owner = Owner::Contact(id);
+        if let Some(row) = tables.contacts.find(Some(id)) {
+            tables.check_department(
+                contact.company_id.unwrap_or(row.company_id),
+                contact.department_id.unwrap_or(row.department_id),
+            )?;
+        }
+        let result = tables.contacts.patch(id, contact.updated_at, |row| {
+            apply!(
+                row,
+                contact,
+                name,
+                company_id,
+                department_id,
+                post_id,
+                post_go_id,
+                rank_id,
+                birthday,
+                note
+            );
+        })?;
+        if result == 0 {
+            return Ok(result);
+        }
+        if let Some(emails) = contact.emails {
+            tables.replace_emails(owner, emails);
+        }
+        if let Some(phones) = contact.phones {
+            tables.replace_phones(owner, false, phones);
+        }
+        if let Some(faxes) = contact.faxes {
+            tables.replace_phones(owner, true, faxes);
+        }
+        tables.sync_employment(id, now().date());
+        Ok(result)
+    }
+
+    pub fn contact_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let owner = Owner::Contact(id);
+        tables.delete_phones(owner, true);
+        tables.delete_phones(owner, false);
+        tables.delete_emails(owner);
+        // Mirrors Employment::close_contacts_with.
+        let today = now().date();
+        tables.employments.rows.retain(|_, e| {
+            e.contact_id != Some(id) || e.date_to.is_some() || e.date_from.is_none_or(|d| d < today)
+        });
+        let open: Vec<i64> = tables
+            .employments
+            .values()
+            .filter(|e| e.contact_id == Some(id) && e.date_to.is_none())
+            .map(|e| e.id)
+            .collect();
+        for open in open {
+            tables
+                .employments
+                .overwrite(open, |row| row.date_to = Some(today));
+        }
+        Ok(tables.contacts.delete(id))
+    }
+
+    pub fn duplicate_find(&self, min_score: i64) -> Result<Vec<DuplicateCandidate>, RpelError> {
+        let tables = self.lock();
+        let people: Vec<Person> = tables
+            .contacts
+            .values()
+            .map(|c| Person {
+                id: c.id,
+                name: c.name.clone(),
+                birthday: c.birthday,
+                phones: tables
+                    .phones(Owner::Contact(c.id), false)
+                    .into_iter()
+                    .collect(),
+                emails: tables
+                    .emails(Owner::Contact(c.id))
+                    .iter()
+                    .map(|e| normalize_email(e))
+                    .collect(),
+            })
+            .collect();
+        Ok(duplicate::candidates(&people, min_score))
+    }
+
+    // Mirrors Contact::merge_with.
+    pub fn contact_merge(
+        &self,
+        survivor_id: i64,
+        duplicate_id: i64,
+    ) -> Result<ContactMerge, RpelError> {
+        let mut tables = self.lock();
+        if survivor_id == duplicate_id {
+            return Err(RpelError::Invalid(format!(
+                "cannot merge contact {duplicate_id} into itself"
+            )));
+        }
+        let survivor = tables.contacts.get(survivor_id)?;
+        let duplicate = tables.contacts.get(duplicate_id)?;
+        let (from, to) = (Owner::Contact(duplicate_id), Owner::Contact(survivor_id));
+        let mut result = ContactMerge::default();
+
+        let kept: Vec<(i64, bool)> = tables
+            .phones
+            .iter()
+            .filter(|row| row.owner == to)
+            .map(|row| (row.phone, row.fax))
+            .collect();
+        tables
+            .phones
+            .retain(|row| row.owner != from || !kept.contains(&(row.phone, row.fax)));
+        for row in tables.phones.iter_mut().filter(|row| row.owner == from) {
+            row.owner = to;
+            result.phones += 1;
+        }
+        let kept: Vec<String> = tables
+            .emails
+            .iter()
+            .filter(|row| row.owner == to)
+            .map(|row| row.email.to_lowercase())
+            .collect();
+        tables
+            .emails
+            .retain(|row| row.owner != from || !kept.contains(&row.email.to_lowercase()));
+        for row in tables.emails.iter_mut().filter(|row| row.owner == from) {
+            row.owner = to;
+            result.emails += 1;
+        }
+
+        let today = now().date();
+        let open: Vec<i64> = tables
+            .employments
+            .values()
+            .filter(|e| e.contact_id == Some(duplicate_id) && e.date_to.is_none())
+            .map(|e| e.id)
+            .collect();
+        for id in open {
+            tables
+                .employments
+                .overwrite(id, |row| row.date_to = Some(today));
+        }
+        macro_rules! reassign {
+            ($table:ident) => {{
+                let ids: Vec<i64> = tables
+                    .$table
+                    .values()
+                    .filter(|row| row.contact_id == Some(duplicate_id))
+                    .map(|row| row.id)
+                    .collect();
+                for &id in &ids {
+                    tables
+                        .$table
+                        .overwrite(id, |row| row.contact_id = Some(survivor_id));
+                }
+                ids.len() as u64
+            }};
+        }
+        result.educations = reassign!(educations);
+        result.certificates = reassign!(certificates);
+        result.sirens = reassign!(sirens);
+        result.siren_checks = reassign!(siren_checks);
+        result.employments = reassign!(employments);
+
+        tables.contacts.delete(duplicate_id);
+        let fits = survivor.company_id.is_none()
+            || survivor.company_id == duplicate.company_id
+            || tables
+                .departments
+                .find(duplicate.department_id)
+                .is_some_and(|d| d.company_id.is_none());
+        let department_id = survivor
+            .department_id
+            .or(duplicate.department_id.filter(|_| fits));
+        tables.contacts.overwrite(survivor_id, |row| {
+            row.company_id = row.company_id.or(duplicate.company_id);
+            row.department_id = department_id;
+            row.post_id = row.post_id.or(duplicate.post_id);
+            row.post_go_id = row.post_go_id.or(duplicate.post_go_id);
+            row.rank_id = row.rank_id.or(duplicate.rank_id);
+            row.birthday = row.birthday.or(duplicate.birthday);
+            row.note = row.note.take().or(duplicate.note);
+        });
+        tables.sync_employment(survivor_id, today);
+        Ok(result)
+    }
+
+    pub fn employment_get(&self, id: i64) -> Result<Employment, RpelError> {
+        self.lock().employments.get(id)
+    }
+
+    pub fn employment_insert(&self, employment: Employment) -> Result<Employment, RpelError> {
+        check_dates(&employment)?;
+        let mut employment = employment;
+        employment.id = self.lock().employments.insert(employment.clone());
+        Ok(employment)
+    }
+
+    pub fn employment_update(&self, employment: Employment) -> Result<u64, RpelError> {
+        check_dates(&employment)?;
+        self.lock().employments.update(employment)
+    }
+
+    pub fn employment_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().employments.delete(id))
+    }
+
+    pub fn employment_list(&self) -> Result<Vec<EmploymentList>, RpelError> {
+        Ok(self.lock().employments_where(|_| true))
+    }
+
+    pub fn employment_by_contact(&self, contact_id: i64) -> Result<Vec<EmploymentList>, RpelError> {
+        Ok(self
+            .lock()
+            .employments_where(|e| e.contact_id == Some(contact_id)))
+    }
+
+    pub fn employment_holders(
+        &self,
+        company_id: i64,
+        post_go_id: i64,
+        date: NaiveDate,
+    ) -> Result<Vec<EmploymentList>, RpelError> {
+        Ok(self.lock().employments_where(|e| {
+            e.company_id == Some(company_id)
+                && e.post_go_id == Some(post_go_id)
+                && e.date_from.is_none_or(|from| from <= date)
+                && e.date_to.is_none_or(|to| to > date)
+        }))
+    }
+
+    pub fn contact_list(&self) -> Result<Vec<ContactList>, RpelError> {
+        let tables = self.lock();
+        let mut contacts: Vec<ContactList> = tables
+            .contacts
+            .values()
+            .map(|c| {
+                let company = tables.companies.find(c.company_id);
+                ContactList {
+                    id: c.id,
+                    name: c.name.clone(),
+                    company_id: company.map(|co| co.id),
+                    company_name: company.and_then(|co| co.name.clone()),
+                    post_name: tables.post_name(c.post_id, None),
+                    emails: tables.emails(Owner::Contact(c.id)),
+                    phones: tables.phones(Owner::Contact(c.id), false),
+                    faxes: tables.phones(Owner::Contact(c.id), true),
+                }
+            })
+            .collect();
+        contacts.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(contacts)
+    }
+
+    pub fn contact_short_by_company(
+        &self,
+        company_id: i64,
+    ) -> Result<Vec<ContactShort>, RpelError> {
+        Ok(self.lock().contacts_by_company(company_id))
+    }
+
+    pub fn phone_update_contacts(
+        &self,
+        id: i64,
+        fax: bool,
+        phones: Vec<i64>,
+    ) -> Result<(), RpelError> {
+        self.lock().replace_phones(Owner::Contact(id), fax, phones);
+        Ok(())
+    }
+
+    pub fn phone_update_companies(
+        &self,
+        id: i64,
+        fax: bool,
+        phones: Vec<i64>,
+    ) -> Result<(), RpelError> {
+        self.lock().replace_phones(Owner::Company(id), fax, phones);
+        Ok(())
+    }
+
+    pub fn phone_delete_contacts(&self, id: i64, fax: bool) -> Result<u64, RpelError> {
+        Ok(self.lock().delete_phones(Owner::Contact(id), fax))
+    }
+
+    pub fn phone_delete_companies(&self, id: i64, fax: bool) -> Result<u64, RpelError> {
+        Ok(self.lock().delete_phones(Owner::Company(id), fax))
+    }
+
+    pub fn email_update_contacts(&self, id: i64, emails: Vec<String>) -> Result<(), RpelError> {
+        self.lock().replace_emails(Owner::Contact(id), emails);
+        Ok(())
+    }
+
+    pub fn email_update_companies(&self, id: i64, emails: Vec<String>) -> Result<(), RpelError> {
+        self.lock().replace_emails(Owner::Company(id), emails);
+        Ok(())
+    }
+
+    pub fn email_delete_contacts(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().delete_emails(Owner::Contact(id)))
+    }
+
+    pub fn email_delete_companies(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().delete_emails(Owner::Company(id)))
+    }
+
+    pub fn practice_get(&self, id: i64) -> Result<Practice, RpelError> {
+        self.lock().practices.get(id)
+    }
+
+    pub fn practice_insert(&self, practice: Practice) -> Result<Practice, RpelError> {
+        let mut practice = practice;
+        practice.id = self.lock().practices.insert(practice.clone());
+        Ok(practice)
+    }
+
+    pub fn practice_update(&self, practice: Practice) -> Result<u64, RpelError> {
+        self.lock().practices.update(practice)
+    }
+
+    pub fn practice_patch(&self, id: i64, practice: PracticePatch) -> Result<u64, RpelError> {
+        self.lock().practices.patch(id, practice.updated_at, |row| {
+            apply!(
+                row,
+                practice,
+                company_id,
+                kind_id,
+                topic,
+                date_of_practice,
+                note
+            );
+        })
+    }
+
+    pub fn practice_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.delete_attachments(AttachmentOwner::Practices, id);
+        Ok(tables.practices.delete(id))
+    }
+
+    pub fn practice_list(&self) -> Result<Vec<PracticeList>, RpelError> {
+        let tables = self.lock();
+        let mut practices: Vec<PracticeList> = tables
+            .practices
+            .values()
+            .map(|p| tables.practice_list(p))
+            .collect();
+        practices.sort_by(|a, b| desc(&a.date_of_practice, &b.date_of_practice));
+        Ok(practices)
+    }
+
+    pub fn practice_list_by_company(
+        &self,
+        company_id: i64,
+    ) -> Result<Vec<PracticeList>, RpelError> {
+        Ok(self.lock().practices_by_company(company_id))
+    }
+
+    pub fn practice_near(&self) -> Result<Vec<PracticeShort>, RpelError> {
+        let tables = self.lock();
+        let mut practices: Vec<PracticeShort> = tables
+            .practices
+            .values()
+            .filter(|p| is_near(p.date_of_practice))
+            .map(|p| PracticeShort {
+                id: p.id,
+                company_id: p.company_id,
+                company_name: tables.company_name(p.company_id),
+                kind_id: p.kind_id,
+                kind_short_name: tables
+                    .kinds
+                    .find(p.kind_id)
+                    .and_then(|k| k.short_name.clone()),
+                date_of_practice: p.date_of_practice,
+            })
+            .collect();
+        practices.sort_by(|a, b| asc(&a.date_of_practice, &b.date_of_practice));
+        practices.truncate(10);
+        Ok(practices)
+    }
+
+    pub fn education_get(&self, id: i64) -> Result<Education, RpelError> {
+        self.lock().educations.get(id)
+    }
+
+    pub fn education_insert(&self, education: Education) -> Result<Education, RpelError> {
+        let mut education = education;
+        education.id = self.lock().educations.insert(education.clone());
+        Ok(education)
+    }
+
+    pub fn education_update(&self, education: Education) -> Result<u64, RpelError> {
+        self.lock().educations.update(education)
+    }
+
+    pub fn education_patch(&self, id: i64, education: EducationPatch) -> Result<u64, RpelError> {
+        self.lock()
+            .educations
+            .patch(id, education.updated_at, |row| {
+                apply!(row, education, contact_id, start_date, end_date, post_id, note);
+            })
+    }
+
+    pub fn education_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.delete_attachments(AttachmentOwner::Educations, id);
+        Ok(tables.educations.delete(id))
+    }
+
+    pub fn education_list(&self) -> Result<Vec<EducationList>, RpelError> {
+        let tables = self.lock();
+        let mut educations: Vec<EducationList> = tables
+            .educations
+            .values()
+            .map(|e| EducationList {
+                id: e.id,
+                contact_id: e.contact_id,
+                contact_name: tables.contact_name(e.contact_id),
+                start_date: e.start_date,
+                end_date: e.end_date,
+                start_str: date_str(e.start_date),
+                end_str: date_str(e.end_date),
+                post_id: e.post_id,
+                post_name: tables.post_name(e.post_id, None),
+                note: e.note.clone(),
+            })
+            .collect();
+        educations.sort_by(|a, b| desc(&a.start_date, &b.start_date));
+        Ok(educations)
+    }
+
+    pub fn education_near(&self) -> Result<Vec<EducationShort>, RpelError> {
+        let tables = self.lock();
+        let mut educations: Vec<EducationShort> = tables
+            .educations
+            .values()
+            .filter(|e| is_near(e.start_date))
+            .map(|e| {
+                let contact = tables.contacts.find(e.contact_id);
+                let company = tables.companies.find(contact.and_then(|c| c.company_id));
+                EducationShort {
+                    id: e.id,
+                    contact_id: e.contact_id,
+                    contact_name: contact.and_then(|c| c.name.clone()),
+                    company_id: company.map(|c| c.id),
+                    company_name: company.and_then(|c| c.name.clone()),
+                    start_date: e.start_date,
+                }
+            })
+            .collect();
+        educations.sort_by(|a, b| asc(&a.start_date, &b.start_date));
+        educations.truncate(10);
+        Ok(educations)
+    }
+
+    pub fn certificate_get(&self, id: i64) -> Result<Certificate, RpelError> {
+        self.lock().certificates.get(id)
+    }
+
+    pub fn certificate_insert(&self, certificate: Certificate) -> Result<Certificate, RpelError> {
+        let mut certificate = certificate;
+        certificate.id = self.lock().certificates.insert(certificate.clone());
+        Ok(certificate)
+    }
+
+    pub fn certificate_update(&self, certificate: Certificate) -> Result<u64, RpelError> {
+        self.lock().certificates.update(certificate)
+    }
+
+    pub fn certificate_patch(
+        &self,
+        id: i64,
+        certificate: CertificatePatch,
+    ) -> Result<u64, RpelError> {
+        self.lock()
+            .certificates
+            .patch(id, certificate.updated_at, |row| {
+                apply!(
+                    row,
+                    certificate,
+                    num,
+                    contact_id,
+                    company_id,
+                    cert_date,
+                    note
+                );
+            })
+    }
+
+    pub fn certificate_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.delete_attachments(AttachmentOwner::Certificates, id);
+        Ok(tables.certificates.delete(id))
+    }
+
+    pub fn certificate_list(&self) -> Result<Vec<CertificateList>, RpelError> {
+        let tables = self.lock();
+        let mut certificates: Vec<CertificateList> = tables
+            .certificates
+            .values()
+            .map(|c| CertificateList {
+                id: c.id,
+                num: c.num.clone(),
+                contact_id: c.contact_id,
+                contact_name: tables.contact_name(c.contact_id),
+                company_id: c.company_id,
+                company_name: tables.company_name(c.company_id),
+                cert_date: date_str(c.cert_date),
+                note: c.note.clone(),
+            })
+            .collect();
+        certificates.sort_by(|a, b| asc(&a.num, &b.num));
+        Ok(certificates)
+    }
+
+    pub fn siren_get(&self, id: i64) -> Result<Siren, RpelError> {
+        self.lock().sirens.get(id)
+    }
+
+    pub fn siren_insert(&self, siren: Siren) -> Result<Siren, RpelError> {
+        let mut siren = siren;
+        siren.id = self.lock().sirens.insert(siren.clone());
+        Ok(siren)
+    }
+
+    pub fn siren_update(&self, siren: Siren) -> Result<u64, RpelError> {
+        self.lock().sirens.update(siren)
+    }
+
+    pub fn siren_patch(&self, id: i64, siren: SirenPatch) -> Result<u64, RpelError> {
+        self.lock().sirens.patch(id, siren.updated_at, |row| {
+            apply!(
+                row,
+                siren,
+                num_id,
+                num_pass,
+                siren_type_id,
+                address,
+                radio,
+                desk,
+                contact_id,
+                company_id,
+                latitude,
+                longitude,
+                stage,
+                own,
+                note
+            );
+        })
+    }
+
+    pub fn siren_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let checks = tables
+            .siren_checks
+            .values()
+            .filter(|c| c.siren_id == Some(id))
+            .count();
+        if checks > 0 {
+            return Err(RpelError::Invalid(format!(
+                "siren {id} has {checks} checks in its journal"
+            )));
+        }
+        tables.addresses.rows.retain(|_, a| a.siren_id != Some(id));
+        tables.delete_attachments(AttachmentOwner::Sirens, id);
+        for zone in tables.warning_zones.rows.values_mut() {
+            zone.siren_ids.retain(|siren_id| *siren_id != id);
+        }
+        Ok(tables.sirens.delete(id))
+    }
+
+    pub fn siren_check_get(&self, id: i64) -> Result<SirenCheck, RpelError> {
+        self.lock().siren_checks.get(id)
+    }
+
+    pub fn siren_check_insert(&self, siren_check: SirenCheck) -> Result<SirenCheck, RpelError> {
+        let mut siren_check = siren_check;
+        siren_check.id = self.lock().siren_checks.insert(siren_check.clone());
+        Ok(siren_check)
+    }
+
+    pub fn siren_check_update(&self, siren_check: SirenCheck) -> Result<u64, RpelError> {
+        self.lock().siren_checks.update(siren_check)
+    }
+
+    pub fn siren_check_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().siren_checks.delete(id))
+    }
+
+    pub fn siren_check_list(&self) -> Result<Vec<SirenCheckList>, RpelError> {
+        Ok(self.lock().siren_checks_where(|_| true))
+    }
+
+    pub fn siren_check_by_siren(&self, siren_id: i64) -> Result<Vec<SirenCheckList>, RpelError> {
+        Ok(self
+            .lock()
+            .siren_checks_where(|c| c.siren_id == Some(siren_id)))
+    }
+
+    pub fn siren_status_all(&self) -> Result<Vec<SirenStatus>, RpelError> {
+        Ok(self.lock().siren_statuses(|_| true))
+    }
+
+    pub fn siren_status_untested(&self) -> Result<Vec<SirenStatus>, RpelError> {
+        Ok(self.lock().siren_statuses(|s| s.last_test.is_none()))
+    }
+
+    pub fn siren_status_failed(&self) -> Result<Vec<SirenStatus>, RpelError> {
+        Ok(self
+            .lock()
+            .siren_statuses(|s| s.last_test_result.is_some_and(|r| r != CheckResult::Worked)))
+    }
+
+    pub fn siren_list(&self) -> Result<Vec<SirenList>, RpelError> {
+        let tables = self.lock();
+        let mut sirens: Vec<SirenList> = tables
+            .sirens
+            .values()
+            .map(|s| SirenList {
+                id: s.id,
+                siren_type_name: tables
+                    .siren_types
+                    .find(s.siren_type_id)
+                    .and_then(|t| t.name.clone()),
+                address: s.address.clone(),
+                contact_name: tables.contact_name(s.contact_id),
+                phones: s
+                    .contact_id
+                    .map(|id| tables.phones(Owner::Contact(id), false))
+                    .unwrap_or_default(),
+            })
+            .collect();
+        sirens.sort_by(|a, b| asc(&a.siren_type_name, &b.siren_type_name));
+        Ok(sirens)
+    }
+
+    pub fn settlement_get(&self, id: i64) -> Result<Settlement, RpelError> {
+        self.lock().settlements.get(id)
+    }
+
+    pub fn settlement_insert(&self, settlement: Settlement) -> Result<Settlement, RpelError> {
+        let mut settlement = settlement;
+        settlement.id = self.lock().settlements.insert(settlement.clone());
+        Ok(settlement)
+    }
+
+    pub fn settlement_update(&self, settlement: Settlement) -> Result<u64, RpelError> {
+        self.lock().settlements.update(settlement)
+    }
+
+    pub fn settlement_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().settlements.delete(id))
+    }
+
+    pub fn settlement_list(&self) -> Result<Vec<SettlementList>, RpelError> {
+        let tables = self.lock();
+        let count = |id: i64, owner: fn(&Address) -> Option<i64>| {
+            tables
+                .addresses
+                .values()
+                .filter(|a| a.settlement_id == Some(id) && owner(a).is_some())
+                .count() as i64
+        };
+        let mut settlements: Vec<SettlementList> = tables
+            .settlements
+            .values()
+            .map(|s| SettlementList {
+                id: s.id,
+                name: s.name.clone(),
+                kind: s.kind.clone(),
+                note: s.note.clone(),
+                streets: tables
+                    .streets
+                    .values()
+                    .filter(|t| t.settlement_id == Some(s.id))
+                    .count() as i64,
+                companies: count(s.id, |a| a.company_id),
+                sirens: count(s.id, |a| a.siren_id),
+            })
+            .collect();
+        settlements.sort_by(|a, b| asc(&a.name, &b.name).then(a.id.cmp(&b.id)));
+        Ok(settlements)
+    }
+
+    pub fn street_get(&self, id: i64) -> Result<Street, RpelError> {
+        self.lock().streets.get(id)
+    }
+
+    pub fn street_insert(&self, street: Street) -> Result<Street, RpelError> {
+        let mut street = street;
+        street.id = self.lock().streets.insert(street.clone());
+        Ok(street)
+    }
+
+    pub fn street_update(&self, street: Street) -> Result<u64, RpelError> {
+        self.lock().streets.update(street)
+    }
+
+    pub fn street_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().streets.delete(id))
+    }
+
+    pub fn street_list(&self) -> Result<Vec<StreetList>, RpelError> {
+        let tables = self.lock();
+        let count = |id: i64, owner: fn(&Address) -> Option<i64>| {
+            tables
+                .addresses
+                .values()
+                .filter(|a| a.street_id == Some(id) && owner(a).is_some())
+                .count() as i64
+        };
+        let mut streets: Vec<StreetList> = tables
+            .streets
+            .values()
+            .map(|t| StreetList {
+                id: t.id,
+                settlement_id: t.settlement_id,
+                settlement_name: tables
+                    .settlements
+                    .find(t.settlement_id)
+                    .and_then(|s| s.name.clone()),
+                name: t.name.clone(),
+                kind: t.kind.clone(),
+                aliases: t.aliases.clone(),
+                companies: count(t.id, |a| a.company_id),
+                sirens: count(t.id, |a| a.siren_id),
+            })
+            .collect();
+        streets.sort_by(|a, b| {
+            asc(&a.settlement_name, &b.settlement_name)
+                .then(asc(&a.name, &b.name))
+                .then(a.id.cmp(&b.id))
+        });
+        Ok(streets)
+    }
+
+    pub fn address_get(&self, id: i64) -> Result<Address, RpelError> {
+        self.lock().addresses.get(id)
+    }
+
+    pub fn address_insert(&self, address: Address) -> Result<Address, RpelError> {
+        check_owner(&address)?;
+        let mut address = address;
+        address.id = self.lock().addresses.insert(address.clone());
+        Ok(address)
+    }
+
+    pub fn address_update(&self, address: Address) -> Result<u64, RpelError> {
+        check_owner(&address)?;
+        self.lock().addresses.update(address)
+    }
+
+    pub fn address_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().addresses.delete(id))
+    }
+
+    pub fn address_list(&self) -> Result<Vec<AddressList>, RpelError> {
+        let tables = self.lock();
+        let mut addresses: Vec<AddressList> = tables
+            .addresses
+            .values()
+            .map(|a| AddressList {
+                id: a.id,
+                company_id: a.company_id,
+                company_name: tables.company_name(a.company_id),
+                siren_id: a.siren_id,
+                siren_num_id: tables.sirens.find(a.siren_id).and_then(|s| s.num_id),
+                settlement_id: a.settlement_id,
+                settlement_name: tables
+                    .settlements
+                    .find(a.settlement_id)
+                    .and_then(|s| s.name.clone()),
+                street_id: a.street_id,
+                street_name: tables
+                    .streets
+                    .find(a.street_id)
+                    .and_then(|t| t.name.clone()),
+                house: a.house.clone(),
+                building: a.building.clone(),
+                entrance: a.entrance.clone(),
+            })
+            .collect();
+        addresses.sort_by(|a, b| {
+            asc(&a.settlement_name, &b.settlement_name)
+                .then(asc(&a.street_name, &b.street_name))
+                .then(asc(&a.house, &b.house))
+                .then(a.id.cmp(&b.id))
+        });
+        Ok(addresses)
+    }
+
+    pub fn address_parse(&self, apply: bool) -> Result<AddressReport, RpelError> {
+        let mut tables = self.lock();
+        let gazetteer = Gazetteer::new(
+            tables.settlements.values().cloned().collect(),
+            tables.streets.values().cloned().collect(),
+        );
+        let text =
+            |address: &Option<String>| address.clone().filter(|text| !text.trim().is_empty());
+        let mut owners = Vec::new();
+        for company in tables.companies.values() {
+            if let Some(text) = text(&company.address) {
+                if !tables
+                    .addresses
+                    .values()
+                    .any(|a| a.company_id == Some(company.id))
+                {
+                    owners.push(("companies".to_string(), company.id, text));
+                }
+            }
+        }
+        for siren in tables.sirens.values() {
+            if let Some(text) = text(&siren.address) {
+                if !tables
+                    .addresses
+                    .values()
+                    .any(|a| a.siren_id == Some(siren.id))
+                {
+                    owners.push(("sirens".to_string(), siren.id, text));
+                }
+            }
+        }
+        let (addresses, failures) = parse_owners(&gazetteer, owners);
+        let parsed = addresses.len() as u64;
+        if apply {
+            for address in addresses {
+                tables.addresses.insert(address);
+            }
+        }
+        Ok(AddressReport { parsed, failures })
+    }
+
+    pub fn attachment_get(&self, id: i64) -> Result<Attachment, RpelError> {
+        self.lock().attachments.get(id)
+    }
+
+    pub fn attachment_upload(
+        &self,
+        attachment: Attachment,
+        data: Vec<u8>,
+    ) -> Result<Attachment, RpelError> {
+        let mut tables = self.lock();
+        let mut attachment = attachment;
+        check_attachment(&mut attachment)?;
+        if !tables.owner_exists(attachment.owner, attachment.owner_id) {
+            return Err(RpelError::Invalid(format!(
+                "owner_id points to missing {} {}",
+                attachment.owner.as_str(),
+                attachment.owner_id
+            )));
+        }
+        let mut checksum = Checksum::new();
+        checksum.update(&data);
+        (attachment.size, attachment.checksum) = checksum.finish();
+        attachment.id = tables.attachments.insert(attachment.clone());
+        tables.attachment_data.insert(attachment.id, data);
+        tables.attachments.get(attachment.id)
+    }
+
+    pub fn attachment_content(&self, id: i64) -> Result<(Attachment, Vec<u8>), RpelError> {
+        let tables = self.lock();
+        let attachment = tables.attachments.get(id)?;
+        let data = tables.attachment_data.get(&id).cloned().unwrap_or_default();
+        Ok((attachment, data))
+    }
+
+    pub fn attachment_update(&self, attachment: Attachment) -> Result<u64, RpelError> {
+        let mut attachment = attachment;
+        check_attachment(&mut attachment)?;
+        self.lock()
+            .attachments
+            .patch(attachment.id, attachment.updated_at, |row| {
+                row.filename = attachment.filename;
+                row.mime_type = attachment.mime_type;
+                row.note = attachment.note;
+            })
+    }
+
+    pub fn attachment_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.attachment_data.remove(&id);
+        Ok(tables.attachments.delete(id))
+    }
+
+    pub fn attachment_list(&self) -> Result<Vec<AttachmentList>, RpelError> {
+        let mut attachments: Vec<AttachmentList> = self
+            .lock()
+            .attachments
+            .values()
+            .map(attachment_list)
+            .collect();
+        attachments.sort_by(|a, b| {
+            a.owner
+                .as_str()
+                .cmp(b.owner.as_str())
+                .then(a.owner_id.cmp(&b.owner_id))
+                .then(a.filename.cmp(&b.filename))
+                .then(a.id.cmp(&b.id))
+        });
+        Ok(attachments)
+    }
+
+    pub fn attachment_by_owner(
+        &self,
+        owner: AttachmentOwner,
+        owner_id: i64,
+    ) -> Result<Vec<AttachmentList>, RpelError> {
+        let mut attachments: Vec<AttachmentList> = self
+            .lock()
+            .attachments
+            .values()
+            .filter(|a| a.owner == owner && a.owner_id == owner_id)
+            .map(attachment_list)
+            .collect();
+        attachments.sort_by(|a, b| a.filename.cmp(&b.filename).then(a.id.cmp(&b.id)));
+        Ok(attachments)
+    }
+
+    pub fn warning_zone_get(&self, id: i64) -> Result<WarningZone, RpelError> {
+        self.lock().warning_zones.get(id)
+    }
+
+    pub fn warning_zone_insert(&self, warning_zone: WarningZone) -> Result<WarningZone, RpelError> {
+        let mut tables = self.lock();
+        let mut warning_zone = warning_zone;
+        let siren_ids = links(&warning_zone.siren_ids, "siren_id", "sirens", |id| {
+            tables.sirens.find(Some(id)).is_some()
+        })?;
+        warning_zone.id = tables.warning_zones.insert(WarningZone {
+            siren_ids,
+            ..warning_zone.clone()
+        });
+        Ok(warning_zone)
+    }
+
+    pub fn warning_zone_update(&self, warning_zone: WarningZone) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let siren_ids = links(&warning_zone.siren_ids, "siren_id", "sirens", |id| {
+            tables.sirens.find(Some(id)).is_some()
+        })?;
+        tables.warning_zones.update(WarningZone {
+            siren_ids,
+            ..warning_zone
+        })
+    }
+
+    pub fn warning_zone_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        for scenario in tables.scenarios.rows.values_mut() {
+            scenario.warning_zone_ids.retain(|zone_id| *zone_id != id);
+        }
+        Ok(tables.warning_zones.delete(id))
+    }
+
+    pub fn warning_zone_list(&self) -> Result<Vec<WarningZoneList>, RpelError> {
+        let mut warning_zones: Vec<WarningZoneList> = self
+            .lock()
+            .warning_zones
+            .values()
+            .map(|z| WarningZoneList {
+                id: z.id,
+                name: z.name.clone(),
+                note: z.note.clone(),
+                sirens: z.siren_ids.len() as i64,
+            })
+            .collect();
+        warning_zones.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(warning_zones)
+    }
+
+    pub fn scenario_get(&self, id: i64) -> Result<Scenario, RpelError> {
+        self.lock().scenarios.get(id)
+    }
+
+    pub fn scenario_insert(&self, scenario: Scenario) -> Result<Scenario, RpelError> {
+        let mut tables = self.lock();
+        let mut scenario = scenario;
+        let warning_zone_ids = links(
+            &scenario.warning_zone_ids,
+            "warning_zone_id",
+            "warning_zones",
+            |id| tables.warning_zones.find(Some(id)).is_some(),
+        )?;
+        scenario.id = tables.scenarios.insert(Scenario {
+            warning_zone_ids,
+            ..scenario.clone()
+        });
+        Ok(scenario)
+    }
+
+    pub fn scenario_update(&self, scenario: Scenario) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        let warning_zone_ids = links(
+            &scenario.warning_zone_ids,
+            "warning_zone_id",
+            "warning_zones",
+            |id| tables.warning_zones.find(Some(id)).is_some(),
+        )?;
+        tables.scenarios.update(Scenario {
+            warning_zone_ids,
+            ..scenario
+        })
+    }
+
+    pub fn scenario_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().scenarios.delete(id))
+    }
+
+    pub fn scenario_list(&self) -> Result<Vec<ScenarioList>, RpelError> {
+        let tables = self.lock();
+        let mut scenarios: Vec<ScenarioList> = tables
+            .scenarios
+            .values()
+            .map(|s| ScenarioList {
+                id: s.id,
+                name: s.name.clone(),
+                note: s.note.clone(),
+                zone_names: s
+                    .warning_zone_ids
+                    .iter()
+                    .filter_map(|id| tables.warning_zones.find(Some(*id)))
+                    .filter_map(|z| z.name.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+            })
+            .collect();
+        scenarios.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(scenarios)
+    }
+
+    pub fn scenario_checklist(&self, id: i64) -> Result<Vec<ChecklistItem>, RpelError> {
+        let tables = self.lock();
+        let scenario = tables.scenarios.get(id)?;
+        let mut zones: BTreeMap<i64, BTreeSet<String>> = BTreeMap::new();
+        for zone in scenario
+            .warning_zone_ids
+            .iter()
+            .filter_map(|id| tables.warning_zones.find(Some(*id)))
+        {
+            for siren_id in &zone.siren_ids {
+                let names = zones.entry(*siren_id).or_default();
+                names.extend(zone.name.clone());
+            }
+        }
+        let mut items: Vec<ChecklistItem> = zones
+            .into_iter()
+            .filter_map(|(siren_id, names)| {
+                let siren = tables.sirens.find(Some(siren_id))?;
+                Some(ChecklistItem {
+                    siren_id,
+                    num_id: siren.num_id,
+                    address: siren.address.clone(),
+                    siren_type_name: tables
+                        .siren_types
+                        .find(siren.siren_type_id)
+                        .and_then(|t| t.name.clone()),
+                    zone_names: names.into_iter().collect(),
+                    contact_id: siren.contact_id,
+                    contact_name: tables.contact_name(siren.contact_id),
+                    phones: siren
+                        .contact_id
+                        .map(|id| tables.phones(Owner::Contact(id), false))
+                        .unwrap_or_default(),
+                })
+            })
+            .collect();
+        items.sort_by(|a, b| asc(&a.address, &b.address).then(a.siren_id.cmp(&b.siren_id)));
+        Ok(items)
+    }
+
+    pub fn department_get(&self, id: i64) -> Result<Department, RpelError> {
+        self.lock().departments.get(id)
+    }
+
+    pub fn department_insert(&self, department: Department) -> Result<Department, RpelError> {
+        let mut tables = self.lock();
+        let mut department = department;
+        tables.check_department_parent(None, department.company_id, department.parent_id)?;
+        department.id = tables.departments.insert(department.clone());
+        Ok(department)
+    }
+
+    pub fn department_update(&self, department: Department) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.check_department_parent(
+            Some(department.id),
+            department.company_id,
+            department.parent_id,
+        )?;
+        tables.check_department_move(department.id, department.company_id)?;
+        tables.departments.update(department)
+    }
+
+    pub fn department_delete(&self, id: i64) -> Result<u64, RpelError> {
+        let mut tables = self.lock();
+        tables.check_department_unused(id)?;
+        let parent_id = tables.departments.find(Some(id)).and_then(|d| d.parent_id);
+        let nested: Vec<i64> = tables
+            .departments
+            .values()
+            .filter(|d| d.parent_id == Some(id))
+            .map(|d| d.id)
+            .collect();
+        for child in nested {
+            tables
+                .departments
+                .overwrite(child, |row| row.parent_id = parent_id);
+        }
+        Ok(tables.departments.delete(id))
+    }
+
+    pub fn department_list(&self) -> Result<Vec<DepartmentList>, RpelError> {
+        let tables = self.lock();
+        let mut departments: Vec<DepartmentList> = tables
+            .departments
+            .values()
+            .map(|d| DepartmentList {
+                id: d.id,
+                company_id: d.company_id,
+                company_name: tables.company_name(d.company_id),
+                parent_name: tables
+                    .departments
+                    .find(d.parent_id)
+                    .and_then(|p| p.name.clone()),
+                name: d.name.clone(),
+                note: d.note.clone(),
+            })
+            .collect();
+        departments
+            .sort_by(|a, b| asc(&a.company_name, &b.company_name).then(asc(&a.name, &b.name)));
+        Ok(departments)
+    }
+
+    pub fn kind_get(&self, id: i64) -> Result<Kind, RpelError> {
+        self.lock().kinds.get(id)
+    }
+
+    pub fn kind_insert(&self, kind: Kind) -> Result<Kind, RpelError> {
+        let mut kind = kind;
+        kind.id = self.lock().kinds.insert(kind.clone());
+        Ok(kind)
+    }
+
+    pub fn kind_update(&self, kind: Kind) -> Result<u64, RpelError> {
+        self.lock().kinds.update(kind)
+    }
+
+    pub fn kind_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().kinds.delete(id))
+    }
+
+    pub fn kind_list(&self) -> Result<Vec<KindList>, RpelError> {
+        let mut kinds: Vec<KindList> = self
+            .lock()
+            .kinds
+            .values()
+            .map(|k| KindList {
+                id: k.id,
+                name: k.name.clone(),
+                short_name: k.short_name.clone(),
+                note: k.note.clone(),
+            })
+            .collect();
+        kinds.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(kinds)
+    }
+
+    pub fn post_get(&self, id: i64) -> Result<Post, RpelError> {
+        self.lock().posts.get(id)
+    }
+
+    pub fn post_insert(&self, post: Post) -> Result<Post, RpelError> {
+        let mut post = post;
+        post.id = self.lock().posts.insert(post.clone());
+        Ok(post)
+    }
+
+    pub fn post_update(&self, post: Post) -> Result<u64, RpelError> {
+        self.lock().posts.update(post)
+    }
+
+    pub fn post_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().posts.delete(id))
+    }
+
+    pub fn post_list(&self) -> Result<Vec<PostList>, RpelError> {
+        let mut posts: Vec<PostList> = self
+            .lock()
+            .posts
+            .values()
+            .map(|p| PostList {
+                id: p.id,
+                name: p.name.clone(),
+                go: p.go,
+                note: p.note.clone(),
+            })
+            .collect();
+        posts.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(posts)
+    }
+
+    pub fn rank_get(&self, id: i64) -> Result<Rank, RpelError> {
+        self.lock().ranks.get(id)
+    }
+
+    pub fn rank_insert(&self, rank: Rank) -> Result<Rank, RpelError> {
+        let mut rank = rank;
+        rank.id = self.lock().ranks.insert(rank.clone());
+        Ok(rank)
+    }
+
+    pub fn rank_update(&self, rank: Rank) -> Result<u64, RpelError> {
+        self.lock().ranks.update(rank)
+    }
+
+    pub fn rank_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().ranks.delete(id))
+    }
+
+    pub fn rank_list(&self) -> Result<Vec<RankList>, RpelError> {
+        let mut ranks: Vec<RankList> = self
+            .lock()
+            .ranks
+            .values()
+            .map(|r| RankList {
+                id: r.id,
+                name: r.name.clone(),
+                note: r.note.clone(),
+            })
+            .collect();
+        ranks.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(ranks)
+    }
+
+    pub fn required_post_get(&self, id: i64) -> Result<RequiredPost, RpelError> {
+        self.lock().required_posts.get(id)
+    }
+
+    pub fn required_post_insert(
+        &self,
+        required_post: RequiredPost,
+    ) -> Result<RequiredPost, RpelError> {
+        let mut required_post = required_post;
+        required_post.id = self.lock().required_posts.insert(required_post.clone());
+        Ok(required_post)
+    }
+
+    pub fn required_post_update(&self, required_post: RequiredPost) -> Result<u64, RpelError> {
+        self.lock().required_posts.update(required_post)
+    }
+
+    pub fn required_post_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().required_posts.delete(id))
+    }
+
+    pub fn required_post_list(&self) -> Result<Vec<RequiredPostList>, RpelError> {
+        let tables = self.lock();
+        let mut required_posts: Vec<RequiredPostList> = tables
+            .required_posts
+            .values()
+            .map(|r| RequiredPostList {
+                id: r.id,
+                scope_id: r.scope_id,
+                scope_name: tables.scopes.find(r.scope_id).and_then(|s| s.name.clone()),
+                post_go_id: r.post_go_id,
+                post_go_name: tables.post_name(r.post_go_id, None),
+                head: r.head,
+                note: r.note.clone(),
+            })
+            .collect();
+        // Posts required of every company, with no scope, come first.
+        required_posts.sort_by(|a, b| {
+            a.scope_name
+                .is_some()
+                .cmp(&b.scope_name.is_some())
+                .then(asc(&a.scope_name, &b.scope_name))
+                .then(b.head.cmp(&a.head))
+                .then(asc(&a.post_go_name, &b.post_go_name))
+                .then(a.id.cmp(&b.id))
+        });
+        Ok(required_posts)
+    }
+
+    pub fn staffing_get(&self, company_id: i64) -> Result<Staffing, RpelError> {
+        let tables = self.lock();
+        let company = tables.companies.get(company_id)?;
+        Ok(tables.staffing(&company))
+    }
+
+    pub fn vacancy_all(&self) -> Result<Vec<Vacancy>, RpelError> {
+        Ok(self.lock().vacancies(|_| true))
+    }
+
+    pub fn vacancy_heads(&self) -> Result<Vec<Vacancy>, RpelError> {
+        Ok(self.lock().vacancies(|p| p.head))
+    }
+
+    pub fn scope_get(&self, id: i64) -> Result<Scope, RpelError> {
+        self.lock().scopes.get(id)
+    }
+
+    pub fn scope_insert(&self, scope: Scope) -> Result<Scope, RpelError> {
+        let mut scope = scope;
+        scope.id = self.lock().scopes.insert(scope.clone());
+        Ok(scope)
+    }
+
+    pub fn scope_update(&self, scope: Scope) -> Result<u64, RpelError> {
+        self.lock().scopes.update(scope)
+    }
+
+    pub fn scope_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().scopes.delete(id))
+    }
+
+    pub fn scope_list(&self) -> Result<Vec<ScopeList>, RpelError> {
+        let mut scopes: Vec<ScopeList> = self
+            .lock()
+            .scopes
+            .values()
+            .map(|s| ScopeList {
+                id: s.id,
+                name: s.name.clone(),
+                note: s.note.clone(),
+            })
+            .collect();
+        scopes.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(scopes)
+    }
+
+    pub fn siren_type_get(&self, id: i64) -> Result<SirenType, RpelError> {
+        self.lock().siren_types.get(id)
+    }
+
+    pub fn siren_type_insert(&self, siren_type: SirenType) -> Result<SirenType, RpelError> {
+        let mut siren_type = siren_type;
+        siren_type.id = self.lock().siren_types.insert(siren_type.clone());
+        Ok(siren_type)
+    }
+
+    pub fn siren_type_update(&self, siren_type: SirenType) -> Result<u64, RpelError> {
+        self.lock().siren_types.update(siren_type)
+    }
+
+    pub fn siren_type_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().siren_types.delete(id))
+    }
+
+    pub fn siren_type_list(&self) -> Result<Vec<SirenTypeList>, RpelError> {
+        let mut siren_types: Vec<SirenTypeList> = self
+            .lock()
+            .siren_types
+            .values()
+            .map(|t| SirenTypeList {
+                id: t.id,
+                name: t.name.clone(),
+                radius: t.radius,
+                note: t.note.clone(),
+            })
+            .collect();
+        siren_types.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(siren_types)
+    }
+
+    pub fn user_get(&self, id: i64) -> Result<User, RpelError> {
+        self.lock().users.get(id)
+    }
+
+    pub fn user_by_name(&self, name: &str) -> Result<Option<User>, RpelError> {
+        Ok(self.lock().users.values().find(|u| u.name == name).cloned())
+    }
+
+    pub fn user_by_key(&self, key: &str) -> Result<Option<User>, RpelError> {
+        Ok(self.lock().users.values().find(|u| u.key == key).cloned())
+    }
+
+    pub fn user_insert(&self, user: User) -> Result<User, RpelError> {
+        let mut user = user;
+        user.id = self.lock().users.insert(user.clone());
+        Ok(user)
+    }
+
+    pub fn user_update(&self, user: User) -> Result<u64, RpelError> {
+        self.lock().users.update(user)
+    }
+
+    pub fn user_delete(&self, id: i64) -> Result<u64, RpelError> {
+        Ok(self.lock().users.delete(id))
+    }
+
+    pub fn user_list(&self) -> Result<Vec<UserList>, RpelError> {
+        let mut users: Vec<UserList> = self
+            .lock()
+            .users
+            .values()
+            .map(|u| UserList {
+                id: u.id,
+                name: u.name.clone(),
+                key: u.key.clone(),
+                role: u.role,
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(users)
+    }
+
+    pub fn select_company_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.companies.values(), |c| SelectItem {
+            id: c.id,
+            name: c.name.clone(),
+        }))
+    }
+
+    pub fn select_contact_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.contacts.values(), |c| SelectItem {
+            id: c.id,
+            name: c.name.clone(),
+        }))
+    }
+
+    pub fn select_department_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.departments.values(), |d| SelectItem {
+            id: d.id,
+            name: d.name.clone(),
+        }))
+    }
+
+    pub fn select_department_by_company(
+        &self,
+        company_id: i64,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        let mut items: Vec<SelectItem> = tables
+            .departments
+            .values()
+            .filter(|d| d.company_id == Some(company_id))
+            .filter_map(|d| {
+                tables
+                    .department_path(d)
+                    .map(|name| SelectItem { id: d.id, name })
+            })
+            .collect();
+        items.sort_by(|a, b| asc(&a.name, &b.name));
+        Ok(items)
+    }
+
+    pub fn select_kind_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.kinds.values(), |k| SelectItem {
+            id: k.id,
+            name: k.name.clone(),
+        }))
+    }
+
+    pub fn select_post_all(&self, go: bool) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.posts.values().filter(|p| p.go == go), |p| {
+            SelectItem {
+                id: p.id,
+                name: p.name.clone(),
+            }
+        }))
+    }
+
+    pub fn select_rank_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.ranks.values(), |r| SelectItem {
+            id: r.id,
+            name: r.name.clone(),
+        }))
+    }
+
+    pub fn select_scope_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.scopes.values(), |s| SelectItem {
+            id: s.id,
+            name: s.name.clone(),
+        }))
+    }
+
+    pub fn select_siren_type_all(&self) -> Result<Vec<SelectItem>, RpelError> {
+        let tables = self.lock();
+        Ok(select(tables.siren_types.values(), |t| SelectItem {
+            id: t.id,
+            name: t.name.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::siren_check::CheckKind;
+
+    #[test]
+    fn company_joins_and_conflicts() {
+        let store = MemoryStore::new();
+        let scope = store
+            .scope_insert(Scope {
+                name: Some("Energy".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let company = store
+            .company_insert(Company {
+                name: Some("Grid".to_string()),
+                scope_id: Some(scope.id),
+                phones: vec![3, 1, 3],
+                ..Default::default()
+            })
+            .unwrap();
+        let list = store.company_list().unwrap();
+        assert_eq!(list[0].scope_name.as_deref(), Some("Energy"));
+        assert_eq!(list[0].phones, vec![1, 3]);
+
+        let stale = store.company_get(company.id).unwrap();
+        store.company_update(stale.clone()).unwrap();
+        assert!(matches!(
+            store.company_update(stale.clone()),
+            Err(RpelError::Conflict(_, _))
+        ));
+        let unversioned = Company {
+            updated_at: None,
+            ..stale
+        };
+        assert!(matches!(
+            store.company_update(unversioned),
+            Err(RpelError::Invalid(_))
+        ));
+        assert!(matches!(
+            store.company_get(99),
+            Err(RpelError::NotFound(_, 99))
+        ));
+    }
+
+    #[test]
+    fn company_hierarchy() {
+        let store = MemoryStore::new();
+        let insert = |name: &str, parent_id: Option<i64>| {
+            store.company_insert(Company {
+                name: Some(name.to_string()),
+                parent_id,
+                ..Default::default()
+            })
+        };
+        let head = insert("Holding", None).unwrap();
+        let north = insert("North", Some(head.id)).unwrap();
+        let south = insert("South", Some(head.id)).unwrap();
+        let depot = insert("North depot", Some(north.id)).unwrap();
+        store
+            .contact_insert(Contact {
+                company_id: Some(depot.id),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let subtree: Vec<(i64, i32)> = store
+            .company_subtree(head.id)
+            .unwrap()
+            .iter()
+            .map(|node| (node.id, node.depth))
+            .collect();
+        assert_eq!(
+            subtree,
+            vec![(head.id, 0), (north.id, 1), (depot.id, 2), (south.id, 1)]
+        );
+        let ancestors: Vec<i64> = store
+            .company_ancestors(depot.id)
+            .unwrap()
+            .iter()
+            .map(|node| node.id)
+            .collect();
+        assert_eq!(ancestors, vec![north.id, head.id]);
+        let rollup = store.company_rollup(head.id).unwrap();
+        assert_eq!((rollup.companies, rollup.contacts), (4, 1));
+
+        let patch = CompanyPatch {
+            parent_id: Some(Some(depot.id)),
+            updated_at: store.company_get(head.id).unwrap().updated_at,
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.company_patch(head.id, patch),
+            Err(RpelError::Invalid(_))
+        ));
+        store.company_delete(north.id).unwrap();
+        let stored = store.company_get(depot.id).unwrap();
+        assert_eq!(stored.parent.map(|p| p.id), Some(head.id));
+        assert_eq!(store.company_get(head.id).unwrap().children.len(), 2);
+    }
+
+    #[test]
+    fn staffing_and_vacancies() {
+        let store = MemoryStore::new();
+        let head = store
+            .post_insert(Post {
+                name: Some("Head of GO".to_string()),
+                go: true,
+                ..Default::default()
+            })
+            .unwrap();
+        for name in ["Grid", "Pump"] {
+            store
+                .company_insert(Company {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        store
+            .required_post_insert(RequiredPost {
+                post_go_id: Some(head.id),
+                head: true,
+                ..Default::default()
+            })
+            .unwrap();
+        store
+            .contact_insert(Contact {
+                name: Some("Ivanov".to_string()),
+                company_id: Some(1),
+                post_go_id: Some(head.id),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let staffing = store.staffing_get(1).unwrap();
+        assert_eq!(staffing.vacant, 0);
+        assert_eq!(staffing.posts[0].holders[0].name.as_deref(), Some("Ivanov"));
+        let heads: Vec<i64> = store
+            .vacancy_heads()
+            .unwrap()
+            .iter()
+            .map(|v| v.company_id)
+            .collect();
+        assert_eq!(heads, vec![2]);
+    }
+
+    #[test]
+    fn siren_status_follows_checks() {
+        let store = MemoryStore::new();
+        for address in ["Main st. 1", "Park 2"] {
+            store
+                .siren_insert(Siren {
+                    address: Some(address.to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let today = now().date();
+        for (kind, result) in [
+            (CheckKind::Test, CheckResult::Faulty),
+            (CheckKind::Maintenance, CheckResult::Worked),
+        ] {
+            store
+                .siren_check_insert(SirenCheck {
+                    siren_id: Some(1),
+                    check_date: today,
+                    kind,
+                    result,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let status = store.siren_status_all().unwrap();
+        assert_eq!(status[0].last_kind, Some(CheckKind::Maintenance));
+        assert_eq!(status[0].last_test_result, Some(CheckResult::Faulty));
+        assert_eq!(store.siren_status_failed().unwrap()[0].siren_id, 1);
+        assert_eq!(store.siren_status_untested().unwrap()[0].siren_id, 2);
+        assert!(matches!(store.siren_delete(1), Err(RpelError::Invalid(_))));
+        assert_eq!(store.siren_check_list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn addresses_parse_into_gazetteer() {
+        let store = MemoryStore::new();
+        let moscow = store
+            .settlement_insert(Settlement {
+                name: Some("Москва".to_string()),
+                kind: Some("г".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let lenina = store
+            .street_insert(Street {
+                settlement_id: Some(moscow.id),
+                name: Some("Ленина".to_string()),
+                kind: Some("ул".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        for address in ["г. Москва, ул. Ленина, д. 5", "г. Тверь, 1"] {
+            store
+                .company_insert(Company {
+                    name: Some(address.to_string()),
+                    address: Some(address.to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        store
+            .siren_insert(Siren {
+                address: Some("Ленина ул. 7к1".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let report = store.address_parse(false).unwrap();
+        assert_eq!(report.parsed, 2);
+        assert_eq!(
+            (report.failures[0].table.as_str(), report.failures[0].id),
+            ("companies", 2)
+        );
+        assert!(store.address_list().unwrap().is_empty());
+        store.address_parse(true).unwrap();
+        let houses: Vec<_> = store
+            .address_list()
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.street_id, a.house, a.building))
+            .collect();
+        assert_eq!(
+            houses,
+            vec![
+                (Some(lenina.id), Some("5".to_string()), None),
+                (
+                    Some(lenina.id),
+                    Some("7".to_string()),
+                    Some("1".to_string())
+                ),
+            ]
+        );
+        let street = &store.street_list().unwrap()[0];
+        assert_eq!((street.companies, street.sirens), (1, 1));
+        assert_eq!(store.address_parse(false).unwrap().parsed, 0);
+
+        store.company_delete(1).unwrap();
+        store.siren_delete(1).unwrap();
+        assert!(store.address_list().unwrap().is_empty());
+        assert!(matches!(
+            store.address_insert(Address {
+                company_id: Some(2),
+                siren_id: Some(1),
+                ..Default::default()
+            }),
+            Err(RpelError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn scenario_checklist_joins_zones() {
+        let store = MemoryStore::new();
+        for address in ["Park 2", "Main st. 1"] {
+            store
+                .siren_insert(Siren {
+                    address: Some(address.to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let zone = |name: &str, siren_ids: Vec<i64>| {
+            store
+                .warning_zone_insert(WarningZone {
+                    name: Some(name.to_string()),
+                    siren_ids,
+                    ..Default::default()
+                })
+                .unwrap()
+                .id
+        };
+        let north = zone("North", vec![1, 2, 1]);
+        let south = zone("South", vec![2]);
+        assert_eq!(store.warning_zone_get(north).unwrap().siren_ids, vec![1, 2]);
+        assert!(matches!(
+            store.warning_zone_insert(WarningZone {
+                siren_ids: vec![9],
+                ..Default::default()
+            }),
+            Err(RpelError::Invalid(_))
+        ));
+        let scenario = store
+            .scenario_insert(Scenario {
+                name: Some("Flood".to_string()),
+                warning_zone_ids: vec![north, south],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let checklist = store.scenario_checklist(scenario.id).unwrap();
+        let sirens: Vec<_> = checklist
+            .iter()
+            .map(|i| (i.siren_id, i.zone_names.len()))
+            .collect();
+        assert_eq!(sirens, vec![(2, 2), (1, 1)]);
+        store.siren_delete(2).unwrap();
+        store.warning_zone_delete(north).unwrap();
+        assert!(store.scenario_checklist(scenario.id).unwrap().is_empty());
+        assert!(matches!(
+            store.scenario_checklist(99),
+            Err(RpelError::NotFound(_, 99))
+        ));
+    }
+
+    #[test]
+    fn contact_changes_record_employment() {
+        let store = MemoryStore::new();
+        let contact = store
+            .contact_insert(Contact {
+                name: Some("Ivanov".to_string()),
+                company_id: Some(1),
+                post_go_id: Some(7),
+                ..Default::default()
+            })
+            .unwrap();
+        let today = now().date();
+        let career = store.employment_by_contact(contact.id).unwrap();
+        let mut open = store.employment_get(career[0].id).unwrap();
+        open.date_from = today.checked_sub_days(chrono::Days::new(30));
+        store.employment_update(open).unwrap();
+
+        let patch = ContactPatch {
+            company_id: Some(Some(2)),
+            updated_at: store.contact_get(contact.id).unwrap().updated_at,
+            ..Default::default()
+        };
+        store.contact_patch(contact.id, patch).unwrap();
+        let career = store.employment_by_contact(contact.id).unwrap();
+        let periods: Vec<_> = career.iter().map(|e| (e.company_id, e.date_to)).collect();
+        assert_eq!(periods, vec![(Some(1), Some(today)), (Some(2), None)]);
+        let yesterday = today.pred_opt().unwrap();
+        assert_eq!(store.employment_holders(1, 7, yesterday).unwrap().len(), 1);
+        assert!(store.employment_holders(1, 7, today).unwrap().is_empty());
+
+        store.contact_delete(contact.id).unwrap();
+        let kept: Vec<_> = store
+            .employment_list()
+            .unwrap()
+            .iter()
+            .map(|e| (e.company_id, e.date_to))
+            .collect();
+        assert_eq!(kept, vec![(Some(1), Some(today))]);
+    }
+
+    #[test]
+    fn duplicates_are_found_and_merged() {
+        let store = MemoryStore::new();
+        let department = store
+            .department_insert(Department {
+                company_id: Some(2),
+                name: Some("Logistics".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let survivor = store
+            .contact_insert(Contact {
+                name: Some("Ivanov Ivan".to_string()),
+                company_id: Some(1),
+                phones: vec![555],
+                ..Default::default()
+            })
+            .unwrap();
+        let duplicate = store
+            .contact_insert(Contact {
+                name: Some("ivan ivanov".to_string()),
+                company_id: Some(2),
+                department_id: Some(department.id),
+                birthday: NaiveDate::from_ymd_opt(1980, 5, 17),
+                phones: vec![555, 556],
+                ..Default::default()
+            })
+            .unwrap();
+        let found = store.duplicate_find(duplicate::DEFAULT_MIN_SCORE).unwrap();
+        let pairs: Vec<_> = found.iter().map(|c| (c.id, c.other_id, c.score)).collect();
+        assert_eq!(pairs, vec![(survivor.id, duplicate.id, 80)]);
+
+        let merged = store.contact_merge(survivor.id, duplicate.id).unwrap();
+        assert_eq!((merged.phones, merged.employments), (1, 1));
+        let stored = store.contact_get(survivor.id).unwrap();
+        assert_eq!(stored.phones, vec![555, 556]);
+        assert_eq!(stored.birthday, NaiveDate::from_ymd_opt(1980, 5, 17));
+        assert_eq!((stored.company_id, stored.department_id), (Some(1), None));
+        assert!(matches!(
+            store.contact_get(duplicate.id),
+            Err(RpelError::NotFound(_, _))
+        ));
+        assert!(store
+            .duplicate_find(duplicate::DEFAULT_MIN_SCORE)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn contact_department_belongs_to_its_company() {
+        let store = MemoryStore::new();
+        let department = store
+            .department_insert(Department {
+                company_id: Some(1),
+                name: Some("Logistics".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let foreign = Contact {
+            name: Some("Ivanov".to_string()),
+            company_id: Some(2),
+            department_id: Some(department.id),
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.contact_insert(foreign.clone()),
+            Err(RpelError::Invalid(_))
+        ));
+        let contact = store
+            .contact_insert(Contact {
+                company_id: Some(1),
+                ..foreign
+            })
+            .unwrap();
+        let patch = ContactPatch {
+            company_id: Some(Some(2)),
+            updated_at: store.contact_get(contact.id).unwrap().updated_at,
+            ..Default::default()
+        };
+        assert!(matches!(
+            store.contact_patch(contact.id, patch),
+            Err(RpelError::Invalid(_))
+        ));
+        assert_eq!(store.contact_get(contact.id).unwrap().company_id, Some(1));
+
+        let mut moved = store.department_get(department.id).unwrap();
+        moved.company_id = Some(2);
+        assert!(matches!(
+            store.department_update(moved.clone()),
+            Err(RpelError::Invalid(_))
+        ));
+        assert!(matches!(
+            store.department_delete(department.id),
+            Err(RpelError::Invalid(_))
+        ));
+        store.contact_delete(contact.id).unwrap();
+        assert_eq!(store.department_update(moved).unwrap(), 1);
+        assert_eq!(store.department_delete(department.id).unwrap(), 1);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use crate::{error::RpelError, health::PoolStats, RpelPool};
+
+// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+#[derive(Default)]
+struct Operation {
+    calls: u64,
+    errors: BTreeMap<&'static str, u64>,
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+}
+
+impl Operation {
+    const fn new() -> Operation {
+        Operation {
+            calls: 0,
+            errors: BTreeMap::new(),
+            buckets: [0; BUCKETS.len()],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, elapsed: Duration, error: Option<&RpelError>) {
+        let seconds = elapsed.as_secs_f64();
+        self.calls += 1;
+        self.sum += seconds;
+        for (count, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+        if let Some(error) = error {
+            *self.errors.entry(error.kind()).or_default() += 1;
+        }
+    }
+}
+
+struct Registry {
+    operations: BTreeMap<(&'static str, &'static str), Operation>,
+    // Taking a connection happens before any operation is traced, so waits
+    // and failures of the pool are kept apart.
+    acquire: Operation,
+}
+
+impl Registry {
+    const fn new() -> Registry {
+        Registry {
+            operations: BTreeMap::new(),
+            acquire: Operation::new(),
+        }
+    }
+
+    fn observe(
+        &mut self,
+        entity: &'static str,
+        operation: &'static str,
+        elapsed: Duration,
+        error: Option<&RpelError>,
+    ) {
+        self.operations
+            .entry((entity, operation))
+            .or_default()
+            .observe(elapsed, error);
+    }
+
+    fn render(&self, out: &mut String) {
+        header(
+            out,
+            "rpel_operations_total",
+            "counter",
+            "Entity operations executed.",
+        );
+        for ((entity, operation), stats) in &self.operations {
+            let _ = writeln!(
+                out,
+                "rpel_operations_total{{entity=\"{entity}\",operation=\"{operation}\"}} {}",
+                stats.calls
+            );
+        }
+        header(
+            out,
+            "rpel_operation_errors_total",
+            "counter",
+            "Entity operations that failed, by error kind.",
+        );
+        for ((entity, operation), stats) in &self.operations {
+            for (kind, count) in &stats.errors {
+                let _ = writeln!(
+                    out,
+                    "rpel_operation_errors_total{{entity=\"{entity}\",operation=\"{operation}\",error=\"{kind}\"}} {count}"
+                );
+            }
+        }
+        header(
+            out,
+            "rpel_operation_duration_seconds",
+            "histogram",
+            "Entity operation latency.",
+        );
+        for ((entity, operation), stats) in &self.operations {
+            let labels = format!("entity=\"{entity}\",operation=\"{operation}\"");
+            for (count, bound) in stats.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "rpel_operation_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "rpel_operation_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.calls
+            );
+            let _ = writeln!(
+                out,
+                "rpel_operation_duration_seconds_sum{{{labels}}} {}",
+                stats.sum
+            );
+            let _ = writeln!(
+                out,
+                "rpel_operation_duration_seconds_count{{{labels}}} {}",
+                stats.calls
+            );
+        }
+        header(
+            out,
+            "rpel_pool_acquire_total",
+            "counter",
+            "Connections taken from the pool.",
+        );
+        let _ = writeln!(out, "rpel_pool_acquire_total {}", self.acquire.calls);
+        header(
+            out,
+            "rpel_pool_acquire_errors_total",
+            "counter",
+            "Connections the pool failed to hand out, by error kind.",
+        );
+        for (kind, count) in &self.acquire.errors {
+            let _ = writeln!(
+                out,
+                "rpel_pool_acquire_errors_total{{error=\"{kind}\"}} {count}"
+            );
+        }
+        header(
+            out,
+            "rpel_pool_acquire_duration_seconds",
+            "histogram",
+            "Time spent waiting for a pooled connection.",
+        );
+        for (count, bound) in self.acquire.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "rpel_pool_acquire_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "rpel_pool_acquire_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.acquire.calls
+        );
+        let _ = writeln!(
+            out,
+            "rpel_pool_acquire_duration_seconds_sum {}",
+            self.acquire.sum
+        );
+        let _ = writeln!(
+            out,
+            "rpel_pool_acquire_duration_seconds_count {}",
+            self.acquire.calls
+        );
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+pub(crate) fn observe(
+    entity: &'static str,
+    operation: &'static str,
+    elapsed: Duration,
+    error: Option<&RpelError>,
+) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.observe(entity, operation, elapsed, error);
+    }
+}
+
+pub(crate) fn observe_acquire(elapsed: Duration, error: Option<&RpelError>) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.acquire.observe(elapsed, error);
+    }
+}
+
+// Renders everything collected so far in the Prometheus text exposition
+// format; the pool gauges are included when a pool is given.
+pub fn render(pool: Option<&RpelPool>) -> String {
+    let mut out = String::new();
+    if let Ok(registry) = REGISTRY.lock() {
+        registry.render(&mut out);
+    }
+    if let Some(pool) = pool {
+        let stats = PoolStats::get(pool);
+        gauge(
+            &mut out,
+            "rpel_pool_max_size",
+            "Maximum number of pooled connections.",
+            stats.max_size,
+        );
+        gauge(
+            &mut out,
+            "rpel_pool_size",
+            "Connections currently open.",
+            stats.size,
+        );
+        gauge(
+            &mut out,
+            "rpel_pool_available",
+            "Idle connections ready for use.",
+            stats.available,
+        );
+        gauge(
+            &mut out,
+            "rpel_pool_waiting",
+            "Callers waiting for a connection.",
+            stats.waiting,
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_counts_calls_errors_and_buckets() {
+        let mut registry = Registry::new();
+        let error = RpelError::Conflict("sirens".to_string(), 1);
+        registry.observe("Siren", "get_all", Duration::from_millis(3), None);
+        registry.observe("Siren", "get_all", Duration::from_secs(7), Some(&error));
+        let mut out = String::new();
+        registry.render(&mut out);
+        assert!(out.contains("rpel_operations_total{entity=\"Siren\",operation=\"get_all\"} 2"));
+        assert!(out.contains(
+            "rpel_operation_errors_total{entity=\"Siren\",operation=\"get_all\",error=\"Conflict\"} 1"
+        ));
+        assert!(out.contains(
+            "rpel_operation_duration_seconds_bucket{entity=\"Siren\",operation=\"get_all\",le=\"0.005\"} 1"
+        ));
+        assert!(out.contains(
+            "rpel_operation_duration_seconds_bucket{entity=\"Siren\",operation=\"get_all\",le=\"+Inf\"} 2"
+        ));
+    }
+
+    #[test]
+    fn render_counts_pool_acquire_errors() {
+        let mut registry = Registry::new();
+        let error = RpelError::Pool(deadpool_postgres::PoolError::Closed);
+        registry.acquire.observe(Duration::from_millis(2), None);
+        registry
+            .acquire
+            .observe(Duration::from_millis(30), Some(&error));
+        let mut out = String::new();
+        registry.render(&mut out);
+        assert!(out.contains("rpel_pool_acquire_total 2"));
+        assert!(out.contains("rpel_pool_acquire_errors_total{error=\"Pool\"} 1"));
+        assert!(out.contains("rpel_pool_acquire_duration_seconds_bucket{le=\"0.005\"} 1"));
+        assert!(out.contains("rpel_pool_acquire_duration_seconds_count 2"));
+    }
+}
@@ -0,0 +1,271 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{acquire, error::RpelError, GenericClient, RpelPool, Transaction};
+
+// The current schema, reference tables first. A fresh database is created from
+// these files and every migration below is recorded as already applied.
+pub const SCHEMA: [&str; 28] = [
+    include_str!("../sql/schema_migration.sql"),
+    include_str!("../sql/scope.sql"),
+    include_str!("../sql/kind.sql"),
+    include_str!("../sql/rank.sql"),
+    include_str!("../sql/post.sql"),
+    include_str!("../sql/department.sql"),
+    include_str!("../sql/siren_type.sql"),
+    include_str!("../sql/required_post.sql"),
+    include_str!("../sql/company.sql"),
+    include_str!("../sql/contacts.sql"),
+    include_str!("../sql/employment.sql"),
+    include_str!("../sql/phone.sql"),
+    include_str!("../sql/email.sql"),
+    include_str!("../sql/practice.sql"),
+    include_str!("../sql/education.sql"),
+    include_str!("../sql/certificate.sql"),
+    include_str!("../sql/siren.sql"),
+    include_str!("../sql/siren_check.sql"),
+    include_str!("../sql/warning_zone.sql"),
+    include_str!("../sql/warning_zone_siren.sql"),
+    include_str!("../sql/scenario.sql"),
+    include_str!("../sql/scenario_zone.sql"),
+    include_str!("../sql/settlement.sql"),
+    include_str!("../sql/street.sql"),
+    include_str!("../sql/address.sql"),
+    include_str!("../sql/attachment.sql"),
+    include_str!("../sql/attachment_chunk.sql"),
+    include_str!("../sql/user.sql"),
+];
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: [Migration; 12] = [
+    Migration {
+        version: 1,
+        name: "add_fullname_to_companies",
+        sql: include_str!("../migrate/1_add_fullname_to_companies.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "rename_fullname_to_full_name",
+        sql: include_str!("../migrate/2_rename_fullname_to_full_name.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "add_parent_id_to_companies",
+        sql: include_str!("../migrate/3_add_parent_id_to_companies.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "scope_departments_to_companies",
+        sql: include_str!("../migrate/4_scope_departments_to_companies.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_employments",
+        sql: include_str!("../migrate/5_add_employments.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "add_required_posts",
+        sql: include_str!("../migrate/6_add_required_posts.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "add_siren_checks",
+        sql: include_str!("../migrate/7_add_siren_checks.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "add_warning_zones_and_scenarios",
+        sql: include_str!("../migrate/8_add_warning_zones_and_scenarios.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "add_addresses",
+        sql: include_str!("../migrate/9_add_addresses.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "add_attachments",
+        sql: include_str!("../migrate/10_add_attachments.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "add_unique_key_to_users",
+        sql: include_str!("../migrate/11_add_unique_key_to_users.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "fill_missing_updated_at",
+        sql: include_str!("../migrate/12_fill_missing_updated_at.sql"),
+    },
+];
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied: bool,
+    pub applied_at: Option<NaiveDateTime>,
+}
+
+// Brings the database up to date in one transaction and returns the versions
+// it applied. An empty database gets the current schema instead of replaying
+// every migration; one with tables but no versions has to be baselined first,
+// since there is no telling which migrations it already has.
+pub async fn run(pool: &RpelPool) -> Result<Vec<i64>, RpelError> {
+    let mut client = acquire(pool).await?;
+    let tx = client.transaction().await?;
+    tx.batch_execute(SCHEMA[0]).await?;
+    let applied = applied_versions(&tx).await?;
+    let row = tx
+        .query_one("SELECT to_regclass('companies') IS NULL", &[])
+        .await?;
+    let fresh: bool = row.try_get(0)?;
+    if applied.is_empty() && !fresh {
+        return Err(RpelError::Invalid(
+            "the database has tables but no recorded migrations, mark the ones it already has with `rpel migrate --baseline <version>`".to_string(),
+        ));
+    }
+    let mut versions = Vec::new();
+    if applied.is_empty() {
+        for ddl in SCHEMA {
+            tx.batch_execute(ddl).await?;
+        }
+        for migration in &MIGRATIONS {
+            record(&tx, migration).await?;
+            versions.push(migration.version);
+        }
+    } else {
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+        {
+            tx.batch_execute(migration.sql).await?;
+            record(&tx, migration).await?;
+            versions.push(migration.version);
+        }
+    }
+    tx.commit().await?;
+    Ok(versions)
+}
+
+// Marks every migration up to `version` as applied without running it, for
+// databases that were migrated by hand before versions were tracked.
+pub async fn baseline(pool: &RpelPool, version: i64) -> Result<Vec<i64>, RpelError> {
+    let mut client = acquire(pool).await?;
+    let tx = client.transaction().await?;
+    tx.batch_execute(SCHEMA[0]).await?;
+    let applied = applied_versions(&tx).await?;
+    let mut versions = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version <= version && !applied.contains(&migration.version))
+    {
+        record(&tx, migration).await?;
+        versions.push(migration.version);
+    }
+    tx.commit().await?;
+    Ok(versions)
+}
+
+pub async fn status(pool: &RpelPool) -> Result<Vec<MigrationStatus>, RpelError> {
+    let client = acquire(pool).await?;
+    let row = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?;
+    let tracked: bool = row.try_get(0)?;
+    let mut applied = Vec::new();
+    if tracked {
+        for row in client
+            .query(
+                "
+                    SELECT
+                        version,
+                        applied_at
+                    FROM
+                        schema_migrations
+                ",
+                &[],
+            )
+            .await?
+        {
+            applied.push((row.try_get::<_, i64>(0)?, row.try_get(1)?));
+        }
+    }
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| {
+            let found = applied
+                .iter()
+                .find(|(version, _)| *version == migration.version);
+            MigrationStatus {
+                version: migration.version,
+                name: migration.name.to_string(),
+                applied: found.is_some(),
+                applied_at: found.and_then(|(_, applied_at)| *applied_at),
+            }
+        })
+        .collect())
+}
+
+// The last migration applied, or 0 for a database that has none recorded.
+pub async fn current_version<C: GenericClient>(client: &C) -> Result<i64, RpelError> {
+    let row = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?;
+    let tracked: bool = row.try_get(0)?;
+    if !tracked {
+        return Ok(0);
+    }
+    let row = client
+        .query_one(
+            "SELECT coalesce(max(version), 0) FROM schema_migrations",
+            &[],
+        )
+        .await?;
+    Ok(row.try_get(0)?)
+}
+
+async fn applied_versions(tx: &Transaction<'_>) -> Result<Vec<i64>, RpelError> {
+    let mut versions = Vec::new();
+    for row in tx
+        .query(
+            "
+                SELECT
+                    version
+                FROM
+                    schema_migrations
+            ",
+            &[],
+        )
+        .await?
+    {
+        versions.push(row.try_get(0)?);
+    }
+    Ok(versions)
+}
+
+async fn record(tx: &Transaction<'_>, migration: &Migration) -> Result<(), RpelError> {
+    tx.execute(
+        "
+            INSERT INTO schema_migrations
+            (
+                version,
+                name
+            )
+            VALUES
+            (
+                $1,
+                $2
+            )
+        ",
+        &[&migration.version, &migration.name],
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use deadpool_postgres::GenericClient;
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_postgres::{
+    tls::{MakeTlsConnect, TlsConnect},
+    AsyncMessage, Config, NoTls, Socket,
+};
+
+use crate::{error::RpelError, require_tls, settings::Settings};
+
+pub const CHANNEL: &str = "rpel_changes";
+
+const MIN_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+const BUFFER: usize = 256;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+// The NOTIFY payload; `entity` is the table name.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    pub entity: String,
+    pub id: i64,
+    pub operation: Operation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Change(Change),
+    // The connection was lost and is back; changes made in between were not
+    // seen, so anything cached should be reloaded.
+    Resync,
+}
+
+// Sends a change notification when `rows` says something was written. Inside
+// a transaction Postgres holds it back until commit and drops it on rollback.
+pub(crate) async fn notify<C: GenericClient>(
+    client: &C,
+    entity: &str,
+    id: i64,
+    operation: Operation,
+    rows: u64,
+) -> Result<(), RpelError> {
+    if rows == 0 {
+        return Ok(());
+    }
+    let payload = serde_json::to_string(&Change {
+        entity: entity.to_string(),
+        id,
+        operation,
+    })?;
+    client
+        .execute("SELECT pg_notify($1, $2)", &[&CHANNEL, &payload])
+        .await?;
+    Ok(())
+}
+
+// Change events read from a connection of its own, which is reopened with
+// backoff whenever it drops. Dropping the stream closes the connection.
+pub struct ChangeStream {
+    events: mpsc::Receiver<Event>,
+    task: JoinHandle<()>,
+}
+
+impl ChangeStream {
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for ChangeStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for ChangeStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub fn listen(pg_cfg: &str) -> Result<ChangeStream, RpelError> {
+    Ok(listen_config(pg_cfg.parse()?, NoTls))
+}
+
+pub fn listen_from_settings(settings: &Settings) -> Result<ChangeStream, RpelError> {
+    let pg_cfg = settings.pg_config()?;
+    Ok(match &settings.tls {
+        Some(tls) => listen_config(require_tls(pg_cfg), tls.connector()?),
+        None => listen_config(pg_cfg, NoTls),
+    })
+}
+
+// Must be called from within a Tokio runtime.
+pub fn listen_config<T>(pg_cfg: Config, tls: T) -> ChangeStream
+where
+    T: MakeTlsConnect<Socket> + Clone + Send + 'static,
+    T::Stream: Send,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let (sender, events) = mpsc::channel(BUFFER);
+    let task = tokio::spawn(run(pg_cfg, tls, sender));
+    ChangeStream { events, task }
+}
+
+async fn run<T>(pg_cfg: Config, tls: T, sender: mpsc::Sender<Event>)
+where
+    T: MakeTlsConnect<Socket> + Clone + Send + 'static,
+    T::Stream: Send,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let mut retry = MIN_RETRY;
+    let mut sessions = 0;
+    loop {
+        let before = sessions;
+        let result = session(&pg_cfg, tls.clone(), &sender, &mut sessions).await;
+        if sender.is_closed() {
+            return;
+        }
+        if sessions > before {
+            retry = MIN_RETRY;
+        }
+        match result {
+            Ok(()) => tracing::warn!("change listener disconnected, reconnecting in {retry:?}"),
+            Err(err) => tracing::warn!("change listener: {err}, reconnecting in {retry:?}"),
+        }
+        tokio::time::sleep(retry).await;
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+// One connection's worth of listening; `sessions` counts the connections that
+// got as far as LISTEN, so every one after the first starts with a resync.
+async fn session<T>(
+    pg_cfg: &Config,
+    tls: T,
+    sender: &mpsc::Sender<Event>,
+    sessions: &mut u64,
+) -> Result<(), RpelError>
+where
+    T: MakeTlsConnect<Socket> + Send + 'static,
+    T::Stream: Send,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    let (client, mut connection) = pg_cfg.connect(tls).await?;
+    let (notes, mut received) = mpsc::unbounded_channel();
+    // Notifications only arrive while the connection itself is polled.
+    let driver = tokio::spawn(async move {
+        while let Some(message) = poll_fn(|cx| connection.poll_message(cx)).await {
+            match message {
+                Ok(AsyncMessage::Notification(note)) => {
+                    let _ = notes.send(note);
+                }
+                Ok(_) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    });
+    client.batch_execute(&format!("LISTEN {CHANNEL}")).await?;
+    if *sessions > 0 && sender.send(Event::Resync).await.is_err() {
+        return Ok(());
+    }
+    *sessions += 1;
+    while let Some(note) = received.recv().await {
+        match serde_json::from_str(note.payload()) {
+            Ok(change) => {
+                if sender.send(Event::Change(change)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(err) => tracing::warn!("change listener: bad payload {:?}: {err}", note.payload()),
+        }
+    }
+    match driver.await {
+        Ok(Err(err)) => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_round_trip() {
+        let change = Change {
+            entity: "companies".to_string(),
+            id: 7,
+            operation: Operation::Delete,
+        };
+        let payload = serde_json::to_string(&change).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"entity":"companies","id":7,"operation":"delete"}"#
+        );
+        assert_eq!(serde_json::from_str::<Change>(&payload).unwrap(), change);
+    }
+}
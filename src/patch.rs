@@ -0,0 +1,120 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Deserializer};
+use tokio_postgres::types::ToSql;
+
+use crate::{
+    check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace,
+};
+
+// Lets a patch tell a field that was left out (`None`) from one that was
+// explicitly cleared with `null` (`Some(None)`).
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub(crate) struct Patch<'a> {
+    table: &'static str,
+    id: &'a i64,
+    columns: Vec<&'static str>,
+    params: Vec<&'a (dyn ToSql + Sync)>,
+}
+
+impl<'a> Patch<'a> {
+    pub(crate) fn new(table: &'static str, id: &'a i64) -> Self {
+        Patch {
+            table,
+            id,
+            columns: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set<T: ToSql + Sync>(
+        &mut self,
+        column: &'static str,
+        value: &'a Option<T>,
+    ) -> &mut Self {
+        if let Some(value) = value {
+            self.columns.push(column);
+            self.params.push(value);
+        }
+        self
+    }
+
+    fn sql(&self) -> String {
+        let mut sets: Vec<String> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{column} = ${}", i + 2))
+            .collect();
+        let now = self.columns.len() + 2;
+        let version = now + 1;
+        sets.push(format!("updated_at = ${now}"));
+        format!(
+            "UPDATE {} SET {} WHERE id = $1 AND updated_at = ${version}",
+            self.table,
+            sets.join(", ")
+        )
+    }
+
+    pub(crate) async fn execute<C: GenericClient>(
+        &self,
+        client: &C,
+        updated_at: &Option<NaiveDateTime>,
+    ) -> Result<u64, RpelError> {
+        let now = Local::now().naive_local();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![self.id];
+        params.extend(self.params.iter().copied());
+        params.push(&now);
+        params.push(updated_at);
+        let sql = self.sql();
+        trace::record(&sql);
+        let result = client.execute(sql.as_str(), &params).await?;
+        let result = check_version(client, self.table, *self.id, *updated_at, result).await?;
+        notify(client, self.table, *self.id, Operation::Update, result).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct NotePatch {
+        #[serde(default, deserialize_with = "double_option")]
+        note: Option<Option<String>>,
+    }
+
+    #[test]
+    fn double_option_tells_missing_from_null() {
+        let missing: NotePatch = serde_json::from_str("{}").unwrap();
+        let null: NotePatch = serde_json::from_str(r#"{"note":null}"#).unwrap();
+        let value: NotePatch = serde_json::from_str(r#"{"note":"x"}"#).unwrap();
+        assert_eq!(missing.note, None);
+        assert_eq!(null.note, Some(None));
+        assert_eq!(value.note, Some(Some("x".to_string())));
+    }
+
+    #[test]
+    fn sql_lists_only_supplied_columns() {
+        let id = 7;
+        let note = Some(Some("x".to_string()));
+        let name: Option<Option<String>> = None;
+        let mut patch = Patch::new("contacts", &id);
+        patch.set("name", &name).set("note", &note);
+        assert_eq!(
+            patch.sql(),
+            "UPDATE contacts SET note = $2, updated_at = $3 WHERE id = $1 AND updated_at = $4"
+        );
+    }
+}
@@ -1,7 +1,13 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    error::RpelError,
+    trace::{prepare, traced},
+    RpelPool,
+};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Phone {
@@ -21,45 +27,76 @@ impl Phone {
         Default::default()
     }
 
-    async fn insert(pool: &RpelPool, phone: Phone) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+    // Brings the numbers stored for one owner in line with `phones`: rows that
+    // are still wanted keep their id and created_at, missing ones are added in
+    // a single statement and only the dropped ones are deleted.
+    async fn replace<C: GenericClient>(
+        client: &C,
+        owner: &str,
+        id: i64,
+        fax: bool,
+        phones: Vec<i64>,
+    ) -> Result<(), RpelError> {
+        let delete = prepare(
+            client,
+            format!(
+                "
+                    DELETE FROM
+                        phones
+                    WHERE
+                        {owner} = $1
+                    AND
+                        fax = $2
+                    AND
+                        (phone IS NULL OR phone <> ALL($3))
+                "
+            )
+            .as_str(),
+        )
+        .await?;
+        client.execute(&delete, &[&id, &fax, &phones]).await?;
+        let insert = prepare(
+            client,
+            format!(
                 "
                     INSERT INTO phones
                     (
-                        company_id,
-                        contact_id,
+                        {owner},
                         phone,
                         fax,
                         created_at,
                         updated_at
                     )
-                    VALUES
-                    (
-                        $1,
-                        $2,
-                        $3,
-                        $4,
-                        $5,
-                        $6
-                    )
-                ",
+                    SELECT DISTINCT
+                        $1::bigint,
+                        p.phone,
+                        $2::bool,
+                        $4::timestamp,
+                        $4::timestamp
+                    FROM
+                        unnest($3::bigint[]) AS p(phone)
+                    WHERE
+                        NOT EXISTS (
+                            SELECT
+                                1
+                            FROM
+                                phones AS e
+                            WHERE
+                                e.{owner} = $1
+                            AND
+                                e.fax = $2
+                            AND
+                                e.phone = p.phone
+                        )
+                "
             )
+            .as_str(),
+        )
+        .await?;
+        client
+            .execute(&insert, &[&id, &fax, &phones, &Local::now().naive_local()])
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &phone.company_id,
-                    &phone.contact_id,
-                    &phone.phone,
-                    &phone.fax,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+        Ok(())
     }
 
     pub async fn update_contacts(
@@ -68,38 +105,63 @@ impl Phone {
         fax: bool,
         phones: Vec<i64>,
     ) -> Result<(), RpelError> {
-        Phone::delete_contacts(pool, id, fax).await?;
-        for value in phones {
-            let mut phone = Phone::new();
-            phone.contact_id = Some(id);
-            phone.phone = Some(value);
-            phone.fax = fax;
-            Phone::insert(pool, phone).await?;
-        }
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        Phone::update_contacts_with(&tx, id, fax, phones).await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    pub async fn update_contacts_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        fax: bool,
+        phones: Vec<i64>,
+    ) -> Result<(), RpelError> {
+        traced("Phone", "update_contacts", async move {
+            Phone::replace(client, "contact_id", id, fax, phones).await
+        })
+        .await
+    }
+
     pub async fn update_companies(
         pool: &RpelPool,
         id: i64,
         fax: bool,
         phones: Vec<i64>,
     ) -> Result<(), RpelError> {
-        Phone::delete_companies(pool, id, fax).await?;
-        for value in phones {
-            let mut phone = Phone::new();
-            phone.company_id = Some(id);
-            phone.phone = Some(value);
-            phone.fax = fax;
-            Phone::insert(pool, phone).await?;
-        }
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        Phone::update_companies_with(&tx, id, fax, phones).await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    pub async fn update_companies_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        fax: bool,
+        phones: Vec<i64>,
+    ) -> Result<(), RpelError> {
+        traced("Phone", "update_companies", async move {
+            Phone::replace(client, "company_id", id, fax, phones).await
+        })
+        .await
+    }
+
     pub async fn delete_contacts(pool: &RpelPool, id: i64, fax: bool) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Phone::delete_contacts_with(&client, id, fax).await
+    }
+
+    pub async fn delete_contacts_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        fax: bool,
+    ) -> Result<u64, RpelError> {
+        traced("Phone", "delete_contacts", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         phones
@@ -110,13 +172,24 @@ impl Phone {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id, &fax]).await?)
+            Ok(client.execute(&stmt, &[&id, &fax]).await?)
+        })
+        .await
     }
 
     pub async fn delete_companies(pool: &RpelPool, id: i64, fax: bool) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Phone::delete_companies_with(&client, id, fax).await
+    }
+
+    pub async fn delete_companies_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        fax: bool,
+    ) -> Result<u64, RpelError> {
+        traced("Phone", "delete_companies", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         phones
@@ -127,6 +200,8 @@ impl Phone {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id, &fax]).await?)
+            Ok(client.execute(&stmt, &[&id, &fax]).await?)
+        })
+        .await
     }
 }
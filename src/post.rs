@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Post {
     #[serde(default)]
     pub id: i64,
@@ -12,10 +19,11 @@ pub struct Post {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(Post);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PostList {
     pub id: i64,
@@ -30,9 +38,14 @@ impl Post {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Post, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Post::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Post, RpelError> {
+        traced("Post", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -47,23 +60,33 @@ impl Post {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let post = Post {
-            id,
-            name: row.try_get(0)?,
-            go: row.try_get(1)?,
-            note: row.try_get(2)?,
-            created_at: row.try_get(3)?,
-            updated_at: row.try_get(4)?,
-        };
-        Ok(post)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("posts".to_string(), id))?;
+            let post = Post {
+                id,
+                name: row.try_get(0)?,
+                go: row.try_get(1)?,
+                note: row.try_get(2)?,
+                created_at: row.try_get(3)?,
+                updated_at: row.try_get(4)?,
+            };
+            Ok(post)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, post: Post) -> Result<Post, RpelError> {
-        let mut post = post;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Post::insert_with(&client, post).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(client: &C, post: Post) -> Result<Post, RpelError> {
+        traced("Post", "insert", async move {
+            let mut post = post;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO posts
                     (
@@ -86,26 +109,34 @@ impl Post {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &post.name,
-                    &post.go,
-                    &post.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        post.id = row.get(0);
-        Ok(post)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &post.name,
+                        &post.go,
+                        &post.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            post.id = row.get(0);
+            notify(client, "posts", post.id, Operation::Insert, 1).await?;
+            Ok(post)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, post: Post) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Post::update_with(&client, post).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, post: Post) -> Result<u64, RpelError> {
+        traced("Post", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE posts SET
                         name = $2,
@@ -114,27 +145,40 @@ impl Post {
                         updated_at = $5
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $6
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &post.id,
-                    &post.name,
-                    &post.go,
-                    &post.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &post.id,
+                        &post.name,
+                        &post.go,
+                        &post.note,
+                        &Local::now().naive_local(),
+                        &post.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(client, "posts", post.id, post.updated_at, result).await?;
+            notify(client, "posts", post.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Post::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Post", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         posts
@@ -143,16 +187,25 @@ impl Post {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "posts", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl PostList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<PostList>, RpelError> {
-        let mut posts = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        PostList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<PostList>, RpelError> {
+        traced("PostList", "get_all", async move {
+            let mut posts = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -166,14 +219,16 @@ impl PostList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            posts.push(PostList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                go: row.try_get(2)?,
-                note: row.try_get(3)?,
-            });
-        }
-        Ok(posts)
+            for row in client.query(&stmt, &[]).await? {
+                posts.push(PostList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    go: row.try_get(2)?,
+                    note: row.try_get(3)?,
+                });
+            }
+            Ok(posts)
+        })
+        .await
     }
 }
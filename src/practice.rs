@@ -1,9 +1,19 @@
 use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    attachment::{Attachment, AttachmentOwner},
+    check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Clone, Default, Debug, Deserialize, Serialize)]
 pub struct Practice {
     #[serde(default)]
     pub id: i64,
@@ -14,11 +24,27 @@ pub struct Practice {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+single_row!(Practice);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PracticePatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub company_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub kind_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub topic: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub date_of_practice: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct PracticeList {
     pub id: i64,
     pub company_id: Option<i64>,
@@ -47,9 +73,14 @@ impl Practice {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Practice, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Practice::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Practice, RpelError> {
+        traced("Practice", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         company_id,
@@ -66,25 +97,38 @@ impl Practice {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let practice = Practice {
-            id,
-            company_id: row.try_get(0)?,
-            kind_id: row.try_get(1)?,
-            topic: row.try_get(2)?,
-            date_of_practice: row.try_get(3)?,
-            note: row.try_get(4)?,
-            created_at: row.try_get(5)?,
-            updated_at: row.try_get(6)?,
-        };
-        Ok(practice)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("practices".to_string(), id))?;
+            let practice = Practice {
+                id,
+                company_id: row.try_get(0)?,
+                kind_id: row.try_get(1)?,
+                topic: row.try_get(2)?,
+                date_of_practice: row.try_get(3)?,
+                note: row.try_get(4)?,
+                created_at: row.try_get(5)?,
+                updated_at: row.try_get(6)?,
+            };
+            Ok(practice)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, practice: Practice) -> Result<Practice, RpelError> {
-        let mut practice = practice;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Practice::insert_with(&client, practice).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        practice: Practice,
+    ) -> Result<Practice, RpelError> {
+        traced("Practice", "insert", async move {
+            let mut practice = practice;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO practices
                     (
@@ -111,28 +155,39 @@ impl Practice {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &practice.company_id,
-                    &practice.kind_id,
-                    &practice.topic,
-                    &practice.date_of_practice,
-                    &practice.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        practice.id = row.get(0);
-        Ok(practice)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &practice.company_id,
+                        &practice.kind_id,
+                        &practice.topic,
+                        &practice.date_of_practice,
+                        &practice.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            practice.id = row.get(0);
+            notify(client, "practices", practice.id, Operation::Insert, 1).await?;
+            Ok(practice)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, practice: Practice) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Practice::update_with(&client, practice).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        practice: Practice,
+    ) -> Result<u64, RpelError> {
+        traced("Practice", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE practices SET
                         company_id = $2,
@@ -143,29 +198,80 @@ impl Practice {
                         updated_at = $7
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $8
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &practice.id,
-                    &practice.company_id,
-                    &practice.kind_id,
-                    &practice.topic,
-                    &practice.date_of_practice,
-                    &practice.note,
-                    &Local::now().naive_local(),
-                ],
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &practice.id,
+                        &practice.company_id,
+                        &practice.kind_id,
+                        &practice.topic,
+                        &practice.date_of_practice,
+                        &practice.note,
+                        &Local::now().naive_local(),
+                        &practice.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "practices",
+                practice.id,
+                practice.updated_at,
+                result,
             )
-            .await?)
+            .await?;
+            notify(client, "practices", practice.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn patch(
+        pool: &RpelPool,
+        id: i64,
+        practice: PracticePatch,
+    ) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Practice::patch_with(&client, id, practice).await
+    }
+
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        practice: PracticePatch,
+    ) -> Result<u64, RpelError> {
+        traced("Practice", "patch", async move {
+            let mut patch = Patch::new("practices", &id);
+            patch
+                .set("company_id", &practice.company_id)
+                .set("kind_id", &practice.kind_id)
+                .set("topic", &practice.topic)
+                .set("date_of_practice", &practice.date_of_practice)
+                .set("note", &practice.note);
+            patch.execute(client, &practice.updated_at).await
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Practice::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Practice", "delete", async move {
+            Attachment::delete_owned_with(client, AttachmentOwner::Practices, id).await?;
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         practices
@@ -174,16 +280,27 @@ impl Practice {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "practices", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl PracticeList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<PracticeList>, RpelError> {
-        let mut practices = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        PracticeList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<PracticeList>, RpelError> {
+        traced("PracticeList", "get_all", async move {
+            let mut practices = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         p.id,
@@ -205,31 +322,41 @@ impl PracticeList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            let date: Option<NaiveDate> = row.get(6);
-            practices.push(PracticeList {
-                id: row.try_get(0)?,
-                company_id: row.try_get(1)?,
-                company_name: row.try_get(2)?,
-                kind_id: row.try_get(3)?,
-                kind_name: row.try_get(4)?,
-                kind_short_name: row.try_get(5)?,
-                date_of_practice: row.try_get(6)?,
-                topic: row.try_get(7)?,
-                date_str: date.map(|d| d.format("%Y-%m-%d").to_string()),
-            });
-        }
-        Ok(practices)
+            for row in client.query(&stmt, &[]).await? {
+                let date: Option<NaiveDate> = row.get(6);
+                practices.push(PracticeList {
+                    id: row.try_get(0)?,
+                    company_id: row.try_get(1)?,
+                    company_name: row.try_get(2)?,
+                    kind_id: row.try_get(3)?,
+                    kind_name: row.try_get(4)?,
+                    kind_short_name: row.try_get(5)?,
+                    date_of_practice: row.try_get(6)?,
+                    topic: row.try_get(7)?,
+                    date_str: date.map(|d| d.format("%Y-%m-%d").to_string()),
+                });
+            }
+            Ok(practices)
+        })
+        .await
     }
 
     pub async fn get_by_company(
         pool: &RpelPool,
         company_id: i64,
     ) -> Result<Vec<PracticeList>, RpelError> {
-        let mut practices = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        PracticeList::get_by_company_with(&client, company_id).await
+    }
+
+    pub async fn get_by_company_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+    ) -> Result<Vec<PracticeList>, RpelError> {
+        traced("PracticeList", "get_by_company", async move {
+            let mut practices = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         p.id,
@@ -253,30 +380,39 @@ impl PracticeList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[&company_id]).await? {
-            let date: Option<NaiveDate> = row.get(6);
-            practices.push(PracticeList {
-                id: row.try_get(0)?,
-                company_id: row.try_get(1)?,
-                company_name: row.try_get(2)?,
-                kind_id: row.try_get(3)?,
-                kind_name: row.try_get(4)?,
-                kind_short_name: row.try_get(5)?,
-                date_of_practice: row.try_get(6)?,
-                topic: row.try_get(7)?,
-                date_str: date.map(|d| d.format("%Y-%m-%d").to_string()),
-            });
-        }
-        Ok(practices)
+            for row in client.query(&stmt, &[&company_id]).await? {
+                let date: Option<NaiveDate> = row.get(6);
+                practices.push(PracticeList {
+                    id: row.try_get(0)?,
+                    company_id: row.try_get(1)?,
+                    company_name: row.try_get(2)?,
+                    kind_id: row.try_get(3)?,
+                    kind_name: row.try_get(4)?,
+                    kind_short_name: row.try_get(5)?,
+                    date_of_practice: row.try_get(6)?,
+                    topic: row.try_get(7)?,
+                    date_str: date.map(|d| d.format("%Y-%m-%d").to_string()),
+                });
+            }
+            Ok(practices)
+        })
+        .await
     }
 }
 
 impl PracticeShort {
     pub async fn get_near(pool: &RpelPool) -> Result<Vec<PracticeShort>, RpelError> {
-        let mut practices = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        PracticeShort::get_near_with(&client).await
+    }
+
+    pub async fn get_near_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<PracticeShort>, RpelError> {
+        traced("PracticeShort", "get_near", async move {
+            let mut practices = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         p.id,
@@ -299,16 +435,18 @@ impl PracticeShort {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            practices.push(PracticeShort {
-                id: row.try_get(0)?,
-                company_id: row.try_get(1)?,
-                company_name: row.try_get(2)?,
-                kind_id: row.try_get(3)?,
-                kind_short_name: row.try_get(4)?,
-                date_of_practice: row.try_get(5)?,
-            });
-        }
-        Ok(practices)
+            for row in client.query(&stmt, &[]).await? {
+                practices.push(PracticeShort {
+                    id: row.try_get(0)?,
+                    company_id: row.try_get(1)?,
+                    company_name: row.try_get(2)?,
+                    kind_id: row.try_get(3)?,
+                    kind_short_name: row.try_get(4)?,
+                    date_of_practice: row.try_get(5)?,
+                });
+            }
+            Ok(practices)
+        })
+        .await
     }
 }
@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Rank {
     #[serde(default)]
     pub id: i64,
@@ -11,10 +18,11 @@ pub struct Rank {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(Rank);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RankList {
     pub id: i64,
@@ -28,9 +36,14 @@ impl Rank {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Rank, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Rank::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Rank, RpelError> {
+        traced("Rank", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -44,22 +57,32 @@ impl Rank {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let rank = Rank {
-            id,
-            name: row.try_get(0)?,
-            note: row.try_get(1)?,
-            created_at: row.try_get(2)?,
-            updated_at: row.try_get(3)?,
-        };
-        Ok(rank)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("ranks".to_string(), id))?;
+            let rank = Rank {
+                id,
+                name: row.try_get(0)?,
+                note: row.try_get(1)?,
+                created_at: row.try_get(2)?,
+                updated_at: row.try_get(3)?,
+            };
+            Ok(rank)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, rank: Rank) -> Result<Rank, RpelError> {
-        let mut rank = rank;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Rank::insert_with(&client, rank).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(client: &C, rank: Rank) -> Result<Rank, RpelError> {
+        traced("Rank", "insert", async move {
+            let mut rank = rank;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO ranks
                     (
@@ -80,25 +103,33 @@ impl Rank {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &rank.name,
-                    &rank.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        rank.id = row.get(0);
-        Ok(rank)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &rank.name,
+                        &rank.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            rank.id = row.get(0);
+            notify(client, "ranks", rank.id, Operation::Insert, 1).await?;
+            Ok(rank)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, rank: Rank) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Rank::update_with(&client, rank).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, rank: Rank) -> Result<u64, RpelError> {
+        traced("Rank", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE ranks SET
                         name = $2,
@@ -106,26 +137,39 @@ impl Rank {
                         updated_at = $4
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $5
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &rank.id,
-                    &rank.name,
-                    &rank.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &rank.id,
+                        &rank.name,
+                        &rank.note,
+                        &Local::now().naive_local(),
+                        &rank.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(client, "ranks", rank.id, rank.updated_at, result).await?;
+            notify(client, "ranks", rank.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Rank::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Rank", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         ranks
@@ -134,16 +178,25 @@ impl Rank {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "ranks", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl RankList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<RankList>, RpelError> {
-        let mut ranks = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        RankList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<RankList>, RpelError> {
+        traced("RankList", "get_all", async move {
+            let mut ranks = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -156,13 +209,15 @@ impl RankList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            ranks.push(RankList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                note: row.try_get(2)?,
-            });
-        }
-        Ok(ranks)
+            for row in client.query(&stmt, &[]).await? {
+                ranks.push(RankList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    note: row.try_get(2)?,
+                });
+            }
+            Ok(ranks)
+        })
+        .await
     }
 }
@@ -0,0 +1,292 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// A GO post every company of a scope has to fill, or every company at all when
+// `scope_id` is empty. `head` marks the post of the head of civil defence.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RequiredPost {
+    #[serde(default)]
+    pub id: i64,
+    pub scope_id: Option<i64>,
+    pub post_go_id: Option<i64>,
+    #[serde(default)]
+    pub head: bool,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(RequiredPost);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RequiredPostList {
+    pub id: i64,
+    pub scope_id: Option<i64>,
+    pub scope_name: Option<String>,
+    pub post_go_id: Option<i64>,
+    pub post_go_name: Option<String>,
+    pub head: bool,
+    pub note: Option<String>,
+}
+
+impl RequiredPost {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<RequiredPost, RpelError> {
+        let client = acquire(pool).await?;
+        RequiredPost::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<RequiredPost, RpelError> {
+        traced("RequiredPost", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        scope_id,
+                        post_go_id,
+                        head,
+                        note,
+                        created_at,
+                        updated_at
+                    FROM
+                        required_posts
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("required_posts".to_string(), id))?;
+            let required_post = RequiredPost {
+                id,
+                scope_id: row.try_get(0)?,
+                post_go_id: row.try_get(1)?,
+                head: row.try_get(2)?,
+                note: row.try_get(3)?,
+                created_at: row.try_get(4)?,
+                updated_at: row.try_get(5)?,
+            };
+            Ok(required_post)
+        })
+        .await
+    }
+
+    pub async fn insert(
+        pool: &RpelPool,
+        required_post: RequiredPost,
+    ) -> Result<RequiredPost, RpelError> {
+        let client = acquire(pool).await?;
+        RequiredPost::insert_with(&client, required_post).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        required_post: RequiredPost,
+    ) -> Result<RequiredPost, RpelError> {
+        traced("RequiredPost", "insert", async move {
+            let mut required_post = required_post;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO required_posts
+                    (
+                        scope_id,
+                        post_go_id,
+                        head,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &required_post.scope_id,
+                        &required_post.post_go_id,
+                        &required_post.head,
+                        &required_post.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            required_post.id = row.get(0);
+            notify(
+                client,
+                "required_posts",
+                required_post.id,
+                Operation::Insert,
+                1,
+            )
+            .await?;
+            Ok(required_post)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, required_post: RequiredPost) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        RequiredPost::update_with(&client, required_post).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        required_post: RequiredPost,
+    ) -> Result<u64, RpelError> {
+        traced("RequiredPost", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE required_posts SET
+                        scope_id = $2,
+                        post_go_id = $3,
+                        head = $4,
+                        note = $5,
+                        updated_at = $6
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $7
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &required_post.id,
+                        &required_post.scope_id,
+                        &required_post.post_go_id,
+                        &required_post.head,
+                        &required_post.note,
+                        &Local::now().naive_local(),
+                        &required_post.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "required_posts",
+                required_post.id,
+                required_post.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "required_posts",
+                required_post.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        RequiredPost::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("RequiredPost", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        required_posts
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "required_posts", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+impl RequiredPostList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<RequiredPostList>, RpelError> {
+        let client = acquire(pool).await?;
+        RequiredPostList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<RequiredPostList>, RpelError> {
+        traced("RequiredPostList", "get_all", async move {
+            let mut required_posts = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        r.id,
+                        r.scope_id,
+                        s.name AS scope_name,
+                        r.post_go_id,
+                        p.name AS post_go_name,
+                        r.head,
+                        r.note
+                    FROM
+                        required_posts AS r
+                    LEFT JOIN
+                        scopes AS s ON s.id = r.scope_id
+                    LEFT JOIN
+                        posts AS p ON p.id = r.post_go_id
+                    ORDER BY
+                        s.name ASC NULLS FIRST,
+                        r.head DESC,
+                        p.name ASC,
+                        r.id ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                required_posts.push(RequiredPostList {
+                    id: row.try_get(0)?,
+                    scope_id: row.try_get(1)?,
+                    scope_name: row.try_get(2)?,
+                    post_go_id: row.try_get(3)?,
+                    post_go_name: row.try_get(4)?,
+                    head: row.try_get(5)?,
+                    note: row.try_get(6)?,
+                });
+            }
+            Ok(required_posts)
+        })
+        .await
+    }
+}
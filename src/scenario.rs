@@ -0,0 +1,371 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    warning_zone::{replace_links, unlink, Link},
+    RpelPool,
+};
+
+const SCENARIO_ZONES: Link = Link {
+    table: "scenario_zones",
+    owner: "scenario_id",
+    column: "warning_zone_id",
+    target: "warning_zones",
+};
+
+// A planned activation: the warning zones sounded together.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub id: i64,
+    pub name: Option<String>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub warning_zone_ids: Vec<i64>,
+}
+
+single_row!(Scenario);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScenarioList {
+    pub id: i64,
+    pub name: Option<String>,
+    pub note: Option<String>,
+    pub zone_names: Vec<String>,
+}
+
+// One line of the duty officer's activation checklist: a siren of the
+// scenario, the zones that bring it in and whom to call about it.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ChecklistItem {
+    pub siren_id: i64,
+    pub num_id: Option<i64>,
+    pub address: Option<String>,
+    pub siren_type_name: Option<String>,
+    pub zone_names: Vec<String>,
+    pub contact_id: Option<i64>,
+    pub contact_name: Option<String>,
+    pub phones: Vec<i64>,
+}
+
+impl Scenario {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Scenario, RpelError> {
+        let client = acquire(pool).await?;
+        Scenario::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Scenario, RpelError> {
+        traced("Scenario", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        s.name,
+                        s.note,
+                        s.created_at,
+                        s.updated_at,
+                        array_remove(array_agg(DISTINCT l.warning_zone_id), NULL) AS warning_zone_ids
+                    FROM
+                        scenarios AS s
+                    LEFT JOIN
+                        scenario_zones AS l ON l.scenario_id = s.id
+                    WHERE
+                        s.id = $1
+                    GROUP BY
+                        s.id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("scenarios".to_string(), id))?;
+            let scenario = Scenario {
+                id,
+                name: row.try_get(0)?,
+                note: row.try_get(1)?,
+                created_at: row.try_get(2)?,
+                updated_at: row.try_get(3)?,
+                warning_zone_ids: row.try_get(4)?,
+            };
+            Ok(scenario)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, scenario: Scenario) -> Result<Scenario, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Scenario::insert_with(&tx, scenario).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        scenario: Scenario,
+    ) -> Result<Scenario, RpelError> {
+        traced("Scenario", "insert", async move {
+            let mut scenario = scenario;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO scenarios
+                    (
+                        name,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &scenario.name,
+                        &scenario.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            scenario.id = row.get(0);
+            replace_links(
+                client,
+                &SCENARIO_ZONES,
+                scenario.id,
+                &scenario.warning_zone_ids,
+            )
+            .await?;
+            notify(client, "scenarios", scenario.id, Operation::Insert, 1).await?;
+            Ok(scenario)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, scenario: Scenario) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Scenario::update_with(&tx, scenario).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        scenario: Scenario,
+    ) -> Result<u64, RpelError> {
+        traced("Scenario", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE scenarios SET
+                        name = $2,
+                        note = $3,
+                        updated_at = $4
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $5
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &scenario.id,
+                        &scenario.name,
+                        &scenario.note,
+                        &Local::now().naive_local(),
+                        &scenario.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "scenarios",
+                scenario.id,
+                scenario.updated_at,
+                result,
+            )
+            .await?;
+            if result > 0 {
+                replace_links(
+                    client,
+                    &SCENARIO_ZONES,
+                    scenario.id,
+                    &scenario.warning_zone_ids,
+                )
+                .await?;
+            }
+            notify(client, "scenarios", scenario.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Scenario::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Scenario", "delete", async move {
+            unlink(client, "scenario_zones", "scenario_id", id).await?;
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        scenarios
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "scenarios", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    // Every siren the scenario sounds, once even when several of its zones
+    // hold it, with the responsible contact and their phones.
+    pub async fn checklist(pool: &RpelPool, id: i64) -> Result<Vec<ChecklistItem>, RpelError> {
+        let client = acquire(pool).await?;
+        Scenario::checklist_with(&client, id).await
+    }
+
+    pub async fn checklist_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+    ) -> Result<Vec<ChecklistItem>, RpelError> {
+        traced("Scenario", "checklist", async move {
+            let stmt = prepare(client, "SELECT id FROM scenarios WHERE id = $1").await?;
+            if client.query_opt(&stmt, &[&id]).await?.is_none() {
+                return Err(RpelError::NotFound("scenarios".to_string(), id));
+            }
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        s.id,
+                        s.num_id,
+                        s.address,
+                        t.name AS siren_type_name,
+                        array_remove(array_agg(DISTINCT z.name), NULL) AS zone_names,
+                        s.contact_id,
+                        c.name AS contact_name,
+                        array_remove(array_agg(DISTINCT ph.phone), NULL) AS phones
+                    FROM
+                        scenario_zones AS sz
+                    JOIN
+                        warning_zones AS z ON z.id = sz.warning_zone_id
+                    JOIN
+                        warning_zone_sirens AS zs ON zs.warning_zone_id = z.id
+                    JOIN
+                        sirens AS s ON s.id = zs.siren_id
+                    LEFT JOIN
+                        siren_types AS t ON t.id = s.siren_type_id
+                    LEFT JOIN
+                        contacts AS c ON c.id = s.contact_id
+                    LEFT JOIN
+                        phones AS ph ON ph.contact_id = s.contact_id AND ph.fax = false
+                    WHERE
+                        sz.scenario_id = $1
+                    GROUP BY
+                        s.id,
+                        t.id,
+                        c.id
+                    ORDER BY
+                        s.address ASC,
+                        s.id ASC
+                ",
+            )
+            .await?;
+            let mut items = Vec::new();
+            for row in client.query(&stmt, &[&id]).await? {
+                items.push(ChecklistItem {
+                    siren_id: row.try_get(0)?,
+                    num_id: row.try_get(1)?,
+                    address: row.try_get(2)?,
+                    siren_type_name: row.try_get(3)?,
+                    zone_names: row.try_get(4)?,
+                    contact_id: row.try_get(5)?,
+                    contact_name: row.try_get(6)?,
+                    phones: row.try_get(7)?,
+                });
+            }
+            Ok(items)
+        })
+        .await
+    }
+}
+
+impl ScenarioList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<ScenarioList>, RpelError> {
+        let client = acquire(pool).await?;
+        ScenarioList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<ScenarioList>, RpelError> {
+        traced("ScenarioList", "get_all", async move {
+            let mut scenarios = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        s.id,
+                        s.name,
+                        s.note,
+                        array_remove(array_agg(DISTINCT z.name), NULL) AS zone_names
+                    FROM
+                        scenarios AS s
+                    LEFT JOIN
+                        scenario_zones AS l ON l.scenario_id = s.id
+                    LEFT JOIN
+                        warning_zones AS z ON z.id = l.warning_zone_id
+                    GROUP BY
+                        s.id
+                    ORDER BY
+                        s.name ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                scenarios.push(ScenarioList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    note: row.try_get(2)?,
+                    zone_names: row.try_get(3)?,
+                });
+            }
+            Ok(scenarios)
+        })
+        .await
+    }
+}
@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Scope {
     #[serde(default)]
     pub id: i64,
@@ -11,10 +18,11 @@ pub struct Scope {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(Scope);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ScopeList {
     pub id: i64,
@@ -28,9 +36,14 @@ impl Scope {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Scope, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Scope::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Scope, RpelError> {
+        traced("Scope", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -44,22 +57,35 @@ impl Scope {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let scope = Scope {
-            id,
-            name: row.try_get(0)?,
-            note: row.try_get(1)?,
-            created_at: row.try_get(2)?,
-            updated_at: row.try_get(3)?,
-        };
-        Ok(scope)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("scopes".to_string(), id))?;
+            let scope = Scope {
+                id,
+                name: row.try_get(0)?,
+                note: row.try_get(1)?,
+                created_at: row.try_get(2)?,
+                updated_at: row.try_get(3)?,
+            };
+            Ok(scope)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, scope: Scope) -> Result<Scope, RpelError> {
-        let mut scope = scope;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Scope::insert_with(&client, scope).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        scope: Scope,
+    ) -> Result<Scope, RpelError> {
+        traced("Scope", "insert", async move {
+            let mut scope = scope;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO scopes
                     (
@@ -80,25 +106,33 @@ impl Scope {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &scope.name,
-                    &scope.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        scope.id = row.get(0);
-        Ok(scope)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &scope.name,
+                        &scope.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            scope.id = row.get(0);
+            notify(client, "scopes", scope.id, Operation::Insert, 1).await?;
+            Ok(scope)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, scope: Scope) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Scope::update_with(&client, scope).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, scope: Scope) -> Result<u64, RpelError> {
+        traced("Scope", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE scopes SET
                         name = $2,
@@ -106,26 +140,40 @@ impl Scope {
                         updated_at = $4
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $5
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &scope.id,
-                    &scope.name,
-                    &scope.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &scope.id,
+                        &scope.name,
+                        &scope.note,
+                        &Local::now().naive_local(),
+                        &scope.updated_at,
+                    ],
+                )
+                .await?;
+            let result =
+                check_version(client, "scopes", scope.id, scope.updated_at, result).await?;
+            notify(client, "scopes", scope.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Scope::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Scope", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         scopes
@@ -134,16 +182,25 @@ impl Scope {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "scopes", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl ScopeList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<ScopeList>, RpelError> {
-        let mut scopes = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        ScopeList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<ScopeList>, RpelError> {
+        traced("ScopeList", "get_all", async move {
+            let mut scopes = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -156,13 +213,15 @@ impl ScopeList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            scopes.push(ScopeList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                note: row.try_get(2)?,
-            });
-        }
-        Ok(scopes)
+            for row in client.query(&stmt, &[]).await? {
+                scopes.push(ScopeList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    note: row.try_get(2)?,
+                });
+            }
+            Ok(scopes)
+        })
+        .await
     }
 }
@@ -1,19 +1,27 @@
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    error::RpelError,
+    trace::{prepare, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SelectItem {
     pub id: i64,
     pub name: Option<String>,
 }
 
-async fn select_name(pool: &RpelPool, name: &str) -> Result<Vec<SelectItem>, RpelError> {
-    let client = pool.get().await?;
-    let stmt = client
-        .prepare(
-            format!(
-                "
+async fn select_name<C: GenericClient>(
+    client: &C,
+    name: &str,
+) -> Result<Vec<SelectItem>, RpelError> {
+    let stmt = prepare(
+        client,
+        format!(
+            "
                 SELECT
                     id,
                     name
@@ -22,10 +30,10 @@ async fn select_name(pool: &RpelPool, name: &str) -> Result<Vec<SelectItem>, Rpe
                 ORDER BY
                     name ASC
             "
-            )
-            .as_str(),
         )
-        .await?;
+        .as_str(),
+    )
+    .await?;
     let mut select_list = Vec::new();
     for row in client.query(&stmt, &[]).await? {
         select_list.push(SelectItem {
@@ -38,57 +46,201 @@ async fn select_name(pool: &RpelPool, name: &str) -> Result<Vec<SelectItem>, Rpe
 
 impl SelectItem {
     pub async fn company_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "companies").await
+        let client = acquire(pool).await?;
+        SelectItem::company_all_with(&client).await
+    }
+
+    pub async fn company_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "company_all", async move {
+            select_name(client, "companies").await
+        })
+        .await
     }
 
     pub async fn contact_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "contacts").await
+        let client = acquire(pool).await?;
+        SelectItem::contact_all_with(&client).await
+    }
+
+    pub async fn contact_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "contact_all", async move {
+            select_name(client, "contacts").await
+        })
+        .await
     }
 
     pub async fn department_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "departments").await
+        let client = acquire(pool).await?;
+        SelectItem::department_all_with(&client).await
+    }
+
+    pub async fn department_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "department_all", async move {
+            select_name(client, "departments").await
+        })
+        .await
+    }
+
+    // The departments of one company, nested ones named by their full path
+    // such as "Logistics / Warehouse".
+    pub async fn department_by_company(
+        pool: &RpelPool,
+        company_id: i64,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        let client = acquire(pool).await?;
+        SelectItem::department_by_company_with(&client, company_id).await
+    }
+
+    pub async fn department_by_company_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "department_by_company", async move {
+            let stmt = prepare(
+                client,
+                "
+                    WITH RECURSIVE tree AS (
+                        SELECT
+                            d.id,
+                            d.name AS path_name,
+                            ARRAY[d.id] AS path
+                        FROM
+                            departments AS d
+                        WHERE
+                            d.company_id = $1
+                        AND
+                            NOT EXISTS (
+                                SELECT 1 FROM departments AS p
+                                WHERE p.id = d.parent_id AND p.company_id = $1
+                            )
+                        UNION ALL
+                        SELECT
+                            d.id,
+                            concat_ws(' / ', t.path_name, d.name),
+                            t.path || d.id
+                        FROM
+                            departments AS d
+                        JOIN
+                            tree AS t ON d.parent_id = t.id
+                        WHERE
+                            d.company_id = $1
+                        AND
+                            NOT d.id = ANY(t.path)
+                    )
+                    SELECT
+                        id,
+                        path_name
+                    FROM
+                        tree
+                    ORDER BY
+                        path_name ASC
+                ",
+            )
+            .await?;
+            let mut departments = Vec::new();
+            for row in client.query(&stmt, &[&company_id]).await? {
+                departments.push(SelectItem {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                });
+            }
+            Ok(departments)
+        })
+        .await
     }
 
     pub async fn kind_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "kinds").await
+        let client = acquire(pool).await?;
+        SelectItem::kind_all_with(&client).await
+    }
+
+    pub async fn kind_all_with<C: GenericClient>(client: &C) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "kind_all", async move {
+            select_name(client, "kinds").await
+        })
+        .await
     }
 
     pub async fn post_all(pool: &RpelPool, go: bool) -> Result<Vec<SelectItem>, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SelectItem::post_all_with(&client, go).await
+    }
+
+    pub async fn post_all_with<C: GenericClient>(
+        client: &C,
+        go: bool,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "post_all", async move {
+            let stmt = prepare(
+                client,
                 "
-        SELECT
-            id,
-            name
-        FROM
-            posts
-        WHERE
-            go = $1
-        ORDER BY
-            name ASC
-    ",
+            SELECT
+                id,
+                name
+            FROM
+                posts
+            WHERE
+                go = $1
+            ORDER BY
+                name ASC
+        ",
             )
             .await?;
-        let mut posts = Vec::new();
-        for row in client.query(&stmt, &[&go]).await? {
-            posts.push(SelectItem {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-            });
-        }
-        Ok(posts)
+            let mut posts = Vec::new();
+            for row in client.query(&stmt, &[&go]).await? {
+                posts.push(SelectItem {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                });
+            }
+            Ok(posts)
+        })
+        .await
     }
 
     pub async fn rank_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "ranks").await
+        let client = acquire(pool).await?;
+        SelectItem::rank_all_with(&client).await
+    }
+
+    pub async fn rank_all_with<C: GenericClient>(client: &C) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "rank_all", async move {
+            select_name(client, "ranks").await
+        })
+        .await
     }
 
     pub async fn scope_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "scopes").await
+        let client = acquire(pool).await?;
+        SelectItem::scope_all_with(&client).await
+    }
+
+    pub async fn scope_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "scope_all", async move {
+            select_name(client, "scopes").await
+        })
+        .await
     }
 
     pub async fn siren_type_all(pool: &RpelPool) -> Result<Vec<SelectItem>, RpelError> {
-        select_name(pool, "siren_types").await
+        let client = acquire(pool).await?;
+        SelectItem::siren_type_all_with(&client).await
+    }
+
+    pub async fn siren_type_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SelectItem>, RpelError> {
+        traced("SelectItem", "siren_type_all", async move {
+            select_name(client, "siren_types").await
+        })
+        .await
     }
 }
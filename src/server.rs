@@ -0,0 +1,843 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{Local, NaiveDate};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address::Address,
+    attachment::{Attachment, AttachmentOwner},
+    certificate::{Certificate, CertificatePatch},
+    company::{Company, CompanyPatch},
+    contact::{Contact, ContactPatch},
+    department::Department,
+    duplicate::DEFAULT_MIN_SCORE,
+    education::{Education, EducationPatch},
+    employment::Employment,
+    error::RpelError,
+    kind::Kind,
+    post::Post,
+    practice::{Practice, PracticePatch},
+    rank::Rank,
+    required_post::RequiredPost,
+    scenario::Scenario,
+    scope::Scope,
+    settlement::Settlement,
+    siren::{Siren, SirenPatch},
+    siren_check::SirenCheck,
+    siren_type::SirenType,
+    store::Store,
+    street::Street,
+    warning_zone::WarningZone,
+};
+
+// Uploads stream into the store, so only this caps their size.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+struct PostQuery {
+    #[serde(default)]
+    go: bool,
+}
+
+#[derive(Deserialize)]
+struct HoldersQuery {
+    post_go_id: i64,
+    // Today when left out.
+    date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+struct DuplicateQuery {
+    // duplicate::DEFAULT_MIN_SCORE when left out.
+    min_score: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    filename: String,
+    note: Option<String>,
+}
+
+// Names the download with the RFC 6266 `filename*` form, which keeps
+// Cyrillic file names intact.
+fn disposition(filename: &str) -> String {
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    format!("attachment; filename*=UTF-8''{encoded}")
+}
+
+// Missing rows are 404 and stale or duplicate writes 409 whichever store
+// answered; anything the caller cannot fix is a 5xx.
+pub fn status(err: &RpelError) -> StatusCode {
+    match err {
+        RpelError::NotFound(_, _) => StatusCode::NOT_FOUND,
+        RpelError::Conflict(_, _) => StatusCode::CONFLICT,
+        RpelError::Json(_) | RpelError::Snapshot(_) | RpelError::Invalid(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        RpelError::Pool(_) | RpelError::CreatePool(_) | RpelError::BuildPool(_) => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        RpelError::DBQuery(err) if err.is_closed() => StatusCode::SERVICE_UNAVAILABLE,
+        RpelError::DBQuery(err) => match err.code().map(|code| code.code()) {
+            Some("23505") => StatusCode::CONFLICT,
+            Some(code) if code.starts_with("23") => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(code) if code.starts_with("22") => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// A 5xx carries database and SQL details the client has no use for; they go
+// to the log and the client gets a generic message.
+impl IntoResponse for RpelError {
+    fn into_response(self) -> Response {
+        let status = status(&self);
+        let message = if status == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!(error = %self, "request failed");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+        let body = ErrorBody {
+            error: self.kind().to_string(),
+            message,
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+// axum answers a body, path or query it cannot parse (and an unknown route or
+// method) with plain text; this gives those the same ErrorBody as the rest.
+async fn rejection_body(response: Response) -> Response {
+    let status = response.status();
+    let json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if json || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let text = to_bytes(body, 64 * 1024).await.unwrap_or_default();
+    let message = match String::from_utf8_lossy(&text).trim() {
+        "" => status.canonical_reason().unwrap_or_default().to_string(),
+        text => text.to_string(),
+    };
+    let body = ErrorBody {
+        error: status
+            .canonical_reason()
+            .unwrap_or_default()
+            .replace(' ', ""),
+        message,
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+    let (json, body) = Json(body).into_response().into_parts();
+    parts.headers.extend(json.headers);
+    Response::from_parts(parts, body)
+}
+
+// The upload body as chunks, failing once more than `max` bytes arrived.
+fn limited(body: Body, max: usize) -> impl Stream<Item = Result<axum::body::Bytes, RpelError>> {
+    let mut received = 0;
+    body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(|err| RpelError::Invalid(format!("upload: {err}")))?;
+        received += chunk.len();
+        if received > max {
+            return Err(RpelError::Invalid(format!(
+                "upload: file is larger than {max} bytes"
+            )));
+        }
+        Ok(chunk)
+    })
+}
+
+// Updates and deletes report the rows they touched; none means the id is gone.
+fn touched(table: &str, id: i64, rows: u64) -> Result<StatusCode, RpelError> {
+    match rows {
+        0 => Err(RpelError::NotFound(table.to_string(), id)),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+// Every request carries `Authorization: Bearer <key>` with the key of a row in
+// `users`; the matching user is passed on as a request extension. A read-only
+// user gets GET and HEAD only.
+async fn authenticate(State(store): State<Store>, mut request: Request, next: Next) -> Response {
+    let key = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|key| !key.is_empty());
+    let user = match key {
+        Some(key) => match store.user_by_key(key).await {
+            Ok(user) => user,
+            Err(err) => return err.into_response(),
+        },
+        None => None,
+    };
+    match user {
+        Some(user)
+            if !user.can_write() && !matches!(*request.method(), Method::GET | Method::HEAD) =>
+        {
+            let body = ErrorBody {
+                error: "Forbidden".to_string(),
+                message: format!("user {} may only read", user.name),
+            };
+            (StatusCode::FORBIDDEN, Json(body)).into_response()
+        }
+        Some(user) => {
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+        None => {
+            let body = ErrorBody {
+                error: "Unauthorized".to_string(),
+                message: "missing or unknown bearer key".to_string(),
+            };
+            (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+        }
+    }
+}
+
+// Expands to the list and CRUD routes of each entity, plus PATCH for the ones
+// that have a patch type.
+macro_rules! entities {
+    ($router:ident; $($path:literal => $entity:ty {
+        $get:ident, $insert:ident, $update:ident, $delete:ident, $list:ident
+        $(, $patch:ident: $patch_ty:ty)?
+    })*) => {
+        $(
+            $router = $router
+                .route(
+                    concat!("/api/", $path),
+                    get(|State(store): State<Store>| async move { store.$list().await.map(Json) })
+                        .post(|State(store): State<Store>, Json(row): Json<$entity>| async move {
+                            let row = store.$insert(row).await?;
+                            Ok::<_, RpelError>((StatusCode::CREATED, Json(row)))
+                        }),
+                )
+                .route(
+                    concat!("/api/", $path, "/{id}"),
+                    get(|State(store): State<Store>, Path(id): Path<i64>| async move {
+                        store.$get(id).await.map(Json)
+                    })
+                    .put(
+                        |State(store): State<Store>, Path(id): Path<i64>, Json(mut row): Json<$entity>| async move {
+                            row.id = id;
+                            touched($path, id, store.$update(row).await?)
+                        },
+                    )
+                    .delete(|State(store): State<Store>, Path(id): Path<i64>| async move {
+                        touched($path, id, store.$delete(id).await?)
+                    }),
+                );
+            $(
+                $router = $router.route(
+                    concat!("/api/", $path, "/{id}"),
+                    axum::routing::patch(
+                        |State(store): State<Store>, Path(id): Path<i64>, Json(patch): Json<$patch_ty>| async move {
+                            touched($path, id, store.$patch(id, patch).await?)
+                        },
+                    ),
+                );
+            )?
+        )*
+    };
+}
+
+macro_rules! feeds {
+    ($router:ident; $($path:literal => $call:ident,)*) => {
+        $(
+            $router = $router.route(
+                $path,
+                get(|State(store): State<Store>| async move { store.$call().await.map(Json) }),
+            );
+        )*
+    };
+}
+
+// The JSON API over every entity. Users are left out on purpose: their keys
+// are managed with the `rpel user` command and never served.
+pub fn router(store: Store) -> Router {
+    let mut router = Router::new();
+    entities! {
+        router;
+        "companies" => Company {
+            company_get, company_insert, company_update, company_delete, company_list,
+            company_patch: CompanyPatch
+        }
+        "contacts" => Contact {
+            contact_get, contact_insert, contact_update, contact_delete, contact_list,
+            contact_patch: ContactPatch
+        }
+        "practices" => Practice {
+            practice_get, practice_insert, practice_update, practice_delete, practice_list,
+            practice_patch: PracticePatch
+        }
+        "educations" => Education {
+            education_get, education_insert, education_update, education_delete, education_list,
+            education_patch: EducationPatch
+        }
+        "certificates" => Certificate {
+            certificate_get, certificate_insert, certificate_update, certificate_delete,
+            certificate_list, certificate_patch: CertificatePatch
+        }
+        "sirens" => Siren {
+            siren_get, siren_insert, siren_update, siren_delete, siren_list,
+            siren_patch: SirenPatch
+        }
+        "siren_checks" => SirenCheck {
+            siren_check_get, siren_check_insert, siren_check_update, siren_check_delete,
+            siren_check_list
+        }
+        "warning_zones" => WarningZone {
+            warning_zone_get, warning_zone_insert, warning_zone_update, warning_zone_delete,
+            warning_zone_list
+        }
+        "scenarios" => Scenario {
+            scenario_get, scenario_insert, scenario_update, scenario_delete, scenario_list
+        }
+        "employments" => Employment {
+            employment_get, employment_insert, employment_update, employment_delete,
+            employment_list
+        }
+        "departments" => Department {
+            department_get, department_insert, department_update, department_delete,
+            department_list
+        }
+        "kinds" => Kind { kind_get, kind_insert, kind_update, kind_delete, kind_list }
+        "posts" => Post { post_get, post_insert, post_update, post_delete, post_list }
+        "ranks" => Rank { rank_get, rank_insert, rank_update, rank_delete, rank_list }
+        "scopes" => Scope { scope_get, scope_insert, scope_update, scope_delete, scope_list }
+        "required_posts" => RequiredPost {
+            required_post_get, required_post_insert, required_post_update, required_post_delete,
+            required_post_list
+        }
+        "siren_types" => SirenType {
+            siren_type_get, siren_type_insert, siren_type_update, siren_type_delete,
+            siren_type_list
+        }
+        "settlements" => Settlement {
+            settlement_get, settlement_insert, settlement_update, settlement_delete,
+            settlement_list
+        }
+        "streets" => Street { street_get, street_insert, street_update, street_delete, street_list }
+        "addresses" => Address {
+            address_get, address_insert, address_update, address_delete, address_list
+        }
+    }
+    feeds! {
+        router;
+        "/api/select/companies" => select_company_all,
+        "/api/select/contacts" => select_contact_all,
+        "/api/select/departments" => select_department_all,
+        "/api/select/kinds" => select_kind_all,
+        "/api/select/ranks" => select_rank_all,
+        "/api/select/scopes" => select_scope_all,
+        "/api/select/siren_types" => select_siren_type_all,
+        "/api/near/practices" => practice_near,
+        "/api/near/educations" => education_near,
+        "/api/rollup/companies" => company_rollup_all,
+        "/api/vacancies" => vacancy_all,
+        "/api/vacancies/heads" => vacancy_heads,
+        "/api/status/sirens" => siren_status_all,
+        "/api/status/sirens/untested" => siren_status_untested,
+        "/api/status/sirens/failed" => siren_status_failed,
+    }
+
+    router = router
+        .route(
+            "/api/companies/{id}/contacts",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.contact_short_by_company(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/practices",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.practice_list_by_company(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/sirens/{id}/checks",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.siren_check_by_siren(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/scenarios/{id}/checklist",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.scenario_checklist(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/duplicates/contacts",
+            get(
+                |State(store): State<Store>, Query(query): Query<DuplicateQuery>| async move {
+                    let min_score = query.min_score.unwrap_or(DEFAULT_MIN_SCORE);
+                    store.duplicate_find(min_score).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/contacts/{id}/merge/{duplicate_id}",
+            axum::routing::post(
+                |State(store): State<Store>, Path((id, duplicate_id)): Path<(i64, i64)>| async move {
+                    store.contact_merge(id, duplicate_id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/contacts/{id}/employments",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.employment_by_contact(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/staffing",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.staffing_get(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/holders",
+            get(
+                |State(store): State<Store>,
+                 Path(id): Path<i64>,
+                 Query(query): Query<HoldersQuery>| async move {
+                    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+                    store
+                        .employment_holders(id, query.post_go_id, date)
+                        .await
+                        .map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/departments",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.select_department_by_company(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/subtree",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.company_subtree(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/ancestors",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.company_ancestors(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/companies/{id}/rollup",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.company_rollup(id).await.map(Json)
+                },
+            ),
+        )
+        .route(
+            "/api/select/posts",
+            get(
+                |State(store): State<Store>, Query(query): Query<PostQuery>| async move {
+                    store.select_post_all(query.go).await.map(Json)
+                },
+            ),
+        );
+
+    // A GET only reports what would fail to parse; a POST stores the rest.
+    router = router.route(
+        "/api/addresses/parse",
+        get(|State(store): State<Store>| async move {
+            store.address_parse(false).await.map(Json)
+        })
+        .post(|State(store): State<Store>| async move {
+            store.address_parse(true).await.map(Json)
+        }),
+    );
+
+    // A file is uploaded as the raw request body, typed by its Content-Type
+    // and named in the query, e.g. POST /api/companies/1/attachments?filename=plan.pdf
+    for owner in AttachmentOwner::ALL {
+        router = router.route(
+            &format!("/api/{}/{{id}}/attachments", owner.as_str()),
+            get(
+                move |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.attachment_by_owner(owner, id).await.map(Json)
+                },
+            )
+            .post(
+                move |State(store): State<Store>,
+                      Path(id): Path<i64>,
+                      Query(query): Query<UploadQuery>,
+                      headers: HeaderMap,
+                      body: Body| async move {
+                    let mime_type = headers
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string();
+                    let attachment = Attachment {
+                        owner,
+                        owner_id: id,
+                        filename: query.filename,
+                        mime_type,
+                        note: query.note,
+                        ..Default::default()
+                    };
+                    let chunks = limited(body, MAX_UPLOAD_BYTES);
+                    let row = store.attachment_upload(attachment, chunks).await?;
+                    Ok::<_, RpelError>((StatusCode::CREATED, Json(row)))
+                },
+            ),
+        );
+    }
+    router = router
+        .route(
+            "/api/attachments",
+            get(
+                |State(store): State<Store>| async move { store.attachment_list().await.map(Json) },
+            ),
+        )
+        .route(
+            "/api/attachments/{id}",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    store.attachment_get(id).await.map(Json)
+                },
+            )
+            .put(
+                |State(store): State<Store>,
+                 Path(id): Path<i64>,
+                 Json(mut row): Json<Attachment>| async move {
+                    row.id = id;
+                    touched("attachments", id, store.attachment_update(row).await?)
+                },
+            )
+            .delete(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    touched("attachments", id, store.attachment_delete(id).await?)
+                },
+            ),
+        )
+        .route(
+            "/api/attachments/{id}/content",
+            get(
+                |State(store): State<Store>, Path(id): Path<i64>| async move {
+                    let (attachment, chunks) = store.attachment_content(id).await?;
+                    let headers = [
+                        (header::CONTENT_TYPE, attachment.mime_type),
+                        (header::CONTENT_LENGTH, attachment.size.to_string()),
+                        (
+                            header::CONTENT_DISPOSITION,
+                            disposition(&attachment.filename),
+                        ),
+                    ];
+                    Ok::<_, RpelError>((headers, Body::from_stream(chunks)))
+                },
+            ),
+        );
+
+    router
+        .layer(middleware::from_fn_with_state(store.clone(), authenticate))
+        .layer(middleware::map_response(rejection_body))
+        .with_state(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{memory::MemoryStore, user::User};
+
+    fn app() -> Router {
+        let memory = MemoryStore::new();
+        for (name, key, role) in [("operator", "secret", 1), ("viewer", "glance", 0)] {
+            memory
+                .user_insert(User {
+                    name: name.to_string(),
+                    key: key.to_string(),
+                    role,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        router(memory.into())
+    }
+
+    async fn call(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<&str>,
+    ) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_keys() {
+        let request = Request::builder()
+            .uri("/api/companies")
+            .header(header::AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn read_only_users_cannot_write() {
+        let app = app();
+        let request = |method: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/api/companies")
+                .header(header::AUTHORIZATION, "Bearer glance")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"name":"Grid","emails":[],"phones":[],"faxes":[]}"#,
+                ))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(request("GET")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(request("POST")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let (_, body) = call(&app, "GET", "/api/companies", None).await;
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn rejections_use_error_body() {
+        let app = app();
+        let (status, body) = call(&app, "POST", "/api/companies", Some("{")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: ErrorBody = serde_json::from_str(&body).unwrap();
+        assert_eq!(error.error, "BadRequest");
+        let (status, body) = call(&app, "GET", "/api/companies/grid", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(serde_json::from_str::<ErrorBody>(&body).is_ok());
+        let (status, body) = call(&app, "GET", "/api/holidays", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            serde_json::from_str::<ErrorBody>(&body).unwrap().error,
+            "NotFound"
+        );
+    }
+
+    #[tokio::test]
+    async fn internal_errors_hide_details() {
+        let err = RpelError::Io(std::io::Error::other("relation \"users\" is broken"));
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let error: ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(error.error, "Io");
+        assert_eq!(error.message, "internal server error");
+    }
+
+    #[tokio::test]
+    async fn uploads_stop_at_the_limit() {
+        let chunks: Vec<_> = limited(Body::from("12345"), 4).collect().await;
+        assert!(matches!(chunks[..], [Err(RpelError::Invalid(_))]));
+        let chunks: Vec<_> = limited(Body::from("1234"), 4).collect().await;
+        assert!(matches!(chunks[..], [Ok(_)]));
+    }
+
+    #[tokio::test]
+    async fn company_crud() {
+        let app = app();
+        let (status, body) = call(
+            &app,
+            "POST",
+            "/api/companies",
+            Some(r#"{"name":"Grid","emails":[],"phones":[],"faxes":[]}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let company: Company = serde_json::from_str(&body).unwrap();
+
+        let uri = format!("/api/companies/{}", company.id);
+        let (_, stale) = call(&app, "GET", &uri, None).await;
+        let (status, _) = call(&app, "PATCH", &uri, Some(r#"{"note":"north"}"#)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let version = serde_json::from_str::<Company>(&stale).unwrap().updated_at;
+        let patch = serde_json::json!({ "note": "north", "updated_at": version }).to_string();
+        let (status, _) = call(&app, "PATCH", &uri, Some(&patch)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = call(&app, "GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored: Company = serde_json::from_str(&body).unwrap();
+        assert_eq!(stored.note.as_deref(), Some("north"));
+        let (status, _) = call(&app, "PUT", &uri, Some(&stale)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, body) = call(&app, "GET", "/api/select/companies", None).await;
+        assert!(body.contains("Grid"));
+
+        let (status, _) = call(&app, "DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = call(&app, "GET", &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let error: ErrorBody = serde_json::from_str(&body).unwrap();
+        assert_eq!(error.error, "NotFound");
+        let (status, _) = call(&app, "DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_found_and_merged() {
+        let app = app();
+        let mut ids = Vec::new();
+        for name in ["Ivanov Ivan", "ivan ivanov"] {
+            let contact = format!(r#"{{"name":"{name}","emails":[],"phones":[555],"faxes":[]}}"#);
+            let (_, body) = call(&app, "POST", "/api/contacts", Some(&contact)).await;
+            ids.push(serde_json::from_str::<Contact>(&body).unwrap().id);
+        }
+        let (status, body) = call(&app, "GET", "/api/duplicates/contacts", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let found: Vec<crate::duplicate::DuplicateCandidate> = serde_json::from_str(&body).unwrap();
+        assert_eq!((found[0].id, found[0].other_id), (ids[0], ids[1]));
+
+        let uri = format!("/api/contacts/{}/merge/{}", ids[0], ids[1]);
+        let (status, _) = call(&app, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&app, "GET", &format!("/api/contacts/{}", ids[1]), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, body) = call(&app, "GET", "/api/duplicates/contacts?min_score=0", None).await;
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn attachments_upload_and_download() {
+        let app = app();
+        let (_, body) = call(
+            &app,
+            "POST",
+            "/api/companies",
+            Some(r#"{"name":"Grid","emails":[],"phones":[],"faxes":[]}"#),
+        )
+        .await;
+        let company: Company = serde_json::from_str(&body).unwrap();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!(
+                "/api/companies/{}/attachments?filename=%D0%BF%D0%BB%D0%B0%D0%BD.pdf",
+                company.id
+            ))
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .body(Body::from("%PDF-1.4"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let attachment: Attachment = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(attachment.filename, "план.pdf");
+        assert_eq!(attachment.size, 8);
+        assert_eq!(
+            attachment.checksum,
+            "e16fa5d9b51928755db85b917f0297babaf22c7a47e97d9212adab56e61ba04e"
+        );
+
+        let uri = format!("/api/companies/{}/attachments", company.id);
+        let (_, body) = call(&app, "GET", &uri, None).await;
+        assert!(body.contains("план.pdf"));
+        let (status, _) = call(&app, "GET", "/api/sirens/1/attachments", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = Request::builder()
+            .uri(format!("/api/attachments/{}/content", attachment.id))
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename*=UTF-8''%D0%BF%D0%BB%D0%B0%D0%BD.pdf"
+        );
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"%PDF-1.4");
+
+        // Files go with the record they belong to.
+        let (status, _) = call(
+            &app,
+            "DELETE",
+            &format!("/api/companies/{}", company.id),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(
+            &app,
+            "GET",
+            &format!("/api/attachments/{}", attachment.id),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
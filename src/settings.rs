@@ -0,0 +1,220 @@
+use std::{collections::HashMap, env, path::PathBuf, time::Duration};
+
+use config::{Config, Environment, File};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::config::SslMode;
+
+use crate::{
+    error::RpelError,
+    tls::{TlsOptions, TlsVerify},
+};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PoolSettings {
+    pub max_size: Option<usize>,
+    pub wait_timeout_ms: Option<u64>,
+    pub create_timeout_ms: Option<u64>,
+    pub recycle_timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Settings {
+    pub database_url: Option<String>,
+    pub application_name: Option<String>,
+    pub statement_timeout_ms: Option<u64>,
+    pub slow_query_ms: Option<u64>,
+    pub listen: Option<String>,
+    #[serde(default)]
+    pub pool: PoolSettings,
+    pub tls: Option<TlsOptions>,
+    #[serde(skip)]
+    pg_env: HashMap<String, String>,
+}
+
+impl Settings {
+    // Reads `path` when given, then `RPEL_*` variables (`RPEL_POOL__MAX_SIZE`
+    // for nested keys), then `DATABASE_URL` and the libpq `PG*` variables.
+    pub fn load(path: Option<&str>) -> Result<Settings, RpelError> {
+        Settings::load_from(path, env::vars().collect())
+    }
+
+    pub fn load_from(
+        path: Option<&str>,
+        vars: HashMap<String, String>,
+    ) -> Result<Settings, RpelError> {
+        let mut builder = Config::builder();
+        if let Some(path) = path {
+            builder = builder.add_source(File::with_name(path));
+        }
+        let mut settings: Settings = builder
+            .add_source(
+                Environment::with_prefix("RPEL")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true)
+                    .source(Some(vars.clone())),
+            )
+            .build()?
+            .try_deserialize()?;
+        if !vars.contains_key("RPEL_DATABASE_URL") {
+            if let Some(url) = vars.get("DATABASE_URL") {
+                settings.database_url = Some(url.clone());
+            }
+        }
+        if settings.tls.is_none() {
+            let verify = match vars.get("PGSSLMODE").map(String::as_str) {
+                Some("require") => Some(TlsVerify::Require),
+                Some("verify-ca") => Some(TlsVerify::VerifyCa),
+                Some("verify-full") => Some(TlsVerify::VerifyFull),
+                _ => None,
+            };
+            settings.tls = verify.map(|verify| TlsOptions {
+                verify,
+                root_cert: vars.get("PGSSLROOTCERT").map(PathBuf::from),
+            });
+        }
+        settings.pg_env = vars
+            .into_iter()
+            .filter(|(key, _)| key.starts_with("PG"))
+            .collect();
+        Ok(settings)
+    }
+
+    pub fn pg_config(&self) -> Result<tokio_postgres::Config, RpelError> {
+        let url = self.database_url.as_deref().unwrap_or_default();
+        let mut cfg: tokio_postgres::Config = url.parse()?;
+        self.apply_pg_env(&mut cfg)?;
+        if let Some(name) = &self.application_name {
+            cfg.application_name(name);
+        }
+        if let Some(timeout) = self.statement_timeout_ms {
+            let option = format!("-c statement_timeout={timeout}");
+            let options = match cfg.get_options() {
+                Some(options) => format!("{options} {option}"),
+                None => option,
+            };
+            cfg.options(&options);
+        }
+        Ok(cfg)
+    }
+
+    // libpq treats the PG* variables as defaults, so they only fill in what the
+    // parsed connection string left unset. sslmode has no unset state: prefer
+    // is the default, so only a URL asking for something else keeps PGSSLMODE out.
+    fn apply_pg_env(&self, cfg: &mut tokio_postgres::Config) -> Result<(), RpelError> {
+        let var = |key: &str| self.pg_env.get(key).filter(|value| !value.is_empty());
+        if cfg.get_hosts().is_empty() {
+            if let Some(hosts) = var("PGHOST") {
+                for host in hosts.split(',') {
+                    cfg.host(host);
+                }
+            }
+        }
+        if cfg.get_ports().is_empty() {
+            if let Some(ports) = var("PGPORT") {
+                for port in ports.split(',') {
+                    cfg.port(port.parse().map_err(|_| invalid("PGPORT", port))?);
+                }
+            }
+        }
+        if cfg.get_user().is_none() {
+            if let Some(user) = var("PGUSER") {
+                cfg.user(user);
+            }
+        }
+        if cfg.get_password().is_none() {
+            if let Some(password) = var("PGPASSWORD") {
+                cfg.password(password);
+            }
+        }
+        if cfg.get_dbname().is_none() {
+            if let Some(dbname) = var("PGDATABASE") {
+                cfg.dbname(dbname);
+            }
+        }
+        if cfg.get_application_name().is_none() {
+            if let Some(name) = var("PGAPPNAME") {
+                cfg.application_name(name);
+            }
+        }
+        if cfg.get_options().is_none() {
+            if let Some(options) = var("PGOPTIONS") {
+                cfg.options(options);
+            }
+        }
+        if cfg.get_connect_timeout().is_none() {
+            if let Some(timeout) = var("PGCONNECT_TIMEOUT") {
+                let secs = timeout
+                    .parse()
+                    .map_err(|_| invalid("PGCONNECT_TIMEOUT", timeout))?;
+                cfg.connect_timeout(Duration::from_secs(secs));
+            }
+        }
+        if cfg.get_ssl_mode() == SslMode::Prefer {
+            if let Some(mode) = var("PGSSLMODE") {
+                cfg.ssl_mode(match mode.as_str() {
+                    "disable" => SslMode::Disable,
+                    "prefer" | "allow" => SslMode::Prefer,
+                    _ => SslMode::Require,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(key: &str, value: &str) -> RpelError {
+    RpelError::Config(config::ConfigError::Message(format!(
+        "invalid {key} value {value:?}"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn load_from_reads_prefixed_and_pg_vars() {
+        let settings = Settings::load_from(
+            None,
+            vars(&[
+                ("DATABASE_URL", "host=db1 user=app dbname=rpel"),
+                ("RPEL_POOL__MAX_SIZE", "32"),
+                ("RPEL_STATEMENT_TIMEOUT_MS", "5000"),
+                ("PGUSER", "ignored"),
+                ("PGPORT", "6432"),
+                ("PGSSLMODE", "verify-full"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(settings.pool.max_size, Some(32));
+        assert_eq!(settings.tls.as_ref().unwrap().verify, TlsVerify::VerifyFull);
+        let cfg = settings.pg_config().unwrap();
+        assert_eq!(cfg.get_user(), Some("app"));
+        assert_eq!(cfg.get_ports(), &[6432]);
+        assert_eq!(cfg.get_options(), Some("-c statement_timeout=5000"));
+    }
+
+    #[test]
+    fn pgsslmode_fills_only_an_unset_sslmode() {
+        let pg_config = |url: &str, mode: &str| {
+            Settings::load_from(None, vars(&[("DATABASE_URL", url), ("PGSSLMODE", mode)]))
+                .unwrap()
+                .pg_config()
+                .unwrap()
+        };
+        let cfg = pg_config("host=db1 password=sslmode dbname=rpel", "disable");
+        assert_eq!(cfg.get_ssl_mode(), SslMode::Disable);
+        let cfg = pg_config("postgres://app@db1/sslmode_archive", "disable");
+        assert_eq!(cfg.get_ssl_mode(), SslMode::Disable);
+        let cfg = pg_config("host=db1 sslmode=disable", "require");
+        assert_eq!(cfg.get_ssl_mode(), SslMode::Disable);
+    }
+}
@@ -0,0 +1,270 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// A town or village of the address gazetteer. `kind` is the short form the
+// parser knows it by, e.g. "г" or "с".
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Settlement {
+    #[serde(default)]
+    pub id: i64,
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Settlement);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SettlementList {
+    pub id: i64,
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    pub note: Option<String>,
+    pub streets: i64,
+    pub companies: i64,
+    pub sirens: i64,
+}
+
+impl Settlement {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Settlement, RpelError> {
+        let client = acquire(pool).await?;
+        Settlement::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Settlement, RpelError> {
+        traced("Settlement", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        name,
+                        kind,
+                        note,
+                        created_at,
+                        updated_at
+                    FROM
+                        settlements
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("settlements".to_string(), id))?;
+            let settlement = Settlement {
+                id,
+                name: row.try_get(0)?,
+                kind: row.try_get(1)?,
+                note: row.try_get(2)?,
+                created_at: row.try_get(3)?,
+                updated_at: row.try_get(4)?,
+            };
+            Ok(settlement)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, settlement: Settlement) -> Result<Settlement, RpelError> {
+        let client = acquire(pool).await?;
+        Settlement::insert_with(&client, settlement).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        settlement: Settlement,
+    ) -> Result<Settlement, RpelError> {
+        traced("Settlement", "insert", async move {
+            let mut settlement = settlement;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO settlements
+                    (
+                        name,
+                        kind,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &settlement.name,
+                        &settlement.kind,
+                        &settlement.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            settlement.id = row.get(0);
+            notify(client, "settlements", settlement.id, Operation::Insert, 1).await?;
+            Ok(settlement)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, settlement: Settlement) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Settlement::update_with(&client, settlement).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        settlement: Settlement,
+    ) -> Result<u64, RpelError> {
+        traced("Settlement", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE settlements SET
+                        name = $2,
+                        kind = $3,
+                        note = $4,
+                        updated_at = $5
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $6
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &settlement.id,
+                        &settlement.name,
+                        &settlement.kind,
+                        &settlement.note,
+                        &Local::now().naive_local(),
+                        &settlement.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "settlements",
+                settlement.id,
+                settlement.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "settlements",
+                settlement.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Settlement::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Settlement", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        settlements
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "settlements", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+impl SettlementList {
+    // Every settlement with the number of its streets and of the companies and
+    // sirens whose address lies in it.
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<SettlementList>, RpelError> {
+        let client = acquire(pool).await?;
+        SettlementList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SettlementList>, RpelError> {
+        traced("SettlementList", "get_all", async move {
+            let mut settlements = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        s.id,
+                        s.name,
+                        s.kind,
+                        s.note,
+                        (SELECT count(*) FROM streets AS t WHERE t.settlement_id = s.id) AS streets,
+                        count(a.company_id) AS companies,
+                        count(a.siren_id) AS sirens
+                    FROM
+                        settlements AS s
+                    LEFT JOIN
+                        addresses AS a ON a.settlement_id = s.id
+                    GROUP BY
+                        s.id
+                    ORDER BY
+                        s.name ASC,
+                        s.id ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                settlements.push(SettlementList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    kind: row.try_get(2)?,
+                    note: row.try_get(3)?,
+                    streets: row.try_get(4)?,
+                    companies: row.try_get(5)?,
+                    sirens: row.try_get(6)?,
+                });
+            }
+            Ok(settlements)
+        })
+        .await
+    }
+}
@@ -1,9 +1,21 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire,
+    address::Address,
+    attachment::{Attachment, AttachmentOwner},
+    check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    patch::{double_option, Patch},
+    trace::{prepare, single_row, traced},
+    warning_zone::unlink,
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Siren {
     #[serde(default)]
     pub id: i64,
@@ -22,7 +34,39 @@ pub struct Siren {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Siren);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SirenPatch {
+    #[serde(default, deserialize_with = "double_option")]
+    pub num_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub num_pass: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub siren_type_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub address: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub radio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub desk: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub contact_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub company_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub latitude: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub longitude: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub stage: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub own: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub note: Option<Option<String>>,
     pub updated_at: Option<NaiveDateTime>,
 }
 
@@ -41,9 +85,14 @@ impl Siren {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<Siren, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Siren::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Siren, RpelError> {
+        traced("Siren", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         num_id,
@@ -68,33 +117,46 @@ impl Siren {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let siren = Siren {
-            id,
-            num_id: row.try_get(0)?,
-            num_pass: row.try_get(1)?,
-            siren_type_id: row.try_get(2)?,
-            address: row.try_get(3)?,
-            radio: row.try_get(4)?,
-            desk: row.try_get(5)?,
-            contact_id: row.try_get(6)?,
-            company_id: row.try_get(7)?,
-            latitude: row.try_get(8)?,
-            longitude: row.try_get(9)?,
-            stage: row.try_get(10)?,
-            own: row.try_get(11)?,
-            note: row.try_get(12)?,
-            created_at: row.try_get(13)?,
-            updated_at: row.try_get(14)?,
-        };
-        Ok(siren)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("sirens".to_string(), id))?;
+            let siren = Siren {
+                id,
+                num_id: row.try_get(0)?,
+                num_pass: row.try_get(1)?,
+                siren_type_id: row.try_get(2)?,
+                address: row.try_get(3)?,
+                radio: row.try_get(4)?,
+                desk: row.try_get(5)?,
+                contact_id: row.try_get(6)?,
+                company_id: row.try_get(7)?,
+                latitude: row.try_get(8)?,
+                longitude: row.try_get(9)?,
+                stage: row.try_get(10)?,
+                own: row.try_get(11)?,
+                note: row.try_get(12)?,
+                created_at: row.try_get(13)?,
+                updated_at: row.try_get(14)?,
+            };
+            Ok(siren)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, siren: Siren) -> Result<Siren, RpelError> {
-        let mut siren = siren;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Siren::insert_with(&client, siren).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        siren: Siren,
+    ) -> Result<Siren, RpelError> {
+        traced("Siren", "insert", async move {
+            let mut siren = siren;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO sirens
                     (
@@ -137,36 +199,44 @@ impl Siren {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &siren.num_id,
-                    &siren.num_pass,
-                    &siren.siren_type_id,
-                    &siren.address,
-                    &siren.radio,
-                    &siren.desk,
-                    &siren.contact_id,
-                    &siren.company_id,
-                    &siren.latitude,
-                    &siren.longitude,
-                    &siren.stage,
-                    &siren.own,
-                    &siren.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        siren.id = row.get(0);
-        Ok(siren)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &siren.num_id,
+                        &siren.num_pass,
+                        &siren.siren_type_id,
+                        &siren.address,
+                        &siren.radio,
+                        &siren.desk,
+                        &siren.contact_id,
+                        &siren.company_id,
+                        &siren.latitude,
+                        &siren.longitude,
+                        &siren.stage,
+                        &siren.own,
+                        &siren.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            siren.id = row.get(0);
+            notify(client, "sirens", siren.id, Operation::Insert, 1).await?;
+            Ok(siren)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, siren: Siren) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        Siren::update_with(&client, siren).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, siren: Siren) -> Result<u64, RpelError> {
+        traced("Siren", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE sirens SET
                         num_id = $2,
@@ -185,37 +255,108 @@ impl Siren {
                         updated_at = $15
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $16
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &siren.id,
-                    &siren.num_id,
-                    &siren.num_pass,
-                    &siren.siren_type_id,
-                    &siren.address,
-                    &siren.radio,
-                    &siren.desk,
-                    &siren.contact_id,
-                    &siren.company_id,
-                    &siren.latitude,
-                    &siren.longitude,
-                    &siren.stage,
-                    &siren.own,
-                    &siren.note,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &siren.id,
+                        &siren.num_id,
+                        &siren.num_pass,
+                        &siren.siren_type_id,
+                        &siren.address,
+                        &siren.radio,
+                        &siren.desk,
+                        &siren.contact_id,
+                        &siren.company_id,
+                        &siren.latitude,
+                        &siren.longitude,
+                        &siren.stage,
+                        &siren.own,
+                        &siren.note,
+                        &Local::now().naive_local(),
+                        &siren.updated_at,
+                    ],
+                )
+                .await?;
+            let result =
+                check_version(client, "sirens", siren.id, siren.updated_at, result).await?;
+            notify(client, "sirens", siren.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn patch(pool: &RpelPool, id: i64, siren: SirenPatch) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Siren::patch_with(&client, id, siren).await
+    }
+
+    pub async fn patch_with<C: GenericClient>(
+        client: &C,
+        id: i64,
+        siren: SirenPatch,
+    ) -> Result<u64, RpelError> {
+        traced("Siren", "patch", async move {
+            let mut patch = Patch::new("sirens", &id);
+            patch
+                .set("num_id", &siren.num_id)
+                .set("num_pass", &siren.num_pass)
+                .set("siren_type_id", &siren.siren_type_id)
+                .set("address", &siren.address)
+                .set("radio", &siren.radio)
+                .set("desk", &siren.desk)
+                .set("contact_id", &siren.contact_id)
+                .set("company_id", &siren.company_id)
+                .set("latitude", &siren.latitude)
+                .set("longitude", &siren.longitude)
+                .set("stage", &siren.stage)
+                .set("own", &siren.own)
+                .set("note", &siren.note);
+            patch.execute(client, &siren.updated_at).await
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = Siren::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Siren", "delete", async move {
+            // The check journal is the audit trail of the siren and outlives
+            // any edit, so a siren with checks on record stays.
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        count(*)
+                    FROM
+                        siren_checks
+                    WHERE
+                        siren_id = $1
+                ",
+            )
+            .await?;
+            let checks: i64 = client.query_one(&stmt, &[&id]).await?.try_get(0)?;
+            if checks > 0 {
+                return Err(RpelError::Invalid(format!(
+                    "siren {id} has {checks} checks in its journal"
+                )));
+            }
+            Address::delete_sirens_with(client, id).await?;
+            Attachment::delete_owned_with(client, AttachmentOwner::Sirens, id).await?;
+            unlink(client, "warning_zone_sirens", "siren_id", id).await?;
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         sirens
@@ -224,16 +365,25 @@ impl Siren {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "sirens", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl SirenList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<SirenList>, RpelError> {
-        let mut sirens = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<SirenList>, RpelError> {
+        traced("SirenList", "get_all", async move {
+            let mut sirens = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         s.id,
@@ -258,15 +408,17 @@ impl SirenList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            sirens.push(SirenList {
-                id: row.try_get(0)?,
-                siren_type_name: row.try_get(1)?,
-                address: row.try_get(2)?,
-                contact_name: row.try_get(3)?,
-                phones: row.try_get(4)?,
-            });
-        }
-        Ok(sirens)
+            for row in client.query(&stmt, &[]).await? {
+                sirens.push(SirenList {
+                    id: row.try_get(0)?,
+                    siren_type_name: row.try_get(1)?,
+                    address: row.try_get(2)?,
+                    contact_name: row.try_get(3)?,
+                    phones: row.try_get(4)?,
+                });
+            }
+            Ok(sirens)
+        })
+        .await
     }
 }
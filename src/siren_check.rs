@@ -0,0 +1,557 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    // A scheduled test activation.
+    #[default]
+    Test,
+    // The siren sounded for a real alert.
+    Alert,
+    Maintenance,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckResult {
+    #[default]
+    Worked,
+    Faulty,
+    NoPower,
+}
+
+// The text kept in `siren_checks`, the same as the JSON names.
+impl CheckKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckKind::Test => "test",
+            CheckKind::Alert => "alert",
+            CheckKind::Maintenance => "maintenance",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<CheckKind, RpelError> {
+        match value {
+            "test" => Ok(CheckKind::Test),
+            "alert" => Ok(CheckKind::Alert),
+            "maintenance" => Ok(CheckKind::Maintenance),
+            _ => Err(RpelError::Invalid(format!(
+                "unknown siren check kind {value:?}"
+            ))),
+        }
+    }
+
+    // Tests and alerts sound the siren; maintenance does not prove it works.
+    pub fn is_activation(self) -> bool {
+        self != CheckKind::Maintenance
+    }
+}
+
+impl CheckResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckResult::Worked => "worked",
+            CheckResult::Faulty => "faulty",
+            CheckResult::NoPower => "no_power",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<CheckResult, RpelError> {
+        match value {
+            "worked" => Ok(CheckResult::Worked),
+            "faulty" => Ok(CheckResult::Faulty),
+            "no_power" => Ok(CheckResult::NoPower),
+            _ => Err(RpelError::Invalid(format!(
+                "unknown siren check result {value:?}"
+            ))),
+        }
+    }
+}
+
+// One entry of a siren's journal: a test, an alert or a maintenance visit.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SirenCheck {
+    #[serde(default)]
+    pub id: i64,
+    pub siren_id: Option<i64>,
+    pub check_date: NaiveDate,
+    pub kind: CheckKind,
+    pub result: CheckResult,
+    pub contact_id: Option<i64>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(SirenCheck);
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct SirenCheckList {
+    pub id: i64,
+    pub siren_id: Option<i64>,
+    pub siren_address: Option<String>,
+    pub check_date: NaiveDate,
+    pub kind: CheckKind,
+    pub result: CheckResult,
+    pub contact_id: Option<i64>,
+    pub contact_name: Option<String>,
+    pub note: Option<String>,
+}
+
+// A siren with its latest journal entry of any kind and its latest activation.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct SirenStatus {
+    pub siren_id: i64,
+    pub num_id: Option<i64>,
+    pub address: Option<String>,
+    pub siren_type_name: Option<String>,
+    pub company_name: Option<String>,
+    pub last_check: Option<NaiveDate>,
+    pub last_kind: Option<CheckKind>,
+    pub last_result: Option<CheckResult>,
+    pub last_test: Option<NaiveDate>,
+    pub last_test_result: Option<CheckResult>,
+}
+
+fn kind(value: Option<String>) -> Result<Option<CheckKind>, RpelError> {
+    value.as_deref().map(CheckKind::parse).transpose()
+}
+
+fn result(value: Option<String>) -> Result<Option<CheckResult>, RpelError> {
+    value.as_deref().map(CheckResult::parse).transpose()
+}
+
+impl SirenCheck {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<SirenCheck, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheck::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<SirenCheck, RpelError> {
+        traced("SirenCheck", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        siren_id,
+                        check_date,
+                        kind,
+                        result,
+                        contact_id,
+                        note,
+                        created_at,
+                        updated_at
+                    FROM
+                        siren_checks
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("siren_checks".to_string(), id))?;
+            let siren_check = SirenCheck {
+                id,
+                siren_id: row.try_get(0)?,
+                check_date: row.try_get(1)?,
+                kind: CheckKind::parse(row.try_get(2)?)?,
+                result: CheckResult::parse(row.try_get(3)?)?,
+                contact_id: row.try_get(4)?,
+                note: row.try_get(5)?,
+                created_at: row.try_get(6)?,
+                updated_at: row.try_get(7)?,
+            };
+            Ok(siren_check)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, siren_check: SirenCheck) -> Result<SirenCheck, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheck::insert_with(&client, siren_check).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        siren_check: SirenCheck,
+    ) -> Result<SirenCheck, RpelError> {
+        traced("SirenCheck", "insert", async move {
+            let mut siren_check = siren_check;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO siren_checks
+                    (
+                        siren_id,
+                        check_date,
+                        kind,
+                        result,
+                        contact_id,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6,
+                        $7,
+                        $8
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &siren_check.siren_id,
+                        &siren_check.check_date,
+                        &siren_check.kind.as_str(),
+                        &siren_check.result.as_str(),
+                        &siren_check.contact_id,
+                        &siren_check.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            siren_check.id = row.get(0);
+            notify(client, "siren_checks", siren_check.id, Operation::Insert, 1).await?;
+            Ok(siren_check)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, siren_check: SirenCheck) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheck::update_with(&client, siren_check).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        siren_check: SirenCheck,
+    ) -> Result<u64, RpelError> {
+        traced("SirenCheck", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE siren_checks SET
+                        siren_id = $2,
+                        check_date = $3,
+                        kind = $4,
+                        result = $5,
+                        contact_id = $6,
+                        note = $7,
+                        updated_at = $8
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $9
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &siren_check.id,
+                        &siren_check.siren_id,
+                        &siren_check.check_date,
+                        &siren_check.kind.as_str(),
+                        &siren_check.result.as_str(),
+                        &siren_check.contact_id,
+                        &siren_check.note,
+                        &Local::now().naive_local(),
+                        &siren_check.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "siren_checks",
+                siren_check.id,
+                siren_check.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "siren_checks",
+                siren_check.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheck::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("SirenCheck", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        siren_checks
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "siren_checks", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+fn list_query(filter: &str) -> String {
+    format!(
+        "
+            SELECT
+                k.id,
+                k.siren_id,
+                s.address AS siren_address,
+                k.check_date,
+                k.kind,
+                k.result,
+                k.contact_id,
+                c.name AS contact_name,
+                k.note
+            FROM
+                siren_checks AS k
+            LEFT JOIN
+                sirens AS s ON s.id = k.siren_id
+            LEFT JOIN
+                contacts AS c ON c.id = k.contact_id
+            WHERE
+                {filter}
+            ORDER BY
+                k.check_date DESC,
+                k.id DESC
+        "
+    )
+}
+
+fn list_row(row: &Row) -> Result<SirenCheckList, RpelError> {
+    Ok(SirenCheckList {
+        id: row.try_get(0)?,
+        siren_id: row.try_get(1)?,
+        siren_address: row.try_get(2)?,
+        check_date: row.try_get(3)?,
+        kind: CheckKind::parse(row.try_get(4)?)?,
+        result: CheckResult::parse(row.try_get(5)?)?,
+        contact_id: row.try_get(6)?,
+        contact_name: row.try_get(7)?,
+        note: row.try_get(8)?,
+    })
+}
+
+impl SirenCheckList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<SirenCheckList>, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheckList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SirenCheckList>, RpelError> {
+        traced("SirenCheckList", "get_all", async move {
+            let stmt = prepare(client, list_query("true").as_str()).await?;
+            let mut checks = Vec::new();
+            for row in client.query(&stmt, &[]).await? {
+                checks.push(list_row(&row)?);
+            }
+            Ok(checks)
+        })
+        .await
+    }
+
+    // A siren's journal, newest entry first.
+    pub async fn get_by_siren(
+        pool: &RpelPool,
+        siren_id: i64,
+    ) -> Result<Vec<SirenCheckList>, RpelError> {
+        let client = acquire(pool).await?;
+        SirenCheckList::get_by_siren_with(&client, siren_id).await
+    }
+
+    pub async fn get_by_siren_with<C: GenericClient>(
+        client: &C,
+        siren_id: i64,
+    ) -> Result<Vec<SirenCheckList>, RpelError> {
+        traced("SirenCheckList", "get_by_siren", async move {
+            let stmt = prepare(client, list_query("k.siren_id = $1").as_str()).await?;
+            let mut checks = Vec::new();
+            for row in client.query(&stmt, &[&siren_id]).await? {
+                checks.push(list_row(&row)?);
+            }
+            Ok(checks)
+        })
+        .await
+    }
+}
+
+fn status_query(filter: &str) -> String {
+    format!(
+        "
+            SELECT
+                s.id,
+                s.num_id,
+                s.address,
+                t.name AS siren_type_name,
+                c.name AS company_name,
+                l.check_date,
+                l.kind,
+                l.result,
+                a.check_date,
+                a.result
+            FROM
+                sirens AS s
+            LEFT JOIN
+                siren_types AS t ON t.id = s.siren_type_id
+            LEFT JOIN
+                companies AS c ON c.id = s.company_id
+            LEFT JOIN LATERAL (
+                SELECT check_date, kind, result FROM siren_checks
+                WHERE siren_id = s.id
+                ORDER BY check_date DESC, id DESC
+                LIMIT 1
+            ) AS l ON true
+            LEFT JOIN LATERAL (
+                SELECT check_date, result FROM siren_checks
+                WHERE siren_id = s.id AND kind <> 'maintenance'
+                ORDER BY check_date DESC, id DESC
+                LIMIT 1
+            ) AS a ON true
+            WHERE
+                {filter}
+            ORDER BY
+                s.address ASC,
+                s.id ASC
+        "
+    )
+}
+
+impl SirenStatus {
+    // Every siren with its last check.
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<SirenStatus>, RpelError> {
+        let client = acquire(pool).await?;
+        SirenStatus::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<SirenStatus>, RpelError> {
+        traced("SirenStatus", "get_all", async move {
+            SirenStatus::query(client, "true").await
+        })
+        .await
+    }
+
+    // Sirens that have never been sounded, by a test or an alert.
+    pub async fn get_untested(pool: &RpelPool) -> Result<Vec<SirenStatus>, RpelError> {
+        let client = acquire(pool).await?;
+        SirenStatus::get_untested_with(&client).await
+    }
+
+    pub async fn get_untested_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SirenStatus>, RpelError> {
+        traced("SirenStatus", "get_untested", async move {
+            SirenStatus::query(client, "a.check_date IS NULL").await
+        })
+        .await
+    }
+
+    // Sirens whose last test or alert did not work.
+    pub async fn get_failed(pool: &RpelPool) -> Result<Vec<SirenStatus>, RpelError> {
+        let client = acquire(pool).await?;
+        SirenStatus::get_failed_with(&client).await
+    }
+
+    pub async fn get_failed_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SirenStatus>, RpelError> {
+        traced("SirenStatus", "get_failed", async move {
+            SirenStatus::query(client, "a.result <> 'worked'").await
+        })
+        .await
+    }
+
+    async fn query<C: GenericClient>(
+        client: &C,
+        filter: &str,
+    ) -> Result<Vec<SirenStatus>, RpelError> {
+        let stmt = prepare(client, status_query(filter).as_str()).await?;
+        let mut sirens = Vec::new();
+        for row in client.query(&stmt, &[]).await? {
+            sirens.push(SirenStatus {
+                siren_id: row.try_get(0)?,
+                num_id: row.try_get(1)?,
+                address: row.try_get(2)?,
+                siren_type_name: row.try_get(3)?,
+                company_name: row.try_get(4)?,
+                last_check: row.try_get(5)?,
+                last_kind: kind(row.try_get(6)?)?,
+                last_result: result(row.try_get(7)?)?,
+                last_test: row.try_get(8)?,
+                last_test_result: result(row.try_get(9)?)?,
+            });
+        }
+        Ok(sirens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_and_results_round_trip() {
+        for kind in [CheckKind::Test, CheckKind::Alert, CheckKind::Maintenance] {
+            assert_eq!(CheckKind::parse(kind.as_str()).unwrap(), kind);
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(json, format!("{:?}", kind.as_str()));
+        }
+        for result in [
+            CheckResult::Worked,
+            CheckResult::Faulty,
+            CheckResult::NoPower,
+        ] {
+            assert_eq!(CheckResult::parse(result.as_str()).unwrap(), result);
+            let json = serde_json::to_string(&result).unwrap();
+            assert_eq!(json, format!("{:?}", result.as_str()));
+        }
+        assert!(matches!(
+            CheckKind::parse("drill"),
+            Err(RpelError::Invalid(_))
+        ));
+    }
+}
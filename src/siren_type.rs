@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct SirenType {
     #[serde(default)]
     pub id: i64,
@@ -12,10 +19,11 @@ pub struct SirenType {
     pub note: Option<String>,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(SirenType);
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SirenTypeList {
     pub id: i64,
@@ -30,9 +38,14 @@ impl SirenType {
     // }
 
     pub async fn get(pool: &RpelPool, id: i64) -> Result<SirenType, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenType::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<SirenType, RpelError> {
+        traced("SirenType", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -47,23 +60,36 @@ impl SirenType {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let siren_type = SirenType {
-            id,
-            name: row.try_get(0)?,
-            radius: row.try_get(1)?,
-            note: row.try_get(2)?,
-            created_at: row.try_get(3)?,
-            updated_at: row.try_get(4)?,
-        };
-        Ok(siren_type)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("siren_types".to_string(), id))?;
+            let siren_type = SirenType {
+                id,
+                name: row.try_get(0)?,
+                radius: row.try_get(1)?,
+                note: row.try_get(2)?,
+                created_at: row.try_get(3)?,
+                updated_at: row.try_get(4)?,
+            };
+            Ok(siren_type)
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, siren_type: SirenType) -> Result<SirenType, RpelError> {
-        let mut siren_type = siren_type;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenType::insert_with(&client, siren_type).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        siren_type: SirenType,
+    ) -> Result<SirenType, RpelError> {
+        traced("SirenType", "insert", async move {
+            let mut siren_type = siren_type;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO siren_types
                     (
@@ -86,26 +112,37 @@ impl SirenType {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &siren_type.name,
-                    &siren_type.radius,
-                    &siren_type.note,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        siren_type.id = row.get(0);
-        Ok(siren_type)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &siren_type.name,
+                        &siren_type.radius,
+                        &siren_type.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            siren_type.id = row.get(0);
+            notify(client, "siren_types", siren_type.id, Operation::Insert, 1).await?;
+            Ok(siren_type)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, siren_type: SirenType) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenType::update_with(&client, siren_type).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        siren_type: SirenType,
+    ) -> Result<u64, RpelError> {
+        traced("SirenType", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE siren_types SET
                         name = $2,
@@ -114,27 +151,54 @@ impl SirenType {
                         updated_at = $5
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $6
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &siren_type.id,
-                    &siren_type.name,
-                    &siren_type.radius,
-                    &siren_type.note,
-                    &Local::now().naive_local(),
-                ],
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &siren_type.id,
+                        &siren_type.name,
+                        &siren_type.radius,
+                        &siren_type.note,
+                        &Local::now().naive_local(),
+                        &siren_type.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "siren_types",
+                siren_type.id,
+                siren_type.updated_at,
+                result,
+            )
+            .await?;
+            notify(
+                client,
+                "siren_types",
+                siren_type.id,
+                Operation::Update,
+                result,
             )
-            .await?)
+            .await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenType::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("SirenType", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         siren_types
@@ -143,16 +207,27 @@ impl SirenType {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "siren_types", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl SirenTypeList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<SirenTypeList>, RpelError> {
-        let mut siren_types = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        SirenTypeList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<SirenTypeList>, RpelError> {
+        traced("SirenTypeList", "get_all", async move {
+            let mut siren_types = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -166,14 +241,16 @@ impl SirenTypeList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            siren_types.push(SirenTypeList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                radius: row.try_get(2)?,
-                note: row.try_get(3)?,
-            });
-        }
-        Ok(siren_types)
+            for row in client.query(&stmt, &[]).await? {
+                siren_types.push(SirenTypeList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    radius: row.try_get(2)?,
+                    note: row.try_get(3)?,
+                });
+            }
+            Ok(siren_types)
+        })
+        .await
     }
 }
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{acquire, error::RpelError, migrate, RpelPool};
+
+// Version 2 added `schema_version`, version 3 left out the contents of
+// attachments and version 4 the keys of users unless they are asked for.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+// Reference tables come first so that a restore never inserts a row before
+// the rows it points to. `attachment_chunks` is left out: the files would all
+// end up in one document, so they are copied one by one with
+// `Attachment::content` and `Attachment::restore_content` instead.
+pub const SNAPSHOT_TABLES: [&str; 26] = [
+    "scopes",
+    "kinds",
+    "ranks",
+    "posts",
+    "departments",
+    "siren_types",
+    "required_posts",
+    "settlements",
+    "streets",
+    "companies",
+    "contacts",
+    "employments",
+    "phones",
+    "emails",
+    "practices",
+    "educations",
+    "certificates",
+    "sirens",
+    "siren_checks",
+    "warning_zones",
+    "warning_zone_sirens",
+    "scenarios",
+    "scenario_zones",
+    "addresses",
+    "attachments",
+    "users",
+];
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub version: u32,
+    // The last migration applied to the database it was taken from. Tables
+    // change shape between migrations, so it only restores into a database
+    // at the same version.
+    #[serde(default)]
+    pub schema_version: i64,
+    pub created_at: NaiveDateTime,
+    pub tables: BTreeMap<String, Vec<Value>>,
+}
+
+impl Snapshot {
+    // The attachments whose contents go along with the snapshot.
+    pub fn attachment_ids(&self) -> Vec<i64> {
+        self.tables
+            .get("attachments")
+            .into_iter()
+            .flatten()
+            .filter_map(|row| row["id"].as_i64())
+            .collect()
+    }
+
+    // User keys authenticate API clients, so they stay out of the file unless
+    // `with_keys` is set; users restored without one get a random key that
+    // has to be reset.
+    pub async fn export(pool: &RpelPool, with_keys: bool) -> Result<Snapshot, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client
+            .build_transaction()
+            .read_only(true)
+            .isolation_level(tokio_postgres::IsolationLevel::RepeatableRead)
+            .start()
+            .await?;
+        let schema_version = migrate::current_version(&tx).await?;
+        let mut tables = BTreeMap::new();
+        for table in SNAPSHOT_TABLES {
+            let stmt = tx
+                .prepare(
+                    format!(
+                        "
+                            SELECT
+                                row_to_json(t)
+                            FROM
+                                {table} AS t
+                            ORDER BY
+                                t.id ASC
+                        "
+                    )
+                    .as_str(),
+                )
+                .await?;
+            let mut rows = Vec::new();
+            for row in tx.query(&stmt, &[]).await? {
+                let mut row: Value = row.try_get(0)?;
+                if table == "users" && !with_keys {
+                    if let Some(row) = row.as_object_mut() {
+                        row.remove("key");
+                    }
+                }
+                rows.push(row);
+            }
+            tables.insert(table.to_string(), rows);
+        }
+        tx.commit().await?;
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            schema_version,
+            created_at: Local::now().naive_local(),
+            tables,
+        })
+    }
+
+    pub async fn restore(pool: &RpelPool, snapshot: &Snapshot) -> Result<u64, RpelError> {
+        if snapshot.version > SNAPSHOT_VERSION {
+            return Err(RpelError::Snapshot(format!(
+                "unsupported version {}, expected at most {SNAPSHOT_VERSION}",
+                snapshot.version
+            )));
+        }
+        if let Some(table) = snapshot
+            .tables
+            .keys()
+            .find(|table| !SNAPSHOT_TABLES.contains(&table.as_str()))
+        {
+            return Err(RpelError::Snapshot(format!("unknown table {table}")));
+        }
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let current = migrate::current_version(&tx).await?;
+        if snapshot.schema_version != current {
+            return Err(RpelError::Snapshot(format!(
+                "taken at schema version {}, the database is at {current}",
+                snapshot.schema_version
+            )));
+        }
+        for table in SNAPSHOT_TABLES {
+            let row = tx
+                .query_one(format!("SELECT count(*) FROM {table}").as_str(), &[])
+                .await?;
+            let count: i64 = row.try_get(0)?;
+            if count > 0 {
+                return Err(RpelError::Snapshot(format!("table {table} is not empty")));
+            }
+        }
+        let mut restored = 0;
+        for table in SNAPSHOT_TABLES {
+            let rows = match snapshot.tables.get(table) {
+                Some(rows) if !rows.is_empty() => Value::Array(rows.clone()),
+                _ => continue,
+            };
+            let columns = match table {
+                "users" => {
+                    "id, name, COALESCE(key, gen_random_uuid()::text), role, created_at, updated_at"
+                }
+                _ => "*",
+            };
+            let stmt = tx
+                .prepare(
+                    format!(
+                        "
+                            INSERT INTO {table}
+                            SELECT
+                                {columns}
+                            FROM
+                                json_populate_recordset(NULL::{table}, $1)
+                        "
+                    )
+                    .as_str(),
+                )
+                .await?;
+            restored += tx.execute(&stmt, &[&rows]).await?;
+            tx.execute(
+                format!(
+                    "
+                        SELECT
+                            setval(pg_get_serial_sequence('{table}', 'id'), MAX(id))
+                        FROM
+                            {table}
+                    "
+                )
+                .as_str(),
+                &[],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(restored)
+    }
+}
@@ -0,0 +1,313 @@
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire,
+    error::RpelError,
+    select::SelectItem,
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// A GO post at a company with the contacts holding it. Posts the company has
+// to fill come first, the head of civil defence leading; posts people hold
+// without being required follow.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StaffingPost {
+    pub post_go_id: i64,
+    pub post_go_name: Option<String>,
+    pub required: bool,
+    pub head: bool,
+    pub holders: Vec<SelectItem>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Staffing {
+    pub company_id: i64,
+    pub company_name: Option<String>,
+    pub scope_name: Option<String>,
+    pub posts: Vec<StaffingPost>,
+    // Required posts nobody holds.
+    pub vacant: i64,
+}
+
+single_row!(Staffing);
+
+// A required post nobody at the company holds.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Vacancy {
+    pub company_id: i64,
+    pub company_name: Option<String>,
+    pub scope_name: Option<String>,
+    pub post_go_id: i64,
+    pub post_go_name: Option<String>,
+    pub head: bool,
+}
+
+impl Staffing {
+    // Sorts the holders, each with the id and name of the post they hold, into
+    // the required posts; both come in the order the report lists them.
+    pub(crate) fn new(
+        company: (i64, Option<String>, Option<String>),
+        required: Vec<StaffingPost>,
+        holders: Vec<(i64, Option<String>, SelectItem)>,
+    ) -> Staffing {
+        let (company_id, company_name, scope_name) = company;
+        let mut posts = required;
+        for (post_go_id, post_go_name, holder) in holders {
+            match posts.iter_mut().find(|p| p.post_go_id == post_go_id) {
+                Some(post) => post.holders.push(holder),
+                None => posts.push(StaffingPost {
+                    post_go_id,
+                    post_go_name,
+                    required: false,
+                    head: false,
+                    holders: vec![holder],
+                }),
+            }
+        }
+        let vacant = posts
+            .iter()
+            .filter(|p| p.required && p.holders.is_empty())
+            .count() as i64;
+        Staffing {
+            company_id,
+            company_name,
+            scope_name,
+            posts,
+            vacant,
+        }
+    }
+
+    pub async fn get(pool: &RpelPool, company_id: i64) -> Result<Staffing, RpelError> {
+        let client = acquire(pool).await?;
+        Staffing::get_with(&client, company_id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(
+        client: &C,
+        company_id: i64,
+    ) -> Result<Staffing, RpelError> {
+        traced("Staffing", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        c.name,
+                        s.name AS scope_name
+                    FROM
+                        companies AS c
+                    LEFT JOIN
+                        scopes AS s ON s.id = c.scope_id
+                    WHERE
+                        c.id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&company_id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("companies".to_string(), company_id))?;
+            let company = (company_id, row.try_get(0)?, row.try_get(1)?);
+
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        r.post_go_id,
+                        p.name AS post_go_name,
+                        bool_or(r.head) AS head
+                    FROM
+                        companies AS c
+                    JOIN
+                        required_posts AS r ON r.scope_id IS NULL OR r.scope_id = c.scope_id
+                    LEFT JOIN
+                        posts AS p ON p.id = r.post_go_id
+                    WHERE
+                        c.id = $1
+                    AND
+                        r.post_go_id IS NOT NULL
+                    GROUP BY
+                        r.post_go_id,
+                        p.name
+                    ORDER BY
+                        head DESC,
+                        p.name ASC,
+                        r.post_go_id ASC
+                ",
+            )
+            .await?;
+            let mut required = Vec::new();
+            for row in client.query(&stmt, &[&company_id]).await? {
+                required.push(StaffingPost {
+                    post_go_id: row.try_get(0)?,
+                    post_go_name: row.try_get(1)?,
+                    required: true,
+                    head: row.try_get(2)?,
+                    holders: Vec::new(),
+                });
+            }
+
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        c.post_go_id,
+                        p.name AS post_go_name,
+                        c.id,
+                        c.name
+                    FROM
+                        contacts AS c
+                    LEFT JOIN
+                        posts AS p ON p.id = c.post_go_id AND p.go = true
+                    WHERE
+                        c.company_id = $1
+                    AND
+                        c.post_go_id IS NOT NULL
+                    ORDER BY
+                        p.name ASC,
+                        c.post_go_id ASC,
+                        c.name ASC,
+                        c.id ASC
+                ",
+            )
+            .await?;
+            let mut holders = Vec::new();
+            for row in client.query(&stmt, &[&company_id]).await? {
+                let holder = SelectItem {
+                    id: row.try_get(2)?,
+                    name: row.try_get(3)?,
+                };
+                holders.push((row.try_get(0)?, row.try_get(1)?, holder));
+            }
+            Ok(Staffing::new(company, required, holders))
+        })
+        .await
+    }
+}
+
+fn vacancies_query(having: &str) -> String {
+    format!(
+        "
+            SELECT
+                c.id,
+                c.name,
+                s.name AS scope_name,
+                r.post_go_id,
+                p.name AS post_go_name,
+                bool_or(r.head) AS head
+            FROM
+                companies AS c
+            JOIN
+                required_posts AS r ON r.scope_id IS NULL OR r.scope_id = c.scope_id
+            LEFT JOIN
+                scopes AS s ON s.id = c.scope_id
+            LEFT JOIN
+                posts AS p ON p.id = r.post_go_id
+            WHERE
+                r.post_go_id IS NOT NULL
+            AND
+                NOT EXISTS (
+                    SELECT 1 FROM contacts AS h
+                    WHERE h.company_id = c.id AND h.post_go_id = r.post_go_id
+                )
+            GROUP BY
+                c.id,
+                c.name,
+                s.name,
+                r.post_go_id,
+                p.name
+            HAVING
+                {having}
+            ORDER BY
+                c.name ASC,
+                c.id ASC,
+                head DESC,
+                p.name ASC,
+                r.post_go_id ASC
+        "
+    )
+}
+
+impl Vacancy {
+    // Every required post left vacant, company by company.
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<Vacancy>, RpelError> {
+        let client = acquire(pool).await?;
+        Vacancy::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<Vacancy>, RpelError> {
+        traced("Vacancy", "get_all", async move {
+            Vacancy::query(client, "true").await
+        })
+        .await
+    }
+
+    // Companies without a head of civil defence. Only companies a head post is
+    // required of are checked.
+    pub async fn get_heads(pool: &RpelPool) -> Result<Vec<Vacancy>, RpelError> {
+        let client = acquire(pool).await?;
+        Vacancy::get_heads_with(&client).await
+    }
+
+    pub async fn get_heads_with<C: GenericClient>(client: &C) -> Result<Vec<Vacancy>, RpelError> {
+        traced("Vacancy", "get_heads", async move {
+            Vacancy::query(client, "bool_or(r.head)").await
+        })
+        .await
+    }
+
+    async fn query<C: GenericClient>(client: &C, having: &str) -> Result<Vec<Vacancy>, RpelError> {
+        let stmt = prepare(client, vacancies_query(having).as_str()).await?;
+        let mut vacancies = Vec::new();
+        for row in client.query(&stmt, &[]).await? {
+            vacancies.push(Vacancy {
+                company_id: row.try_get(0)?,
+                company_name: row.try_get(1)?,
+                scope_name: row.try_get(2)?,
+                post_go_id: row.try_get(3)?,
+                post_go_name: row.try_get(4)?,
+                head: row.try_get(5)?,
+            });
+        }
+        Ok(vacancies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(post_go_id: i64, name: &str, head: bool) -> StaffingPost {
+        StaffingPost {
+            post_go_id,
+            post_go_name: Some(name.to_string()),
+            required: true,
+            head,
+            holders: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn holders_fill_required_posts() {
+        let holder = |id: i64, name: &str| SelectItem {
+            id,
+            name: Some(name.to_string()),
+        };
+        let staffing = Staffing::new(
+            (1, Some("Grid".to_string()), None),
+            vec![post(10, "Head of GO", true), post(11, "Evacuation", false)],
+            vec![
+                (12, Some("Signaller".to_string()), holder(3, "Petrov")),
+                (10, Some("Head of GO".to_string()), holder(2, "Ivanov")),
+            ],
+        );
+        let posts: Vec<(i64, bool, usize)> = staffing
+            .posts
+            .iter()
+            .map(|p| (p.post_go_id, p.required, p.holders.len()))
+            .collect();
+        assert_eq!(posts, vec![(10, true, 1), (11, true, 0), (12, false, 1)]);
+        assert_eq!(staffing.vacant, 1);
+    }
+}
@@ -0,0 +1,290 @@
+use chrono::NaiveDate;
+use futures_core::Stream;
+use futures_util::{stream, StreamExt};
+
+use crate::{
+    address::{Address, AddressList, AddressReport},
+    attachment::{Attachment, AttachmentList, AttachmentOwner, Chunks},
+    certificate::{Certificate, CertificateList, CertificatePatch},
+    company::{Company, CompanyList, CompanyNode, CompanyPatch, CompanyRollup},
+    contact::{Contact, ContactList, ContactMerge, ContactPatch, ContactShort},
+    department::{Department, DepartmentList},
+    duplicate::DuplicateCandidate,
+    education::{Education, EducationList, EducationPatch, EducationShort},
+    email::Email,
+    employment::{Employment, EmploymentList},
+    error::RpelError,
+    kind::{Kind, KindList},
+    memory::MemoryStore,
+    phone::Phone,
+    post::{Post, PostList},
+    practice::{Practice, PracticeList, PracticePatch, PracticeShort},
+    rank::{Rank, RankList},
+    required_post::{RequiredPost, RequiredPostList},
+    scenario::{ChecklistItem, Scenario, ScenarioList},
+    scope::{Scope, ScopeList},
+    select::SelectItem,
+    settlement::{Settlement, SettlementList},
+    siren::{Siren, SirenList, SirenPatch},
+    siren_check::{SirenCheck, SirenCheckList, SirenStatus},
+    siren_type::{SirenType, SirenTypeList},
+    staffing::{Staffing, Vacancy},
+    street::{Street, StreetList},
+    user::{User, UserList},
+    warning_zone::{WarningZone, WarningZoneList},
+    RpelPool,
+};
+
+// Handlers written against `Store` run on the Postgres pool in production and
+// on `MemoryStore` in unit tests.
+#[derive(Clone)]
+pub enum Store {
+    Postgres(RpelPool),
+    Memory(MemoryStore),
+}
+
+impl From<RpelPool> for Store {
+    fn from(pool: RpelPool) -> Self {
+        Store::Postgres(pool)
+    }
+}
+
+impl From<MemoryStore> for Store {
+    fn from(memory: MemoryStore) -> Self {
+        Store::Memory(memory)
+    }
+}
+
+macro_rules! dispatch {
+    ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty => $postgres:path;)*) => {
+        impl Store {
+            $(pub async fn $name(&self, $($arg: $ty),*) -> Result<$ret, RpelError> {
+                match self {
+                    Store::Postgres(pool) => $postgres(pool, $($arg),*).await,
+                    Store::Memory(memory) => memory.$name($($arg),*),
+                }
+            })*
+        }
+    };
+}
+
+dispatch! {
+    company_get(id: i64) -> Company => Company::get;
+    company_insert(company: Company) -> Company => Company::insert;
+    company_update(company: Company) -> u64 => Company::update;
+    company_patch(id: i64, company: CompanyPatch) -> u64 => Company::patch;
+    company_delete(id: i64) -> u64 => Company::delete;
+    company_list() -> Vec<CompanyList> => CompanyList::get_all;
+    company_subtree(id: i64) -> Vec<CompanyNode> => Company::subtree;
+    company_ancestors(id: i64) -> Vec<CompanyNode> => Company::ancestors;
+    company_rollup(id: i64) -> CompanyRollup => CompanyRollup::get;
+    company_rollup_all() -> Vec<CompanyRollup> => CompanyRollup::get_all;
+
+    contact_get(id: i64) -> Contact => Contact::get;
+    contact_insert(contact: Contact) -> Contact => Contact::insert;
+    contact_update(contact: Contact) -> u64 => Contact::update;
+    contact_patch(id: i64, contact: ContactPatch) -> u64 => Contact::patch;
+    contact_delete(id: i64) -> u64 => Contact::delete;
+    contact_list() -> Vec<ContactList> => ContactList::get_all;
+    contact_short_by_company(company_id: i64) -> Vec<ContactShort> => ContactShort::get_by_company;
+    contact_merge(survivor_id: i64, duplicate_id: i64) -> ContactMerge => Contact::merge;
+    duplicate_find(min_score: i64) -> Vec<DuplicateCandidate> => DuplicateCandidate::find;
+
+    phone_update_contacts(id: i64, fax: bool, phones: Vec<i64>) -> () => Phone::update_contacts;
+    phone_update_companies(id: i64, fax: bool, phones: Vec<i64>) -> () => Phone::update_companies;
+    phone_delete_contacts(id: i64, fax: bool) -> u64 => Phone::delete_contacts;
+    phone_delete_companies(id: i64, fax: bool) -> u64 => Phone::delete_companies;
+
+    email_update_contacts(id: i64, emails: Vec<String>) -> () => Email::update_contacts;
+    email_update_companies(id: i64, emails: Vec<String>) -> () => Email::update_companies;
+    email_delete_contacts(id: i64) -> u64 => Email::delete_contacts;
+    email_delete_companies(id: i64) -> u64 => Email::delete_companies;
+
+    practice_get(id: i64) -> Practice => Practice::get;
+    practice_insert(practice: Practice) -> Practice => Practice::insert;
+    practice_update(practice: Practice) -> u64 => Practice::update;
+    practice_patch(id: i64, practice: PracticePatch) -> u64 => Practice::patch;
+    practice_delete(id: i64) -> u64 => Practice::delete;
+    practice_list() -> Vec<PracticeList> => PracticeList::get_all;
+    practice_list_by_company(company_id: i64) -> Vec<PracticeList> => PracticeList::get_by_company;
+    practice_near() -> Vec<PracticeShort> => PracticeShort::get_near;
+
+    education_get(id: i64) -> Education => Education::get;
+    education_insert(education: Education) -> Education => Education::insert;
+    education_update(education: Education) -> u64 => Education::update;
+    education_patch(id: i64, education: EducationPatch) -> u64 => Education::patch;
+    education_delete(id: i64) -> u64 => Education::delete;
+    education_list() -> Vec<EducationList> => EducationList::get_all;
+    education_near() -> Vec<EducationShort> => EducationShort::get_near;
+
+    employment_get(id: i64) -> Employment => Employment::get;
+    employment_insert(employment: Employment) -> Employment => Employment::insert;
+    employment_update(employment: Employment) -> u64 => Employment::update;
+    employment_delete(id: i64) -> u64 => Employment::delete;
+    employment_list() -> Vec<EmploymentList> => EmploymentList::get_all;
+    employment_by_contact(contact_id: i64) -> Vec<EmploymentList> => EmploymentList::get_by_contact;
+    employment_holders(company_id: i64, post_go_id: i64, date: NaiveDate) -> Vec<EmploymentList> => EmploymentList::get_holders;
+
+    certificate_get(id: i64) -> Certificate => Certificate::get;
+    certificate_insert(certificate: Certificate) -> Certificate => Certificate::insert;
+    certificate_update(certificate: Certificate) -> u64 => Certificate::update;
+    certificate_patch(id: i64, certificate: CertificatePatch) -> u64 => Certificate::patch;
+    certificate_delete(id: i64) -> u64 => Certificate::delete;
+    certificate_list() -> Vec<CertificateList> => CertificateList::get_all;
+
+    siren_get(id: i64) -> Siren => Siren::get;
+    siren_insert(siren: Siren) -> Siren => Siren::insert;
+    siren_update(siren: Siren) -> u64 => Siren::update;
+    siren_patch(id: i64, siren: SirenPatch) -> u64 => Siren::patch;
+    siren_delete(id: i64) -> u64 => Siren::delete;
+    siren_list() -> Vec<SirenList> => SirenList::get_all;
+
+    siren_check_get(id: i64) -> SirenCheck => SirenCheck::get;
+    siren_check_insert(siren_check: SirenCheck) -> SirenCheck => SirenCheck::insert;
+    siren_check_update(siren_check: SirenCheck) -> u64 => SirenCheck::update;
+    siren_check_delete(id: i64) -> u64 => SirenCheck::delete;
+    siren_check_list() -> Vec<SirenCheckList> => SirenCheckList::get_all;
+    siren_check_by_siren(siren_id: i64) -> Vec<SirenCheckList> => SirenCheckList::get_by_siren;
+    siren_status_all() -> Vec<SirenStatus> => SirenStatus::get_all;
+    siren_status_untested() -> Vec<SirenStatus> => SirenStatus::get_untested;
+    siren_status_failed() -> Vec<SirenStatus> => SirenStatus::get_failed;
+
+    warning_zone_get(id: i64) -> WarningZone => WarningZone::get;
+    warning_zone_insert(warning_zone: WarningZone) -> WarningZone => WarningZone::insert;
+    warning_zone_update(warning_zone: WarningZone) -> u64 => WarningZone::update;
+    warning_zone_delete(id: i64) -> u64 => WarningZone::delete;
+    warning_zone_list() -> Vec<WarningZoneList> => WarningZoneList::get_all;
+
+    scenario_get(id: i64) -> Scenario => Scenario::get;
+    scenario_insert(scenario: Scenario) -> Scenario => Scenario::insert;
+    scenario_update(scenario: Scenario) -> u64 => Scenario::update;
+    scenario_delete(id: i64) -> u64 => Scenario::delete;
+    scenario_list() -> Vec<ScenarioList> => ScenarioList::get_all;
+    scenario_checklist(id: i64) -> Vec<ChecklistItem> => Scenario::checklist;
+
+    settlement_get(id: i64) -> Settlement => Settlement::get;
+    settlement_insert(settlement: Settlement) -> Settlement => Settlement::insert;
+    settlement_update(settlement: Settlement) -> u64 => Settlement::update;
+    settlement_delete(id: i64) -> u64 => Settlement::delete;
+    settlement_list() -> Vec<SettlementList> => SettlementList::get_all;
+
+    street_get(id: i64) -> Street => Street::get;
+    street_insert(street: Street) -> Street => Street::insert;
+    street_update(street: Street) -> u64 => Street::update;
+    street_delete(id: i64) -> u64 => Street::delete;
+    street_list() -> Vec<StreetList> => StreetList::get_all;
+
+    address_get(id: i64) -> Address => Address::get;
+    address_insert(address: Address) -> Address => Address::insert;
+    address_update(address: Address) -> u64 => Address::update;
+    address_delete(id: i64) -> u64 => Address::delete;
+    address_list() -> Vec<AddressList> => AddressList::get_all;
+    address_parse(apply: bool) -> AddressReport => Address::parse_all;
+
+    attachment_get(id: i64) -> Attachment => Attachment::get;
+    attachment_update(attachment: Attachment) -> u64 => Attachment::update;
+    attachment_delete(id: i64) -> u64 => Attachment::delete;
+    attachment_list() -> Vec<AttachmentList> => AttachmentList::get_all;
+    attachment_by_owner(owner: AttachmentOwner, owner_id: i64) -> Vec<AttachmentList> => AttachmentList::get_by_owner;
+
+    department_get(id: i64) -> Department => Department::get;
+    department_insert(department: Department) -> Department => Department::insert;
+    department_update(department: Department) -> u64 => Department::update;
+    department_delete(id: i64) -> u64 => Department::delete;
+    department_list() -> Vec<DepartmentList> => DepartmentList::get_all;
+
+    kind_get(id: i64) -> Kind => Kind::get;
+    kind_insert(kind: Kind) -> Kind => Kind::insert;
+    kind_update(kind: Kind) -> u64 => Kind::update;
+    kind_delete(id: i64) -> u64 => Kind::delete;
+    kind_list() -> Vec<KindList> => KindList::get_all;
+
+    post_get(id: i64) -> Post => Post::get;
+    post_insert(post: Post) -> Post => Post::insert;
+    post_update(post: Post) -> u64 => Post::update;
+    post_delete(id: i64) -> u64 => Post::delete;
+    post_list() -> Vec<PostList> => PostList::get_all;
+
+    rank_get(id: i64) -> Rank => Rank::get;
+    rank_insert(rank: Rank) -> Rank => Rank::insert;
+    rank_update(rank: Rank) -> u64 => Rank::update;
+    rank_delete(id: i64) -> u64 => Rank::delete;
+    rank_list() -> Vec<RankList> => RankList::get_all;
+
+    required_post_get(id: i64) -> RequiredPost => RequiredPost::get;
+    required_post_insert(required_post: RequiredPost) -> RequiredPost => RequiredPost::insert;
+    required_post_update(required_post: RequiredPost) -> u64 => RequiredPost::update;
+    required_post_delete(id: i64) -> u64 => RequiredPost::delete;
+    required_post_list() -> Vec<RequiredPostList> => RequiredPostList::get_all;
+
+    staffing_get(company_id: i64) -> Staffing => Staffing::get;
+    vacancy_all() -> Vec<Vacancy> => Vacancy::get_all;
+    vacancy_heads() -> Vec<Vacancy> => Vacancy::get_heads;
+
+    scope_get(id: i64) -> Scope => Scope::get;
+    scope_insert(scope: Scope) -> Scope => Scope::insert;
+    scope_update(scope: Scope) -> u64 => Scope::update;
+    scope_delete(id: i64) -> u64 => Scope::delete;
+    scope_list() -> Vec<ScopeList> => ScopeList::get_all;
+
+    siren_type_get(id: i64) -> SirenType => SirenType::get;
+    siren_type_insert(siren_type: SirenType) -> SirenType => SirenType::insert;
+    siren_type_update(siren_type: SirenType) -> u64 => SirenType::update;
+    siren_type_delete(id: i64) -> u64 => SirenType::delete;
+    siren_type_list() -> Vec<SirenTypeList> => SirenTypeList::get_all;
+
+    user_get(id: i64) -> User => User::get;
+    user_by_name(name: &str) -> Option<User> => User::get_by_name;
+    user_by_key(key: &str) -> Option<User> => User::get_by_key;
+    user_insert(user: User) -> User => User::insert;
+    user_update(user: User) -> u64 => User::update;
+    user_delete(id: i64) -> u64 => User::delete;
+    user_list() -> Vec<UserList> => UserList::get_all;
+
+    select_company_all() -> Vec<SelectItem> => SelectItem::company_all;
+    select_contact_all() -> Vec<SelectItem> => SelectItem::contact_all;
+    select_department_all() -> Vec<SelectItem> => SelectItem::department_all;
+    select_department_by_company(company_id: i64) -> Vec<SelectItem> => SelectItem::department_by_company;
+    select_kind_all() -> Vec<SelectItem> => SelectItem::kind_all;
+    select_post_all(go: bool) -> Vec<SelectItem> => SelectItem::post_all;
+    select_rank_all() -> Vec<SelectItem> => SelectItem::rank_all;
+    select_scope_all() -> Vec<SelectItem> => SelectItem::scope_all;
+    select_siren_type_all() -> Vec<SelectItem> => SelectItem::siren_type_all;
+}
+
+// File contents go through streams, which `dispatch!` cannot pass to the
+// synchronous `MemoryStore`; it takes and hands back whole files instead.
+impl Store {
+    pub async fn attachment_upload<S, B>(
+        &self,
+        attachment: Attachment,
+        chunks: S,
+    ) -> Result<Attachment, RpelError>
+    where
+        S: Stream<Item = Result<B, RpelError>> + Send + Unpin,
+        B: AsRef<[u8]> + Send,
+    {
+        match self {
+            Store::Postgres(pool) => Attachment::upload(pool, attachment, chunks).await,
+            Store::Memory(memory) => {
+                let mut chunks = chunks;
+                let mut data = Vec::new();
+                while let Some(chunk) = chunks.next().await {
+                    data.extend_from_slice(chunk?.as_ref());
+                }
+                memory.attachment_upload(attachment, data)
+            }
+        }
+    }
+
+    pub async fn attachment_content(&self, id: i64) -> Result<(Attachment, Chunks), RpelError> {
+        match self {
+            Store::Postgres(pool) => Attachment::content(pool, id).await,
+            Store::Memory(memory) => {
+                let (attachment, data) = memory.attachment_content(id)?;
+                let chunks: Chunks = Box::pin(stream::once(async move { Ok(data) }));
+                Ok((attachment, chunks))
+            }
+        }
+    }
+}
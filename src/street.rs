@@ -0,0 +1,279 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// A street of a settlement. `aliases` holds the other spellings met in the
+// free-text addresses that the parser should take for this street.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Street {
+    #[serde(default)]
+    pub id: i64,
+    pub settlement_id: Option<i64>,
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+single_row!(Street);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StreetList {
+    pub id: i64,
+    pub settlement_id: Option<i64>,
+    pub settlement_name: Option<String>,
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    pub aliases: Vec<String>,
+    pub companies: i64,
+    pub sirens: i64,
+}
+
+impl Street {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<Street, RpelError> {
+        let client = acquire(pool).await?;
+        Street::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<Street, RpelError> {
+        traced("Street", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        settlement_id,
+                        name,
+                        kind,
+                        aliases,
+                        note,
+                        created_at,
+                        updated_at
+                    FROM
+                        streets
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("streets".to_string(), id))?;
+            let street = Street {
+                id,
+                settlement_id: row.try_get(0)?,
+                name: row.try_get(1)?,
+                kind: row.try_get(2)?,
+                aliases: row.try_get(3)?,
+                note: row.try_get(4)?,
+                created_at: row.try_get(5)?,
+                updated_at: row.try_get(6)?,
+            };
+            Ok(street)
+        })
+        .await
+    }
+
+    pub async fn insert(pool: &RpelPool, street: Street) -> Result<Street, RpelError> {
+        let client = acquire(pool).await?;
+        Street::insert_with(&client, street).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        street: Street,
+    ) -> Result<Street, RpelError> {
+        traced("Street", "insert", async move {
+            let mut street = street;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO streets
+                    (
+                        settlement_id,
+                        name,
+                        kind,
+                        aliases,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4,
+                        $5,
+                        $6,
+                        $7
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &street.settlement_id,
+                        &street.name,
+                        &street.kind,
+                        &street.aliases,
+                        &street.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            street.id = row.get(0);
+            notify(client, "streets", street.id, Operation::Insert, 1).await?;
+            Ok(street)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, street: Street) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Street::update_with(&client, street).await
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        street: Street,
+    ) -> Result<u64, RpelError> {
+        traced("Street", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE streets SET
+                        settlement_id = $2,
+                        name = $3,
+                        kind = $4,
+                        aliases = $5,
+                        note = $6,
+                        updated_at = $7
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $8
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &street.id,
+                        &street.settlement_id,
+                        &street.name,
+                        &street.kind,
+                        &street.aliases,
+                        &street.note,
+                        &Local::now().naive_local(),
+                        &street.updated_at,
+                    ],
+                )
+                .await?;
+            let result =
+                check_version(client, "streets", street.id, street.updated_at, result).await?;
+            notify(client, "streets", street.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let client = acquire(pool).await?;
+        Street::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("Street", "delete", async move {
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        streets
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "streets", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+impl StreetList {
+    // Every street with the number of companies and sirens on it, settlement
+    // by settlement.
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<StreetList>, RpelError> {
+        let client = acquire(pool).await?;
+        StreetList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<StreetList>, RpelError> {
+        traced("StreetList", "get_all", async move {
+            let mut streets = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        t.id,
+                        t.settlement_id,
+                        s.name AS settlement_name,
+                        t.name,
+                        t.kind,
+                        t.aliases,
+                        count(a.company_id) AS companies,
+                        count(a.siren_id) AS sirens
+                    FROM
+                        streets AS t
+                    LEFT JOIN
+                        settlements AS s ON s.id = t.settlement_id
+                    LEFT JOIN
+                        addresses AS a ON a.street_id = t.id
+                    GROUP BY
+                        t.id,
+                        s.name
+                    ORDER BY
+                        s.name ASC,
+                        t.name ASC,
+                        t.id ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                streets.push(StreetList {
+                    id: row.try_get(0)?,
+                    settlement_id: row.try_get(1)?,
+                    settlement_name: row.try_get(2)?,
+                    name: row.try_get(3)?,
+                    kind: row.try_get(4)?,
+                    aliases: row.try_get(5)?,
+                    companies: row.try_get(6)?,
+                    sirens: row.try_get(7)?,
+                });
+            }
+            Ok(streets)
+        })
+        .await
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use serde::{Deserialize, Serialize};
+
+use crate::error::RpelError;
+
+// Mirrors the libpq sslmode levels that decide how much of the server
+// certificate is checked: require, verify-ca and verify-full.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TlsVerify {
+    Require,
+    VerifyCa,
+    #[default]
+    VerifyFull,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TlsOptions {
+    #[serde(default)]
+    pub verify: TlsVerify,
+    pub root_cert: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    pub fn verify_full(root_cert: impl Into<PathBuf>) -> Self {
+        TlsOptions {
+            verify: TlsVerify::VerifyFull,
+            root_cert: Some(root_cert.into()),
+        }
+    }
+
+    pub fn connector(&self) -> Result<MakeTlsConnector, RpelError> {
+        let mut builder = TlsConnector::builder();
+        if let Some(path) = &self.root_cert {
+            let pem = std::fs::read_to_string(path)?;
+            for cert in pem_certificates(&pem) {
+                builder.add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
+            }
+        }
+        match self.verify {
+            TlsVerify::Require => {
+                builder.danger_accept_invalid_certs(true);
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            TlsVerify::VerifyCa => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            TlsVerify::VerifyFull => {}
+        }
+        Ok(MakeTlsConnector::new(builder.build()?))
+    }
+}
+
+// A root certificate file may hold a whole chain; native-tls only reads the
+// first PEM block of a buffer, so split them up front.
+fn pem_certificates(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    pem.split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(|block| block.trim_start().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pem_certificates_splits_chain() {
+        let pem = "-----BEGIN CERTIFICATE-----\nAAA\n-----END CERTIFICATE-----\n-----BEGIN CERTIFICATE-----\nBBB\n-----END CERTIFICATE-----\n";
+        let certs = pem_certificates(pem);
+        assert_eq!(certs.len(), 2);
+        assert!(certs[1].starts_with("-----BEGIN CERTIFICATE-----\nBBB"));
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use deadpool_postgres::GenericClient;
+use tokio_postgres::Statement;
+use tracing::{field::Empty, Instrument};
+
+use crate::{error::RpelError, metrics};
+
+// Zero keeps slow-query logging off.
+static SLOW_QUERY_MS: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    static STATEMENTS: RefCell<Vec<String>>;
+}
+
+pub fn set_slow_query_threshold(threshold: Option<Duration>) {
+    let ms = threshold.map_or(0, |t| t.as_millis().max(1) as u64);
+    SLOW_QUERY_MS.store(ms, Ordering::Relaxed);
+}
+
+pub fn slow_query_threshold() -> Option<Duration> {
+    match SLOW_QUERY_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+fn is_slow(elapsed: Duration, threshold: Option<Duration>) -> bool {
+    threshold.is_some_and(|threshold| elapsed >= threshold)
+}
+
+pub trait Rows {
+    fn rows(&self) -> u64;
+}
+
+impl Rows for u64 {
+    fn rows(&self) -> u64 {
+        *self
+    }
+}
+
+impl Rows for () {
+    fn rows(&self) -> u64 {
+        0
+    }
+}
+
+impl<T: Rows> Rows for Option<T> {
+    fn rows(&self) -> u64 {
+        self.as_ref().map_or(0, Rows::rows)
+    }
+}
+
+impl<T> Rows for Vec<T> {
+    fn rows(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+// Entities returned by a single-row operation; each module lists its own.
+macro_rules! single_row {
+    ($($entity:ty),* $(,)?) => {
+        $(impl $crate::trace::Rows for $entity {
+            fn rows(&self) -> u64 {
+                1
+            }
+        })*
+    };
+}
+
+pub(crate) use single_row;
+
+// Remembers the statement text for the slow-query report of the operation
+// that is currently running. With no threshold there is no report, so the
+// text is not copied at all.
+pub(crate) fn record(sql: &str) {
+    if SLOW_QUERY_MS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let _ = STATEMENTS.try_with(|statements| statements.borrow_mut().push(sql.to_string()));
+}
+
+pub(crate) async fn prepare<C: GenericClient>(
+    client: &C,
+    sql: &str,
+) -> Result<Statement, tokio_postgres::Error> {
+    record(sql);
+    client.prepare(sql).await
+}
+
+pub(crate) async fn traced<T, F>(
+    entity: &'static str,
+    operation: &'static str,
+    work: F,
+) -> Result<T, RpelError>
+where
+    T: Rows,
+    F: Future<Output = Result<T, RpelError>>,
+{
+    let span = tracing::debug_span!("rpel", entity, operation, rows = Empty, duration_ms = Empty);
+    let started = Instant::now();
+    let (result, statements) = STATEMENTS
+        .scope(RefCell::new(Vec::new()), async {
+            let result = work.await;
+            (result, STATEMENTS.with(|statements| statements.take()))
+        })
+        .instrument(span.clone())
+        .await;
+    let elapsed = started.elapsed();
+    // A nested operation (phones saved with their contact) also counts towards
+    // the statements of the one that called it.
+    let _ = STATEMENTS.try_with(|outer| outer.borrow_mut().extend(statements.iter().cloned()));
+    let duration_ms = elapsed.as_millis() as u64;
+    span.record("duration_ms", duration_ms);
+    metrics::observe(entity, operation, elapsed, result.as_ref().err());
+    match &result {
+        Ok(value) => {
+            span.record("rows", value.rows());
+            tracing::debug!(parent: &span, "{entity}::{operation} done");
+        }
+        Err(err) => {
+            tracing::debug!(parent: &span, error = %err, "{entity}::{operation} failed");
+        }
+    }
+    if is_slow(elapsed, slow_query_threshold()) {
+        tracing::warn!(
+            parent: &span,
+            duration_ms,
+            statement = %statements.join(";\n"),
+            "slow query in {entity}::{operation}"
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_statements_over_the_threshold_are_slow() {
+        let threshold = Some(Duration::from_millis(100));
+        assert!(is_slow(Duration::from_millis(150), threshold));
+        assert!(is_slow(Duration::from_millis(100), threshold));
+        assert!(!is_slow(Duration::from_millis(99), threshold));
+        assert!(!is_slow(Duration::from_secs(60), None));
+    }
+
+    #[test]
+    fn statements_are_recorded_only_with_a_threshold() {
+        let recorded = || {
+            STATEMENTS.sync_scope(RefCell::new(Vec::new()), || {
+                record("SELECT 1");
+                STATEMENTS.with(|statements| statements.take())
+            })
+        };
+        assert!(recorded().is_empty());
+        set_slow_query_threshold(Some(Duration::from_millis(100)));
+        assert_eq!(recorded(), vec!["SELECT 1".to_string()]);
+        set_slow_query_threshold(None);
+    }
+}
@@ -1,9 +1,16 @@
 use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::RpelError, RpelPool};
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct User {
     #[serde(default)]
     pub id: i64,
@@ -12,10 +19,14 @@ pub struct User {
     pub role: i64,
     #[serde(skip_serializing)]
     pub created_at: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
     pub updated_at: Option<NaiveDateTime>,
 }
 
+single_row!(User);
+
+// Role 0 may only read; any other role may also write.
+pub const READ_ONLY_ROLE: i64 = 0;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UserList {
     pub id: i64,
@@ -25,10 +36,19 @@ pub struct UserList {
 }
 
 impl User {
+    pub fn can_write(&self) -> bool {
+        self.role != READ_ONLY_ROLE
+    }
+
     pub async fn get(pool: &RpelPool, id: i64) -> Result<User, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        User::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<User, RpelError> {
+        traced("User", "get", async move {
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         name,
@@ -43,23 +63,117 @@ impl User {
                 ",
             )
             .await?;
-        let row = client.query_one(&stmt, &[&id]).await?;
-        let user = User {
-            id,
-            name: row.try_get(0)?,
-            key: row.try_get(1)?,
-            role: row.try_get(2)?,
-            created_at: row.try_get(3)?,
-            updated_at: row.try_get(4)?,
-        };
-        Ok(user)
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("users".to_string(), id))?;
+            let user = User {
+                id,
+                name: row.try_get(0)?,
+                key: row.try_get(1)?,
+                role: row.try_get(2)?,
+                created_at: row.try_get(3)?,
+                updated_at: row.try_get(4)?,
+            };
+            Ok(user)
+        })
+        .await
+    }
+
+    pub async fn get_by_name(pool: &RpelPool, name: &str) -> Result<Option<User>, RpelError> {
+        let client = acquire(pool).await?;
+        User::get_by_name_with(&client, name).await
+    }
+
+    pub async fn get_by_name_with<C: GenericClient>(
+        client: &C,
+        name: &str,
+    ) -> Result<Option<User>, RpelError> {
+        traced("User", "get_by_name", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        id,
+                        name,
+                        key,
+                        role,
+                        created_at,
+                        updated_at
+                    FROM
+                        users
+                    WHERE
+                        name = $1
+                ",
+            )
+            .await?;
+            match client.query_opt(&stmt, &[&name]).await? {
+                Some(row) => Ok(Some(User {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    key: row.try_get(2)?,
+                    role: row.try_get(3)?,
+                    created_at: row.try_get(4)?,
+                    updated_at: row.try_get(5)?,
+                })),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_by_key(pool: &RpelPool, key: &str) -> Result<Option<User>, RpelError> {
+        let client = acquire(pool).await?;
+        User::get_by_key_with(&client, key).await
+    }
+
+    pub async fn get_by_key_with<C: GenericClient>(
+        client: &C,
+        key: &str,
+    ) -> Result<Option<User>, RpelError> {
+        traced("User", "get_by_key", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        id,
+                        name,
+                        key,
+                        role,
+                        created_at,
+                        updated_at
+                    FROM
+                        users
+                    WHERE
+                        key = $1
+                ",
+            )
+            .await?;
+            match client.query_opt(&stmt, &[&key]).await? {
+                Some(row) => Ok(Some(User {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    key: row.try_get(2)?,
+                    role: row.try_get(3)?,
+                    created_at: row.try_get(4)?,
+                    updated_at: row.try_get(5)?,
+                })),
+                None => Ok(None),
+            }
+        })
+        .await
     }
 
     pub async fn insert(pool: &RpelPool, user: User) -> Result<User, RpelError> {
-        let mut user = user;
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        User::insert_with(&client, user).await
+    }
+
+    pub async fn insert_with<C: GenericClient>(client: &C, user: User) -> Result<User, RpelError> {
+        traced("User", "insert", async move {
+            let mut user = user;
+            let stmt = prepare(
+                client,
                 "
                     INSERT INTO users
                     (
@@ -82,26 +196,34 @@ impl User {
                 ",
             )
             .await?;
-        let row = client
-            .query_one(
-                &stmt,
-                &[
-                    &user.name,
-                    &user.key,
-                    &user.role,
-                    &Local::now().naive_local(),
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?;
-        user.id = row.get(0);
-        Ok(user)
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &user.name,
+                        &user.key,
+                        &user.role,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            user.id = row.get(0);
+            notify(client, "users", user.id, Operation::Insert, 1).await?;
+            Ok(user)
+        })
+        .await
     }
 
     pub async fn update(pool: &RpelPool, user: User) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        User::update_with(&client, user).await
+    }
+
+    pub async fn update_with<C: GenericClient>(client: &C, user: User) -> Result<u64, RpelError> {
+        traced("User", "update", async move {
+            let stmt = prepare(
+                client,
                 "
                     UPDATE users SET
                         name = $2,
@@ -110,27 +232,40 @@ impl User {
                         updated_at = $5
                     WHERE
                         id = $1
+                    AND
+                        updated_at = $6
                 ",
             )
             .await?;
-        Ok(client
-            .execute(
-                &stmt,
-                &[
-                    &user.id,
-                    &user.name,
-                    &user.key,
-                    &user.role,
-                    &Local::now().naive_local(),
-                ],
-            )
-            .await?)
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &user.id,
+                        &user.name,
+                        &user.key,
+                        &user.role,
+                        &Local::now().naive_local(),
+                        &user.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(client, "users", user.id, user.updated_at, result).await?;
+            notify(client, "users", user.id, Operation::Update, result).await?;
+            Ok(result)
+        })
+        .await
     }
 
     pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        User::delete_with(&client, id).await
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("User", "delete", async move {
+            let stmt = prepare(
+                client,
                 "
                     DELETE FROM
                         users
@@ -139,16 +274,25 @@ impl User {
                 ",
             )
             .await?;
-        Ok(client.execute(&stmt, &[&id]).await?)
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "users", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
     }
 }
 
 impl UserList {
     pub async fn get_all(pool: &RpelPool) -> Result<Vec<UserList>, RpelError> {
-        let mut users = Vec::new();
-        let client = pool.get().await?;
-        let stmt = client
-            .prepare(
+        let client = acquire(pool).await?;
+        UserList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(client: &C) -> Result<Vec<UserList>, RpelError> {
+        traced("UserList", "get_all", async move {
+            let mut users = Vec::new();
+            let stmt = prepare(
+                client,
                 "
                     SELECT
                         id,
@@ -162,14 +306,16 @@ impl UserList {
                 ",
             )
             .await?;
-        for row in client.query(&stmt, &[]).await? {
-            users.push(UserList {
-                id: row.try_get(0)?,
-                name: row.try_get(1)?,
-                key: row.try_get(2)?,
-                role: row.try_get(3)?,
-            });
-        }
-        Ok(users)
+            for row in client.query(&stmt, &[]).await? {
+                users.push(UserList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    key: row.try_get(2)?,
+                    role: row.try_get(3)?,
+                });
+            }
+            Ok(users)
+        })
+        .await
     }
 }
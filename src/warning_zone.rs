@@ -0,0 +1,416 @@
+use chrono::{Local, NaiveDateTime};
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    acquire, check_version,
+    error::RpelError,
+    notify::{notify, Operation},
+    trace::{prepare, single_row, traced},
+    RpelPool,
+};
+
+// A link table between an owner and the rows of `target` it groups.
+pub(crate) struct Link {
+    pub table: &'static str,
+    pub owner: &'static str,
+    pub column: &'static str,
+    pub target: &'static str,
+}
+
+pub(crate) const ZONE_SIRENS: Link = Link {
+    table: "warning_zone_sirens",
+    owner: "warning_zone_id",
+    column: "siren_id",
+    target: "sirens",
+};
+
+// Brings the links of one owner in line with `ids`, keeping the rows that are
+// still wanted. Ids missing from the target table are refused.
+pub(crate) async fn replace_links<C: GenericClient>(
+    client: &C,
+    link: &Link,
+    owner_id: i64,
+    ids: &[i64],
+) -> Result<(), RpelError> {
+    let Link {
+        table,
+        owner,
+        column,
+        target,
+    } = link;
+    let stmt = prepare(
+        client,
+        format!(
+            "
+                SELECT
+                    i.id
+                FROM
+                    unnest($1::bigint[]) AS i(id)
+                WHERE
+                    NOT EXISTS (SELECT 1 FROM {target} AS t WHERE t.id = i.id)
+                ORDER BY
+                    i.id ASC
+                LIMIT 1
+            "
+        )
+        .as_str(),
+    )
+    .await?;
+    if let Some(row) = client.query_opt(&stmt, &[&ids]).await? {
+        let missing: i64 = row.try_get(0)?;
+        return Err(RpelError::Invalid(format!(
+            "{column} points to missing {target} {missing}"
+        )));
+    }
+    let stmt = prepare(
+        client,
+        format!(
+            "
+                DELETE FROM
+                    {table}
+                WHERE
+                    {owner} = $1
+                AND
+                    {column} <> ALL($2)
+            "
+        )
+        .as_str(),
+    )
+    .await?;
+    client.execute(&stmt, &[&owner_id, &ids]).await?;
+    let stmt = prepare(
+        client,
+        format!(
+            "
+                INSERT INTO {table}
+                (
+                    {owner},
+                    {column},
+                    created_at,
+                    updated_at
+                )
+                SELECT DISTINCT
+                    $1::bigint,
+                    i.id,
+                    $3::timestamp,
+                    $3::timestamp
+                FROM
+                    unnest($2::bigint[]) AS i(id)
+                ON CONFLICT DO NOTHING
+            "
+        )
+        .as_str(),
+    )
+    .await?;
+    client
+        .execute(&stmt, &[&owner_id, &ids, &Local::now().naive_local()])
+        .await?;
+    Ok(())
+}
+
+// Drops every link that points at `id` through `column`, for rows that are
+// being deleted.
+pub(crate) async fn unlink<C: GenericClient>(
+    client: &C,
+    table: &str,
+    column: &str,
+    id: i64,
+) -> Result<u64, RpelError> {
+    let stmt = prepare(
+        client,
+        format!(
+            "
+                DELETE FROM
+                    {table}
+                WHERE
+                    {column} = $1
+            "
+        )
+        .as_str(),
+    )
+    .await?;
+    Ok(client.execute(&stmt, &[&id]).await?)
+}
+
+// A named district whose sirens are sounded together.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct WarningZone {
+    #[serde(default)]
+    pub id: i64,
+    pub name: Option<String>,
+    pub note: Option<String>,
+    #[serde(skip_serializing)]
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub siren_ids: Vec<i64>,
+}
+
+single_row!(WarningZone);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WarningZoneList {
+    pub id: i64,
+    pub name: Option<String>,
+    pub note: Option<String>,
+    pub sirens: i64,
+}
+
+impl WarningZone {
+    pub async fn get(pool: &RpelPool, id: i64) -> Result<WarningZone, RpelError> {
+        let client = acquire(pool).await?;
+        WarningZone::get_with(&client, id).await
+    }
+
+    pub async fn get_with<C: GenericClient>(client: &C, id: i64) -> Result<WarningZone, RpelError> {
+        traced("WarningZone", "get", async move {
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        z.name,
+                        z.note,
+                        z.created_at,
+                        z.updated_at,
+                        array_remove(array_agg(DISTINCT l.siren_id), NULL) AS siren_ids
+                    FROM
+                        warning_zones AS z
+                    LEFT JOIN
+                        warning_zone_sirens AS l ON l.warning_zone_id = z.id
+                    WHERE
+                        z.id = $1
+                    GROUP BY
+                        z.id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_opt(&stmt, &[&id])
+                .await?
+                .ok_or_else(|| RpelError::NotFound("warning_zones".to_string(), id))?;
+            let warning_zone = WarningZone {
+                id,
+                name: row.try_get(0)?,
+                note: row.try_get(1)?,
+                created_at: row.try_get(2)?,
+                updated_at: row.try_get(3)?,
+                siren_ids: row.try_get(4)?,
+            };
+            Ok(warning_zone)
+        })
+        .await
+    }
+
+    pub async fn insert(
+        pool: &RpelPool,
+        warning_zone: WarningZone,
+    ) -> Result<WarningZone, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = WarningZone::insert_with(&tx, warning_zone).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn insert_with<C: GenericClient>(
+        client: &C,
+        warning_zone: WarningZone,
+    ) -> Result<WarningZone, RpelError> {
+        traced("WarningZone", "insert", async move {
+            let mut warning_zone = warning_zone;
+            let stmt = prepare(
+                client,
+                "
+                    INSERT INTO warning_zones
+                    (
+                        name,
+                        note,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES
+                    (
+                        $1,
+                        $2,
+                        $3,
+                        $4
+                    )
+                    RETURNING
+                        id
+                ",
+            )
+            .await?;
+            let row = client
+                .query_one(
+                    &stmt,
+                    &[
+                        &warning_zone.name,
+                        &warning_zone.note,
+                        &Local::now().naive_local(),
+                        &Local::now().naive_local(),
+                    ],
+                )
+                .await?;
+            warning_zone.id = row.get(0);
+            replace_links(
+                client,
+                &ZONE_SIRENS,
+                warning_zone.id,
+                &warning_zone.siren_ids,
+            )
+            .await?;
+            notify(
+                client,
+                "warning_zones",
+                warning_zone.id,
+                Operation::Insert,
+                1,
+            )
+            .await?;
+            Ok(warning_zone)
+        })
+        .await
+    }
+
+    pub async fn update(pool: &RpelPool, warning_zone: WarningZone) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = WarningZone::update_with(&tx, warning_zone).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn update_with<C: GenericClient>(
+        client: &C,
+        warning_zone: WarningZone,
+    ) -> Result<u64, RpelError> {
+        traced("WarningZone", "update", async move {
+            let stmt = prepare(
+                client,
+                "
+                    UPDATE warning_zones SET
+                        name = $2,
+                        note = $3,
+                        updated_at = $4
+                    WHERE
+                        id = $1
+                    AND
+                        updated_at = $5
+                ",
+            )
+            .await?;
+            let result = client
+                .execute(
+                    &stmt,
+                    &[
+                        &warning_zone.id,
+                        &warning_zone.name,
+                        &warning_zone.note,
+                        &Local::now().naive_local(),
+                        &warning_zone.updated_at,
+                    ],
+                )
+                .await?;
+            let result = check_version(
+                client,
+                "warning_zones",
+                warning_zone.id,
+                warning_zone.updated_at,
+                result,
+            )
+            .await?;
+            if result > 0 {
+                replace_links(
+                    client,
+                    &ZONE_SIRENS,
+                    warning_zone.id,
+                    &warning_zone.siren_ids,
+                )
+                .await?;
+            }
+            notify(
+                client,
+                "warning_zones",
+                warning_zone.id,
+                Operation::Update,
+                result,
+            )
+            .await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    pub async fn delete(pool: &RpelPool, id: i64) -> Result<u64, RpelError> {
+        let mut client = acquire(pool).await?;
+        let tx = client.transaction().await?;
+        let result = WarningZone::delete_with(&tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete_with<C: GenericClient>(client: &C, id: i64) -> Result<u64, RpelError> {
+        traced("WarningZone", "delete", async move {
+            unlink(client, "warning_zone_sirens", "warning_zone_id", id).await?;
+            unlink(client, "scenario_zones", "warning_zone_id", id).await?;
+            let stmt = prepare(
+                client,
+                "
+                    DELETE FROM
+                        warning_zones
+                    WHERE
+                        id = $1
+                ",
+            )
+            .await?;
+            let result = client.execute(&stmt, &[&id]).await?;
+            notify(client, "warning_zones", id, Operation::Delete, result).await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+impl WarningZoneList {
+    pub async fn get_all(pool: &RpelPool) -> Result<Vec<WarningZoneList>, RpelError> {
+        let client = acquire(pool).await?;
+        WarningZoneList::get_all_with(&client).await
+    }
+
+    pub async fn get_all_with<C: GenericClient>(
+        client: &C,
+    ) -> Result<Vec<WarningZoneList>, RpelError> {
+        traced("WarningZoneList", "get_all", async move {
+            let mut warning_zones = Vec::new();
+            let stmt = prepare(
+                client,
+                "
+                    SELECT
+                        z.id,
+                        z.name,
+                        z.note,
+                        count(l.id) AS sirens
+                    FROM
+                        warning_zones AS z
+                    LEFT JOIN
+                        warning_zone_sirens AS l ON l.warning_zone_id = z.id
+                    GROUP BY
+                        z.id
+                    ORDER BY
+                        z.name ASC
+                ",
+            )
+            .await?;
+            for row in client.query(&stmt, &[]).await? {
+                warning_zones.push(WarningZoneList {
+                    id: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    note: row.try_get(2)?,
+                    sirens: row.try_get(3)?,
+                });
+            }
+            Ok(warning_zones)
+        })
+        .await
+    }
+}
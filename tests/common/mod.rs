@@ -0,0 +1,112 @@
+use std::{
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use deadpool_postgres::{Manager, ManagerConfig, Pool, Runtime};
+use rpel::RpelPool;
+use tokio_postgres::{Config, NoTls};
+
+// Points at a database the tests may connect to with CREATEDB rights, e.g.
+// `host=/var/run/postgresql user=postgres dbname=postgres`. Without it the
+// tests are skipped.
+pub const DATABASE_URL: &str = "RPEL_TEST_DATABASE_URL";
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// A database of its own for one test, created with the full schema and
+// dropped again when the value goes out of scope.
+pub struct TestDb {
+    pub pool: RpelPool,
+    admin: Config,
+    name: String,
+}
+
+impl TestDb {
+    pub async fn new() -> Option<TestDb> {
+        let db = TestDb::empty().await?;
+        rpel::migrate::run(&db.pool).await.expect("apply schema");
+        Some(db)
+    }
+
+    // A database with no tables at all, for tests that build the schema
+    // themselves.
+    pub async fn empty() -> Option<TestDb> {
+        let Ok(url) = std::env::var(DATABASE_URL) else {
+            eprintln!("{DATABASE_URL} is not set, skipping");
+            return None;
+        };
+        let admin: Config = url.parse().expect("invalid test database url");
+        let name = format!(
+            "rpel_test_{}_{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let (client, connection) = admin.connect(NoTls).await.expect("connect");
+        tokio::spawn(connection);
+        client
+            .batch_execute(&format!("CREATE DATABASE {name}"))
+            .await
+            .expect("create test database");
+
+        let mut cfg = admin.clone();
+        cfg.dbname(&name);
+        let manager = Manager::from_config(cfg, NoTls, ManagerConfig::default());
+        let pool = Pool::builder(manager)
+            .runtime(Runtime::Tokio1)
+            .max_size(4)
+            .build()
+            .expect("build pool");
+        Some(TestDb { pool, admin, name })
+    }
+
+    pub fn config(&self) -> Config {
+        let mut cfg = self.admin.clone();
+        cfg.dbname(&self.name);
+        cfg
+    }
+}
+
+impl Drop for TestDb {
+    // Drop cannot await, so the database is removed from a runtime of its own
+    // on a separate thread.
+    fn drop(&mut self) {
+        self.pool.close();
+        let admin = self.admin.clone();
+        let name = self.name.clone();
+        let dropped = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("runtime");
+            runtime.block_on(async move {
+                let (client, connection) = admin.connect(NoTls).await?;
+                tokio::spawn(connection);
+                client
+                    .batch_execute(&format!("DROP DATABASE IF EXISTS {name} WITH (FORCE)"))
+                    .await
+            })
+        })
+        .join();
+        if !matches!(dropped, Ok(Ok(()))) {
+            eprintln!("could not drop test database {}", self.name);
+        }
+    }
+}
+
+// Skips the calling test when no database is configured.
+#[macro_export]
+macro_rules! test_db {
+    () => {
+        match common::TestDb::new().await {
+            Some(db) => db,
+            None => return,
+        }
+    };
+    (empty) => {
+        match common::TestDb::empty().await {
+            Some(db) => db,
+            None => return,
+        }
+    };
+}
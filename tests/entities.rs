@@ -0,0 +1,2398 @@
+mod common;
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Local, NaiveDate};
+use futures_util::{stream, StreamExt};
+use rpel::{
+    address::{Address, AddressList},
+    attachment::{Attachment, AttachmentList, AttachmentOwner, CHUNK_SIZE},
+    certificate::{Certificate, CertificateList},
+    company::{Company, CompanyList, CompanyPatch, CompanyRollup},
+    contact::{Contact, ContactList, ContactMerge, ContactPatch, ContactShort},
+    department::{Department, DepartmentList},
+    duplicate::{self, DuplicateCandidate},
+    education::{Education, EducationList, EducationShort},
+    employment::{Employment, EmploymentList},
+    error::RpelError,
+    health::Health,
+    integrity::{self, Issue},
+    kind::{Kind, KindList},
+    migrate,
+    notify::{self, Change, Event, Operation},
+    phone::Phone,
+    post::{Post, PostList},
+    practice::{Practice, PracticeList, PracticeShort},
+    rank::{Rank, RankList},
+    required_post::{RequiredPost, RequiredPostList},
+    scenario::{Scenario, ScenarioList},
+    scope::{Scope, ScopeList},
+    select::SelectItem,
+    settlement::{Settlement, SettlementList},
+    siren::{Siren, SirenList, SirenPatch},
+    siren_check::{CheckKind, CheckResult, SirenCheck, SirenCheckList, SirenStatus},
+    siren_type::{SirenType, SirenTypeList},
+    snapshot::{Snapshot, SNAPSHOT_VERSION},
+    staffing::{Staffing, Vacancy},
+    street::{Street, StreetList},
+    user::{User, UserList},
+    warning_zone::{WarningZone, WarningZoneList},
+};
+use sha2::{Digest, Sha256};
+
+fn text(value: &str) -> Option<String> {
+    Some(value.to_string())
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+#[tokio::test]
+async fn reference_tables() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let department = Department::insert(
+        pool,
+        Department {
+            name: text("Logistics"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut stored = Department::get(pool, department.id).await.unwrap();
+    assert_eq!(stored.name, text("Logistics"));
+    stored.note = text("second floor");
+    assert_eq!(Department::update(pool, stored).await.unwrap(), 1);
+    let list = DepartmentList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].note, text("second floor"));
+
+    let kind = Kind::insert(
+        pool,
+        Kind {
+            name: text("Evacuation"),
+            short_name: text("EV"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        Kind::get(pool, kind.id).await.unwrap().short_name,
+        text("EV")
+    );
+    assert_eq!(KindList::get_all(pool).await.unwrap().len(), 1);
+
+    for (name, go) in [("Engineer", false), ("Warden", true)] {
+        Post::insert(
+            pool,
+            Post {
+                name: text(name),
+                go,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(PostList::get_all(pool).await.unwrap().len(), 2);
+    let go_posts = SelectItem::post_all(pool, true).await.unwrap();
+    assert_eq!(go_posts.len(), 1);
+    assert_eq!(go_posts[0].name, text("Warden"));
+
+    let rank = Rank::insert(
+        pool,
+        Rank {
+            name: text("Captain"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        Rank::get(pool, rank.id).await.unwrap().name,
+        text("Captain")
+    );
+    assert_eq!(RankList::get_all(pool).await.unwrap().len(), 1);
+
+    for name in ["Transport", "Energy"] {
+        Scope::insert(
+            pool,
+            Scope {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    let scopes = ScopeList::get_all(pool).await.unwrap();
+    assert_eq!(scopes[0].name, text("Energy"));
+    let scopes = SelectItem::scope_all(pool).await.unwrap();
+    assert_eq!(scopes[1].name, text("Transport"));
+
+    let siren_type = SirenType::insert(
+        pool,
+        SirenType {
+            name: text("S-40"),
+            radius: Some(400),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        SirenType::get(pool, siren_type.id).await.unwrap().radius,
+        Some(400)
+    );
+    assert_eq!(SirenTypeList::get_all(pool).await.unwrap().len(), 1);
+    assert_eq!(SelectItem::siren_type_all(pool).await.unwrap().len(), 1);
+
+    let user = User::insert(
+        pool,
+        User {
+            id: 0,
+            name: "operator".to_string(),
+            key: "secret".to_string(),
+            role: 1,
+            created_at: None,
+            updated_at: None,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(User::get(pool, user.id).await.unwrap().name, "operator");
+    assert_eq!(
+        User::get_by_name(pool, "operator")
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        user.id
+    );
+    assert!(User::get_by_name(pool, "nobody").await.unwrap().is_none());
+    // Keys authenticate, so a second user cannot share one.
+    assert!(matches!(
+        User::insert(
+            pool,
+            User {
+                id: 0,
+                name: "intruder".to_string(),
+                key: "secret".to_string(),
+                role: 1,
+                created_at: None,
+                updated_at: None,
+            },
+        )
+        .await,
+        Err(RpelError::DBQuery(_))
+    ));
+    assert_eq!(UserList::get_all(pool).await.unwrap().len(), 1);
+
+    assert_eq!(Department::delete(pool, department.id).await.unwrap(), 1);
+    assert_eq!(Kind::delete(pool, kind.id).await.unwrap(), 1);
+    assert_eq!(Rank::delete(pool, rank.id).await.unwrap(), 1);
+    assert_eq!(SirenType::delete(pool, siren_type.id).await.unwrap(), 1);
+    assert_eq!(User::delete(pool, user.id).await.unwrap(), 1);
+    assert!(DepartmentList::get_all(pool).await.unwrap().is_empty());
+    assert!(matches!(
+        Department::get(pool, department.id).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+}
+
+#[tokio::test]
+async fn company_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let scope = Scope::insert(
+        pool,
+        Scope {
+            name: text("Energy"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            scope_id: Some(scope.id),
+            emails: vec!["b@grid.test".to_string(), "a@grid.test".to_string()],
+            phones: vec![300, 100, 300],
+            faxes: vec![900],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let stored = Company::get(pool, company.id).await.unwrap();
+    assert_eq!(stored.emails, vec!["a@grid.test", "b@grid.test"]);
+    assert_eq!(stored.phones, vec![100, 300]);
+    assert_eq!(stored.faxes, vec![900]);
+
+    Practice::insert(
+        pool,
+        Practice {
+            company_id: Some(company.id),
+            date_of_practice: Some(today()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let list = CompanyList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].scope_name, text("Energy"));
+    assert_eq!(list[0].practices, vec![today()]);
+    assert_eq!(
+        Company::get(pool, company.id)
+            .await
+            .unwrap()
+            .practices
+            .len(),
+        1
+    );
+
+    let patch = CompanyPatch {
+        note: Some(text("night shift")),
+        phones: Some(vec![200]),
+        updated_at: stored.updated_at,
+        ..Default::default()
+    };
+    assert_eq!(Company::patch(pool, company.id, patch).await.unwrap(), 1);
+    let patched = Company::get(pool, company.id).await.unwrap();
+    assert_eq!(patched.name, text("Grid"));
+    assert_eq!(patched.note, text("night shift"));
+    assert_eq!(patched.phones, vec![200]);
+
+    // `stored` still carries the version from before the patch.
+    assert!(matches!(
+        Company::update(pool, stored).await,
+        Err(RpelError::Conflict(_, _))
+    ));
+    // Without a version there is nothing to guard the write with.
+    let unversioned = Company {
+        updated_at: None,
+        ..patched.clone()
+    };
+    assert!(matches!(
+        Company::update(pool, unversioned).await,
+        Err(RpelError::Invalid(_))
+    ));
+    assert_eq!(
+        Company::get(pool, company.id).await.unwrap().note,
+        text("night shift")
+    );
+
+    assert_eq!(Company::delete(pool, company.id).await.unwrap(), 1);
+    assert_eq!(
+        Phone::delete_companies(pool, company.id, false)
+            .await
+            .unwrap(),
+        0
+    );
+
+    // Patching the deleted company must not leave phones behind for it.
+    let patch = CompanyPatch {
+        phones: Some(vec![300]),
+        updated_at: patched.updated_at,
+        ..Default::default()
+    };
+    assert_eq!(Company::patch(pool, company.id, patch).await.unwrap(), 0);
+    assert_eq!(
+        Phone::delete_companies(pool, company.id, false)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn company_hierarchy() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let insert = |name: &str, parent_id: Option<i64>| {
+        Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                parent_id,
+                ..Default::default()
+            },
+        )
+    };
+    let head = insert("Holding", None).await.unwrap();
+    let north = insert("North", Some(head.id)).await.unwrap();
+    let south = insert("South", Some(head.id)).await.unwrap();
+    let depot = insert("North depot", Some(north.id)).await.unwrap();
+    assert!(matches!(
+        insert("Nowhere", Some(404)).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    let stored = Company::get(pool, head.id).await.unwrap();
+    assert_eq!(stored.parent, None);
+    let children: Vec<i64> = stored.children.iter().map(|c| c.id).collect();
+    assert_eq!(children, vec![north.id, south.id]);
+    let stored = Company::get(pool, depot.id).await.unwrap();
+    assert_eq!(stored.parent.map(|p| p.name), Some(text("North")));
+    assert!(stored.children.is_empty());
+    let list = CompanyList::get_all(pool).await.unwrap();
+    let listed = list.iter().find(|c| c.id == depot.id).unwrap();
+    assert_eq!(listed.parent_name, text("North"));
+
+    let subtree: Vec<(i64, i32)> = Company::subtree(pool, head.id)
+        .await
+        .unwrap()
+        .iter()
+        .map(|node| (node.id, node.depth))
+        .collect();
+    assert_eq!(
+        subtree,
+        vec![(head.id, 0), (north.id, 1), (depot.id, 2), (south.id, 1)]
+    );
+    let ancestors: Vec<i64> = Company::ancestors(pool, depot.id)
+        .await
+        .unwrap()
+        .iter()
+        .map(|node| node.id)
+        .collect();
+    assert_eq!(ancestors, vec![north.id, head.id]);
+    assert!(Company::ancestors(pool, head.id).await.unwrap().is_empty());
+
+    let mut moved = Company::get(pool, head.id).await.unwrap();
+    moved.parent_id = Some(depot.id);
+    assert!(matches!(
+        Company::update(pool, moved).await,
+        Err(RpelError::Invalid(_))
+    ));
+    let patch = CompanyPatch {
+        parent_id: Some(Some(north.id)),
+        updated_at: Company::get(pool, north.id).await.unwrap().updated_at,
+        ..Default::default()
+    };
+    assert!(matches!(
+        Company::patch(pool, north.id, patch).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    for (name, company_id) in [("Ivanov", depot.id), ("Petrov", south.id)] {
+        Contact::insert(
+            pool,
+            Contact {
+                name: text(name),
+                company_id: Some(company_id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    let last = today() - Duration::days(10);
+    let next = today() + Duration::days(10);
+    for (company_id, date) in [(north.id, last), (south.id, next)] {
+        Practice::insert(
+            pool,
+            Practice {
+                company_id: Some(company_id),
+                date_of_practice: Some(date),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    Siren::insert(
+        pool,
+        Siren {
+            num_id: Some(17),
+            company_id: Some(depot.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let rollup = CompanyRollup::get(pool, head.id).await.unwrap();
+    assert_eq!(
+        rollup,
+        CompanyRollup {
+            id: head.id,
+            name: text("Holding"),
+            companies: 4,
+            contacts: 2,
+            practices: 2,
+            sirens: 1,
+            last_practice: Some(last),
+            next_practice: Some(next),
+        }
+    );
+    let north_rollup = CompanyRollup::get(pool, north.id).await.unwrap();
+    assert_eq!((north_rollup.companies, north_rollup.contacts), (2, 1));
+    assert_eq!(north_rollup.next_practice, None);
+    assert_eq!(CompanyRollup::get_all(pool).await.unwrap(), vec![rollup]);
+
+    // Deleting a branch hands its own branches to the organisation above.
+    assert_eq!(Company::delete(pool, north.id).await.unwrap(), 1);
+    let stored = Company::get(pool, depot.id).await.unwrap();
+    assert_eq!(stored.parent_id, Some(head.id));
+}
+
+#[tokio::test]
+async fn contact_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let department = Department::insert(
+        pool,
+        Department {
+            name: text("Logistics"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let post = Post::insert(
+        pool,
+        Post {
+            name: text("Engineer"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let post_go = Post::insert(
+        pool,
+        Post {
+            name: text("Warden"),
+            go: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(company.id),
+            department_id: Some(department.id),
+            post_id: Some(post.id),
+            post_go_id: Some(post_go.id),
+            birthday: NaiveDate::from_ymd_opt(1980, 5, 17),
+            phones: vec![555],
+            emails: vec!["ivanov@grid.test".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    Education::insert(
+        pool,
+        Education {
+            contact_id: Some(contact.id),
+            start_date: Some(today()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut stored = Contact::get(pool, contact.id).await.unwrap();
+    assert_eq!(stored.birthday, NaiveDate::from_ymd_opt(1980, 5, 17));
+    assert_eq!(stored.phones, vec![555]);
+    assert_eq!(stored.educations, vec![today()]);
+
+    let list = ContactList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].company_name, text("Grid"));
+    assert_eq!(list[0].post_name, text("Engineer"));
+    assert_eq!(list[0].emails, vec!["ivanov@grid.test".to_string()]);
+
+    let short = ContactShort::get_by_company(pool, company.id)
+        .await
+        .unwrap();
+    assert_eq!(short[0].department_name, text("Logistics"));
+    assert_eq!(short[0].post_name, text("Engineer"));
+    assert_eq!(short[0].post_go_name, text("Warden"));
+
+    stored.faxes = vec![777];
+    stored.phones = Vec::new();
+    assert_eq!(Contact::update(pool, stored).await.unwrap(), 1);
+    let updated = Contact::get(pool, contact.id).await.unwrap();
+    assert!(updated.phones.is_empty());
+    assert_eq!(updated.faxes, vec![777]);
+
+    assert_eq!(SelectItem::contact_all(pool).await.unwrap().len(), 1);
+    assert_eq!(Contact::delete(pool, contact.id).await.unwrap(), 1);
+    assert!(ContactList::get_all(pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn practice_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let kind = Kind::insert(
+        pool,
+        Kind {
+            name: text("Evacuation"),
+            short_name: text("EV"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let dates = [
+        today() + Duration::days(7),
+        today() - Duration::days(60),
+        today() - Duration::days(3),
+    ];
+    for date in dates {
+        Practice::insert(
+            pool,
+            Practice {
+                company_id: Some(company.id),
+                kind_id: Some(kind.id),
+                topic: text("drill"),
+                date_of_practice: Some(date),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let list = PracticeList::get_all(pool).await.unwrap();
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[0].date_of_practice, Some(dates[0]));
+    assert_eq!(list[0].company_name, text("Grid"));
+    assert_eq!(list[0].kind_short_name, text("EV"));
+    assert_eq!(
+        list[0].date_str,
+        Some(dates[0].format("%Y-%m-%d").to_string())
+    );
+    assert_eq!(
+        PracticeList::get_by_company(pool, company.id)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+
+    // The window starts a month back, so the practice 60 days ago is left out.
+    let near = PracticeShort::get_near(pool).await.unwrap();
+    let near_dates: Vec<_> = near.iter().map(|p| p.date_of_practice).collect();
+    assert_eq!(near_dates, vec![Some(dates[2]), Some(dates[0])]);
+
+    let mut practice = Practice::get(pool, list[0].id).await.unwrap();
+    practice.topic = text("fire");
+    assert_eq!(Practice::update(pool, practice).await.unwrap(), 1);
+    assert_eq!(
+        Practice::get(pool, list[0].id).await.unwrap().topic,
+        text("fire")
+    );
+    assert_eq!(Practice::delete(pool, list[0].id).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn education_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(company.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let post = Post::insert(
+        pool,
+        Post {
+            name: text("Warden"),
+            go: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let dates = [today() + Duration::days(10), today() - Duration::days(45)];
+    for date in dates {
+        Education::insert(
+            pool,
+            Education {
+                contact_id: Some(contact.id),
+                post_id: Some(post.id),
+                start_date: Some(date),
+                end_date: Some(date + Duration::days(5)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let list = EducationList::get_all(pool).await.unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].start_date, Some(dates[0]));
+    assert_eq!(list[0].contact_name, text("Ivanov"));
+    assert_eq!(list[0].post_name, text("Warden"));
+    assert_eq!(
+        list[0].end_str,
+        Some(
+            (dates[0] + Duration::days(5))
+                .format("%Y-%m-%d")
+                .to_string()
+        )
+    );
+
+    let near = EducationShort::get_near(pool).await.unwrap();
+    assert_eq!(near.len(), 1);
+    assert_eq!(near[0].company_name, text("Grid"));
+    assert_eq!(near[0].start_date, Some(dates[0]));
+
+    let education = Education::get(pool, list[1].id).await.unwrap();
+    assert_eq!(education.end_date, Some(dates[1] + Duration::days(5)));
+    assert_eq!(Education::delete(pool, education.id).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn certificate_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 3, 1);
+    let certificate = Certificate::insert(
+        pool,
+        Certificate {
+            num: text("C-17"),
+            contact_id: Some(contact.id),
+            company_id: Some(company.id),
+            cert_date: date,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        Certificate::get(pool, certificate.id)
+            .await
+            .unwrap()
+            .cert_date,
+        date
+    );
+    let list = CertificateList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].contact_name, text("Ivanov"));
+    assert_eq!(list[0].company_name, text("Grid"));
+    assert_eq!(list[0].cert_date, text("2024-03-01"));
+    assert_eq!(Certificate::delete(pool, certificate.id).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn siren_round_trip() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let siren_type = SirenType::insert(
+        pool,
+        SirenType {
+            name: text("S-40"),
+            radius: Some(400),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            phones: vec![555],
+            faxes: vec![777],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let siren = Siren::insert(
+        pool,
+        Siren {
+            num_id: Some(17),
+            siren_type_id: Some(siren_type.id),
+            contact_id: Some(contact.id),
+            address: text("Main st. 1"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let list = SirenList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].siren_type_name, text("S-40"));
+    assert_eq!(list[0].contact_name, text("Ivanov"));
+    assert_eq!(list[0].phones, vec![555]);
+
+    let patch = SirenPatch {
+        stage: Some(Some(2)),
+        address: Some(None),
+        updated_at: Siren::get(pool, siren.id).await.unwrap().updated_at,
+        ..Default::default()
+    };
+    assert_eq!(Siren::patch(pool, siren.id, patch).await.unwrap(), 1);
+    let patched = Siren::get(pool, siren.id).await.unwrap();
+    assert_eq!(patched.stage, Some(2));
+    assert_eq!(patched.address, None);
+    assert_eq!(patched.num_id, Some(17));
+    assert_eq!(Siren::delete(pool, siren.id).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn siren_journal() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut sirens = Vec::new();
+    for (num_id, address) in [(1, "Main st. 1"), (2, "Park 2"), (3, "River 3")] {
+        let siren = Siren::insert(
+            pool,
+            Siren {
+                num_id: Some(num_id),
+                address: text(address),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        sirens.push(siren.id);
+    }
+    let days_ago = |days| today() - Duration::days(days);
+    for (siren_id, days, kind, result) in [
+        (sirens[0], 60, CheckKind::Test, CheckResult::Worked),
+        (sirens[0], 10, CheckKind::Maintenance, CheckResult::Worked),
+        (sirens[1], 30, CheckKind::Test, CheckResult::Worked),
+        (sirens[1], 5, CheckKind::Alert, CheckResult::NoPower),
+        (sirens[2], 20, CheckKind::Maintenance, CheckResult::Faulty),
+    ] {
+        SirenCheck::insert(
+            pool,
+            SirenCheck {
+                siren_id: Some(siren_id),
+                check_date: days_ago(days),
+                kind,
+                result,
+                contact_id: Some(contact.id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let journal = SirenCheckList::get_by_siren(pool, sirens[0]).await.unwrap();
+    let entries: Vec<_> = journal.iter().map(|c| (c.check_date, c.kind)).collect();
+    assert_eq!(
+        entries,
+        vec![
+            (days_ago(10), CheckKind::Maintenance),
+            (days_ago(60), CheckKind::Test),
+        ]
+    );
+    assert_eq!(journal[0].contact_name, text("Ivanov"));
+    assert_eq!(journal[0].siren_address, text("Main st. 1"));
+
+    let status = SirenStatus::get_all(pool).await.unwrap();
+    assert_eq!(status.len(), 3);
+    assert_eq!(
+        (status[0].last_check, status[0].last_kind),
+        (Some(days_ago(10)), Some(CheckKind::Maintenance))
+    );
+    assert_eq!(
+        (status[0].last_test, status[0].last_test_result),
+        (Some(days_ago(60)), Some(CheckResult::Worked))
+    );
+    let ids = |status: Vec<SirenStatus>| status.iter().map(|s| s.siren_id).collect::<Vec<_>>();
+    assert_eq!(
+        ids(SirenStatus::get_untested(pool).await.unwrap()),
+        vec![sirens[2]]
+    );
+    assert_eq!(
+        ids(SirenStatus::get_failed(pool).await.unwrap()),
+        vec![sirens[1]]
+    );
+
+    let mut check = SirenCheck::get(pool, journal[1].id).await.unwrap();
+    check.result = CheckResult::Faulty;
+    assert_eq!(SirenCheck::update(pool, check).await.unwrap(), 1);
+    assert_eq!(
+        SirenCheck::get(pool, journal[1].id).await.unwrap().result,
+        CheckResult::Faulty
+    );
+
+    // The journal keeps the siren it is about.
+    assert!(matches!(
+        Siren::delete(pool, sirens[0]).await,
+        Err(RpelError::Invalid(_))
+    ));
+    assert_eq!(SirenCheckList::get_all(pool).await.unwrap().len(), 5);
+    for check in journal {
+        SirenCheck::delete(pool, check.id).await.unwrap();
+    }
+    assert_eq!(Siren::delete(pool, sirens[0]).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn siren_zones_and_scenarios() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            phones: vec![555],
+            faxes: vec![777],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut sirens = Vec::new();
+    for (num_id, address) in [(1, "Park 2"), (2, "Main st. 1"), (3, "River 3")] {
+        let siren = Siren::insert(
+            pool,
+            Siren {
+                num_id: Some(num_id),
+                address: text(address),
+                contact_id: Some(contact.id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        sirens.push(siren.id);
+    }
+    let mut zones = Vec::new();
+    for (name, siren_ids) in [
+        ("North", vec![sirens[0], sirens[1], sirens[0]]),
+        ("South", vec![sirens[1]]),
+        ("East", vec![sirens[2]]),
+    ] {
+        let zone = WarningZone::insert(
+            pool,
+            WarningZone {
+                name: text(name),
+                siren_ids,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        zones.push(zone.id);
+    }
+    let north = WarningZone::get(pool, zones[0]).await.unwrap();
+    assert_eq!(north.siren_ids, vec![sirens[0], sirens[1]]);
+    assert!(matches!(
+        WarningZone::insert(
+            pool,
+            WarningZone {
+                name: text("Nowhere"),
+                siren_ids: vec![sirens[2] + 100],
+                ..Default::default()
+            },
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+    let counts: Vec<_> = WarningZoneList::get_all(pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|z| (z.name.clone(), z.sirens))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![(text("East"), 1), (text("North"), 2), (text("South"), 1)]
+    );
+
+    let scenario = Scenario::insert(
+        pool,
+        Scenario {
+            name: text("Flood"),
+            warning_zone_ids: vec![zones[0], zones[1]],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let list = ScenarioList::get_all(pool).await.unwrap();
+    assert_eq!(list[0].zone_names, vec!["North", "South"]);
+
+    let checklist = Scenario::checklist(pool, scenario.id).await.unwrap();
+    let items: Vec<_> = checklist
+        .iter()
+        .map(|i| (i.siren_id, i.zone_names.clone()))
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            (sirens[1], vec!["North".to_string(), "South".to_string()]),
+            (sirens[0], vec!["North".to_string()]),
+        ]
+    );
+    assert_eq!(checklist[0].contact_name, text("Ivanov"));
+    assert_eq!(checklist[0].phones, vec![555]);
+    assert!(matches!(
+        Scenario::checklist(pool, scenario.id + 100).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+
+    let mut scenario = Scenario::get(pool, scenario.id).await.unwrap();
+    scenario.warning_zone_ids = vec![zones[2]];
+    assert_eq!(Scenario::update(pool, scenario.clone()).await.unwrap(), 1);
+    let checklist = Scenario::checklist(pool, scenario.id).await.unwrap();
+    assert_eq!(checklist.len(), 1);
+    assert_eq!(checklist[0].siren_id, sirens[2]);
+
+    assert_eq!(Siren::delete(pool, sirens[2]).await.unwrap(), 1);
+    assert!(Scenario::checklist(pool, scenario.id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(WarningZone::delete(pool, zones[2]).await.unwrap(), 1);
+    assert!(Scenario::get(pool, scenario.id)
+        .await
+        .unwrap()
+        .warning_zone_ids
+        .is_empty());
+}
+
+#[tokio::test]
+async fn structured_addresses() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let mut settlements = Vec::new();
+    for (name, kind) in [("Москва", "г"), ("Ивановка", "д")] {
+        let settlement = Settlement::insert(
+            pool,
+            Settlement {
+                name: text(name),
+                kind: text(kind),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        settlements.push(settlement.id);
+    }
+    let mut streets = Vec::new();
+    for (settlement_id, name, alias) in [
+        (settlements[0], "Ленина", "Ильича"),
+        (settlements[1], "Садовая", "Садовая-Кудринская"),
+    ] {
+        let street = Street::insert(
+            pool,
+            Street {
+                settlement_id: Some(settlement_id),
+                name: text(name),
+                kind: text("ул"),
+                aliases: vec![alias.to_string()],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        streets.push(street.id);
+    }
+    let mut companies = Vec::new();
+    for (name, address) in [
+        ("Grid", "г. Москва, ул. Ленина, д. 5"),
+        ("Water", "Ильича, 7 корп. 2"),
+        ("Gas", "ул. Пушкина 1"),
+    ] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                address: text(address),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    let siren = Siren::insert(
+        pool,
+        Siren {
+            address: text("д. Ивановка, Садовая 3"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let report = Address::parse_all(pool, false).await.unwrap();
+    assert_eq!(report.parsed, 3);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(
+        (report.failures[0].id, report.failures[0].reason.as_str()),
+        (companies[2], "unknown street \"ул пушкина\"")
+    );
+    assert!(AddressList::get_all(pool).await.unwrap().is_empty());
+
+    assert_eq!(Address::parse_all(pool, true).await.unwrap().parsed, 3);
+    let addresses = AddressList::get_all(pool).await.unwrap();
+    let parsed: Vec<_> = addresses
+        .iter()
+        .map(|a| {
+            (
+                a.settlement_name.clone(),
+                a.house.clone(),
+                a.building.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        parsed,
+        vec![
+            (text("Ивановка"), text("3"), None),
+            (text("Москва"), text("5"), None),
+            (text("Москва"), text("7"), text("2")),
+        ]
+    );
+    assert_eq!(addresses[0].siren_id, Some(siren.id));
+    let counts: Vec<_> = SettlementList::get_all(pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|s| (s.streets, s.companies, s.sirens))
+        .collect();
+    assert_eq!(counts, vec![(1, 0, 1), (1, 2, 0)]);
+    let street = &StreetList::get_all(pool).await.unwrap()[1];
+    assert_eq!((street.id, street.companies), (streets[0], 2));
+    assert_eq!(Address::parse_all(pool, false).await.unwrap().parsed, 0);
+
+    let mut address = Address::insert(
+        pool,
+        Address {
+            company_id: Some(companies[2]),
+            settlement_id: Some(settlements[0]),
+            street_id: Some(streets[1]),
+            house: text("1"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let issues = integrity::check(pool).await.unwrap();
+    assert_eq!(
+        (issues[0].check.as_str(), issues[0].id),
+        ("foreign_street", address.id)
+    );
+    address = Address::get(pool, address.id).await.unwrap();
+    address.street_id = Some(streets[0]);
+    assert_eq!(Address::update(pool, address).await.unwrap(), 1);
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+    assert!(matches!(
+        Address::insert(
+            pool,
+            Address {
+                company_id: Some(companies[0]),
+                siren_id: Some(siren.id),
+                ..Default::default()
+            },
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    assert_eq!(Company::delete(pool, companies[0]).await.unwrap(), 1);
+    assert_eq!(Siren::delete(pool, siren.id).await.unwrap(), 1);
+    assert_eq!(AddressList::get_all(pool).await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn employment_history() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let mut companies = Vec::new();
+    for name in ["Grid", "Water"] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    let warden = Post::insert(
+        pool,
+        Post {
+            name: text("Warden"),
+            go: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(companies[0]),
+            post_go_id: Some(warden.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let career = EmploymentList::get_by_contact(pool, contact.id)
+        .await
+        .unwrap();
+    assert_eq!(career.len(), 1);
+    assert_eq!(career[0].company_name, text("Grid"));
+    assert_eq!(career[0].post_go_name, text("Warden"));
+    assert_eq!(
+        (career[0].date_from, career[0].date_to),
+        (Some(today()), None)
+    );
+
+    // Pretend the period began long ago, then move the contact to Water.
+    let started = today() - Duration::days(100);
+    let mut open = Employment::get(pool, career[0].id).await.unwrap();
+    open.date_from = Some(started);
+    assert_eq!(Employment::update(pool, open.clone()).await.unwrap(), 1);
+    open.date_to = Some(started - Duration::days(1));
+    assert!(matches!(
+        Employment::update(pool, open).await,
+        Err(RpelError::Invalid(_))
+    ));
+    let patch = ContactPatch {
+        company_id: Some(Some(companies[1])),
+        updated_at: Contact::get(pool, contact.id).await.unwrap().updated_at,
+        ..Default::default()
+    };
+    assert_eq!(Contact::patch(pool, contact.id, patch).await.unwrap(), 1);
+
+    let career = EmploymentList::get_by_contact(pool, contact.id)
+        .await
+        .unwrap();
+    let periods: Vec<_> = career
+        .iter()
+        .map(|e| (e.company_name.clone(), e.date_from, e.date_to))
+        .collect();
+    assert_eq!(
+        periods,
+        vec![
+            (text("Grid"), Some(started), Some(today())),
+            (text("Water"), Some(today()), None),
+        ]
+    );
+
+    // A second change on the same day replaces the period it opened.
+    let mut stored = Contact::get(pool, contact.id).await.unwrap();
+    stored.post_go_id = None;
+    Contact::update(pool, stored).await.unwrap();
+    let career = EmploymentList::get_by_contact(pool, contact.id)
+        .await
+        .unwrap();
+    assert_eq!(career.len(), 2);
+    assert_eq!(career[1].post_go_name, None);
+
+    let holders = |company_id, date| EmploymentList::get_holders(pool, company_id, warden.id, date);
+    let held = holders(companies[0], today() - Duration::days(50))
+        .await
+        .unwrap();
+    assert_eq!(held.len(), 1);
+    assert_eq!(held[0].contact_name, text("Ivanov"));
+    assert!(holders(companies[0], today()).await.unwrap().is_empty());
+    assert!(holders(companies[1], today()).await.unwrap().is_empty());
+
+    // The period that began today goes with the contact, the closed one stays.
+    assert_eq!(Contact::delete(pool, contact.id).await.unwrap(), 1);
+    let kept = EmploymentList::get_all(pool).await.unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].date_to, Some(today()));
+    assert_eq!(
+        holders(companies[0], today() - Duration::days(50))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn company_staffing() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let mut scopes = Vec::new();
+    for name in ["Energy", "Water"] {
+        let scope = Scope::insert(
+            pool,
+            Scope {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        scopes.push(scope.id);
+    }
+    let mut posts = Vec::new();
+    for name in ["Head of GO", "Evacuation", "Signaller"] {
+        let post = Post::insert(
+            pool,
+            Post {
+                name: text(name),
+                go: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        posts.push(post.id);
+    }
+    let (head, evacuation, signaller) = (posts[0], posts[1], posts[2]);
+    let mut companies = Vec::new();
+    for (name, scope_id) in [
+        ("Grid", Some(scopes[0])),
+        ("Pump", Some(scopes[1])),
+        ("Shop", None),
+    ] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                scope_id,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    let grid = companies[0];
+
+    // The head is required everywhere; Energy repeats it and adds evacuation.
+    for (scope_id, post_go_id, is_head) in [
+        (None, head, true),
+        (Some(scopes[0]), head, false),
+        (Some(scopes[0]), evacuation, false),
+    ] {
+        RequiredPost::insert(
+            pool,
+            RequiredPost {
+                scope_id,
+                post_go_id: Some(post_go_id),
+                head: is_head,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+    let required = RequiredPostList::get_all(pool).await.unwrap();
+    assert_eq!(required.len(), 3);
+    assert_eq!(required[0].scope_name, None);
+    assert_eq!(required[0].post_go_name, text("Head of GO"));
+
+    for (name, post_go_id) in [("Ivanov", head), ("Petrov", signaller)] {
+        Contact::insert(
+            pool,
+            Contact {
+                name: text(name),
+                company_id: Some(grid),
+                post_go_id: Some(post_go_id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    let staffing = Staffing::get(pool, grid).await.unwrap();
+    assert_eq!(staffing.scope_name, text("Energy"));
+    let report: Vec<_> = staffing
+        .posts
+        .iter()
+        .map(|p| {
+            let holders: Vec<_> = p.holders.iter().map(|h| h.name.clone()).collect();
+            (p.post_go_name.clone(), p.required, p.head, holders)
+        })
+        .collect();
+    assert_eq!(
+        report,
+        vec![
+            (text("Head of GO"), true, true, vec![text("Ivanov")]),
+            (text("Evacuation"), true, false, vec![]),
+            (text("Signaller"), false, false, vec![text("Petrov")]),
+        ]
+    );
+    assert_eq!(staffing.vacant, 1);
+    assert!(matches!(
+        Staffing::get(pool, 404).await,
+        Err(RpelError::NotFound(_, 404))
+    ));
+
+    let vacancies: Vec<_> = Vacancy::get_all(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|v| (v.company_name, v.post_go_id))
+        .collect();
+    assert_eq!(
+        vacancies,
+        vec![
+            (text("Grid"), evacuation),
+            (text("Pump"), head),
+            (text("Shop"), head),
+        ]
+    );
+    let heads: Vec<_> = Vacancy::get_heads(pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|v| v.company_id)
+        .collect();
+    assert_eq!(heads, vec![companies[1], companies[2]]);
+}
+
+#[tokio::test]
+async fn duplicate_contacts_merge() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let survivor = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov Ivan"),
+            phones: vec![555],
+            emails: vec!["ivanov@grid.test".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let duplicate = Contact::insert(
+        pool,
+        Contact {
+            name: text("ivan ivanov"),
+            birthday: NaiveDate::from_ymd_opt(1980, 5, 17),
+            phones: vec![555, 556],
+            emails: vec!["Ivanov@Grid.test".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    Contact::insert(
+        pool,
+        Contact {
+            name: text("Petrov"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    Education::insert(
+        pool,
+        Education {
+            contact_id: Some(duplicate.id),
+            start_date: Some(today()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    Certificate::insert(
+        pool,
+        Certificate {
+            num: text("C-17"),
+            contact_id: Some(duplicate.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let siren = Siren::insert(
+        pool,
+        Siren {
+            num_id: Some(17),
+            contact_id: Some(duplicate.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    SirenCheck::insert(
+        pool,
+        SirenCheck {
+            siren_id: Some(siren.id),
+            check_date: today(),
+            contact_id: Some(duplicate.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let found = DuplicateCandidate::find(pool, duplicate::DEFAULT_MIN_SCORE)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(
+        (found[0].id, found[0].other_id),
+        (survivor.id, duplicate.id)
+    );
+    assert!(found[0].same_name);
+    assert_eq!(found[0].same_birthday, None);
+    assert_eq!(found[0].shared_phones, vec![555]);
+    assert_eq!(found[0].shared_emails, vec!["ivanov@grid.test".to_string()]);
+
+    assert!(matches!(
+        Contact::merge(pool, survivor.id, survivor.id).await,
+        Err(RpelError::Invalid(_))
+    ));
+    let merged = Contact::merge(pool, survivor.id, duplicate.id)
+        .await
+        .unwrap();
+    assert_eq!(
+        merged,
+        ContactMerge {
+            phones: 1,
+            emails: 0,
+            educations: 1,
+            certificates: 1,
+            sirens: 1,
+            siren_checks: 1,
+            employments: 0,
+        }
+    );
+    let stored = Contact::get(pool, survivor.id).await.unwrap();
+    assert_eq!(stored.phones, vec![555, 556]);
+    assert_eq!(stored.emails, vec!["ivanov@grid.test".to_string()]);
+    assert_eq!(stored.birthday, NaiveDate::from_ymd_opt(1980, 5, 17));
+    assert_eq!(stored.educations, vec![today()]);
+    assert_eq!(
+        Siren::get(pool, siren.id).await.unwrap().contact_id,
+        Some(survivor.id)
+    );
+    assert!(matches!(
+        Contact::get(pool, duplicate.id).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+    assert!(matches!(
+        Contact::merge(pool, survivor.id, duplicate.id).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+    assert!(DuplicateCandidate::find(pool, duplicate::DEFAULT_MIN_SCORE)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // A department of the duplicate's company stays behind when the survivor
+    // works elsewhere; a shared one comes along.
+    let mut companies = Vec::new();
+    for name in ["Grid", "Water"] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    let mut departments = Vec::new();
+    for company_id in [Some(companies[1]), None] {
+        let department = Department::insert(
+            pool,
+            Department {
+                company_id,
+                name: text("Logistics"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        departments.push(department.id);
+    }
+    for department_id in departments.iter().copied() {
+        let survivor = Contact::insert(
+            pool,
+            Contact {
+                name: text("Sidorov"),
+                company_id: Some(companies[0]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let duplicate = Contact::insert(
+            pool,
+            Contact {
+                name: text("sidorov"),
+                company_id: Some(companies[1]),
+                department_id: Some(department_id),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        Contact::merge(pool, survivor.id, duplicate.id)
+            .await
+            .unwrap();
+        let stored = Contact::get(pool, survivor.id).await.unwrap();
+        assert_eq!(stored.company_id, Some(companies[0]));
+        let kept = (department_id == departments[1]).then_some(department_id);
+        assert_eq!(stored.department_id, kept);
+        Contact::delete(pool, survivor.id).await.unwrap();
+    }
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn departments_by_company() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let mut companies = Vec::new();
+    for name in ["Grid", "Water"] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    let insert = |name: &str, company_id: i64, parent_id: Option<i64>| {
+        Department::insert(
+            pool,
+            Department {
+                company_id: Some(company_id),
+                parent_id,
+                name: text(name),
+                ..Default::default()
+            },
+        )
+    };
+    let logistics = insert("Logistics", companies[0], None).await.unwrap();
+    let warehouse = insert("Warehouse", companies[0], Some(logistics.id))
+        .await
+        .unwrap();
+    insert("Logistics", companies[1], None).await.unwrap();
+    assert!(matches!(
+        insert("Garage", companies[1], Some(logistics.id)).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    let names: Vec<Option<String>> = SelectItem::department_by_company(pool, companies[0])
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|item| item.name)
+        .collect();
+    assert_eq!(
+        names,
+        vec![text("Logistics"), text("Logistics / Warehouse")]
+    );
+    assert_eq!(
+        SelectItem::department_by_company(pool, companies[1])
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let mut looped = Department::get(pool, logistics.id).await.unwrap();
+    looped.parent_id = Some(warehouse.id);
+    assert!(matches!(
+        Department::update(pool, looped).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    assert!(matches!(
+        Contact::insert(
+            pool,
+            Contact {
+                name: text("Ivanov"),
+                company_id: Some(companies[1]),
+                department_id: Some(warehouse.id),
+                ..Default::default()
+            },
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(companies[0]),
+            department_id: Some(warehouse.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let patch = ContactPatch {
+        company_id: Some(Some(companies[1])),
+        updated_at: Contact::get(pool, contact.id).await.unwrap().updated_at,
+        ..Default::default()
+    };
+    assert!(matches!(
+        Contact::patch(pool, contact.id, patch).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    // Only a write past Contact can leave the contact in a foreign department.
+    pool.get()
+        .await
+        .unwrap()
+        .execute(
+            "UPDATE contacts SET company_id = $1 WHERE id = $2",
+            &[&companies[1], &contact.id],
+        )
+        .await
+        .unwrap();
+    let issues = integrity::check(pool).await.unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(
+        (issues[0].check.as_str(), issues[0].id),
+        ("foreign_department", contact.id)
+    );
+
+    // A department keeps its company while departments are nested in it or
+    // contacts are in it, and is not deleted from under a contact.
+    for id in [logistics.id, warehouse.id] {
+        let mut moved = Department::get(pool, id).await.unwrap();
+        moved.company_id = Some(companies[1]);
+        assert!(matches!(
+            Department::update(pool, moved).await,
+            Err(RpelError::Invalid(_))
+        ));
+    }
+    assert!(matches!(
+        Department::delete(pool, warehouse.id).await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    assert_eq!(Department::delete(pool, logistics.id).await.unwrap(), 1);
+    assert_eq!(
+        Department::get(pool, warehouse.id).await.unwrap().parent_id,
+        None
+    );
+
+    // The company keeps its departments while a contact is in one of them
+    // and takes them along once nobody is.
+    assert!(matches!(
+        Company::delete(pool, companies[0]).await,
+        Err(RpelError::Invalid(_))
+    ));
+    assert_eq!(Contact::delete(pool, contact.id).await.unwrap(), 1);
+    assert_eq!(Company::delete(pool, companies[0]).await.unwrap(), 1);
+    assert!(matches!(
+        Department::get(pool, warehouse.id).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+    assert_eq!(DepartmentList::get_all(pool).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn shared_departments_migration() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    // Put `departments` back the way it was before version 4.
+    let client = pool.get().await.unwrap();
+    client
+        .batch_execute(
+            "
+                ALTER TABLE departments DROP CONSTRAINT departments_company_id_name_key;
+                ALTER TABLE departments DROP COLUMN company_id;
+                ALTER TABLE departments DROP COLUMN parent_id;
+                ALTER TABLE departments ADD CONSTRAINT departments_name_key UNIQUE (name);
+                DELETE FROM schema_migrations WHERE version = 4;
+            ",
+        )
+        .await
+        .unwrap();
+    let mut companies = Vec::new();
+    for name in ["Grid", "Water"] {
+        let company = Company::insert(
+            pool,
+            Company {
+                name: text(name),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(Some(company.id));
+    }
+    let mut departments = Vec::new();
+    for name in ["Logistics", "Accounting", "Archive"] {
+        let row = client
+            .query_one(
+                "INSERT INTO departments (name) VALUES ($1) RETURNING id",
+                &[&name],
+            )
+            .await
+            .unwrap();
+        departments.push(Some(row.get::<_, i64>(0)));
+    }
+    let mut contacts = Vec::new();
+    for (name, company_id, department_id) in [
+        ("Ivanov", companies[0], departments[0]),
+        ("Petrov", companies[1], departments[0]),
+        ("Sidorov", None, departments[0]),
+        ("Orlov", companies[1], departments[1]),
+    ] {
+        let row = client
+            .query_one(
+                "INSERT INTO contacts (name, company_id, department_id) VALUES ($1, $2, $3) RETURNING id",
+                &[&name, &company_id, &department_id],
+            )
+            .await
+            .unwrap();
+        contacts.push(row.get::<_, i64>(0));
+    }
+
+    assert_eq!(migrate::run(pool).await.unwrap(), vec![4]);
+
+    let mut moved = Vec::new();
+    for id in &contacts {
+        let contact = Contact::get(pool, *id).await.unwrap();
+        let department = Department::get(pool, contact.department_id.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(department.company_id, contact.company_id);
+        moved.push((department.id, department.name));
+    }
+    // Logistics was shared: Grid and Water get copies and the contact without
+    // a company keeps the original. Accounting simply moves into Water.
+    assert_ne!(moved[0].0, moved[1].0);
+    assert_eq!(moved[2].0, departments[0].unwrap());
+    assert_eq!(moved[3].0, departments[1].unwrap());
+    assert!(moved
+        .iter()
+        .take(3)
+        .all(|(_, name)| *name == text("Logistics")));
+    let archive = Department::get(pool, departments[2].unwrap())
+        .await
+        .unwrap();
+    assert_eq!(archive.company_id, None);
+    assert_eq!(DepartmentList::get_all(pool).await.unwrap().len(), 5);
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn migrations_and_integrity() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    assert!(migrate::run(pool).await.unwrap().is_empty());
+    let status = migrate::status(pool).await.unwrap();
+    assert_eq!(status.len(), migrate::MIGRATIONS.len());
+    assert!(status.iter().all(|migration| migration.applied));
+
+    let contact = Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(404),
+            phones: vec![555],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let career = EmploymentList::get_by_contact(pool, contact.id)
+        .await
+        .unwrap();
+    let issues = integrity::check(pool).await.unwrap();
+    assert_eq!(
+        issues,
+        vec![
+            Issue {
+                check: "orphan_reference".to_string(),
+                table: "contacts".to_string(),
+                id: contact.id,
+                detail: "company_id points to missing companies 404".to_string(),
+            },
+            Issue {
+                check: "orphan_reference".to_string(),
+                table: "employments".to_string(),
+                id: career[0].id,
+                detail: "company_id points to missing companies 404".to_string(),
+            },
+        ]
+    );
+
+    // Company::update refuses loops, so write one by hand.
+    Contact::delete(pool, contact.id).await.unwrap();
+    let a = Company::insert(
+        pool,
+        Company {
+            name: text("A"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let b = Company::insert(
+        pool,
+        Company {
+            name: text("B"),
+            parent_id: Some(a.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    pool.get()
+        .await
+        .unwrap()
+        .execute(
+            "UPDATE companies SET parent_id = $1 WHERE id = $2",
+            &[&b.id, &a.id],
+        )
+        .await
+        .unwrap();
+    let cycles: Vec<i64> = integrity::check(pool)
+        .await
+        .unwrap()
+        .iter()
+        .filter(|issue| issue.check == "company_cycle")
+        .map(|issue| issue.id)
+        .collect();
+    assert_eq!(cycles, vec![a.id, b.id]);
+    let subtree = Company::subtree(pool, a.id).await.unwrap();
+    assert_eq!(subtree.len(), 2);
+}
+
+#[tokio::test]
+async fn untracked_schema_needs_a_baseline() {
+    let db = test_db!(empty);
+    let pool = &db.pool;
+    pool.get()
+        .await
+        .unwrap()
+        .batch_execute(include_str!("fixtures/baseline_schema.sql"))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        migrate::run(pool).await,
+        Err(RpelError::Invalid(message)) if message.contains("--baseline")
+    ));
+    assert_eq!(
+        migrate::current_version(&pool.get().await.unwrap())
+            .await
+            .unwrap(),
+        0
+    );
+
+    assert_eq!(migrate::baseline(pool, 2).await.unwrap(), vec![1, 2]);
+    let rest: Vec<i64> = (3..=migrate::MIGRATIONS.len() as i64).collect();
+    assert_eq!(migrate::run(pool).await.unwrap(), rest);
+
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            full_name: text("Grid Company"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let department = Department::insert(
+        pool,
+        Department {
+            name: text("Dispatch"),
+            company_id: Some(company.id),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    Contact::insert(
+        pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(company.id),
+            department_id: Some(department.id),
+            phones: vec![555],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn readiness() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let health = Health::ready(pool).await;
+    assert!(health.ok, "{:?}", health.error);
+    assert!(health.server_version.is_some());
+    assert_eq!(health.migration, Some(migrate::MIGRATIONS.len() as i64));
+
+    // With every connection checked out the check has to give up rather
+    // than wait for one.
+    let mut held = Vec::new();
+    for _ in 0..pool.status().max_size {
+        held.push(pool.get().await.unwrap());
+    }
+    let health = Health::ready_within(pool, StdDuration::from_millis(200)).await;
+    assert!(!health.ok);
+    assert_eq!(health.error.as_deref(), Some("no answer within 200 ms"));
+    drop(held);
+    assert!(Health::ready(pool).await.ok);
+}
+
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let source = test_db!();
+    let target = test_db!();
+
+    let scope = Scope::insert(
+        &source.pool,
+        Scope {
+            name: text("Energy"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut companies = Vec::new();
+    for name in ["Grid", "Water", "Gas"] {
+        let company = Company::insert(
+            &source.pool,
+            Company {
+                name: text(name),
+                scope_id: Some(scope.id),
+                phones: vec![100 + companies.len() as i64],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        companies.push(company.id);
+    }
+    // A gap in the ids, which the restore has to keep.
+    Company::delete(&source.pool, companies[1]).await.unwrap();
+    Contact::insert(
+        &source.pool,
+        Contact {
+            name: text("Ivanov"),
+            company_id: Some(companies[2]),
+            emails: vec!["ivanov@gas.test".to_string()],
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let data: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| (i % 7) as u8).collect();
+    let attachment = Attachment::upload(
+        &source.pool,
+        Attachment {
+            owner: AttachmentOwner::Companies,
+            owner_id: companies[2],
+            filename: "license.pdf".to_string(),
+            ..Default::default()
+        },
+        stream::iter(vec![Ok::<_, RpelError>(data.as_slice())]),
+    )
+    .await
+    .unwrap();
+
+    User::insert(
+        &source.pool,
+        User {
+            id: 0,
+            name: "operator".to_string(),
+            key: "secret".to_string(),
+            role: 1,
+            created_at: None,
+            updated_at: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    // Keys only leave the database when asked for.
+    let keyed = Snapshot::export(&source.pool, true).await.unwrap();
+    assert_eq!(keyed.tables["users"][0]["key"], "secret");
+    let mut snapshot = Snapshot::export(&source.pool, false).await.unwrap();
+    assert!(snapshot.tables["users"][0].get("key").is_none());
+    assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+    assert_eq!(
+        snapshot.schema_version,
+        migrate::MIGRATIONS.last().unwrap().version
+    );
+    assert_eq!(snapshot.tables["companies"].len(), 2);
+    // The file itself travels apart from the snapshot.
+    assert!(!snapshot.tables.contains_key("attachment_chunks"));
+    assert_eq!(snapshot.attachment_ids(), vec![attachment.id]);
+    let rows: usize = snapshot.tables.values().map(Vec::len).sum();
+
+    snapshot.version = SNAPSHOT_VERSION + 1;
+    assert!(matches!(
+        Snapshot::restore(&target.pool, &snapshot).await,
+        Err(RpelError::Snapshot(_))
+    ));
+    snapshot.version = SNAPSHOT_VERSION;
+    snapshot.schema_version -= 1;
+    assert!(matches!(
+        Snapshot::restore(&target.pool, &snapshot).await,
+        Err(RpelError::Snapshot(_))
+    ));
+    snapshot.schema_version += 1;
+    snapshot.tables.insert("bogus".to_string(), Vec::new());
+    assert!(matches!(
+        Snapshot::restore(&target.pool, &snapshot).await,
+        Err(RpelError::Snapshot(_))
+    ));
+    snapshot.tables.remove("bogus");
+    assert!(CompanyList::get_all(&target.pool).await.unwrap().is_empty());
+
+    let restored = Snapshot::restore(&target.pool, &snapshot).await.unwrap();
+    assert_eq!(restored, rows as u64);
+    let user = User::get_by_name(&target.pool, "operator")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!user.key.is_empty());
+    assert_ne!(user.key, "secret");
+    let company = Company::get(&target.pool, companies[2]).await.unwrap();
+    assert_eq!(company.name, text("Gas"));
+    assert_eq!(company.phones, vec![102]);
+    assert!(matches!(
+        Company::get(&target.pool, companies[1]).await,
+        Err(RpelError::NotFound(_, _))
+    ));
+    let contacts = ContactList::get_all(&target.pool).await.unwrap();
+    assert_eq!(contacts[0].company_id, Some(companies[2]));
+    assert_eq!(contacts[0].emails, vec!["ivanov@gas.test".to_string()]);
+
+    let (_, chunks) = Attachment::content(&source.pool, attachment.id)
+        .await
+        .unwrap();
+    let copied: Vec<Vec<u8>> = chunks.map(Result::unwrap).collect().await;
+    assert!(matches!(
+        Attachment::restore_content(
+            &target.pool,
+            attachment.id,
+            stream::iter(vec![Ok::<_, RpelError>(b"forged")]),
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+    Attachment::restore_content(
+        &target.pool,
+        attachment.id,
+        stream::iter(copied.into_iter().map(Ok::<_, RpelError>)),
+    )
+    .await
+    .unwrap();
+    let (_, chunks) = Attachment::content(&target.pool, attachment.id)
+        .await
+        .unwrap();
+    let restored: Vec<Vec<u8>> = chunks.map(Result::unwrap).collect().await;
+    assert_eq!(restored.concat(), data);
+    assert!(matches!(
+        Attachment::restore_content(
+            &target.pool,
+            attachment.id,
+            stream::iter(vec![Ok::<_, RpelError>(data.as_slice())]),
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+    assert!(integrity::check(&target.pool).await.unwrap().is_empty());
+    // The sequences carry on after the restored ids.
+    let company = Company::insert(
+        &target.pool,
+        Company {
+            name: text("Heat"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(company.id > companies[2]);
+
+    assert!(matches!(
+        Snapshot::restore(&target.pool, &snapshot).await,
+        Err(RpelError::Snapshot(_))
+    ));
+}
+
+#[tokio::test]
+async fn change_notifications() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let mut changes = notify::listen_config(db.config(), tokio_postgres::NoTls);
+    // LISTEN runs on the listener's own connection; wait until it has.
+    let client = pool.get().await.unwrap();
+    for attempt in 0.. {
+        assert!(attempt < 250, "listener did not start");
+        let row = client
+            .query_one(
+                "SELECT count(*) FROM pg_stat_activity WHERE datname = current_database() AND query LIKE 'LISTEN%'",
+                &[],
+            )
+            .await
+            .unwrap();
+        if row.get::<_, i64>(0) > 0 {
+            break;
+        }
+        tokio::time::sleep(StdDuration::from_millis(20)).await;
+    }
+    let company = Company::insert(
+        pool,
+        Company {
+            name: text("Grid"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let patch = CompanyPatch {
+        note: Some(text("north")),
+        updated_at: Company::get(pool, company.id).await.unwrap().updated_at,
+        ..Default::default()
+    };
+    Company::patch(pool, company.id, patch).await.unwrap();
+    Company::delete(pool, company.id).await.unwrap();
+    assert_eq!(Company::delete(pool, company.id).await.unwrap(), 0);
+
+    for operation in [Operation::Insert, Operation::Update, Operation::Delete] {
+        let event = tokio::time::timeout(StdDuration::from_secs(5), changes.recv())
+            .await
+            .expect("change event");
+        assert_eq!(
+            event,
+            Some(Event::Change(Change {
+                entity: "companies".to_string(),
+                id: company.id,
+                operation,
+            }))
+        );
+    }
+}
+
+#[tokio::test]
+async fn attachments_are_chunked_and_follow_owners() {
+    let db = test_db!();
+    let pool = &db.pool;
+
+    let certificate = Certificate::insert(
+        pool,
+        Certificate {
+            num: text("77-1"),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 1000)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    // Pieces that do not line up with the stored chunks.
+    let pieces: Vec<Result<&[u8], RpelError>> = data.chunks(100_000).map(Ok).collect();
+    let attachment = Attachment::upload(
+        pool,
+        Attachment {
+            owner: AttachmentOwner::Certificates,
+            owner_id: certificate.id,
+            filename: " scan.pdf ".to_string(),
+            mime_type: "application/pdf".to_string(),
+            ..Default::default()
+        },
+        stream::iter(pieces),
+    )
+    .await
+    .unwrap();
+    assert_eq!(attachment.filename, "scan.pdf");
+    assert_eq!(attachment.size, data.len() as i64);
+    let digest: String = Sha256::digest(&data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    assert_eq!(attachment.checksum, digest);
+
+    let (stored, chunks) = Attachment::content(pool, attachment.id).await.unwrap();
+    assert_eq!(stored.checksum, digest);
+    let chunks: Vec<Vec<u8>> = chunks.map(Result::unwrap).collect().await;
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.concat(), data);
+
+    let empty = Attachment::upload(
+        pool,
+        Attachment {
+            owner: AttachmentOwner::Certificates,
+            owner_id: certificate.id,
+            filename: "empty.txt".to_string(),
+            ..Default::default()
+        },
+        stream::iter(Vec::<Result<&[u8], RpelError>>::new()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(empty.mime_type, "application/octet-stream");
+    assert_eq!(empty.size, 0);
+    assert!(matches!(
+        Attachment::upload(
+            pool,
+            Attachment {
+                owner: AttachmentOwner::Sirens,
+                owner_id: certificate.id,
+                filename: "plan.pdf".to_string(),
+                ..Default::default()
+            },
+            stream::iter(vec![Ok::<_, RpelError>(b"x")]),
+        )
+        .await,
+        Err(RpelError::Invalid(_))
+    ));
+
+    let mut renamed = Attachment::get(pool, empty.id).await.unwrap();
+    renamed.filename = "act.txt".to_string();
+    assert_eq!(Attachment::update(pool, renamed).await.unwrap(), 1);
+    let names: Vec<_> =
+        AttachmentList::get_by_owner(pool, AttachmentOwner::Certificates, certificate.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.filename, a.size))
+            .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("act.txt".to_string(), 0),
+            ("scan.pdf".to_string(), data.len() as i64)
+        ]
+    );
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+
+    let client = pool.get().await.unwrap();
+    client
+        .execute("DELETE FROM attachment_chunks WHERE seq = 2", &[])
+        .await
+        .unwrap();
+    let issues = integrity::check(pool).await.unwrap();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(
+        (issues[0].check.as_str(), issues[0].id),
+        ("attachment_size", attachment.id)
+    );
+
+    // A short read is an error, whether chunks are missing or the file is
+    // deleted while it is being read.
+    let (_, chunks) = Attachment::content(pool, attachment.id).await.unwrap();
+    let read: Vec<_> = chunks.collect().await;
+    assert_eq!(read.len(), 3);
+    assert!(matches!(read[2], Err(RpelError::Invalid(_))));
+    let (_, mut chunks) = Attachment::content(pool, attachment.id).await.unwrap();
+    assert!(chunks.next().await.unwrap().is_ok());
+    assert_eq!(Attachment::delete(pool, attachment.id).await.unwrap(), 1);
+    assert!(matches!(
+        chunks.next().await,
+        Some(Err(RpelError::NotFound(_, _)))
+    ));
+    drop(chunks);
+
+    assert_eq!(Certificate::delete(pool, certificate.id).await.unwrap(), 1);
+    assert!(AttachmentList::get_all(pool).await.unwrap().is_empty());
+    let row = client
+        .query_one("SELECT count(*) FROM attachment_chunks", &[])
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, i64>(0), 0);
+    assert!(integrity::check(pool).await.unwrap().is_empty());
+}
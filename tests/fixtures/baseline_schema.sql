@@ -0,0 +1,200 @@
+-- The schema of databases created before migrations were tracked, as kept
+-- in sql/ at the time. Migrations 1 and 2 were already part of it. The files
+-- did not run as committed, so the missing comma after
+-- contacts.department_id, the sirens key on the renamed type_id and the
+-- practice and education dates declared as time are fixed the way the code
+-- of that version read them.
+
+CREATE TABLE IF NOT EXISTS
+    scopes (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    kinds (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        short_name text,
+        note       text,
+        created_at TIMESTAMP without time zone,
+        updated_at TIMESTAMP without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    ranks (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    posts (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        go         bool NOT NULL DEFAULT FALSE,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE (name, go)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    departments (
+        id         bigserial primary key,
+        name       text,
+        note       text,
+        created_at TIMESTAMP without time zone,
+        updated_at TIMESTAMP without time zone,
+        UNIQUE (name)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    siren_types (
+        id         bigserial primary key,
+        name       text,
+        radius     bigint,
+        note       text,
+        created_at TIMESTAMP without time zone,
+        updated_at TIMESTAMP without time zone,
+        UNIQUE(name, radius)
+    );
+
+CREATE TABLE IF NOT EXISTS hideout_types (
+    id         bigserial primary key,
+    name       text,
+    note       text,
+    created_at TIMESTAMP without time zone,
+    updated_at TIMESTAMP without time zone default now(),
+    UNIQUE(name)
+);
+
+CREATE TABLE IF NOT EXISTS 
+    companies (
+        id         bigserial PRIMARY KEY,
+        name       text,
+        full_name  text,
+        address    text,
+        scope_id   bigint,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now(),
+        UNIQUE(name, scope_id)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    contacts (
+        id            bigserial PRIMARY KEY,
+        name          text,
+        company_id    bigint,
+        department_id bigint,
+        post_id       bigint,
+        post_go_id    bigint,
+        rank_id       bigint,
+        birthday      date,
+        note          text,
+        created_at    timestamp without time zone,
+        updated_at    timestamp without time zone DEFAULT now(),
+        UNIQUE (name, birthday)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    phones (
+        id         bigserial PRIMARY KEY,
+        contact_id bigint,
+        company_id bigint,
+        phone      bigint,
+        fax        bool NOT NULL DEFAULT false,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now()
+    );
+
+CREATE TABLE IF NOT EXISTS
+    emails (
+        id         bigserial PRIMARY KEY,
+        company_id bigint,
+        contact_id bigint,
+        email      text,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now()
+    );
+
+CREATE TABLE IF NOT EXISTS
+    practices (
+        id               bigserial PRIMARY KEY,
+        company_id       bigint,
+        kind_id          bigint,
+        topic            text,
+        date_of_practice date,
+        note             text,
+        created_at       timestamp without time zone,
+        updated_at       timestamp without time zone DEFAULT now(),
+        UNIQUE (company_id, kind_id, date_of_practice)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    educations (
+        id         bigserial PRIMARY KEY,
+        contact_id bigint,
+        post_id    bigint,
+        start_date date,
+        end_date   date,
+        note       text,
+        created_at timestamp without time zone,
+        updated_at timestamp without time zone DEFAULT now()
+    );
+
+CREATE TABLE IF NOT EXISTS
+	certificates (
+		id         BIGSERIAL PRIMARY KEY,
+		num        TEXT,
+		contact_id BIGINT,
+		company_id BIGINT,
+		cert_date  DATE,
+		note       TEXT,
+		created_at TIMESTAMP without time zone,
+		updated_at TIMESTAMP without time zone default now(),
+		UNIQUE(num)
+	);
+
+CREATE TABLE IF NOT EXISTS
+    sirens (
+        id            bigserial PRIMARY KEY,
+        num_id        bigint,
+        num_pass      text,
+        siren_type_id bigint,
+        address       text,
+        radio         text,
+        desk          text,
+        contact_id    bigint,
+        company_id    bigint,
+        latitude      text,
+        longitude     text,
+        stage         bigint,
+        own           text,
+        note          text,
+        created_at    TIMESTAMP without time zone,
+        updated_at    TIMESTAMP without time zone,
+        UNIQUE(num_id, num_pass, siren_type_id)
+    );
+
+CREATE TABLE IF NOT EXISTS
+    users (
+        id         bigserial primary key,
+        name       text NOT NULL,
+        key        text NOT NULL,
+        role       bigint NOT NULL,
+        created_at TIMESTAMP without time zone,
+        updated_at TIMESTAMP without time zone,
+        UNIQUE (name)
+    );